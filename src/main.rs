@@ -1,5 +1,6 @@
 #[macro_use] extern crate lazy_static;
 #[macro_use] extern crate downcast;
+extern crate libc;
 extern crate termion;
 
 mod prolog;
@@ -8,6 +9,10 @@ use prolog::ast::*;
 use prolog::io::*;
 use prolog::machine::*;
 
+use std::env;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
 #[cfg(test)]
 mod tests;
 
@@ -22,33 +27,80 @@ fn parse_and_compile_line(wam: &mut Machine, buffer: &str)
             let result = compile_packet(wam, packet);
             print(wam, result);
         },
-        Err(s) => println!("{:?}", s)
+        Err(e) => print(wam, EvalSession::from(e))
+    }
+}
+
+// indexes a library module, which is loaded on first use.
+fn load_library(wam: &mut Machine, src_str: &'static str, file: &'static str)
+{
+    if let Err(_) = wam.add_library(src_str, file) {
+        panic!("failed to parse library from string.");
     }
 }
 
-fn load_init_str(wam: &mut Machine, src_str: &str)
+fn parse_args(wam: &mut Machine)
 {
-    match compile_listing(wam, src_str) {
-        EvalSession::Error(_) => panic!("failed to parse batch from string."),
-        _ => {}
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--query-timeout" =>
+                match args.next().and_then(|secs| secs.parse::<u64>().ok()) {
+                    Some(secs) => wam.set_query_timeout(Some(Duration::from_secs(secs))),
+                    None => {
+                        let expected = "a number of seconds";
+                        wam.print_message(Message::InvalidOptionValue(arg.clone(), expected));
+                    }
+                },
+            "--heap-limit" | "--stack-limit" | "--choice-limit" | "--trail-limit" => {
+                let flag  = format!("{}_limit", &arg[2 .. arg.len() - 6]);
+                let limit = ResourceLimit::from_flag(flag.as_str()).unwrap();
+
+                match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                    Some(n) if n > 0 => wam.set_resource_limit(limit, n),
+                    _ => {
+                        let expected = "a positive integer";
+                        wam.print_message(Message::InvalidOptionValue(arg.clone(), expected));
+                    }
+                }
+            },
+            _ => wam.print_message(Message::UnknownOption(arg.clone()))
+        }
+    }
+}
+
+extern "C" fn handle_sigint(_: libc::c_int) {
+    INTERRUPT.store(true, Ordering::Relaxed);
+}
+
+fn install_sigint_handler() {
+    unsafe {
+        libc::signal(libc::SIGINT, handle_sigint as libc::sighandler_t);
     }
 }
 
 fn prolog_repl() {
     let mut wam = Machine::new();
 
-    load_init_str(&mut wam, LISTS);
-    load_init_str(&mut wam, CONTROL);
-    load_init_str(&mut wam, QUEUES);
+    parse_args(&mut wam);
+    install_sigint_handler();
+
+    load_library(&mut wam, LISTS, "src/prolog/lib/lists.pl");
+    load_library(&mut wam, CONTROL, "src/prolog/lib/control.pl");
+    load_library(&mut wam, QUEUES, "src/prolog/lib/queues.pl");
 
     loop {
-        print!("prolog> ");
+        match wam.toplevel_module().as_str() {
+            "user" => print!("prolog> "),
+            module_name => print!("{}> ", module_name)
+        };
 
         match read() {
             Input::Line(line) => parse_and_compile_line(&mut wam, line.as_str()),
             Input::Batch(batch) =>
                 match compile_listing(&mut wam, batch.as_str()) {
-                    EvalSession::Error(e) => println!("{}", e),
+                    EvalSession::Error(e) => wam.print_message(Message::Error(e)),
                     _ => {}
                 },
             Input::Quit => break,
@@ -8,6 +8,7 @@ pub struct Frame {
     pub global_index: usize,
     pub e: usize,
     pub cp: CodePtr,
+    pub depth: usize,
     perms: Vec<Addr>
 }
 
@@ -17,6 +18,7 @@ impl Frame {
             global_index,
             e: e,
             cp: cp,
+            depth: 0,
             perms: (1 .. n+1).map(|i| Addr::StackCell(fr, i)).collect()
         }
     }
@@ -46,6 +48,10 @@ impl AndStack {
     pub fn clear(&mut self) {
         self.0.clear()
     }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len)
+    }
     
     pub fn resize(&mut self, fr: usize, n: usize) {                        
         let len = self[fr].perms.len();               
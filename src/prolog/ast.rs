@@ -10,6 +10,7 @@ use std::collections::{BTreeSet, HashMap, VecDeque};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::io::Error as IOError;
+use std::mem;
 use std::num::{ParseFloatError};
 use std::ops::{Add, AddAssign, Div, Index, IndexMut, Sub, Mul, Neg};
 use std::rc::Rc;
@@ -153,27 +154,229 @@ pub struct ModuleDecl {
     pub exports: Vec<PredicateKey>
 }
 
+impl ModuleDecl {
+    // the key of an export, Name/Arity, or Name//Arity for a
+    // nonterminal, whose predicate takes two more arguments.
+    pub fn export_key(term: &Term) -> Option<PredicateKey> {
+        match term {
+            &Term::Clause(_, ref slash, ref terms, _) if terms.len() == 2 => {
+                let name = match terms[0].as_ref() {
+                    &Term::Constant(_, Constant::Atom(ref name)) => name.clone(),
+                    _ => return None
+                };
+
+                let arity = match terms[1].as_ref() {
+                    &Term::Constant(_, Constant::Number(Number::Integer(ref n))) =>
+                        n.to_usize()?,
+                    _ => return None
+                };
+
+                match slash.as_str() {
+                    "/"  => Some((name, arity)),
+                    "//" => Some((name, arity + 2)),
+                    _ => None
+                }
+            },
+            _ => None
+        }
+    }
+}
+
+// the argument modes of a meta_predicate declaration.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MetaSpec {
+    Closure(usize), // 0..9: a goal lacking that many arguments.
+    Qualified, // :
+    Existential, // ^
+    Dcg, // //
+    Plain // +, -, ? or *
+}
+
+impl MetaSpec {
+    pub fn from_term(term: &Term) -> Option<Self> {
+        match term {
+            &Term::Constant(_, Constant::Number(Number::Integer(ref n))) =>
+                match n.to_usize() {
+                    Some(n) if n <= 9 => Some(MetaSpec::Closure(n)),
+                    _ => None
+                },
+            &Term::Constant(_, Constant::Atom(ref name)) =>
+                match name.as_str() {
+                    ":"  => Some(MetaSpec::Qualified),
+                    "^"  => Some(MetaSpec::Existential),
+                    "//" => Some(MetaSpec::Dcg),
+                    "+" | "-" | "?" | "*" => Some(MetaSpec::Plain),
+                    _ => None
+                },
+            _ => None
+        }
+    }
+
+    // meta arguments are qualified by the module of the caller.
+    pub fn is_meta(self) -> bool {
+        self != MetaSpec::Plain
+    }
+}
+
+pub type MetaPredicateDir = HashMap<PredicateKey, Vec<MetaSpec>>;
+
+// the argument modes of a mode declaration.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    In,  // +: bound on entry.
+    Out, // -: unbound on entry.
+    Any  // ?
+}
+
+impl Mode {
+    pub fn from_term(term: &Term) -> Option<Self> {
+        match term {
+            &Term::Constant(_, Constant::Atom(ref name)) =>
+                match name.as_str() {
+                    "+" => Some(Mode::In),
+                    "-" => Some(Mode::Out),
+                    "?" => Some(Mode::Any),
+                    _ => None
+                },
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Mode::In  => write!(f, "+"),
+            &Mode::Out => write!(f, "-"),
+            &Mode::Any => write!(f, "?")
+        }
+    }
+}
+
+pub type ModeDir = HashMap<PredicateKey, Vec<Mode>>;
+
 pub struct Module {
     pub module_decl: ModuleDecl,
     pub code_dir: CodeDir,
-    pub op_dir: OpDir
+    pub op_dir: OpDir,
+    pub meta_predicates: MetaPredicateDir,
+    pub term_dir: TermDir,
+    pub file: Option<ClauseName> // the file the module was loaded from, if any.
 }
 
 impl Module {
     pub fn new(module_decl: ModuleDecl) -> Self {
         Module { module_decl,
                  code_dir: CodeDir::new(),
-                 op_dir: OpDir::new() }
+                 op_dir: OpDir::new(),
+                 meta_predicates: MetaPredicateDir::new(),
+                 term_dir: TermDir::new(),
+                 file: None }
     }
 }
 
-impl SubModuleUser for Module {
+// how clashes between imports, and between imports and definitions,
+// are treated.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ImportConflicts {
+    Error,
+    Warning
+}
+
+// how double-quoted text is read, as set by the double_quotes flag.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DoubleQuotes {
+    Atom,
+    Chars,
+    Codes,
+    String
+}
+
+impl DoubleQuotes {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "atom"   => Some(DoubleQuotes::Atom),
+            "chars"  => Some(DoubleQuotes::Chars),
+            "codes"  => Some(DoubleQuotes::Codes),
+            "string" => Some(DoubleQuotes::String),
+            _ => None
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DoubleQuotes::Atom   => "atom",
+            DoubleQuotes::Chars  => "chars",
+            DoubleQuotes::Codes  => "codes",
+            DoubleQuotes::String => "string"
+        }
+    }
+}
+
+// the imports of a qualified use_module or reexport: the listed
+// predicates, each with the name given it by as, if any, or every
+// export except those listed.
+#[derive(Clone)]
+pub enum ImportList {
+    Only(Vec<(PredicateKey, Option<ClauseName>)>),
+    Except(Vec<PredicateKey>)
+}
+
+impl ImportList {
+    // the exports of the submodule named by the list, which may only
+    // name its exports.
+    pub fn imports(self, submodule: &Module)
+                   -> Result<Vec<(PredicateKey, Option<ClauseName>)>, EvalError>
+    {
+        let exports = &submodule.module_decl.exports;
+
+        match self {
+            ImportList::Only(imports) =>
+                if imports.iter().all(|&(ref key, _)| exports.contains(key)) {
+                    Ok(imports)
+                } else {
+                    Err(EvalError::ModuleDoesNotContainExport)
+                },
+            ImportList::Except(keys) =>
+                Ok(exports.iter()
+                          .filter(|key| !keys.contains(key))
+                          .map(|key| (key.clone(), None))
+                          .collect())
+        }
+    }
+}
+
+// a module importing from its submodules, collecting the messages
+// raised by the imports.
+pub struct ModuleImports<'a> {
+    pub module: &'a mut Module,
+    pub messages: &'a mut Vec<Message>,
+    pub conflicts: ImportConflicts
+}
+
+impl<'a> SubModuleUser for ModuleImports<'a> {
     fn op_dir(&mut self) -> &mut OpDir {
-        &mut self.op_dir
+        &mut self.module.op_dir
     }
 
     fn code_dir(&mut self) -> &mut CodeDir {
-        &mut self.code_dir
+        &mut self.module.code_dir
+    }
+
+    fn module_name(&self) -> ClauseName {
+        self.module.module_decl.name.clone()
+    }
+
+    fn conflicts(&self) -> ImportConflicts {
+        self.conflicts
+    }
+
+    fn report(&mut self, message: Message) {
+        self.messages.push(message);
+    }
+
+    fn exports(&mut self) -> Option<&mut Vec<PredicateKey>> {
+        Some(&mut self.module.module_decl.exports)
     }
 }
 
@@ -181,53 +384,129 @@ pub trait SubModuleUser {
     fn op_dir(&mut self) -> &mut OpDir;
     fn code_dir(&mut self) -> &mut CodeDir;
 
-    // returns true on successful import.
-    fn import_decl(&mut self, name: ClauseName, arity: usize, submodule: &Module) -> bool {
-        let name = name.defrock_brackets();
+    // the module importing, which is user at the toplevel.
+    fn module_name(&self) -> ClauseName;
+    fn conflicts(&self) -> ImportConflicts;
 
-        if arity == 1 {
-            if let Some(op_data) = submodule.op_dir.get(&(name.clone(), Fixity::Pre)) {
-                self.op_dir().insert((name.clone(), Fixity::Pre), op_data.clone());
-            }
+    // the exports extended by a reexport, or None if the user exports
+    // nothing, as the toplevel does.
+    fn exports(&mut self) -> Option<&mut Vec<PredicateKey>>;
 
-            if let Some(op_data) = submodule.op_dir.get(&(name.clone(), Fixity::Post)) {
-                self.op_dir().insert((name.clone(), Fixity::Post), op_data.clone());
-            }
-        } else if arity == 2 {
-            if let Some(op_data) = submodule.op_dir.get(&(name.clone(), Fixity::In)) {
-                self.op_dir().insert((name.clone(), Fixity::In), op_data.clone());
+    // messages are printed by print_message/2 once the imports are done.
+    fn report(&mut self, message: Message);
+
+    // the module name/arity is imported from, if it is neither defined
+    // by the user, built in, nor undefined.
+    fn imported_from(&mut self, key: &PredicateKey) -> Option<ClauseName> {
+        let module_name = self.module_name();
+
+        match self.code_dir().get(key) {
+            Some(&CodeIndex (ref idx, ref mod_name)) =>
+                if idx.get() != IndexPtr::Undefined && mod_name != &module_name
+                    && mod_name.as_str() != "builtin"
+                {
+                    Some(mod_name.clone())
+                } else {
+                    None
+                },
+            None => None
+        }
+    }
+
+    // a definition of an imported predicate is a conflict, or drops the
+    // import with a warning.
+    fn define_local(&mut self, name: ClauseName, arity: usize) -> Result<(), EvalError> {
+        let key = (name, arity);
+
+        if let Some(import_module) = self.imported_from(&key) {
+            match self.conflicts() {
+                ImportConflicts::Error => {
+                    let (name, arity) = key;
+                    return Err(EvalError::RedefinedImport(self.module_name(), import_module,
+                                                          name, arity));
+                },
+                ImportConflicts::Warning => {
+                    self.report(Message::ImportOverwrite(key.0.clone(), key.1, import_module));
+                    self.code_dir().remove(&key);
+                }
             }
         }
 
-        if self.code_dir().contains_key(&(name.clone(), arity)) {
-            println!("warning: overwriting {}/{}", &name, arity);
+        Ok(())
+    }
+
+    // imports name/arity from the submodule, as alias if it is renamed.
+    fn import_decl(&mut self, name: ClauseName, arity: usize, alias: Option<ClauseName>,
+                   submodule: &Module)
+                   -> Result<(), EvalError>
+    {
+        let name = name.defrock_brackets();
+
+        let code_data = match submodule.code_dir.get(&(name.clone(), arity)) {
+            Some(code_data) => code_data.clone(),
+            None => return Err(EvalError::ModuleDoesNotContainExport)
+        };
+
+        // operators are not renamed along with their predicates.
+        if alias.is_none() {
+            if arity == 1 {
+                if let Some(op_data) = submodule.op_dir.get(&(name.clone(), Fixity::Pre)) {
+                    self.op_dir().insert((name.clone(), Fixity::Pre), op_data.clone());
+                }
+
+                if let Some(op_data) = submodule.op_dir.get(&(name.clone(), Fixity::Post)) {
+                    self.op_dir().insert((name.clone(), Fixity::Post), op_data.clone());
+                }
+            } else if arity == 2 {
+                if let Some(op_data) = submodule.op_dir.get(&(name.clone(), Fixity::In)) {
+                    self.op_dir().insert((name.clone(), Fixity::In), op_data.clone());
+                }
+            }
         }
 
-        if let Some(code_data) = submodule.code_dir.get(&(name.clone(), arity)) {
-            if let Some(ref mut global_code_data) = self.code_dir().get_mut(&(name.clone(), arity)) {
-                global_code_data.1 = code_data.1.clone();
-                global_code_data.0.set(code_data.0.get());
+        let key = (alias.unwrap_or(name), arity);
+
+        if let Some(idx) = self.code_dir().get(&key).cloned() {
+            // undefined entries are placeholders of calls made before
+            // the import.
+            let clash = idx.0.get() != IndexPtr::Undefined && idx.1 != code_data.1;
 
-                return true; // done to appease the borrow checker.
+            if clash {
+                match self.conflicts() {
+                    ImportConflicts::Error => {
+                        let (name, arity) = key;
+                        return Err(EvalError::ImportConflict(self.module_name(), code_data.1,
+                                                             name, arity));
+                    },
+                    ImportConflicts::Warning => {
+                        let module_name = submodule.module_decl.name.clone();
+                        self.report(Message::ImportOverwrite(key.0.clone(), key.1, module_name));
+                    }
+                }
             }
 
-            self.code_dir().insert((name, arity), code_data.clone());
-        } else {
-            return false;
+            // the call sites sharing the cells of placeholders and local
+            // definitions are rebound to the import.
+            if !clash || idx.1 == self.module_name() {
+                idx.0.set(code_data.0.get());
+
+                if let Some(entry) = self.code_dir().get_mut(&key) {
+                    entry.1 = code_data.1;
+                }
+
+                return Ok(());
+            }
         }
 
-        true
+        self.code_dir().insert(key, code_data);
+        Ok(())
     }
 
-    fn use_qualified_module(&mut self, submodule: &Module, exports: Vec<PredicateKey>) -> EvalSession
+    fn use_qualified_module(&mut self, submodule: &Module, imports: ImportList) -> EvalSession
     {
-        for (name, arity) in exports {
-            if !submodule.module_decl.exports.contains(&(name.clone(), arity)) {
-                continue;
-            }
-
-            if !self.import_decl(name, arity, submodule) {
-                return EvalSession::from(EvalError::ModuleDoesNotContainExport);
+        for ((name, arity), alias) in try_eval_session!(imports.imports(submodule)) {
+            if let Err(err) = self.import_decl(name, arity, alias, submodule) {
+                return EvalSession::from(err);
             }
         }
 
@@ -236,20 +515,61 @@ pub trait SubModuleUser {
 
     fn use_module(&mut self, submodule: &Module) -> EvalSession {
         for (name, arity) in submodule.module_decl.exports.iter().cloned() {
-            if !self.import_decl(name, arity, submodule) {
-                return EvalSession::from(EvalError::ModuleDoesNotContainExport);
+            if let Err(err) = self.import_decl(name, arity, None, submodule) {
+                return EvalSession::from(err);
             }
         }
 
         EvalSession::EntrySuccess
     }
+
+    fn add_exports(&mut self, keys: Vec<PredicateKey>) {
+        if let Some(exports) = self.exports() {
+            for key in keys {
+                if !exports.contains(&key) {
+                    exports.push(key);
+                }
+            }
+        }
+    }
+
+    // imports the exports of the submodule, and exports them in turn,
+    // along with their operators.
+    fn reexport_module(&mut self, submodule: &Module) -> Result<(), EvalError> {
+        for (name, arity) in submodule.module_decl.exports.iter().cloned() {
+            self.import_decl(name, arity, None, submodule)?;
+        }
+
+        self.add_exports(submodule.module_decl.exports.clone());
+        Ok(())
+    }
+
+    fn reexport_qualified_module(&mut self, submodule: &Module, imports: ImportList)
+                                 -> Result<(), EvalError>
+    {
+        let imports = imports.imports(submodule)?;
+
+        let exports = imports.iter().map(|&((ref name, arity), ref alias)| {
+            (alias.clone().unwrap_or(name.clone()), arity)
+        }).collect();
+
+        for ((name, arity), alias) in imports {
+            self.import_decl(name, arity, alias, submodule)?;
+        }
+
+        self.add_exports(exports);
+        Ok(())
+    }
 }
 
 pub enum Declaration {
+    MetaPredicate(Vec<(ClauseName, Vec<MetaSpec>)>),
     Module(ModuleDecl),
     Op(OpDecl),
+    QualifiedReexport(ClauseName, ImportList),
+    Reexport(ClauseName),
     UseModule(ClauseName),
-    UseQualifiedModule(ClauseName, Vec<PredicateKey>)
+    UseQualifiedModule(ClauseName, ImportList)
 }
 
 pub enum TopLevel {
@@ -261,6 +581,24 @@ pub enum TopLevel {
 }
 
 impl TopLevel {
+    pub fn qualify_goals(&mut self) {
+        match self {
+            &mut TopLevel::Predicate(ref mut clauses) =>
+                for clause in clauses.0.iter_mut() {
+                    if let &mut PredicateClause::Rule(ref mut rule) = clause {
+                        rule.qualify_goals();
+                    }
+                },
+            &mut TopLevel::Query(ref mut query) =>
+                for query_term in query.iter_mut() {
+                    query_term.qualify();
+                },
+            &mut TopLevel::Rule(ref mut rule) =>
+                rule.qualify_goals(),
+            &mut TopLevel::Declaration(_) | &mut TopLevel::Fact(_) => {}
+        }
+    }
+
     pub fn name(&self) -> Option<ClauseName> {
         match self {
             &TopLevel::Declaration(_) => None,
@@ -378,15 +716,94 @@ pub struct TempVarData {
 pub type HeapVarDict  = HashMap<Rc<Var>, Addr>;
 pub type AllocVarDict = HashMap<Rc<Var>, VarData>;
 
+// a conditional compilation directive out of place.
+pub enum ConditionalError {
+    FollowsElse(&'static str), // the elif/1 or else/0 directive.
+    NoMatchingEndif,
+    NoMatchingIf(&'static str) // the elif/1, else/0 or endif/0 directive.
+}
+
 pub enum EvalError {
+    ConditionalError(ConditionalError),
+    ExpansionLimit(String), // the source of the term whose expansion doesn't terminate.
     ImpermissibleEntry(String),
+    ImportConflict(ClauseName, ClauseName, ClauseName, usize), // importer, module, name, arity.
+    InvalidModeDeclaration(String), // the source of the declaration.
+    LateModeDeclaration(ClauseName, usize), // the name and arity of the predicate.
     ModuleDoesNotContainExport,
     ModuleNotFound,
     NamelessEntry,
     OpIsInfixAndPostFix,
     ParserError(ParserError),
+    QueryAborted,
     QueryFailure,
-    QueryFailureWithException(String)
+    // the printed ball, its backtrace, and the cells of the ball laid
+    // out from address 0.
+    QueryFailureWithException(String, Vec<String>, Vec<HeapCellValue>),
+    RedefinedImport(ClauseName, ClauseName, ClauseName, usize) // importer, module, name, arity.
+}
+
+#[derive(Clone, Copy)]
+pub enum MessageKind {
+    Error,
+    Warning,
+    Informational,
+    Silent
+}
+
+impl MessageKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MessageKind::Error => "error",
+            MessageKind::Warning => "warning",
+            MessageKind::Informational => "informational",
+            MessageKind::Silent => "silent"
+        }
+    }
+
+    pub fn from(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(MessageKind::Error),
+            "warning" => Some(MessageKind::Warning),
+            "informational" => Some(MessageKind::Informational),
+            "silent" => Some(MessageKind::Silent),
+            _ => None
+        }
+    }
+
+    // the prefix of each printed line of a message.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            MessageKind::Error => "error: ",
+            MessageKind::Warning => "warning: ",
+            MessageKind::Informational => "% ",
+            MessageKind::Silent => ""
+        }
+    }
+}
+
+// diagnostics raised outside of queries, which the machine passes to
+// print_message/2 as terms.
+pub enum Message {
+    Error(EvalError),
+    ImportOverwrite(ClauseName, usize, ClauseName), // name, arity, and the exporting module.
+    InvalidOptionValue(String, &'static str), // the option, and the value it expects.
+    CreatedModule(ClauseName),
+    LostImport(ClauseName, ClauseName, usize, ClauseName), // importer, name, arity, module.
+    UnknownOption(String)
+}
+
+impl Message {
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            &Message::Error(_) => MessageKind::Error,
+            &Message::CreatedModule(..)
+          | &Message::ImportOverwrite(..)
+          | &Message::InvalidOptionValue(..)
+          | &Message::LostImport(..)
+          | &Message::UnknownOption(_) => MessageKind::Warning
+        }
+    }
 }
 
 pub enum EvalSession {
@@ -417,36 +834,41 @@ impl From<ParserError> for EvalSession {
 pub struct OpDecl(pub usize, pub Specifier, pub ClauseName);
 
 impl OpDecl {
+    // the fixity of the operators of a specifier.
+    pub fn fixity(spec: Specifier) -> Option<Fixity> {
+        match spec {
+            XFY | XFX | YFX => Some(Fixity::In),
+            XF | YF => Some(Fixity::Post),
+            FX | FY => Some(Fixity::Pre),
+            _ => None
+        }
+    }
+
     pub fn submit(&self, module: ClauseName, op_dir: &mut OpDir) -> Result<(), EvalError>
     {
         let (prec, spec, name) = (self.0, self.1, self.2.clone());
 
-        if is_infix!(spec) {
+        if prec > 0 && is_infix!(spec) {
             match op_dir.get(&(name.clone(), Fixity::Post)) {
                 Some(_) => return Err(EvalError::OpIsInfixAndPostFix),
                 _ => {}
             };
         }
 
-        if is_postfix!(spec) {
+        if prec > 0 && is_postfix!(spec) {
             match op_dir.get(&(name.clone(), Fixity::In)) {
                 Some(_) => return Err(EvalError::OpIsInfixAndPostFix),
                 _ => {}
             };
         }
 
-        if prec > 0 {
-            match spec {
-                XFY | XFX | YFX => op_dir.insert((name.clone(), Fixity::In),
-                                                 (spec, prec, module.clone())),
-                XF | YF => op_dir.insert((name.clone(), Fixity::Post), (spec, prec, module.clone())),
-                FX | FY => op_dir.insert((name.clone(), Fixity::Pre), (spec, prec, module.clone())),
-                _ => None
-            };
-        } else {
-            op_dir.remove(&(name.clone(), Fixity::Pre));
-            op_dir.remove(&(name.clone(), Fixity::In));
-            op_dir.remove(&(name.clone(), Fixity::Post));
+        // a precedence of 0 removes the operator of the fixity of spec.
+        if let Some(fixity) = OpDecl::fixity(spec) {
+            if prec > 0 {
+                op_dir.insert((name, fixity), (spec, prec, module));
+            } else {
+                op_dir.remove(&(name, fixity));
+            }
         }
 
         Ok(())
@@ -633,7 +1055,7 @@ pub enum CompareNumberQT {
 }
 
 impl CompareNumberQT {
-    fn name(self) -> &'static str {
+    pub fn name(self) -> &'static str {
         match self {
             CompareNumberQT::GreaterThan => ">",
             CompareNumberQT::LessThan => "<",
@@ -687,6 +1109,40 @@ impl QueryTerm {
             &QueryTerm::Jump(ref vars) => vars.len()
         }
     }
+
+    // M:G whose module and predicate are known at compile time calls
+    // the predicate of G directly, rather than through call/1.
+    pub fn qualify(&mut self) {
+        let qualified = match self {
+            &mut QueryTerm::Clause(_, ClauseType::ModuleCall, ref mut terms) => {
+                let module_name = match terms[0].as_ref() {
+                    &Term::Constant(_, Constant::Atom(ref name)) => name.clone(),
+                    _ => return
+                };
+
+                let (name, subterms) = match terms[1].as_mut() {
+                    &mut Term::Clause(_, ref name, ref mut subterms, fixity) =>
+                        match ClauseType::from(name.clone(), subterms.len(), fixity) {
+                            ClauseType::Named(..) | ClauseType::Op(..) =>
+                                (name.clone(), mem::replace(subterms, vec![])),
+                            _ => return
+                        },
+                    &mut Term::Constant(_, Constant::Atom(ref name)) =>
+                        match ClauseType::from(name.clone(), 0, None) {
+                            ClauseType::Named(..) => (name.clone(), vec![]),
+                            _ => return
+                        },
+                    _ => return
+                };
+
+                let ct = ClauseType::Qualified(module_name, name, CodeIndex::default());
+                QueryTerm::Clause(Cell::default(), ct, subterms)
+            },
+            _ => return
+        };
+
+        *self = qualified;
+    }
 }
 
 pub struct Rule {
@@ -694,16 +1150,34 @@ pub struct Rule {
     pub clauses: Vec<QueryTerm>
 }
 
+impl Rule {
+    fn qualify_goals(&mut self) {
+        self.head.2.qualify();
+
+        for clause in self.clauses.iter_mut() {
+            clause.qualify();
+        }
+    }
+}
+
 #[derive(Clone)]
 pub enum ClauseType {
     AcyclicTerm,
     Arg,
+    Backtrace,
+    CallHook,
     CallN,
     CallWithInferenceLimit,
     Catch,
     Compare,
     CompareTerm(CompareTermQT),
+    ContextModule,
+    CurrentModules,
+    CurrentOps,
+    CurrentPrologFlags,
     CyclicTerm,
+    DeclareOp,
+    DefaultMessage,
     Display,
     DuplicateTerm,
     Eq,
@@ -712,12 +1186,31 @@ pub enum ClauseType {
     Inlined(InlinedClauseType),
     Is,
     KeySort,
+    ModuleCall,
+    ModuleProperties,
     NotEq,
     Op(ClauseName, Fixity, CodeIndex),
     Named(ClauseName, CodeIndex),
+    Phrase,
+    PredicateHeads,
+    PredicateIndicators,
+    PredicateProperties,
+    PrintMessageLines,
+    Qualified(ClauseName, ClauseName, CodeIndex), // the module, the name of the predicate, and its index.
+    SetModule,
+    SetPrologFlag,
     SetupCallCleanup,
     Sort,
+    Statistics,
+    StripModule,
+    SubsumesTerm,
+    TermFactorized,
     Throw,
+    TimeMessage,
+    UnifyWithOccursCheck,
+    UnknownProcedure,
+    UnloadModule,
+    UseModule
 }
 
 #[derive(Clone)]
@@ -790,7 +1283,8 @@ impl ClauseType {
         match self {
             &ClauseType::Compare | &ClauseType::CompareTerm(_)
           | &ClauseType::Inlined(InlinedClauseType::CompareNumber(_))
-          | &ClauseType::NotEq | &ClauseType::Is | &ClauseType::Eq => Some(Fixity::In),
+          | &ClauseType::NotEq | &ClauseType::Is | &ClauseType::Eq
+          | &ClauseType::ModuleCall => Some(Fixity::In),
             &ClauseType::Op(_, fixity, _) => Some(fixity),
             _ => None
         }
@@ -800,12 +1294,20 @@ impl ClauseType {
         match self {
             &ClauseType::AcyclicTerm => clause_name!("acyclic_term"),
             &ClauseType::Arg => clause_name!("arg"),
+            &ClauseType::Backtrace => clause_name!("$backtrace"),
+            &ClauseType::CallHook => clause_name!("$call_hook"),
             &ClauseType::CallN => clause_name!("call"),
             &ClauseType::CallWithInferenceLimit => clause_name!("call_with_inference_limit"),
             &ClauseType::Catch => clause_name!("catch"),
             &ClauseType::Compare => clause_name!("compare"),
             &ClauseType::CompareTerm(qt) => clause_name!(qt.name()),
+            &ClauseType::ContextModule => clause_name!("context_module"),
+            &ClauseType::CurrentModules => clause_name!("$current_modules"),
+            &ClauseType::CurrentOps => clause_name!("$current_ops"),
+            &ClauseType::CurrentPrologFlags => clause_name!("$current_prolog_flags"),
             &ClauseType::CyclicTerm => clause_name!("cyclic_term"),
+            &ClauseType::DeclareOp => clause_name!("op"),
+            &ClauseType::DefaultMessage => clause_name!("$default_message"),
             &ClauseType::Display => clause_name!("display"),
             &ClauseType::DuplicateTerm => clause_name!("duplicate_term"),
             &ClauseType::Eq => clause_name!("=="),
@@ -814,12 +1316,31 @@ impl ClauseType {
             &ClauseType::Inlined(inlined) => clause_name!(inlined.name()),
             &ClauseType::Is => clause_name!("is"),
             &ClauseType::KeySort => clause_name!("keysort"),
+            &ClauseType::ModuleCall => clause_name!(":"),
+            &ClauseType::ModuleProperties => clause_name!("$module_properties"),
             &ClauseType::NotEq => clause_name!("\\=="),
             &ClauseType::Op(ref name, ..) => name.clone(),
             &ClauseType::Named(ref name, ..) => name.clone(),
+            &ClauseType::Phrase => clause_name!("phrase"),
+            &ClauseType::PredicateHeads => clause_name!("$predicate_heads"),
+            &ClauseType::PredicateIndicators => clause_name!("$predicate_indicators"),
+            &ClauseType::PredicateProperties => clause_name!("$predicate_properties"),
+            &ClauseType::PrintMessageLines => clause_name!("$print_message_lines"),
+            &ClauseType::Qualified(_, ref name, _) => name.clone(),
+            &ClauseType::SetPrologFlag => clause_name!("set_prolog_flag"),
             &ClauseType::SetupCallCleanup => clause_name!("setup_call_cleanup"),
+            &ClauseType::SetModule => clause_name!("module"),
             &ClauseType::Sort => clause_name!("sort"),
-            &ClauseType::Throw => clause_name!("throw")
+            &ClauseType::Statistics => clause_name!("statistics"),
+            &ClauseType::StripModule => clause_name!("strip_module"),
+            &ClauseType::SubsumesTerm => clause_name!("subsumes_term"),
+            &ClauseType::TermFactorized => clause_name!("term_factorized"),
+            &ClauseType::Throw => clause_name!("throw"),
+            &ClauseType::TimeMessage => clause_name!("$time_message"),
+            &ClauseType::UnifyWithOccursCheck => clause_name!("unify_with_occurs_check"),
+            &ClauseType::UnknownProcedure => clause_name!("$unknown_procedure"),
+            &ClauseType::UnloadModule => clause_name!("unload_module"),
+            &ClauseType::UseModule => clause_name!("use_module")
         }
     }
 
@@ -827,17 +1348,25 @@ impl ClauseType {
         match (name.as_str(), arity) {
             ("acyclic_term", 1) => ClauseType::AcyclicTerm,
             ("arg", 3)   => ClauseType::Arg,
+            ("$backtrace", 1) => ClauseType::Backtrace,
+            ("$call_hook", 1) => ClauseType::CallHook,
             ("call", _)  => ClauseType::CallN,
             ("call_with_inference_limit", 3) => ClauseType::CallWithInferenceLimit,
             ("catch", 3) => ClauseType::Catch,
             ("compare", 3) => ClauseType::Compare,
+            ("context_module", 1) => ClauseType::ContextModule,
+            ("$current_modules", 1) => ClauseType::CurrentModules,
+            ("$current_ops", 4) => ClauseType::CurrentOps,
+            ("$current_prolog_flags", 3) => ClauseType::CurrentPrologFlags,
             ("cyclic_term", 1) => ClauseType::CyclicTerm,
+            ("op", 3) => ClauseType::DeclareOp,
             ("@>", 2) => ClauseType::CompareTerm(CompareTermQT::GreaterThan),
             ("@<", 2) => ClauseType::CompareTerm(CompareTermQT::LessThan),
             ("@>=", 2) => ClauseType::CompareTerm(CompareTermQT::GreaterThanOrEqual),
             ("@<=", 2) => ClauseType::CompareTerm(CompareTermQT::LessThanOrEqual),
             ("\\=@=", 2) => ClauseType::CompareTerm(CompareTermQT::NotEqual),
             ("=@=", 2) => ClauseType::CompareTerm(CompareTermQT::Equal),
+            ("$default_message", 2) => ClauseType::DefaultMessage,
             ("display", 1) => ClauseType::Display,
             ("duplicate_term", 2) => ClauseType::DuplicateTerm,
             ("==", 2) => ClauseType::Eq,
@@ -845,10 +1374,27 @@ impl ClauseType {
             ("ground", 1) => ClauseType::Ground,
             ("is", 2) => ClauseType::Is,
             ("keysort", 2) => ClauseType::KeySort,
+            (":", 2) => ClauseType::ModuleCall,
+            ("$module_properties", 2) => ClauseType::ModuleProperties,
             ("\\==", 2) => ClauseType::NotEq,
+            ("$predicate_heads", 2) => ClauseType::PredicateHeads,
+            ("$predicate_indicators", 2) => ClauseType::PredicateIndicators,
+            ("$predicate_properties", 2) => ClauseType::PredicateProperties,
+            ("$print_message_lines", 2) => ClauseType::PrintMessageLines,
+            ("module", 1) | ("set_module", 1) => ClauseType::SetModule,
+            ("set_prolog_flag", 2) => ClauseType::SetPrologFlag,
             ("setup_call_cleanup", 3) => ClauseType::SetupCallCleanup,
             ("sort", 2) => ClauseType::Sort,
+            ("statistics", 2) => ClauseType::Statistics,
+            ("strip_module", 3) => ClauseType::StripModule,
+            ("subsumes_term", 2) => ClauseType::SubsumesTerm,
+            ("term_factorized", 3) => ClauseType::TermFactorized,
             ("throw", 1) => ClauseType::Throw,
+            ("$time_message", 4) => ClauseType::TimeMessage,
+            ("unify_with_occurs_check", 2) => ClauseType::UnifyWithOccursCheck,
+            ("$unknown_procedure", 2) => ClauseType::UnknownProcedure,
+            ("unload_module", 1) => ClauseType::UnloadModule,
+            ("use_module", 1) => ClauseType::UseModule,
             _ => if let Some(fixity) = fixity {
                 ClauseType::Op(name, fixity, CodeIndex::default())
             } else {
@@ -1222,12 +1768,14 @@ pub enum ArithmeticInstruction {
 
 pub enum BuiltInInstruction {
     CallInlined(InlinedClauseType, Vec<RegType>),
+    CheckModes(ClauseName, Vec<Mode>), // the predicate, and its declared modes.
     CleanUpBlock,
     CompareNumber(CompareNumberQT, ArithmeticTerm, ArithmeticTerm),
     DefaultRetryMeElse(usize),
     DefaultSetCutPoint(RegType),
     DefaultTrustMe,
     EraseBall,
+    ExitDepthLimit(RegType, RegType),
     Fail,
     GetArg(bool), // last call.
     GetBall,
@@ -1235,14 +1783,20 @@ pub enum BuiltInInstruction {
     GetCutPoint(RegType),
     InferenceLevel(RegType, RegType),
     InstallCleaner,
+    InstallDepthLimit(RegType, RegType),
     InstallInferenceCounter(RegType, RegType, RegType),
     InstallNewBlock,
+    InstallTimeLimit(RegType, RegType),
     InternalCallN,
+    PopDepthLimit,
+    RedoDepthLimit(RegType),
     RemoveCallPolicyCheck,
+    RemoveDepthLimit(RegType),
     RemoveInferenceCounter(RegType, RegType),
+    RemoveTimeLimit(RegType),
     ResetBlock,
     RestoreCutPolicy,
-    SetBall,
+    SetBall(bool), // true if the backtrace is taken, false on a rethrow.
     SetCutPoint(RegType),
     Succeed,
     Unify,
@@ -1288,7 +1842,13 @@ impl From<IndexingInstruction> for Line {
 }
 
 pub enum FactInstruction {
+    GetBoundConstant(Constant, RegType),
+    GetBoundList(RegType),
+    GetBoundStructure(ClauseType, usize, RegType),
     GetConstant(Level, Constant, RegType),
+    GetFreshConstant(Constant, RegType),
+    GetFreshList(RegType),
+    GetFreshStructure(ClauseType, usize, RegType),
     GetList(Level, RegType),
     GetStructure(ClauseType, usize, RegType),
     GetValue(RegType, usize),
@@ -1510,6 +2070,10 @@ impl Heap {
         self.heap.clear();
         self.h = 0;
     }
+
+    pub fn cells_from(&self, h: usize) -> &[HeapCellValue] {
+        &self.heap[h ..]
+    }
 }
 
 impl Index<usize> for Heap {
@@ -233,9 +233,15 @@ fn get_builtins() -> Code {
          deallocate!(),
          goto_execute!(165, 3), // goto get_arg/3, 185.
          trust_me!(),
-         query![get_var_in_query!(temp_v!(4), 1),
-                put_structure!("type_error", 1, temp_v!(1), None),
-                set_constant!(atom!("integer_expected"))],
+         query![put_structure!("type_error", 2, temp_v!(4), None),
+                set_constant!(atom!("integer")),
+                set_value!(temp_v!(1)),
+                put_structure!("/", 2, temp_v!(5), Some(infix!())),
+                set_constant!(atom!("arg")),
+                set_constant!(integer!(3)),
+                put_structure!("error", 2, temp_v!(1), None),
+                set_value!(temp_v!(4)),
+                set_value!(temp_v!(5))],
          goto_execute!(59, 1), // goto throw/1.
          try_me_else!(5), // arg_/5, 189.
          fact![get_value!(temp_v!(1), 2),
@@ -402,9 +408,15 @@ fn get_builtins() -> Code {
          trust_me!(),
          fact![get_var_in_fact!(temp_v!(3), 1),
                get_var_in_fact!(temp_v!(4), 2)],
-         query![put_structure!("type_error", 2, temp_v!(1), None),
-                set_constant!(atom!("integer_expected")),
-                set_value!(temp_v!(4))],
+         query![put_structure!("type_error", 2, temp_v!(5), None),
+                set_constant!(atom!("integer")),
+                set_value!(temp_v!(4)),
+                put_structure!("/", 2, temp_v!(6), Some(infix!())),
+                set_constant!(atom!("length")),
+                set_constant!(integer!(2)),
+                put_structure!("error", 2, temp_v!(1), None),
+                set_value!(temp_v!(5)),
+                set_value!(temp_v!(6))],
          goto_execute!(59, 1), // goto throw/1, 296.
          switch_on_term!(1, 2, 5, 0), // length/3, 297.
          try_me_else!(3),
@@ -445,9 +457,12 @@ fn get_builtins() -> Code {
          try_me_else!(5), // 320.
          is_var!(temp_v!(1)),
          neck_cut!(),
-         query![put_constant!(Level::Shallow,
-                              atom!("instantiation_error"),
-                              temp_v!(1))],
+         query![put_structure!("/", 2, temp_v!(2), Some(infix!())),
+                set_constant!(atom!("setup_call_cleanup")),
+                set_constant!(integer!(3)),
+                put_structure!("error", 2, temp_v!(1), None),
+                set_constant!(atom!("instantiation_error")),
+                set_value!(temp_v!(2))],
          goto_execute!(59, 1),
          default_trust_me!(),
          query![get_var_in_query!(temp_v!(4), 2),
@@ -482,7 +497,7 @@ fn get_builtins() -> Code {
          goto_call!(358, 0), // goto run_cleaners_with_handling/0, 349.
          query![put_unsafe_value!(1, 1)],
          deallocate!(),
-         goto_execute!(59, 1), // goto throw/1, 59.
+         goto_execute!(639, 1), // goto $rethrow/1, 639.
          default_trust_me!(),
          allocate!(0),
          goto_call!(370, 0), // goto run_cleaners_without_handling/0, 355.
@@ -622,7 +637,7 @@ fn get_builtins() -> Code {
          default_trust_me!(),
          remove_call_policy_check!(),
          query![put_value!(temp_v!(2), 1)],
-         goto_execute!(59, 1), // goto throw/1, 59.
+         goto_execute!(639, 1), // goto $rethrow/1, 639.
          try_me_else!(6), // end_block/4, 468.
          query![put_value!(temp_v!(3), 1)],
          clean_up_block!(),
@@ -644,9 +659,226 @@ fn get_builtins() -> Code {
          keysort_execute!(), // keysort/2, 484.
          acyclic_term_execute!(), // acyclic_term/1, 485.
          cyclic_term_execute!(), // cyclic_term/1, 486.
+         statistics_execute!(), // statistics/2, 487.
+         time_message_execute!(), // $time_message/4, 488.
+         allocate!(3), // call_with_time_limit/2, 489.
+         get_level!(perm_v!(1)),
+         fact![get_var_in_fact!(perm_v!(2), 2)],
+         query![put_var!(perm_v!(3), 2)],
+         install_time_limit!(temp_v!(1), perm_v!(3)),
+         query![put_constant!(Level::Shallow, atom!("true"), temp_v!(1)),
+                put_value!(perm_v!(2), 2),
+                put_structure!("$remove_time_limit", 1, temp_v!(3), None),
+                set_value!(perm_v!(3))],
+         goto_call!(310, 3), // goto setup_call_cleanup/3, 495.
+         cut!(perm_v!(1)),
+         deallocate!(),
+         proceed!(),
+         remove_time_limit!(temp_v!(1)), // $remove_time_limit/1, 499.
+         proceed!(),
+         try_me_else!(9), // call_with_depth_limit/3, 501.
+         allocate!(2),
+         fact![get_var_in_fact!(perm_v!(2), 3)],
+         install_depth_limit!(temp_v!(2), perm_v!(1)),
+         query![put_var!(temp_v!(4), 2),
+                put_structure!("$depth_limit_except", 2, temp_v!(3), None),
+                set_value!(perm_v!(1)),
+                set_value!(temp_v!(4))],
+         goto_call!(5, 3), // goto catch/3, 506.
+         query![put_value!(perm_v!(1), 1),
+                put_value!(perm_v!(2), 2)],
+         deallocate!(),
+         goto_execute!(514, 2), // goto depth_limit_exit/2, 509.
+         default_trust_me!(),
+         pop_depth_limit!(),
+         fact![get_constant!(atom!("depth_limit_exceeded"), temp_v!(3))],
+         proceed!(),
+         try_me_else!(4), // depth_limit_exit/2, 514.
+         exit_depth_limit!(temp_v!(1), temp_v!(3)),
+         fact![get_value!(temp_v!(3), 2)],
+         proceed!(),
+         default_trust_me!(),
+         redo_depth_limit!(temp_v!(1)),
+         fail!(),
+         remove_depth_limit!(temp_v!(1)), // $depth_limit_except/2, 521.
+         query![put_value!(temp_v!(2), 1)],
+         goto_execute!(639, 1), // goto $rethrow/1, 523.
+         set_prolog_flag_execute!(), // set_prolog_flag/2, 524.
+         backtrace_execute!(), // $backtrace/1, 525.
+         allocate!(3), // print_message/2, 526.
+         fact![get_var_in_fact!(perm_v!(2), 1), get_var_in_fact!(perm_v!(3), 2)],
+         query![put_value!(perm_v!(3), 1), put_var!(temp_v!(2), 2),
+                get_var_in_query!(perm_v!(1), 2)],
+         goto_call!(533, 2), // goto $translate_message/2, 533.
+         query![put_value!(perm_v!(2), 1), put_value!(perm_v!(3), 2), put_value!(perm_v!(1), 3)],
+         deallocate!(),
+         goto_execute!(543, 3), // goto $print_message/3, 543.
+         try_me_else!(8), // $translate_message/2, 533.
+         allocate!(1),
+         get_level!(perm_v!(1)),
+         query![put_structure!("message", 3, temp_v!(3), None),
+                set_value!(temp_v!(1)),
+                set_value!(temp_v!(2)),
+                set_constant!(Constant::EmptyList),
+                put_value!(temp_v!(3), 1)],
+         call_hook!(),
+         cut!(perm_v!(1)),
+         deallocate!(),
+         proceed!(),
+         default_trust_me!(),
+         default_message_execute!(),
+         try_me_else!(8), // $print_message/3, 543.
+         allocate!(1),
+         get_level!(perm_v!(1)),
+         query![put_structure!("message_hook", 3, temp_v!(4), None),
+                set_value!(temp_v!(2)),
+                set_value!(temp_v!(1)),
+                set_value!(temp_v!(3)),
+                put_value!(temp_v!(4), 1)],
+         call_hook!(),
+         cut!(perm_v!(1)),
+         deallocate!(),
+         proceed!(),
+         default_trust_me!(),
+         query![put_value!(temp_v!(3), 2)],
+         print_message_lines_execute!(), // $print_message_lines/2, 553.
+         context_module_execute!(), // context_module/1, 554.
+         strip_module_execute!(), // strip_module/3, 555.
+         try_me_else!(3), // $member/2, 556.
+         fact![get_list!(Level::Shallow, temp_v!(2)),
+               unify_value!(temp_v!(1)),
+               unify_void!(1)],
+         proceed!(),
+         trust_me!(),
+         fact![get_list!(Level::Shallow, temp_v!(2)),
+               unify_void!(1),
+               unify_variable!(temp_v!(2))],
+         goto_execute!(556, 2), // goto $member/2, 561.
+         allocate!(2), // current_module/1, 562.
+         fact![get_var_in_fact!(perm_v!(1), 1)],
+         query![put_var!(perm_v!(2), 1)],
+         current_modules_call!(),
+         query![put_value!(perm_v!(1), 1),
+                put_unsafe_value!(2, 2)],
+         deallocate!(),
+         goto_execute!(556, 2), // goto $member/2, 568.
+         allocate!(4), // module_property/2, 569.
+         fact![get_var_in_fact!(perm_v!(1), 1),
+               get_var_in_fact!(perm_v!(2), 2)],
+         query![put_var!(perm_v!(3), 1)],
+         current_modules_call!(),
+         query![put_value!(perm_v!(1), 1),
+                put_value!(perm_v!(3), 2)],
+         goto_call!(556, 2), // goto $member/2, 574.
+         query![put_value!(perm_v!(1), 1),
+                put_var!(perm_v!(4), 2)],
+         module_properties_call!(),
+         query![put_value!(perm_v!(2), 1),
+                put_unsafe_value!(4, 2)],
+         deallocate!(),
+         goto_execute!(556, 2), // goto $member/2, 579.
+         allocate!(2), // current_predicate/1, 580.
+         fact![get_var_in_fact!(perm_v!(1), 1)],
+         query![put_value!(perm_v!(1), 1),
+                put_var!(perm_v!(2), 2)],
+         predicate_indicators_call!(),
+         query![put_value!(perm_v!(1), 1),
+                put_unsafe_value!(2, 2)],
+         deallocate!(),
+         goto_execute!(556, 2), // goto $member/2, 586.
+         allocate!(3), // current_predicate/2, 587.
+         fact![get_var_in_fact!(perm_v!(1), 1),
+               get_var_in_fact!(perm_v!(2), 2)],
+         query![put_value!(perm_v!(2), 1),
+                put_var!(perm_v!(3), 2)],
+         predicate_heads_call!(),
+         query![put_structure!("-", 2, temp_v!(1), Some(infix!())),
+                set_value!(perm_v!(1)),
+                set_value!(perm_v!(2)),
+                put_unsafe_value!(3, 2)],
+         deallocate!(),
+         goto_execute!(556, 2), // goto $member/2, 593.
+         allocate!(4), // predicate_property/2, 594.
+         fact![get_var_in_fact!(perm_v!(1), 1),
+               get_var_in_fact!(perm_v!(2), 2)],
+         query![put_value!(perm_v!(1), 1),
+                put_var!(perm_v!(3), 2)],
+         predicate_properties_call!(),
+         query![put_structure!("-", 2, temp_v!(1), Some(infix!())),
+                set_value!(perm_v!(1)),
+                set_var!(perm_v!(4)),
+                put_value!(perm_v!(3), 2)],
+         goto_call!(556, 2), // goto $member/2, 599.
+         query![put_value!(perm_v!(2), 1),
+                put_unsafe_value!(4, 2)],
+         deallocate!(),
+         goto_execute!(556, 2), // goto $member/2, 602.
+         declare_op_execute!(), // op/3, 603.
+         allocate!(2), // current_op/3, 604.
+         query![put_structure!("op", 3, temp_v!(5), None),
+                set_value!(temp_v!(1)),
+                set_value!(temp_v!(2)),
+                set_value!(temp_v!(3)),
+                get_var_in_query!(perm_v!(1), 5),
+                put_var!(perm_v!(2), 4)],
+         current_ops_call!(),
+         query![put_value!(perm_v!(1), 1),
+                put_unsafe_value!(2, 2)],
+         deallocate!(),
+         goto_execute!(556, 2), // goto $member/2, 609.
+         unload_module_execute!(), // unload_module/1, 610.
+         set_module_execute!(), // module/1 and set_module/1, 611.
+         query![put_constant!(Level::Shallow, Constant::EmptyList, temp_v!(3))], // phrase/2, 612.
+         phrase_execute!(), // phrase/3, 613.
+         allocate!(2), // current_prolog_flag/2, 614.
+         query![put_structure!("-", 2, temp_v!(4), Some(infix!())),
+                set_value!(temp_v!(1)),
+                set_value!(temp_v!(2)),
+                get_var_in_query!(perm_v!(1), 4),
+                put_var!(perm_v!(2), 3)],
+         current_prolog_flags_call!(),
+         query![put_value!(perm_v!(1), 1),
+                put_unsafe_value!(2, 2)],
+         deallocate!(),
+         goto_execute!(556, 2), // goto $member/2, 619.
+         try_me_else!(10), // $undefined_procedure/3, 620.
+         allocate!(3),
+         get_level!(perm_v!(1)),
+         fact![get_var_in_fact!(perm_v!(2), 1),
+               get_var_in_fact!(perm_v!(3), 2)],
+         query![put_structure!("unknown_procedure", 2, temp_v!(4), None),
+                set_value!(temp_v!(1)),
+                set_value!(temp_v!(3)),
+                put_value!(temp_v!(4), 1)],
+         call_hook!(),
+         cut!(perm_v!(1)),
+         query![put_value!(perm_v!(2), 1),
+                put_value!(perm_v!(3), 2)],
+         deallocate!(),
+         unknown_procedure_execute!(), // $unknown_procedure/2, 629.
+         default_trust_me!(),
+         unknown_procedure_execute!(), // $unknown_procedure/2, 631.
+         allocate!(0), // $unknown_procedure_warning/2, 632.
+         goto_call!(526, 2), // goto print_message/2, 633.
+         fail!(),
+         use_module_execute!(), // use_module/1, 635.
+         unify_with_occurs_check_execute!(), // unify_with_occurs_check/2, 636.
+         subsumes_term_execute!(), // subsumes_term/2, 637.
+         term_factorized_execute!(), // term_factorized/3, 638.
+         rethrow_ball!(), // $rethrow/1, 639.
+         unwind_stack!(),
     ]
 }
 
+// the operators of the declarations read ahead of the parser, known
+// only while a batch or toplevel entry is first read.
+pub fn add_directive_ops(op_dir: &mut OpDir)
+{
+    let builtin = ClauseName::BuiltIn("builtin");
+    op_dir.insert((clause_name!("meta_predicate"), Fixity::Pre), (FX, 1150, builtin.clone()));
+    op_dir.insert((clause_name!("mode"), Fixity::Pre), (FX, 1150, builtin));
+}
+
 pub fn build_code_and_op_dirs() -> (CodeDir, OpDir)
 {
     let mut code_dir = HashMap::new();
@@ -657,6 +889,7 @@ pub fn build_code_and_op_dirs() -> (CodeDir, OpDir)
     op_dir.insert((clause_name!(":-"), Fixity::In),   (XFX, 1200, builtin.clone()));
     op_dir.insert((clause_name!(":-"), Fixity::Pre),  (FX, 1200, builtin.clone()));
     op_dir.insert((clause_name!("?-"), Fixity::Pre),  (FX, 1200, builtin.clone()));
+    op_dir.insert((clause_name!("-->"), Fixity::In),  (XFX, 1200, builtin.clone()));
 
     // control operators.
     op_dir.insert((clause_name!("\\+"), Fixity::Pre), (FY, 900, builtin.clone()));
@@ -674,6 +907,7 @@ pub fn build_code_and_op_dirs() -> (CodeDir, OpDir)
     op_dir.insert((clause_name!("div"), Fixity::In), (YFX, 400, builtin.clone()));
     op_dir.insert((clause_name!("*"), Fixity::In), (YFX, 400, builtin.clone()));
     op_dir.insert((clause_name!("-"), Fixity::Pre), (FY, 200, builtin.clone()));
+    op_dir.insert((clause_name!(":"), Fixity::In), (XFY, 200, builtin.clone()));
     op_dir.insert((clause_name!("rdiv"), Fixity::In), (YFX, 400, builtin.clone()));
     op_dir.insert((clause_name!("<<"), Fixity::In), (YFX, 400, builtin.clone()));
     op_dir.insert((clause_name!(">>"), Fixity::In), (YFX, 400, builtin.clone()));
@@ -702,6 +936,9 @@ pub fn build_code_and_op_dirs() -> (CodeDir, OpDir)
     op_dir.insert((clause_name!("=@="), Fixity::In), (XFX, 700, builtin.clone()));
     op_dir.insert((clause_name!("\\=@="), Fixity::In), (XFX, 700, builtin.clone()));
 
+    // renames imports in use_module/2 lists.
+    op_dir.insert((clause_name!("as"), Fixity::In), (XFX, 700, builtin.clone()));
+
     // there are 63 registers in the VM, so call/N is defined for all 0 <= N <= 62
     // (an extra register is needed for the predicate name)
     for arity in 0 .. 63 {
@@ -763,6 +1000,57 @@ pub fn build_code_and_op_dirs() -> (CodeDir, OpDir)
     code_dir.insert((clause_name!("keysort"), 2), CodeIndex::from((484, builtin.clone())));
     code_dir.insert((clause_name!("acyclic_term"), 1), CodeIndex::from((485, builtin.clone())));
     code_dir.insert((clause_name!("cyclic_term"), 1), CodeIndex::from((486, builtin.clone())));
+    code_dir.insert((clause_name!("statistics"), 2), CodeIndex::from((487, builtin.clone())));
+    code_dir.insert((clause_name!("$time_message"), 4), CodeIndex::from((488, builtin.clone())));
+    code_dir.insert((clause_name!("call_with_time_limit"), 2),
+                    CodeIndex::from((489, builtin.clone())));
+    code_dir.insert((clause_name!("$remove_time_limit"), 1),
+                    CodeIndex::from((499, builtin.clone())));
+    code_dir.insert((clause_name!("call_with_depth_limit"), 3),
+                    CodeIndex::from((501, builtin.clone())));
+    code_dir.insert((clause_name!("$depth_limit_except"), 2),
+                    CodeIndex::from((521, builtin.clone())));
+    code_dir.insert((clause_name!("set_prolog_flag"), 2),
+                    CodeIndex::from((524, builtin.clone())));
+    code_dir.insert((clause_name!("$backtrace"), 1), CodeIndex::from((525, builtin.clone())));
+    code_dir.insert((clause_name!("print_message"), 2),
+                    CodeIndex::from((526, builtin.clone())));
+    code_dir.insert((clause_name!("$translate_message"), 2),
+                    CodeIndex::from((533, builtin.clone())));
+    code_dir.insert((clause_name!("$print_message"), 3),
+                    CodeIndex::from((543, builtin.clone())));
+    code_dir.insert((clause_name!("$print_message_lines"), 2),
+                    CodeIndex::from((553, builtin.clone())));
+    code_dir.insert((clause_name!("context_module"), 1),
+                    CodeIndex::from((554, builtin.clone())));
+    code_dir.insert((clause_name!("strip_module"), 3), CodeIndex::from((555, builtin.clone())));
+    code_dir.insert((clause_name!("$member"), 2), CodeIndex::from((556, builtin.clone())));
+    code_dir.insert((clause_name!("current_module"), 1),
+                    CodeIndex::from((562, builtin.clone())));
+    code_dir.insert((clause_name!("module_property"), 2),
+                    CodeIndex::from((569, builtin.clone())));
+    code_dir.insert((clause_name!("current_predicate"), 1),
+                    CodeIndex::from((580, builtin.clone())));
+    code_dir.insert((clause_name!("current_predicate"), 2),
+                    CodeIndex::from((587, builtin.clone())));
+    code_dir.insert((clause_name!("predicate_property"), 2),
+                    CodeIndex::from((594, builtin.clone())));
+    code_dir.insert((clause_name!("op"), 3), CodeIndex::from((603, builtin.clone())));
+    code_dir.insert((clause_name!("current_op"), 3), CodeIndex::from((604, builtin.clone())));
+    code_dir.insert((clause_name!("unload_module"), 1),
+                    CodeIndex::from((610, builtin.clone())));
+    code_dir.insert((clause_name!("module"), 1), CodeIndex::from((611, builtin.clone())));
+    code_dir.insert((clause_name!("set_module"), 1), CodeIndex::from((611, builtin.clone())));
+    code_dir.insert((clause_name!("phrase"), 2), CodeIndex::from((612, builtin.clone())));
+    code_dir.insert((clause_name!("phrase"), 3), CodeIndex::from((613, builtin.clone())));
+    code_dir.insert((clause_name!("current_prolog_flag"), 2),
+                    CodeIndex::from((614, builtin.clone())));
+    code_dir.insert((clause_name!("use_module"), 1), CodeIndex::from((635, builtin.clone())));
+    code_dir.insert((clause_name!("unify_with_occurs_check"), 2),
+                    CodeIndex::from((636, builtin.clone())));
+    code_dir.insert((clause_name!("subsumes_term"), 2), CodeIndex::from((637, builtin.clone())));
+    code_dir.insert((clause_name!("term_factorized"), 3), CodeIndex::from((638, builtin.clone())));
+    code_dir.insert((clause_name!("$rethrow"), 1), CodeIndex::from((639, builtin.clone())));
 
     (code_dir, op_dir)
 }
@@ -826,11 +1114,71 @@ pub fn builtin_module() -> Module
                                             (clause_name!("sort"), 2),
                                             (clause_name!("keysort"), 2),
                                             (clause_name!("acyclic_term"), 1),
-                                            (clause_name!("cyclic_term"), 1)]);
+                                            (clause_name!("cyclic_term"), 1),
+                                            (clause_name!("statistics"), 2),
+                                            (clause_name!("$time_message"), 4),
+                                            (clause_name!("call_with_time_limit"), 2),
+                                            (clause_name!("$remove_time_limit"), 1),
+                                            (clause_name!("call_with_depth_limit"), 3),
+                                            (clause_name!("$depth_limit_except"), 2),
+                                            (clause_name!("set_prolog_flag"), 2),
+                                            (clause_name!("$backtrace"), 1),
+                                            (clause_name!("print_message"), 2),
+                                            (clause_name!("context_module"), 1),
+                                            (clause_name!("strip_module"), 3),
+                                            (clause_name!("current_module"), 1),
+                                            (clause_name!("module_property"), 2),
+                                            (clause_name!("current_predicate"), 1),
+                                            (clause_name!("current_predicate"), 2),
+                                            (clause_name!("predicate_property"), 2),
+                                            (clause_name!("op"), 3),
+                                            (clause_name!("current_op"), 3),
+                                            (clause_name!("unload_module"), 1),
+                                            (clause_name!("module"), 1),
+                                            (clause_name!("set_module"), 1),
+                                            (clause_name!("phrase"), 2),
+                                            (clause_name!("phrase"), 3),
+                                            (clause_name!("current_prolog_flag"), 2),
+                                            (clause_name!("use_module"), 1),
+                                            (clause_name!("unify_with_occurs_check"), 2),
+                                            (clause_name!("subsumes_term"), 2),
+                                            (clause_name!("term_factorized"), 3),
+                                            (clause_name!("$rethrow"), 1)]);
 
     for arity in 0 .. 63 {
         module_decl.exports.push((clause_name!("call"), arity));
     }
 
-    Module { module_decl, code_dir, op_dir }
+    Module { module_decl, code_dir, op_dir, meta_predicates: builtin_meta_predicates(),
+             term_dir: TermDir::new(), file: None }
+}
+
+// the control constructs and builtins taking goals or predicates as
+// arguments, which are qualified by the module calling them.
+pub fn builtin_meta_predicates() -> MetaPredicateDir
+{
+    let mut meta_predicates = HashMap::new();
+
+    let goal = MetaSpec::Closure(0);
+    let plain = MetaSpec::Plain;
+    let qualified = MetaSpec::Qualified;
+
+    meta_predicates.insert((clause_name!(","), 2), vec![goal, goal]);
+    meta_predicates.insert((clause_name!(";"), 2), vec![goal, goal]);
+    meta_predicates.insert((clause_name!("->"), 2), vec![goal, goal]);
+    meta_predicates.insert((clause_name!("\\+"), 1), vec![goal]);
+    meta_predicates.insert((clause_name!("catch"), 3), vec![goal, plain, goal]);
+    meta_predicates.insert((clause_name!("setup_call_cleanup"), 3), vec![goal, goal, goal]);
+    meta_predicates.insert((clause_name!("call_with_inference_limit"), 3),
+                           vec![goal, plain, plain]);
+    meta_predicates.insert((clause_name!("call_with_time_limit"), 2), vec![plain, goal]);
+    meta_predicates.insert((clause_name!("call_with_depth_limit"), 3),
+                           vec![goal, plain, plain]);
+    meta_predicates.insert((clause_name!("current_predicate"), 1), vec![qualified]);
+    meta_predicates.insert((clause_name!("current_predicate"), 2), vec![plain, qualified]);
+    meta_predicates.insert((clause_name!("predicate_property"), 2), vec![qualified, plain]);
+    meta_predicates.insert((clause_name!("phrase"), 2), vec![MetaSpec::Dcg, plain]);
+    meta_predicates.insert((clause_name!("phrase"), 3), vec![MetaSpec::Dcg, plain, plain]);
+
+    meta_predicates
 }
@@ -13,7 +13,8 @@ use std::vec::Vec;
 
 pub struct CodeGenerator<TermMarker> {
     marker: TermMarker,
-    var_count: HashMap<Rc<Var>, usize>
+    var_count: HashMap<Rc<Var>, usize>,
+    arg_modes: Vec<Mode>
 }
 
 pub struct ConjunctInfo<'a> {
@@ -45,7 +46,22 @@ impl<'a, TermMarker: Allocator<'a>> CodeGenerator<TermMarker>
 {
     pub fn new() -> Self {
         CodeGenerator { marker:  Allocator::new(),
-                        var_count: HashMap::new() }
+                        var_count: HashMap::new(),
+                        arg_modes: Vec::new() }
+    }
+
+    // the declared modes of the relation, by which the get
+    // instructions of its head arguments are specialized.
+    pub fn set_arg_modes(&mut self, modes: Option<&Vec<Mode>>) {
+        self.arg_modes = modes.cloned().unwrap_or(Vec::new());
+    }
+
+    fn arg_mode(&self, term_loc: GenContext, lvl: Level, reg: RegType) -> Mode {
+        match (term_loc, lvl, reg) {
+            (GenContext::Head, Level::Shallow, RegType::Temp(i)) if i > 0 =>
+                self.arg_modes.get(i - 1).cloned().unwrap_or(Mode::Any),
+            _ => Mode::Any
+        }
     }
 
     pub fn take_vars(self) -> AllocVarDict {
@@ -138,7 +154,8 @@ impl<'a, TermMarker: Allocator<'a>> CodeGenerator<TermMarker>
             match term {
                 TermRef::Clause(lvl, cell, ct, terms) => {
                     self.marker.mark_non_var(lvl, term_loc, cell, &mut target);
-                    target.push(Target::to_structure(ct, terms.len(), cell.get()));
+                    let mode = self.arg_mode(term_loc, lvl, cell.get());
+                    target.push(Target::to_structure(ct, terms.len(), cell.get()).specialize(mode));
 
                     for subterm in terms {
                         self.subterm_to_instr(subterm.as_ref(), term_loc, is_exposed, &mut target);
@@ -146,14 +163,17 @@ impl<'a, TermMarker: Allocator<'a>> CodeGenerator<TermMarker>
                 },
                 TermRef::Cons(lvl, cell, head, tail) => {
                     self.marker.mark_non_var(lvl, term_loc, cell, &mut target);
-                    target.push(Target::to_list(lvl, cell.get()));
+                    let mode = self.arg_mode(term_loc, lvl, cell.get());
+                    target.push(Target::to_list(lvl, cell.get()).specialize(mode));
 
                     self.subterm_to_instr(head, term_loc, is_exposed, &mut target);
                     self.subterm_to_instr(tail, term_loc, is_exposed, &mut target);
                 },
                 TermRef::Constant(lvl @ Level::Shallow, cell, constant) => {
                     self.marker.mark_non_var(lvl, term_loc, cell, &mut target);
-                    target.push(Target::to_constant(lvl, constant.clone(), cell.get()));
+                    let mode = self.arg_mode(term_loc, lvl, cell.get());
+                    target.push(Target::to_constant(lvl, constant.clone(), cell.get())
+                                .specialize(mode));
                 },
                 TermRef::AnonVar(lvl @ Level::Shallow) =>
                     if let GenContext::Head = term_loc {
@@ -659,7 +679,7 @@ impl<'a, TermMarker: Allocator<'a>> CodeGenerator<TermMarker>
         subseqs
     }
 
-    fn compile_pred_subseq<'b: 'a>(&mut self, clauses: &'b [PredicateClause])
+    fn compile_pred_subseq<'b: 'a>(&mut self, clauses: &'b [PredicateClause], is_indexed: bool)
                                    -> Result<Code, ParserError>
     {
         let mut code_body = Vec::new();
@@ -687,10 +707,12 @@ impl<'a, TermMarker: Allocator<'a>> CodeGenerator<TermMarker>
                 code_body.push(Line::Choice(choice));
             }
 
-            clause.first_arg().map(|arg| {
-                let index = code_body.len();
-                code_offsets.index_term(arg, index);
-            });
+            if is_indexed {
+                clause.first_arg().map(|arg| {
+                    let index = code_body.len();
+                    code_offsets.index_term(arg, index);
+                });
+            }
 
             code_body.append(&mut clause_code);
         }
@@ -704,12 +726,18 @@ impl<'a, TermMarker: Allocator<'a>> CodeGenerator<TermMarker>
     pub fn compile_predicate<'b: 'a>(&mut self, clauses: &'b Vec<PredicateClause>)
                                      -> Result<Code, ParserError>
     {
+        // a first argument of mode - is unbound on every call, so
+        // the clauses are tried in order, with no switch on it.
+        if self.arg_modes.first() == Some(&Mode::Out) {
+            return self.compile_pred_subseq(&clauses[..], false);
+        }
+
         let mut code   = Vec::new();
         let split_pred = Self::split_predicate(&clauses);
         let multi_seq  = split_pred.len() > 1;
 
         for (l, r) in split_pred {
-            let mut code_segment = try!(self.compile_pred_subseq(&clauses[l .. r]));
+            let mut code_segment = try!(self.compile_pred_subseq(&clauses[l .. r], true));
 
             if multi_seq {
                 let choice = match l {
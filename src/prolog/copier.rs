@@ -1,6 +1,7 @@
 use prolog::and_stack::*;
 use prolog::ast::*;
 
+use std::collections::HashMap;
 use std::ops::IndexMut;
 
 pub trait CopierTarget
@@ -14,10 +15,13 @@ pub trait CopierTarget
 
     // duplicate_term(L1, L2) uses Cheney's algorithm to copy the term
     // at L1 to L2. trail is kept to restore the innards of L1 after
-    // it's been copied to L2.
+    // it's been copied to L2. structures are forwarded to their
+    // copies, as are list cells through forwarded_lists, so the
+    // copies of cyclic terms are cyclic.
     fn duplicate_term(&mut self, a: Addr) where Self: IndexMut<usize, Output=HeapCellValue>
     {
         let mut trail: Vec<(Ref, HeapCellValue)>= Vec::new();
+        let mut forwarded_lists = HashMap::new();
         let mut scan = self.source();
         let old_h = self.threshold();
 
@@ -30,7 +34,17 @@ pub trait CopierTarget
                 HeapCellValue::Addr(a) =>
                     match a.clone() {
                         Addr::Lis(a) => {
-                            self[scan] = HeapCellValue::Addr(Addr::Lis(self.threshold()));
+                            if let Some(&l) = forwarded_lists.get(&a) {
+                                self[scan] = HeapCellValue::Addr(Addr::Lis(l));
+                                scan += 1;
+
+                                continue;
+                            }
+
+                            let threshold = self.threshold();
+
+                            forwarded_lists.insert(a, threshold);
+                            self[scan] = HeapCellValue::Addr(Addr::Lis(threshold));
                             
                             let hcv = self[a].clone();
                             self.push(hcv);
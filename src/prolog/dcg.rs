@@ -0,0 +1,228 @@
+use prolog::ast::*;
+use prolog::tabled_rc::*;
+
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+pub fn is_dcg_rule(term: &Term) -> bool {
+    match term {
+        &Term::Clause(_, ref name, ref terms, _) =>
+            name.as_str() == "-->" && terms.len() == 2,
+        _ => false
+    }
+}
+
+fn op_clause(name: &'static str, fixity: Fixity, terms: Vec<Term>) -> Term {
+    let terms = terms.into_iter().map(Box::new).collect();
+    Term::Clause(Cell::default(), clause_name!(name), terms, Some(fixity))
+}
+
+fn unify(s0: &Term, s: &Term) -> Term {
+    op_clause("=", Fixity::In, vec![s0.clone(), s.clone()])
+}
+
+// the names of the variables of terms.
+fn var_names(terms: Vec<&Term>) -> HashSet<Rc<Var>> {
+    let mut names = HashSet::new();
+    let mut terms = terms;
+
+    while let Some(term) = terms.pop() {
+        match term {
+            &Term::Var(_, ref name) => {
+                names.insert(name.clone());
+            },
+            &Term::Clause(_, _, ref subterms, _) =>
+                terms.extend(subterms.iter().map(|t| t.as_ref())),
+            &Term::Cons(_, ref head, ref tail) => {
+                terms.push(head.as_ref());
+                terms.push(tail.as_ref());
+            },
+            _ => {}
+        }
+    }
+
+    names
+}
+
+// DCG rules are translated to clauses by threading a pair of list
+// variables through the nonterminals of their heads and bodies.
+pub struct DcgTranslator {
+    atom_tbl: TabledData<Atom>,
+    var_count: usize,
+    var_names: HashSet<Rc<Var>> // the variables of the rule, which fresh variables avoid.
+}
+
+impl DcgTranslator {
+    pub fn new(atom_tbl: TabledData<Atom>) -> Self {
+        DcgTranslator { atom_tbl, var_count: 0, var_names: HashSet::new() }
+    }
+
+    fn fresh_var(&mut self) -> Term {
+        loop {
+            self.var_count += 1;
+            let name = Rc::new(format!("_S{}", self.var_count));
+
+            if !self.var_names.contains(&name) {
+                return Term::Var(Cell::default(), name);
+            }
+        }
+    }
+
+    // translates the body of phrase(Body, S0, S) to a goal.
+    pub fn translate_body(&mut self, body: &Term, s0: &Term, s: &Term) -> Result<Term, ParserError> {
+        self.var_names = var_names(vec![body, s0, s]);
+        self.body(body, s0, s)
+    }
+
+    // translates Head --> Body, or Head, Pushback --> Body, to a clause.
+    pub fn translate_rule(&mut self, term: &Term) -> Result<Term, ParserError> {
+        let (head, body) = match term {
+            &Term::Clause(_, _, ref terms, _) if is_dcg_rule(term) =>
+                (terms[0].as_ref(), terms[1].as_ref()),
+            _ => return Err(ParserError::InvalidRuleHead)
+        };
+
+        self.var_names = var_names(vec![term]);
+
+        let s0 = self.fresh_var();
+        let s  = self.fresh_var();
+
+        let (head, body) = match head {
+            &Term::Clause(_, ref name, ref terms, _) if name.as_str() == "," && terms.len() == 2 => {
+                let mid = self.fresh_var();
+
+                let head = self.nonterminal(&terms[0], &s0, &s)
+                    .map_err(|_| ParserError::InvalidRuleHead)?;
+                let pushback = self.terminals(&terms[1], &s, &mid)
+                    .map_err(|_| ParserError::InvalidRuleHead)?;
+
+                let body = self.body(body, &s0, &mid)?;
+                (head, op_clause(",", Fixity::In, vec![body, pushback]))
+            },
+            _ => {
+                let head = self.nonterminal(head, &s0, &s)
+                    .map_err(|_| ParserError::InvalidRuleHead)?;
+                (head, self.body(body, &s0, &s)?)
+            }
+        };
+
+        Ok(op_clause(":-", Fixity::In, vec![head, body]))
+    }
+
+    fn body(&mut self, term: &Term, s0: &Term, s: &Term) -> Result<Term, ParserError> {
+        match term {
+            &Term::Var(..) | &Term::AnonVar => {
+                let terms = vec![Box::new(term.clone()), Box::new(s0.clone()), Box::new(s.clone())];
+                Ok(Term::Clause(Cell::default(), clause_name!("phrase"), terms, None))
+            },
+            &Term::Clause(_, ref name, ref terms, _) if terms.len() == 2 =>
+                match name.as_str() {
+                    "," => {
+                        let mid = self.fresh_var();
+
+                        let first  = self.body(&terms[0], s0, &mid)?;
+                        let second = self.body(&terms[1], &mid, s)?;
+
+                        Ok(op_clause(",", Fixity::In, vec![first, second]))
+                    },
+                    "->" => {
+                        let mid = self.fresh_var();
+
+                        let cond = self.body(&terms[0], s0, &mid)?;
+                        let then = self.body(&terms[1], &mid, s)?;
+
+                        Ok(op_clause("->", Fixity::In, vec![cond, then]))
+                    },
+                    ";" | "|" => {
+                        let left  = self.body(&terms[0], s0, s)?;
+                        let right = self.body(&terms[1], s0, s)?;
+
+                        Ok(op_clause(";", Fixity::In, vec![left, right]))
+                    },
+                    _ => self.nonterminal(term, s0, s)
+                },
+            &Term::Clause(_, ref name, ref terms, _) if terms.len() == 1 =>
+                match name.as_str() {
+                    "\\+" => {
+                        let rest = self.fresh_var();
+                        let goal = self.body(&terms[0], s0, &rest)?;
+
+                        let not_goal = op_clause("\\+", Fixity::Pre, vec![goal]);
+                        Ok(op_clause(",", Fixity::In, vec![not_goal, unify(s0, s)]))
+                    },
+                    "{}" =>
+                        Ok(op_clause(",", Fixity::In, vec![terms[0].as_ref().clone(), unify(s0, s)])),
+                    _ => self.nonterminal(term, s0, s)
+                },
+            &Term::Constant(_, Constant::Atom(ref name)) if name.as_str() == "!" =>
+                Ok(op_clause(",", Fixity::In, vec![term.clone(), unify(s0, s)])),
+            &Term::Constant(_, Constant::Atom(ref name)) if name.as_str() == "[]" =>
+                Ok(unify(s0, s)),
+            &Term::Constant(_, Constant::EmptyList) | &Term::Cons(..)
+          | &Term::Constant(_, Constant::String(_)) =>
+                self.terminals(term, s0, s),
+            _ => self.nonterminal(term, s0, s)
+        }
+    }
+
+    // a nonterminal is called with the list pair appended to its
+    // arguments. call//N and M:NT are nonterminals as well.
+    fn nonterminal(&self, term: &Term, s0: &Term, s: &Term) -> Result<Term, ParserError> {
+        match term {
+            &Term::Constant(_, Constant::Atom(ref name)) => {
+                let terms = vec![Box::new(s0.clone()), Box::new(s.clone())];
+                Ok(Term::Clause(Cell::default(), name.clone(), terms, None))
+            },
+            &Term::Clause(_, ref name, ref terms, _) if name.as_str() == ":" && terms.len() == 2 => {
+                let nonterminal = self.nonterminal(&terms[1], s0, s)?;
+                Ok(op_clause(":", Fixity::In, vec![terms[0].as_ref().clone(), nonterminal]))
+            },
+            &Term::Clause(_, ref name, ref terms, _) => {
+                let mut terms = terms.clone();
+
+                terms.push(Box::new(s0.clone()));
+                terms.push(Box::new(s.clone()));
+
+                Ok(Term::Clause(Cell::default(), name.clone(), terms, None))
+            },
+            _ => Err(ParserError::InadmissibleQueryTerm)
+        }
+    }
+
+    // S0 = [T1, ..., Tn | S] of a list of terminals, or of a string,
+    // which is a list of its chars.
+    fn terminals(&self, term: &Term, s0: &Term, s: &Term) -> Result<Term, ParserError> {
+        let mut elements = vec![];
+
+        match term {
+            &Term::Constant(_, Constant::String(ref string)) =>
+                for c in string.chars() {
+                    let c = atom!(c.to_string(), self.atom_tbl);
+                    elements.push(Term::Constant(Cell::default(), c));
+                },
+            _ => {
+                let mut term = term;
+
+                loop {
+                    match term {
+                        &Term::Cons(_, ref head, ref tail) => {
+                            elements.push(head.as_ref().clone());
+                            term = tail.as_ref();
+                        },
+                        &Term::Constant(_, Constant::EmptyList) => break,
+                        &Term::Constant(_, Constant::Atom(ref name)) if name.as_str() == "[]" =>
+                            break,
+                        _ => return Err(ParserError::InadmissibleQueryTerm)
+                    }
+                }
+            }
+        };
+
+        let list = elements.into_iter().rev().fold(s.clone(), |tail, head| {
+            Term::Cons(Cell::default(), Box::new(head), Box::new(tail))
+        });
+
+        Ok(unify(s0, &list))
+    }
+}
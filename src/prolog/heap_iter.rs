@@ -72,6 +72,91 @@ impl<'a> Iterator for HeapCellPreOrderIterator<'a> {
     }
 }
 
+fn ellipsis() -> HeapCellValue {
+    HeapCellValue::Addr(Addr::Con(Constant::Atom(ClauseName::BuiltIn("..."))))
+}
+
+enum RationalTreeState {
+    Visit(Addr),
+    Leave(usize) // the structure whose subterms have all been visited.
+}
+
+// a pre-order iterator that cuts the cycles of rational trees. where
+// a subterm refers back to a structure enclosing it, the atom '...'
+// is visited instead.
+pub struct HeapCellRationalTreeIterator<'a> {
+    machine_st: &'a MachineState,
+    state_stack: Vec<RationalTreeState>,
+    enclosing: HashSet<usize>
+}
+
+impl<'a> HeapCellRationalTreeIterator<'a> {
+    pub fn new(machine_st: &'a MachineState, a: Addr) -> Self
+    {
+        HeapCellRationalTreeIterator {
+            machine_st, state_stack: vec![RationalTreeState::Visit(a)], enclosing: HashSet::new()
+        }
+    }
+
+    fn visit(&mut self, addr: Addr) -> HeapCellValue
+    {
+        let da = self.machine_st.store(self.machine_st.deref(addr));
+
+        match da {
+            Addr::Con(_) =>
+                HeapCellValue::Addr(da),
+            Addr::HeapCell(h) =>
+                self.machine_st.heap[h].clone(),
+            Addr::StackCell(fr, sc) =>
+                HeapCellValue::Addr(self.machine_st.and_stack[fr][sc].clone()),
+            Addr::Lis(a) => {
+                if !self.enclosing.insert(a) {
+                    return ellipsis();
+                }
+
+                self.state_stack.push(RationalTreeState::Leave(a));
+                self.state_stack.push(RationalTreeState::Visit(Addr::HeapCell(a + 1)));
+                self.state_stack.push(RationalTreeState::Visit(Addr::HeapCell(a)));
+
+                HeapCellValue::Addr(da)
+            },
+            Addr::Str(s) => {
+                if !self.enclosing.insert(s) {
+                    return ellipsis();
+                }
+
+                self.state_stack.push(RationalTreeState::Leave(s));
+
+                if let &HeapCellValue::NamedStr(arity, ..) = &self.machine_st.heap[s] {
+                    for idx in (1 .. arity + 1).rev() {
+                        self.state_stack.push(RationalTreeState::Visit(Addr::HeapCell(s + idx)));
+                    }
+                }
+
+                self.machine_st.heap[s].clone()
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for HeapCellRationalTreeIterator<'a> {
+    type Item = HeapCellValue;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(state) = self.state_stack.pop() {
+            match state {
+                RationalTreeState::Visit(addr) =>
+                    return Some(self.visit(addr)),
+                RationalTreeState::Leave(a) => {
+                    self.enclosing.remove(&a);
+                }
+            }
+        }
+
+        None
+    }
+}
+
 pub struct HeapCellPostOrderIterator<'a> {
     pre_iter:     HeapCellPreOrderIterator<'a>,
     parent_stack: Vec<(usize, HeapCellValue)> // number of children, parent node.
@@ -128,7 +213,7 @@ impl MachineState {
     pub fn acyclic_pre_order_iter<'a>(&'a self, a: Addr)
                                       -> HeapCellAcyclicIterator<HeapCellPreOrderIterator<'a>>
     {
-        HeapCellAcyclicIterator::new(HeapCellPreOrderIterator::new(self, a))
+        HeapCellAcyclicIterator::new(self.pre_order_iter(a))
     }
 
     pub fn zipped_acyclic_pre_order_iter<'a>(&'a self, a1: Addr, a2: Addr)
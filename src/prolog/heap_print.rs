@@ -142,14 +142,14 @@ impl HeapCellValueFormatter for TermFormatter {
 pub struct HeapCellPrinter<'a, Formatter, Outputter> {
     formatter:   Formatter,
     outputter:   Outputter,
-    iter:        HeapCellPreOrderIterator<'a>,
+    iter:        HeapCellRationalTreeIterator<'a>,
     state_stack: Vec<TokenOrRedirect>
 }
 
 impl<'a, Formatter: HeapCellValueFormatter, Outputter: HeapCellValueOutputter>
     HeapCellPrinter<'a, Formatter, Outputter>
 {
-    pub fn new(iter: HeapCellPreOrderIterator<'a>, formatter: Formatter, outputter: Outputter)
+    pub fn new(iter: HeapCellRationalTreeIterator<'a>, formatter: Formatter, outputter: Outputter)
                -> Self
     {
         HeapCellPrinter { formatter, outputter, iter, state_stack: vec![] }
@@ -1,17 +1,24 @@
 use prolog::ast::*;
 use prolog::builtins::*;
 use prolog::codegen::*;
+use prolog::dcg::*;
 use prolog::debray_allocator::*;
 use prolog::heap_print::*;
 use prolog::machine::*;
+use prolog::num::bigint::BigInt;
+use prolog::parser::parser::*;
 use prolog::parser::toplevel::*;
+use prolog::tabled_rc::*;
 
 use termion::raw::IntoRawMode;
 use termion::input::TermRead;
 use termion::event::Key;
 
-use std::io::{Write, stdin, stdout};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::io::{Read, Write, stdin, stdout};
 use std::fmt;
+use std::mem;
 
 
 impl fmt::Display for IndexPtr {
@@ -32,6 +39,18 @@ impl fmt::Display for ClauseName {
 impl fmt::Display for FactInstruction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            &FactInstruction::GetBoundConstant(ref constant, ref r) =>
+                write!(f, "get_bound_constant {}, {}", constant, r),
+            &FactInstruction::GetBoundList(ref r) =>
+                write!(f, "get_bound_list {}", r),
+            &FactInstruction::GetBoundStructure(ref ct, ref arity, ref r) =>
+                write!(f, "get_bound_structure {}/{}, {}", ct.name(), arity, r),
+            &FactInstruction::GetFreshConstant(ref constant, ref r) =>
+                write!(f, "get_fresh_constant {}, {}", constant, r),
+            &FactInstruction::GetFreshList(ref r) =>
+                write!(f, "get_fresh_list {}", r),
+            &FactInstruction::GetFreshStructure(ref ct, ref arity, ref r) =>
+                write!(f, "get_fresh_structure {}/{}, {}", ct.name(), arity, r),
             &FactInstruction::GetConstant(lvl, ref constant, ref r) =>
                 write!(f, "get_constant {}, {}{}", constant, lvl, r.reg_num()),
             &FactInstruction::GetList(lvl, ref r) =>
@@ -118,6 +137,8 @@ impl fmt::Display for ClauseType {
         match self {
             &ClauseType::Named(ref name, ref idx) | &ClauseType::Op(ref name, _, ref idx) =>
                 write!(f, "{}:{}/{}", idx.1, name, idx.0.get()),
+            &ClauseType::Qualified(ref module_name, ref name, _) =>
+                write!(f, "{}:{}", module_name, name),
             ref ct =>
                 write!(f, "{}", ct.name())
         }
@@ -177,6 +198,10 @@ impl fmt::Display for BuiltInInstruction {
                 write!(f, "number_test {}, {}, {}", cmp, &rs[0], &rs[1]),
             &BuiltInInstruction::CallInlined(ict, ref rs) =>
                 write!(f, "call_inlined_{}, {}", ict.name(), &rs[0]),
+            &BuiltInInstruction::CheckModes(ref name, ref modes) => {
+                let modes: Vec<String> = modes.iter().map(|mode| format!("{}", mode)).collect();
+                write!(f, "check_modes {}({})", name, modes.join(", "))
+            },
             &BuiltInInstruction::CleanUpBlock =>
                 write!(f, "clean_up_block"),
             &BuiltInInstruction::CompareNumber(cmp, ref at_1, ref at_2) =>
@@ -191,6 +216,8 @@ impl fmt::Display for BuiltInInstruction {
                 write!(f, "install_inference_counter {}, {}, {}", r1, r2, r3),
             &BuiltInInstruction::EraseBall =>
                 write!(f, "erase_ball"),
+            &BuiltInInstruction::ExitDepthLimit(r1, r2) =>
+                write!(f, "exit_depth_limit {}, {}", r1, r2),
             &BuiltInInstruction::Fail =>
                 write!(f, "false"),
             &BuiltInInstruction::GetArg(false) =>
@@ -207,16 +234,26 @@ impl fmt::Display for BuiltInInstruction {
                 write!(f, "inference_level {}, {}", r1, r2),
             &BuiltInInstruction::InstallCleaner =>
                 write!(f, "install_cleaner"),
+            &BuiltInInstruction::InstallDepthLimit(r1, r2) =>
+                write!(f, "install_depth_limit {}, {}", r1, r2),
             &BuiltInInstruction::InstallNewBlock =>
                 write!(f, "install_new_block"),
+            &BuiltInInstruction::InstallTimeLimit(r1, r2) =>
+                write!(f, "install_time_limit {}, {}", r1, r2),
             &BuiltInInstruction::InternalCallN =>
                 write!(f, "internal_call_N"),
+            &BuiltInInstruction::PopDepthLimit =>
+                write!(f, "pop_depth_limit"),
+            &BuiltInInstruction::RedoDepthLimit(r) =>
+                write!(f, "redo_depth_limit {}", r),
             &BuiltInInstruction::ResetBlock =>
                 write!(f, "reset_block"),
             &BuiltInInstruction::RestoreCutPolicy =>
                 write!(f, "restore_cut_point"),
-            &BuiltInInstruction::SetBall =>
+            &BuiltInInstruction::SetBall(true) =>
                 write!(f, "set_ball"),
+            &BuiltInInstruction::SetBall(false) =>
+                write!(f, "rethrow_ball"),
             &BuiltInInstruction::SetCutPoint(r) =>
                 write!(f, "set_cp {}", r),
             &BuiltInInstruction::Succeed =>
@@ -227,8 +264,12 @@ impl fmt::Display for BuiltInInstruction {
                 write!(f, "unify"),
             &BuiltInInstruction::RemoveCallPolicyCheck =>
                 write!(f, "remove_call_policy_check"),
+            &BuiltInInstruction::RemoveDepthLimit(r) =>
+                write!(f, "remove_depth_limit {}", r),
             &BuiltInInstruction::RemoveInferenceCounter(r1, r2) =>
-                write!(f, "remove_inference_counter {}, {}", r1, r2)
+                write!(f, "remove_inference_counter {}, {}", r1, r2),
+            &BuiltInInstruction::RemoveTimeLimit(r) =>
+                write!(f, "remove_time_limit {}", r)
         }
     }
 }
@@ -264,13 +305,32 @@ impl fmt::Display for EvalError {
         match self {
             &EvalError::ModuleNotFound => write!(f, "module not found."),
             &EvalError::ModuleDoesNotContainExport => write!(f, "module does not contain claimed export."),
+            &EvalError::QueryAborted => write!(f, "% execution aborted."),
             &EvalError::QueryFailure => write!(f, "false."),
-            &EvalError::QueryFailureWithException(ref e) => write!(f, "{}", error_string(e)),
-            &EvalError::ImpermissibleEntry(ref msg) => write!(f, "cannot overwrite builtin {}.", msg),
+            &EvalError::QueryFailureWithException(ref e, ref backtrace, _) =>
+                write!(f, "{}", exception_string(e, backtrace)),
+            &EvalError::ConditionalError(ConditionalError::FollowsElse(directive)) =>
+                write!(f, "{} directive follows else/0.", directive),
+            &EvalError::ConditionalError(ConditionalError::NoMatchingEndif) =>
+                write!(f, "if/1 directive without a matching endif/0."),
+            &EvalError::ConditionalError(ConditionalError::NoMatchingIf(directive)) =>
+                write!(f, "{} directive without a matching if/1.", directive),
+            &EvalError::ImpermissibleEntry(ref msg) => write!(f, "impermissible entry: {}", msg),
+            &EvalError::ImportConflict(ref importer, ref module_name, ref name, arity)
+          | &EvalError::RedefinedImport(ref importer, ref module_name, ref name, arity) =>
+                write!(f, "{}", error_string(&format!("error(permission_error(import_into({0}), \
+                                                      procedure, {1}:{2}/{3}), {2}/{3})",
+                                                      importer, module_name, name, arity))),
+            &EvalError::InvalidModeDeclaration(ref decl) =>
+                write!(f, "invalid mode declaration: {}.", decl),
+            &EvalError::LateModeDeclaration(ref name, arity) =>
+                write!(f, "the mode declaration of {}/{} follows its clauses.", name, arity),
+            &EvalError::ExpansionLimit(ref term) =>
+                write!(f, "the expansion of {} exceeds the depth limit.", term),
             &EvalError::OpIsInfixAndPostFix =>
                 write!(f, "cannot define an op to be both postfix and infix."),
             &EvalError::NamelessEntry => write!(f, "the predicate head is not an atom or clause."),
-            &EvalError::ParserError(ref e) => write!(f, "{:?}", e)
+            &EvalError::ParserError(ref e) => write!(f, "{:?}", e),
         }
     }
 }
@@ -396,10 +456,1077 @@ pub fn print_code(code: &Code) {
     }
 }
 
-pub fn parse_code(wam: &Machine, buffer: &str) -> Result<TopLevelPacket, ParserError>
+pub fn parse_code(wam: &mut Machine, buffer: &str) -> Result<TopLevelPacket, EvalError>
+{
+    let mut op_dir = wam.toplevel_op_dir().clone();
+    add_directive_ops(&mut op_dir);
+
+    let term = read_term(wam, &mut Parser::new(buffer.as_bytes(), wam.atom_tbl()), &op_dir)?;
+
+    if let Some(decl) = declaration_directive(&term) {
+        return Ok(TopLevelPacket::Decl(TopLevel::Declaration(decl?), vec![]));
+    }
+
+    // flags are set as they're read, so a set_prolog_flag/2 directive
+    // is run as a query.
+    if let Some(goal) = flag_directive_goal(&term) {
+        let src = query_source(goal);
+        let mut worker = TopLevelWorker::new(src.as_bytes(), wam.atom_tbl());
+
+        return Ok(worker.parse_code(wam.toplevel_op_dir())?);
+    }
+
+    if let Some(decls) = mode_directive(&term) {
+        let module_name = wam.toplevel_module();
+
+        for (name, modes) in decls? {
+            if wam.defines(&module_name, &(name.clone(), modes.len())) {
+                return Err(EvalError::LateModeDeclaration(name, modes.len()));
+            }
+
+            wam.declare_modes(module_name.clone(), name, modes);
+        }
+
+        let mut worker = TopLevelWorker::new("?- true.".as_bytes(), wam.atom_tbl());
+        return Ok(worker.parse_code(wam.toplevel_op_dir())?);
+    }
+
+    let packet = if wam.has_expansion_hooks() || wam.double_quotes() != DoubleQuotes::String {
+        parse_expanded_code(wam, term)?
+    } else {
+        let mut worker = TopLevelWorker::new(buffer.as_bytes(), wam.atom_tbl());
+        worker.parse_code(wam.toplevel_op_dir())?
+    };
+
+    match translate_dcg_rules(wam, &packet)? {
+        Some(src) => {
+            let mut worker = TopLevelWorker::new(src.as_bytes(), wam.atom_tbl());
+            Ok(worker.parse_code(wam.toplevel_op_dir())?)
+        },
+        None => Ok(packet)
+    }
+}
+
+// reads the expansion of a toplevel entry, which must be a query or
+// the clauses of a single predicate.
+fn parse_expanded_code(wam: &mut Machine, term: Term) -> Result<TopLevelPacket, EvalError>
+{
+    let is_query = is_query(&term);
+
+    let module_name = wam.toplevel_module();
+    let src = expanded_source(wam, &module_name, term)?;
+
+    let mut worker = TopLevelWorker::new(src.as_bytes(), wam.atom_tbl());
+
+    if is_query {
+        return Ok(worker.parse_code(wam.toplevel_op_dir())?);
+    }
+
+    let mut packets = worker.parse_batch(wam.toplevel_dirs().1)?;
+
+    match packets.len() {
+        1 => Ok(packets.pop().unwrap()),
+        _ => Err(EvalError::ImpermissibleEntry(String::from("the entry doesn't expand to \
+                                                              the clauses of one predicate.")))
+    }
+}
+
+// the source of the clauses translated from the DCG rules of a
+// packet, or None if it holds none.
+fn translate_dcg_rules(wam: &Machine, packet: &TopLevelPacket) -> Result<Option<String>, ParserError>
+{
+    let rules: Vec<&Term> = match packet {
+        &TopLevelPacket::Decl(TopLevel::Fact(ref term), _) if is_dcg_rule(term) =>
+            vec![term],
+        &TopLevelPacket::Decl(TopLevel::Predicate(ref pred), _) =>
+            pred.0.iter().filter_map(|clause| match clause {
+                &PredicateClause::Fact(ref term) if is_dcg_rule(term) => Some(term),
+                _ => None
+            }).collect(),
+        _ => vec![]
+    };
+
+    if rules.is_empty() {
+        return Ok(None);
+    }
+
+    let mut translator = DcgTranslator::new(wam.atom_tbl());
+    let mut src = String::new();
+
+    for rule in rules {
+        src += &clause_source(&translator.translate_rule(rule)?);
+    }
+
+    Ok(Some(src))
+}
+
+// the module declaration heading the source of a library, read
+// without compiling the library.
+pub fn library_module_decl(wam: &Machine, src_str: &str) -> Result<ModuleDecl, EvalError>
+{
+    let (_, mut op_dir) = build_code_and_op_dirs();
+    let mut worker = TopLevelWorker::new(src_str.as_bytes(), wam.atom_tbl());
+
+    match worker.parse_batch(&mut op_dir)?.into_iter().next() {
+        Some(TopLevelPacket::Decl(TopLevel::Declaration(Declaration::Module(module_decl)), _)) =>
+            Ok(module_decl),
+        _ => Err(EvalError::from(ParserError::InvalidModuleDecl))
+    }
+}
+
+// the DCG rules of a batch are replaced by the clauses read back from
+// their translations.
+fn expand_dcg_rules(wam: &Machine, tls: Vec<TopLevelPacket>, op_dir: &mut OpDir)
+                    -> Result<Vec<TopLevelPacket>, ParserError>
+{
+    let mut expanded_tls = vec![];
+
+    for packet in tls {
+        match translate_dcg_rules(wam, &packet)? {
+            Some(src) => {
+                let mut worker = TopLevelWorker::new(src.as_bytes(), wam.atom_tbl());
+                expanded_tls.extend(worker.parse_batch(op_dir)?.into_iter());
+            },
+            None => expanded_tls.push(packet)
+        }
+    }
+
+    Ok(expanded_tls)
+}
+
+// a batch as it's read by expand_batch: the source of its terms,
+// broken by the declarations of the directives read there.
+enum BatchSegment {
+    Source(String),
+    Decl(Declaration)
+}
+
+// the segments of a batch, its terms replaced by their expansions and
+// the sections skipped by its conditional directives left out. None
+// if the batch has none of these to apply.
+fn expand_batch(wam: &mut Machine, src_str: &str, op_dir: &mut OpDir)
+                -> Result<Option<Vec<BatchSegment>>, EvalError>
+{
+    let mut parser = Parser::new(src_str.as_bytes(), wam.atom_tbl());
+    let mut module_name = wam.toplevel_module();
+    let mut segments = vec![];
+    let mut src = String::new();
+
+    let mut is_expanded = wam.has_expansion_hooks();
+    let mut conditionals = Conditionals::new();
+
+    // the predicates of the module read with clauses so far.
+    let mut heads = HashSet::new();
+
+    while !parser.eof()? {
+        let term = read_term(wam, &mut parser, op_dir)?;
+
+        if let Some(directive) = conditional_directive(&term) {
+            conditionals.update(wam, directive, op_dir)?;
+            is_expanded = true;
+            continue;
+        }
+
+        if conditionals.is_skipping() {
+            continue;
+        }
+
+        // flags are set as they're read, so they apply to the terms
+        // following them. the unknown flag is set in the module read.
+        if let Some(goal) = flag_directive_goal(&term) {
+            let goal = goal.clone();
+
+            wam.with_toplevel_module(module_name.clone(),
+                                     |wam| run_goal(wam, goal, op_dir))?;
+            is_expanded = true;
+            continue;
+        }
+
+        // modes are declared of the predicates of the module read,
+        // whose clauses must follow.
+        if let Some(decls) = mode_directive(&term) {
+            for (name, modes) in decls? {
+                if heads.contains(&(name.clone(), modes.len())) {
+                    return Err(EvalError::LateModeDeclaration(name, modes.len()));
+                }
+
+                wam.declare_modes(module_name.clone(), name, modes);
+            }
+
+            is_expanded = true;
+            continue;
+        }
+
+        if let Some(decl) = declaration_directive(&term) {
+            segments.push(BatchSegment::Source(mem::replace(&mut src, String::new())));
+            segments.push(BatchSegment::Decl(decl?));
+
+            is_expanded = true;
+            continue;
+        }
+
+        if wam.double_quotes() != DoubleQuotes::String {
+            is_expanded = true;
+        }
+
+        let is_directive = is_directive(&term);
+
+        if let Some(name) = module_decl_name(&term) {
+            module_name = name;
+            heads.clear();
+        }
+
+        if let Some(key) = clause_key(&term) {
+            heads.insert(key);
+        }
+
+        let term_src = expanded_source(wam, &module_name, term)?;
+
+        // the operators declared by a directive are read in the terms
+        // following it.
+        if is_directive {
+            TopLevelWorker::new(term_src.as_bytes(), wam.atom_tbl()).parse_batch(op_dir)?;
+        }
+
+        src += &term_src;
+    }
+
+    conditionals.finish()?;
+    segments.push(BatchSegment::Source(src));
+
+    Ok(if is_expanded { Some(segments) } else { None })
+}
+
+enum ConditionalDirective {
+    If(Term),
+    Elif(Term),
+    Else,
+    Endif
+}
+
+fn conditional_directive(term: &Term) -> Option<ConditionalDirective> {
+    if let &Term::Clause(_, ref name, ref terms, _) = term {
+        if name.as_str() == ":-" && terms.len() == 1 {
+            return match terms[0].as_ref() {
+                &Term::Clause(_, ref name, ref terms, _) if terms.len() == 1 =>
+                    match name.as_str() {
+                        "if"   => Some(ConditionalDirective::If(terms[0].as_ref().clone())),
+                        "elif" => Some(ConditionalDirective::Elif(terms[0].as_ref().clone())),
+                        _ => None
+                    },
+                &Term::Constant(_, Constant::Atom(ref name)) =>
+                    match name.as_str() {
+                        "else"  => Some(ConditionalDirective::Else),
+                        "endif" => Some(ConditionalDirective::Endif),
+                        _ => None
+                    },
+                _ => None
+            };
+        }
+    }
+
+    None
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum BranchState {
+    Taken,     // the terms of the current branch are compiled.
+    Searching, // no branch has been taken yet.
+    Skipped    // a branch was taken before, or the section is skipped.
+}
+
+// the open if/1 sections of a batch, innermost last, each with the
+// state of its branch and whether its else/0 branch was reached.
+struct Conditionals {
+    sections: Vec<(BranchState, bool)>
+}
+
+impl Conditionals {
+    fn new() -> Self {
+        Conditionals { sections: vec![] }
+    }
+
+    fn is_skipping(&self) -> bool {
+        match self.sections.last() {
+            Some(&(state, _)) => state != BranchState::Taken,
+            None => false
+        }
+    }
+
+    fn unmatched(directive: &'static str) -> EvalError {
+        EvalError::ConditionalError(ConditionalError::NoMatchingIf(directive))
+    }
+
+    fn follows_else(directive: &'static str) -> EvalError {
+        EvalError::ConditionalError(ConditionalError::FollowsElse(directive))
+    }
+
+    // the goals of if/1 and elif/1 are run only if their branches
+    // might be taken. they're run as the batch is read, before any of
+    // its clauses are compiled, so they can't call them.
+    fn update(&mut self, wam: &mut Machine, directive: ConditionalDirective, op_dir: &OpDir)
+              -> Result<(), EvalError>
+    {
+        match directive {
+            ConditionalDirective::If(goal) => {
+                let state = if self.is_skipping() {
+                    BranchState::Skipped
+                } else if run_goal(wam, goal, op_dir)? {
+                    BranchState::Taken
+                } else {
+                    BranchState::Searching
+                };
+
+                self.sections.push((state, false));
+            },
+            ConditionalDirective::Elif(goal) =>
+                match self.sections.pop() {
+                    Some((_, true)) =>
+                        return Err(Self::follows_else("elif/1")),
+                    Some((BranchState::Searching, _)) => {
+                        let state = if run_goal(wam, goal, op_dir)? {
+                            BranchState::Taken
+                        } else {
+                            BranchState::Searching
+                        };
+
+                        self.sections.push((state, false));
+                    },
+                    Some(_) => self.sections.push((BranchState::Skipped, false)),
+                    None => return Err(Self::unmatched("elif/1"))
+                },
+            ConditionalDirective::Else =>
+                match self.sections.pop() {
+                    Some((_, true)) =>
+                        return Err(Self::follows_else("else/0")),
+                    Some((BranchState::Searching, _)) =>
+                        self.sections.push((BranchState::Taken, true)),
+                    Some(_) => self.sections.push((BranchState::Skipped, true)),
+                    None => return Err(Self::unmatched("else/0"))
+                },
+            ConditionalDirective::Endif =>
+                if self.sections.pop().is_none() {
+                    return Err(Self::unmatched("endif/0"));
+                }
+        };
+
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), EvalError> {
+        if self.sections.is_empty() {
+            Ok(())
+        } else {
+            Err(EvalError::ConditionalError(ConditionalError::NoMatchingEndif))
+        }
+    }
+}
+
+// the goal of a set_prolog_flag/2 directive.
+fn flag_directive_goal(term: &Term) -> Option<&Term> {
+    if let &Term::Clause(_, ref name, ref terms, _) = term {
+        if name.as_str() == ":-" && terms.len() == 1 {
+            if let &Term::Clause(_, ref name, ref args, _) = terms[0].as_ref() {
+                if name.as_str() == "set_prolog_flag" && args.len() == 2 {
+                    return Some(terms[0].as_ref());
+                }
+            }
+        }
+    }
+
+    None
+}
+
+// the declarations whose directives are read here rather than by the
+// parser.
+fn declaration_directive(term: &Term) -> Option<Result<Declaration, EvalError>> {
+    if let &Term::Clause(_, ref name, ref terms, _) = term {
+        if name.as_str() == ":-" && terms.len() == 1 {
+            if let &Term::Clause(_, ref name, ref args, _) = terms[0].as_ref() {
+                match (name.as_str(), args.len()) {
+                    ("meta_predicate", 1) =>
+                        return Some(meta_predicate_decls(args[0].as_ref())),
+                    ("use_module", 2) =>
+                        return Some(module_name_arg(args[0].as_ref()).and_then(|name| {
+                            let imports = import_list(args[1].as_ref())?;
+                            Ok(Declaration::UseQualifiedModule(name, imports))
+                        })),
+                    ("reexport", 1) =>
+                        return Some(module_name_arg(args[0].as_ref()).map(Declaration::Reexport)),
+                    ("reexport", 2) =>
+                        return Some(module_name_arg(args[0].as_ref()).and_then(|name| {
+                            let imports = import_list(args[1].as_ref())?;
+                            Ok(Declaration::QualifiedReexport(name, imports))
+                        })),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    None
+}
+
+// a module named as Name or library(Name).
+fn module_name_arg(term: &Term) -> Result<ClauseName, EvalError> {
+    match term {
+        &Term::Constant(_, Constant::Atom(ref name)) => Ok(name.clone()),
+        &Term::Clause(_, ref name, ref args, _) if name.as_str() == "library" && args.len() == 1 =>
+            module_name_arg(args[0].as_ref()),
+        _ => Err(EvalError::from(ParserError::InvalidModuleDecl))
+    }
+}
+
+// the import list of use_module/2 or reexport/2: a list of Name/Arity
+// keys, each possibly renamed by `as Alias`, or except(Keys).
+fn import_list(term: &Term) -> Result<ImportList, EvalError> {
+    let invalid = || EvalError::from(ParserError::InvalidModuleDecl);
+
+    if let &Term::Clause(_, ref name, ref args, _) = term {
+        if name.as_str() == "except" && args.len() == 1 {
+            let keys: Option<Vec<_>> = list_items(args[0].as_ref()).ok_or_else(&invalid)?
+                .into_iter()
+                .map(ModuleDecl::export_key)
+                .collect();
+
+            return keys.map(ImportList::Except).ok_or_else(invalid);
+        }
+    }
+
+    let mut imports = vec![];
+
+    for item in list_items(term).ok_or_else(&invalid)? {
+        let import = match item {
+            &Term::Clause(_, ref name, ref args, _) if name.as_str() == "as" && args.len() == 2 =>
+                match (ModuleDecl::export_key(args[0].as_ref()), args[1].as_ref()) {
+                    (Some(key), &Term::Constant(_, Constant::Atom(ref alias))) =>
+                        (key, Some(alias.clone())),
+                    _ => return Err(invalid())
+                },
+            _ => (ModuleDecl::export_key(item).ok_or_else(&invalid)?, None)
+        };
+
+        imports.push(import);
+    }
+
+    Ok(ImportList::Only(imports))
+}
+
+// the items of a proper list, or None if the term isn't one.
+fn list_items(mut term: &Term) -> Option<Vec<&Term>> {
+    let mut items = vec![];
+
+    loop {
+        match term {
+            &Term::Cons(_, ref head, ref tail) => {
+                items.push(head.as_ref());
+                term = tail.as_ref();
+            },
+            &Term::Constant(_, Constant::EmptyList) => return Some(items),
+            _ => return None
+        }
+    }
+}
+
+// the terms of a conjunction, in order.
+fn conjuncts(mut term: &Term) -> Vec<&Term> {
+    let mut terms = vec![];
+
+    loop {
+        match term {
+            &Term::Clause(_, ref name, ref args, _) if name.as_str() == "," && args.len() == 2 => {
+                terms.push(args[0].as_ref());
+                term = args[1].as_ref();
+            },
+            _ => {
+                terms.push(term);
+                return terms;
+            }
+        }
+    }
+}
+
+// a conjunction of meta_predicate declarations, each of the form
+// p(0, ?).
+fn meta_predicate_decls(term: &Term) -> Result<Declaration, EvalError> {
+    let mut decls = vec![];
+
+    for term in conjuncts(term) {
+        if let &Term::Clause(_, ref name, ref args, _) = term {
+            let specs: Option<Vec<MetaSpec>> = args.iter().map(|arg| MetaSpec::from_term(arg))
+                                                   .collect();
+
+            if let Some(specs) = specs {
+                decls.push((name.clone(), specs));
+                continue;
+            }
+        }
+
+        return Err(EvalError::from(ParserError::InvalidModuleDecl));
+    }
+
+    Ok(Declaration::MetaPredicate(decls))
+}
+
+// the predicates declared by a mode/1 directive, with their modes.
+fn mode_directive(term: &Term) -> Option<Result<Vec<(ClauseName, Vec<Mode>)>, EvalError>> {
+    if let &Term::Clause(_, ref name, ref terms, _) = term {
+        if name.as_str() == ":-" && terms.len() == 1 {
+            if let &Term::Clause(_, ref name, ref args, _) = terms[0].as_ref() {
+                if name.as_str() == "mode" && args.len() == 1 {
+                    return Some(mode_decls(args[0].as_ref()));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+// a conjunction of mode declarations, each of the form p(+, -, ?).
+fn mode_decls(term: &Term) -> Result<Vec<(ClauseName, Vec<Mode>)>, EvalError> {
+    conjuncts(term).into_iter().map(mode_decl).collect()
+}
+
+fn mode_decl(term: &Term) -> Result<(ClauseName, Vec<Mode>), EvalError> {
+    if let &Term::Clause(_, ref name, ref args, _) = term {
+        let modes: Option<Vec<Mode>> = args.iter().map(|arg| Mode::from_term(arg)).collect();
+
+        if let Some(modes) = modes {
+            return Ok((name.clone(), modes));
+        }
+    }
+
+    Err(EvalError::InvalidModeDeclaration(term_source(term)))
+}
+
+fn query_source(goal: &Term) -> String {
+    let query = Term::Clause(Cell::default(), clause_name!("?-"), vec![Box::new(goal.clone())],
+                             Some(Fixity::Pre));
+    clause_source(&query)
+}
+
+// runs the goal of a directive as a query, at load time, succeeding
+// if the goal does.
+fn run_goal(wam: &mut Machine, goal: Term, op_dir: &OpDir) -> Result<bool, EvalError>
+{
+    let src = query_source(&goal);
+
+    let (terms, queue) = match TopLevelWorker::new(src.as_bytes(), wam.atom_tbl()).parse_code(op_dir)? {
+        TopLevelPacket::Query(terms, queue) => (terms, queue),
+        _ => return Err(EvalError::from(ParserError::ExpectedRel))
+    };
+
+    wam.reset();
+
+    let code_size = wam.code_size();
+    let (code, alloc_locs) = compile_query(terms, queue, code_size, wam.toplevel_dirs().0)?;
+
+    let result = match wam.submit_query(code, alloc_locs) {
+        EvalSession::Error(EvalError::QueryFailure) => Ok(false),
+        EvalSession::Error(err) => Err(err),
+        _ => Ok(true)
+    };
+
+    wam.reset();
+    result
+}
+
+fn is_directive(term: &Term) -> bool {
+    match term {
+        &Term::Clause(_, ref name, ref terms, _) => name.as_str() == ":-" && terms.len() == 1,
+        _ => false
+    }
+}
+
+// the predicate a clause of the batch adds to, grammar rules included.
+fn clause_key(term: &Term) -> Option<PredicateKey> {
+    match term {
+        &Term::Clause(_, ref name, ref terms, _) if terms.len() == 2 =>
+            match name.as_str() {
+                ":-"  => terms[0].name().map(|name| (name, terms[0].arity())),
+                "-->" => terms[0].name().map(|name| (name, terms[0].arity() + 2)),
+                _ => Some((name.clone(), 2))
+            },
+        &Term::Clause(_, ref name, ref terms, _) =>
+            match name.as_str() {
+                ":-" | "?-" if terms.len() == 1 => None,
+                _ => Some((name.clone(), terms.len()))
+            },
+        &Term::Constant(_, Constant::Atom(ref name)) => Some((name.clone(), 0)),
+        _ => None
+    }
+}
+
+fn is_query(term: &Term) -> bool {
+    match term {
+        &Term::Clause(_, ref name, ref terms, _) => name.as_str() == "?-" && terms.len() == 1,
+        _ => false
+    }
+}
+
+fn module_decl_name(term: &Term) -> Option<ClauseName> {
+    if let &Term::Clause(_, ref name, ref terms, _) = term {
+        if name.as_str() == ":-" && terms.len() == 1 {
+            if let &Term::Clause(_, ref name, ref terms, _) = terms[0].as_ref() {
+                if name.as_str() == "module" && terms.len() == 2 {
+                    return terms[0].name();
+                }
+            }
+        }
+    }
+
+    None
+}
+
+// the modules whose term_expansion/2 or goal_expansion/2 hooks expand
+// the code of module_name: module_name itself, then user.
+fn expansion_modules(wam: &Machine, module_name: &ClauseName, hook: &'static str)
+                     -> Vec<ClauseName>
 {
-    let mut worker = TopLevelWorker::new(buffer.as_bytes(), wam.atom_tbl());
-    worker.parse_code(&wam.op_dir)
+    let key  = (clause_name!(hook), 2);
+    let user = clause_name!("user");
+
+    let mut modules = vec![];
+
+    if *module_name != user && wam.defines(module_name, &key) {
+        modules.push(module_name.clone());
+    }
+
+    if wam.defines(&user, &key) {
+        modules.push(user);
+    }
+
+    modules
+}
+
+// the number of times a term or goal may be rewritten by the
+// expansion hooks before expansion is abandoned.
+const MAX_EXPANSION_DEPTH: usize = 256;
+
+// true if t1 and t2 are the same term. anonymous variables stand for
+// the variables naming them in the term returned by a hook.
+fn same_term(t1: &Term, t2: &Term) -> bool {
+    match (t1, t2) {
+        (&Term::Clause(_, ref name1, ref terms1, _), &Term::Clause(_, ref name2, ref terms2, _)) =>
+            name1 == name2 && terms1.len() == terms2.len()
+            && terms1.iter().zip(terms2.iter()).all(|(t1, t2)| same_term(t1, t2)),
+        (&Term::Cons(_, ref head1, ref tail1), &Term::Cons(_, ref head2, ref tail2)) =>
+            same_term(head1, head2) && same_term(tail1, tail2),
+        (&Term::Constant(_, ref c1), &Term::Constant(_, ref c2)) =>
+            c1 == c2,
+        (&Term::Var(_, ref var1), &Term::Var(_, ref var2)) =>
+            var1 == var2,
+        (&Term::AnonVar, &Term::AnonVar)
+      | (&Term::AnonVar, &Term::Var(..))
+      | (&Term::Var(..), &Term::AnonVar) =>
+            true,
+        _ => false
+    }
+}
+
+// the expansion of term by the first of the hooks of modules to
+// change it, or None if none of them do.
+fn expand_by_hooks(wam: &mut Machine, modules: &Vec<ClauseName>, hook: &'static str, term: &Term)
+                   -> Result<Option<Term>, EvalError>
+{
+    for module_name in modules {
+        if let Some(expanded) = wam.call_expansion_hook(module_name, hook, term)? {
+            if !same_term(&expanded, term) {
+                return Ok(Some(expanded));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+// expands term by term_expansion/2 until no hook changes it. a term
+// may expand to a list of terms, each of which is expanded in turn.
+fn expand_term(wam: &mut Machine, modules: &Vec<ClauseName>, term: Term, terms: &mut Vec<Term>,
+               depth: usize)
+               -> Result<(), EvalError>
+{
+    if depth > MAX_EXPANSION_DEPTH {
+        return Err(EvalError::ExpansionLimit(term_source(&term)));
+    }
+
+    let expanded = match expand_by_hooks(wam, modules, "term_expansion", &term)? {
+        Some(expanded) => expanded,
+        None => {
+            terms.push(term);
+            return Ok(());
+        }
+    };
+
+    match expanded {
+        Term::Cons(..) | Term::Constant(_, Constant::EmptyList) => {
+            let mut list = expanded;
+
+            while let Term::Cons(_, head, tail) = list {
+                expand_term(wam, modules, *head, terms, depth + 1)?;
+                list = *tail;
+            }
+
+            Ok(())
+        },
+        expanded => expand_term(wam, modules, expanded, terms, depth + 1)
+    }
+}
+
+// expands the goals of a body by goal_expansion/2 until they're
+// unchanged, descending into the control constructs and the goal
+// arguments of the meta-predicates visible from module_name.
+fn expand_goals(wam: &mut Machine, module_name: &ClauseName, modules: &Vec<ClauseName>,
+                term: Term, depth: usize)
+                -> Result<Term, EvalError>
+{
+    if depth > MAX_EXPANSION_DEPTH {
+        return Err(EvalError::ExpansionLimit(term_source(&term)));
+    }
+
+    match term {
+        Term::Clause(cell, name, terms, fixity) => {
+            let is_control = match (name.as_str(), terms.len()) {
+                (",", 2) | (";", 2) | ("->", 2) => true,
+                _ => false
+            };
+
+            let goal = Term::Clause(cell, name, terms, fixity);
+
+            if !is_control {
+                if let Some(expanded) = expand_by_hooks(wam, modules, "goal_expansion", &goal)? {
+                    return expand_goals(wam, module_name, modules, expanded, depth + 1);
+                }
+            }
+
+            match goal {
+                Term::Clause(cell, name, terms, fixity) => {
+                    let specs = if is_control {
+                        Some(vec![MetaSpec::Closure(0); terms.len()])
+                    } else {
+                        wam.meta_specs(module_name, &(name.clone(), terms.len()))
+                    };
+
+                    let terms = expand_goal_args(wam, module_name, modules, terms, specs)?;
+                    Ok(Term::Clause(cell, name, terms, fixity))
+                },
+                goal => Ok(goal)
+            }
+        },
+        Term::Constant(..) =>
+            match expand_by_hooks(wam, modules, "goal_expansion", &term)? {
+                Some(expanded) => expand_goals(wam, module_name, modules, expanded, depth + 1),
+                None => Ok(term)
+            },
+        term => Ok(term)
+    }
+}
+
+// expands the arguments of a goal given as goals by its meta-predicate
+// specs, if it has any.
+fn expand_goal_args(wam: &mut Machine, module_name: &ClauseName, modules: &Vec<ClauseName>,
+                    terms: Vec<Box<Term>>, specs: Option<Vec<MetaSpec>>)
+                    -> Result<Vec<Box<Term>>, EvalError>
+{
+    let specs = match specs {
+        Some(specs) => specs,
+        None => return Ok(terms)
+    };
+
+    let mut expanded_terms = vec![];
+
+    for (term, spec) in terms.into_iter().zip(specs.into_iter()) {
+        let term = if spec == MetaSpec::Closure(0) {
+            expand_goals(wam, module_name, modules, *term, 0)?
+        } else {
+            *term
+        };
+
+        expanded_terms.push(Box::new(term));
+    }
+
+    Ok(expanded_terms)
+}
+
+// the source of term, read in module_name, after its expansion by
+// term_expansion/2, the translation of DCG rules, and the expansion of
+// the goals of clause bodies and queries by goal_expansion/2.
+fn expanded_source(wam: &mut Machine, module_name: &ClauseName, term: Term)
+                   -> Result<String, EvalError>
+{
+    let term_hooks = expansion_modules(wam, module_name, "term_expansion");
+    let goal_hooks = expansion_modules(wam, module_name, "goal_expansion");
+
+    let mut terms = vec![];
+
+    if is_query(&term) {
+        terms.push(term);
+    } else {
+        expand_term(wam, &term_hooks, term, &mut terms, 0)?;
+    }
+
+    let mut translator = DcgTranslator::new(wam.atom_tbl());
+    let mut src = String::new();
+
+    for term in terms {
+        let term = if is_dcg_rule(&term) {
+            translator.translate_rule(&term)?
+        } else {
+            term
+        };
+
+        let term = match term {
+            Term::Clause(cell, name, mut terms, fixity) => {
+                let has_body = match (name.as_str(), terms.len()) {
+                    (":-", 2) | ("?-", 1) => true,
+                    _ => false
+                };
+
+                if has_body {
+                    let body = *terms.pop().unwrap();
+                    terms.push(Box::new(expand_goals(wam, module_name, &goal_hooks, body, 0)?));
+                }
+
+                Term::Clause(cell, name, terms, fixity)
+            },
+            term => term
+        };
+
+        src += &clause_source(&term);
+    }
+
+    Ok(src)
+}
+
+// atoms are quoted unless they're made of letters, digits and
+// underscores, beginning with a lowercase letter.
+fn write_atom(src: &mut String, atom: &str)
+{
+    let is_plain = match atom.chars().next() {
+        Some(c) if c.is_lowercase() =>
+            atom.chars().all(|c| c.is_alphanumeric() || c == '_'),
+        _ => atom == "[]" || atom == "!" || atom == "{}" || atom == ";"
+    };
+
+    if is_plain {
+        src.push_str(atom);
+        return;
+    }
+
+    src.push('\'');
+
+    for c in atom.chars() {
+        match c {
+            '\'' => src.push_str("\\'"),
+            '\\' => src.push_str("\\\\"),
+            '\n' => src.push_str("\\n"),
+            c => src.push(c)
+        }
+    }
+
+    src.push('\'');
+}
+
+// terms are written back as source in canonical form, save for those
+// read as operators and the control constructs, which are written as
+// parenthesized operators, so they're read back as they were.
+fn write_term(src: &mut String, term: &Term)
+{
+    match term {
+        &Term::AnonVar =>
+            src.push('_'),
+        &Term::Var(_, ref var) =>
+            src.push_str(var.as_str()),
+        &Term::Constant(_, Constant::Atom(ref name)) =>
+            write_atom(src, name.as_str()),
+        &Term::Constant(_, Constant::EmptyList) =>
+            src.push_str("[]"),
+        &Term::Constant(_, Constant::Number(Number::Float(fl))) =>
+            src.push_str(&float_source(fl.0)),
+        &Term::Constant(_, Constant::Number(ref n)) =>
+            src.push_str(&format!("{}", n)),
+        &Term::Constant(_, Constant::String(ref string)) => {
+            src.push('"');
+
+            for c in string.chars() {
+                match c {
+                    '"'  => src.push_str("\\\""),
+                    '\\' => src.push_str("\\\\"),
+                    '\n' => src.push_str("\\n"),
+                    c => src.push(c)
+                }
+            }
+
+            src.push('"');
+        },
+        &Term::Constant(_, Constant::Usize(n)) =>
+            src.push_str(&format!("{}", n)),
+        &Term::Cons(_, ref head, ref tail) => {
+            src.push('[');
+            write_term(src, head);
+
+            let mut tail = tail.as_ref();
+
+            while let &Term::Cons(_, ref head, ref next) = tail {
+                src.push_str(", ");
+                write_term(src, head);
+                tail = next.as_ref();
+            }
+
+            match tail {
+                &Term::Constant(_, Constant::EmptyList) => {},
+                tail => {
+                    src.push('|');
+                    write_term(src, tail);
+                }
+            };
+
+            src.push(']');
+        },
+        &Term::Clause(_, ref name, ref terms, _) if name.as_str() == "{}" && terms.len() == 1 => {
+            src.push('{');
+            write_term(src, &terms[0]);
+            src.push('}');
+        },
+        &Term::Clause(_, ref name, ref terms, fixity) => {
+            let fixity = match (name.as_str(), terms.len()) {
+                (",", 2) | (";", 2) | ("->", 2) => Some(Fixity::In),
+                (_, 1) | (_, 2) => fixity,
+                _ => None
+            };
+
+            match fixity {
+                Some(Fixity::In) if terms.len() == 2 => {
+                    src.push('(');
+                    write_term(src, &terms[0]);
+                    src.push_str(&format!(" {} ", name));
+                    write_term(src, &terms[1]);
+                    src.push(')');
+                },
+                Some(Fixity::Pre) if terms.len() == 1 => {
+                    src.push_str(&format!("{} (", name));
+                    write_term(src, &terms[0]);
+                    src.push(')');
+                },
+                Some(Fixity::Post) if terms.len() == 1 => {
+                    src.push('(');
+                    write_term(src, &terms[0]);
+                    src.push_str(&format!(") {}", name));
+                },
+                _ => {
+                    write_atom(src, name.as_str());
+                    src.push('(');
+
+                    for (i, term) in terms.iter().enumerate() {
+                        if i > 0 {
+                            src.push_str(", ");
+                        }
+
+                        write_term(src, term);
+                    }
+
+                    src.push(')');
+                }
+            }
+        }
+    }
+}
+
+// reads the next term of parser, its double quoted text read as the
+// double_quotes flag directs.
+fn read_term<R: Read>(wam: &Machine, parser: &mut Parser<R>, op_dir: &OpDir)
+                      -> Result<Term, ParserError>
+{
+    let term = parser.read_term(op_dir)?;
+
+    Ok(match wam.double_quotes() {
+        DoubleQuotes::String => term,
+        double_quotes => convert_strings(term, double_quotes, &wam.atom_tbl())
+    })
+}
+
+// term, its strings read as the double_quotes flag directs.
+fn convert_strings(term: Term, double_quotes: DoubleQuotes, atom_tbl: &TabledData<Atom>) -> Term
+{
+    match term {
+        Term::Constant(cell, Constant::String(string)) => {
+            let to_list = |elements: Vec<Constant>| {
+                elements.into_iter().rev().fold(Term::Constant(Cell::default(), Constant::EmptyList),
+                                                |tail, c| {
+                    let head = Term::Constant(Cell::default(), c);
+                    Term::Cons(Cell::default(), Box::new(head), Box::new(tail))
+                })
+            };
+
+            match double_quotes {
+                DoubleQuotes::Atom =>
+                    Term::Constant(cell, atom!(string.as_str(), atom_tbl)),
+                DoubleQuotes::Chars =>
+                    to_list(string.chars().map(|c| atom!(c.to_string(), atom_tbl)).collect()),
+                DoubleQuotes::Codes =>
+                    to_list(string.chars().map(|c| integer!(c as u32)).collect()),
+                DoubleQuotes::String =>
+                    Term::Constant(cell, Constant::String(string))
+            }
+        },
+        Term::Cons(cell, head, tail) => {
+            let head = convert_strings(*head, double_quotes, atom_tbl);
+            let tail = convert_strings(*tail, double_quotes, atom_tbl);
+
+            Term::Cons(cell, Box::new(head), Box::new(tail))
+        },
+        Term::Clause(cell, name, terms, fixity) => {
+            let terms = terms.into_iter().map(|term| {
+                Box::new(convert_strings(*term, double_quotes, atom_tbl))
+            }).collect();
+
+            Term::Clause(cell, name, terms, fixity)
+        },
+        term => term
+    }
+}
+
+// a float as the reader reads it back: {:?} drops the fraction of
+// floats written with an exponent, as in 1e16.
+fn float_source(fl: f64) -> String
+{
+    let src = format!("{:?}", fl);
+
+    match src.find('e') {
+        Some(i) if !src[.. i].contains('.') => format!("{}.0{}", &src[.. i], &src[i ..]),
+        _ => src
+    }
+}
+
+fn term_source(term: &Term) -> String
+{
+    let mut src = String::new();
+    write_term(&mut src, term);
+    src
+}
+
+// the source of a clause, directive or query.
+fn clause_source(term: &Term) -> String
+{
+    let mut src = String::new();
+
+    match term {
+        &Term::Clause(_, ref name, ref terms, _) if name.as_str() == ":-" && terms.len() == 2 => {
+            write_term(&mut src, &terms[0]);
+            src.push_str(" :- ");
+            write_term(&mut src, &terms[1]);
+        },
+        &Term::Clause(_, ref name, ref terms, _)
+            if (name.as_str() == ":-" || name.as_str() == "?-") && terms.len() == 1 => {
+                src.push_str(&format!("{} ", name));
+                write_term(&mut src, &terms[0]);
+            },
+        _ => write_term(&mut src, term)
+    };
+
+    src.push_str(".\n");
+    src
 }
 
 pub enum Input {
@@ -441,6 +1568,39 @@ pub fn read() -> Input {
     }
 }
 
+pub enum InterruptAction {
+    Abort,
+    Backtrace,
+    Continue,
+    Trace
+}
+
+// asks what to do with a query stopped by SIGINT. end of input
+// aborts the query.
+pub fn read_interrupt_action() -> InterruptAction {
+    let stdin = stdin();
+
+    loop {
+        print!("\nAction (a)bort, (c)ontinue, (t)race, (b)acktrace? ");
+        let _ = stdout().flush();
+
+        let mut buffer = String::new();
+
+        match stdin.read_line(&mut buffer) {
+            Ok(0) | Err(_) => return InterruptAction::Abort,
+            _ => {}
+        };
+
+        match &*buffer.trim() {
+            "a" => return InterruptAction::Abort,
+            "b" => return InterruptAction::Backtrace,
+            "c" => return InterruptAction::Continue,
+            "t" => return InterruptAction::Trace,
+            _   => println!("unknown action.")
+        }
+    }
+}
+
 pub(crate) trait TLInfo {
     fn update_entry_index(&self, &ClauseName, usize, CodeIndex, &mut CodeIndex, usize);
 
@@ -494,20 +1654,28 @@ impl TLInfo for QueryInfo {
 }
 
 // throw errors if declaration or query found.
-fn compile_relation(tl: &TopLevel) -> Result<Code, ParserError>
+pub(crate) fn compile_relation(tl: &TopLevel, modes: Option<&Vec<Mode>>) -> Result<Code, ParserError>
 {
     let mut cg = CodeGenerator::<DebrayAllocator>::new();
+    cg.set_arg_modes(modes);
 
-    match tl {
+    let mut code = match tl {
         &TopLevel::Declaration(_) | &TopLevel::Query(_) =>
-            Err(ParserError::ExpectedRel),
+            return Err(ParserError::ExpectedRel),
         &TopLevel::Predicate(ref clauses) =>
-            cg.compile_predicate(&clauses.0),
+            cg.compile_predicate(&clauses.0)?,
         &TopLevel::Fact(ref fact) =>
-            Ok(cg.compile_fact(fact)),
+            cg.compile_fact(fact),
         &TopLevel::Rule(ref rule) =>
-            cg.compile_rule(rule)
+            cg.compile_rule(rule)?
+    };
+
+    // the declared modes are checked before any clause is tried.
+    if let (Some(modes), Some(name)) = (modes, tl.name()) {
+        code.insert(0, Line::BuiltIn(BuiltInInstruction::CheckModes(name, modes.clone())));
     }
+
+    Ok(code)
 }
 
 // set first jmp_by_call or jmp_by_index instruction to code.len() -
@@ -530,18 +1698,24 @@ fn set_first_index(code: &mut Code)
 
 fn compile_appendix(code: &mut Code, queue: Vec<TopLevel>) -> Result<(), ParserError>
 {
-    for tl in queue.iter() {
+    for mut tl in queue {
+        tl.qualify_goals();
+
         set_first_index(code);
-        code.append(&mut compile_relation(tl)?);
+        code.append(&mut compile_relation(&tl, None)?);
     }
 
     Ok(())
 }
 
-fn compile_query(terms: Vec<QueryTerm>, queue: Vec<TopLevel>, code_size: usize,
+fn compile_query(mut terms: Vec<QueryTerm>, queue: Vec<TopLevel>, code_size: usize,
                  code_dir: &mut CodeDir)
                  -> Result<(Code, AllocVarDict), ParserError>
 {
+    for term in terms.iter_mut() {
+        term.qualify();
+    }
+
     let mut cg = CodeGenerator::<DebrayAllocator>::new();
     let mut code = try!(cg.compile_query(&terms));
 
@@ -553,33 +1727,50 @@ fn compile_query(terms: Vec<QueryTerm>, queue: Vec<TopLevel>, code_size: usize,
     Ok((code, cg.take_vars()))
 }
 
-fn compile_decl(wam: &mut Machine, tl: TopLevel, queue: Vec<TopLevel>) -> EvalSession
+fn compile_decl(wam: &mut Machine, mut tl: TopLevel, queue: Vec<TopLevel>) -> EvalSession
 {
     match tl {
         TopLevel::Declaration(Declaration::Op(op_decl)) => {
-            try_eval_session!(op_decl.submit(clause_name!("user"), &mut wam.op_dir));
+            let module_name = wam.toplevel_module();
+            try_eval_session!(op_decl.submit(module_name, wam.toplevel_dirs().1));
             EvalSession::EntrySuccess
         },
-        TopLevel::Declaration(Declaration::UseModule(name)) =>
+        // the toplevel exports nothing, so it reexports as it imports.
+        TopLevel::Declaration(Declaration::UseModule(name))
+      | TopLevel::Declaration(Declaration::Reexport(name)) =>
             wam.use_module_in_toplevel(name),
-        TopLevel::Declaration(Declaration::UseQualifiedModule(name, exports)) =>
-            wam.use_qualified_module_in_toplevel(name, exports),
+        TopLevel::Declaration(Declaration::UseQualifiedModule(name, imports))
+      | TopLevel::Declaration(Declaration::QualifiedReexport(name, imports)) =>
+            wam.use_qualified_module_in_toplevel(name, imports),
+        TopLevel::Declaration(Declaration::MetaPredicate(meta_decls)) => {
+            for (name, specs) in meta_decls {
+                wam.toplevel_dirs().2.insert((name, specs.len()), specs);
+            }
+
+            EvalSession::EntrySuccess
+        },
         TopLevel::Declaration(_) =>
             EvalSession::from(ParserError::InvalidModuleDecl),
         _ => {
+            tl.qualify_goals();
+
             let name = try_eval_session!(if let Some(name) = tl.name() {
                 Ok(name)
             } else {
                 Err(EvalError::NamelessEntry)
             });
 
-            let mut code = try_eval_session!(compile_relation(&tl));
+            let modes = wam.modes(&wam.toplevel_module(), &(name.clone(), tl.arity())).cloned();
+
+            let mut code = try_eval_session!(compile_relation(&tl, modes.as_ref()));
             try_eval_session!(compile_appendix(&mut code, queue));
 
             let decl_info = DeclInfo { name: name.clone(), arity: tl.arity(),
-                                       module_name: clause_name!("user") };
+                                       module_name: wam.toplevel_module() };
 
-            decl_info.label_clauses(wam.code_size(), &mut wam.code_dir, &mut code);
+            try_eval_session!(wam.define_in_toplevel(vec![(name.clone(), tl.arity())]));
+            let code_size = wam.code_size();
+            decl_info.label_clauses(code_size, wam.toplevel_dirs().0, &mut code);
 
             if !code.is_empty() {
                 wam.add_user_code(name, tl.arity(), code, tl.as_predicate().ok().unwrap())
@@ -592,33 +1783,99 @@ fn compile_decl(wam: &mut Machine, tl: TopLevel, queue: Vec<TopLevel>) -> EvalSe
 
 pub fn compile_packet(wam: &mut Machine, tl: TopLevelPacket) -> EvalSession
 {
+    wam.compact_code();
+
     match tl {
         TopLevelPacket::Query(terms, queue) =>
-            match compile_query(terms, queue, wam.code_size(), &mut wam.code_dir) {
+            match compile_query(terms, queue, wam.code_size(), wam.toplevel_dirs().0) {
                 Ok((mut code, vars)) => wam.submit_query(code, vars),
                 Err(e) => EvalSession::from(e)
             },
         TopLevelPacket::Decl(tl, queue) =>
-            compile_decl(wam, tl, queue)
+            match compile_decl(wam, tl, queue) {
+                EvalSession::Error(err) => wam.load_error(err),
+                result => result
+            }
     }
 }
 
 pub fn compile_listing(wam: &mut Machine, src_str: &str) -> EvalSession
 {
-    fn get_module_name(module: &Option<Module>) -> ClauseName {
+    wam.compact_code();
+    compile_file(wam, src_str, None)
+}
+
+// compiles a listing, recording the file it was read from, if any, as
+// the file of the module it declares.
+pub fn compile_file(wam: &mut Machine, src_str: &str, file: Option<ClauseName>) -> EvalSession
+{
+    match compile_batch(wam, src_str, file) {
+        EvalSession::Error(err) => wam.load_error(err),
+        result => result
+    }
+}
+
+fn compile_batch(wam: &mut Machine, src_str: &str, file: Option<ClauseName>) -> EvalSession
+{
+    fn get_module_name(module: &Option<Module>, toplevel: &ClauseName) -> ClauseName {
         match module {
             &Some(ref module) => module.module_decl.name.clone(),
-            _ => ClauseName::BuiltIn("user")
+            _ => toplevel.clone()
         }
     }
 
     let mut module: Option<Module> = None;
     let (mut code_dir, mut op_dir) = build_code_and_op_dirs();
+    let mut term_dir = TermDir::new();
+
+    let mut messages = vec![];
+    let conflicts = wam.import_conflicts();
+    let toplevel = wam.toplevel_module();
 
     let mut code = Vec::new();
 
-    let mut worker = TopLevelWorker::new(src_str.as_bytes(), wam.atom_tbl());
-    let tls = try_eval_session!(worker.parse_batch(&mut op_dir));
+    // the batch is read from its expansion if it has conditional
+    // directives or declarations read ahead of the parser, or any
+    // expansion hooks are defined.
+    let (_, mut expansion_op_dir) = build_code_and_op_dirs();
+    add_directive_ops(&mut expansion_op_dir);
+
+    let segments = match try_eval_session!(expand_batch(wam, src_str, &mut expansion_op_dir)) {
+        Some(segments) => segments,
+        None => vec![BatchSegment::Source(String::from(src_str))]
+    };
+
+    let mut tls = vec![];
+
+    for segment in segments {
+        match segment {
+            BatchSegment::Source(src) => {
+                let mut worker = TopLevelWorker::new(src.as_bytes(), wam.atom_tbl());
+                let packets = try_eval_session!(worker.parse_batch(&mut op_dir));
+
+                tls.extend(try_eval_session!(expand_dcg_rules(wam, packets, &mut op_dir)));
+            },
+            BatchSegment::Decl(decl) =>
+                tls.push(TopLevelPacket::Decl(TopLevel::Declaration(decl), vec![]))
+        }
+    }
+
+    // the libraries named by the imports of the batch are loaded
+    // ahead of them.
+    for tl in &tls {
+        if let &TopLevelPacket::Decl(TopLevel::Declaration(ref decl), _) = tl {
+            match decl {
+                &Declaration::UseModule(ref name)
+              | &Declaration::UseQualifiedModule(ref name, _)
+              | &Declaration::Reexport(ref name)
+              | &Declaration::QualifiedReexport(ref name, _) =>
+                    if let EvalSession::Error(err) = wam.load_library(name) {
+                        return EvalSession::Error(err);
+                    },
+                _ => {}
+            }
+        }
+    }
 
     for tl in tls {
         match tl {
@@ -626,45 +1883,107 @@ pub fn compile_listing(wam: &mut Machine, src_str: &str) -> EvalSession
                 return EvalSession::from(ParserError::ExpectedRel),
             TopLevelPacket::Decl(TopLevel::Declaration(Declaration::Module(module_decl)), _) =>
                 if module.is_none() {
-                    let (builtin_code_dir, builtin_op_dir) = build_code_and_op_dirs();
-
-                    code_dir.extend(builtin_code_dir.into_iter());
+                    let (_, builtin_op_dir) = build_code_and_op_dirs();
                     op_dir.extend(builtin_op_dir.into_iter());
 
-                    module = Some(Module::new(module_decl));
+                    // the clauses of a module are labelled in its own
+                    // code dir, alongside its imports.
+                    let mut new_module = Module::new(module_decl);
+                    new_module.code_dir.extend(code_dir.drain());
+
+                    module = Some(new_module);
                 } else {
                     return EvalSession::from(ParserError::InvalidModuleDecl);
                 },
             TopLevelPacket::Decl(TopLevel::Declaration(Declaration::UseModule(name)), _) => {
                 if let Some(ref submodule) = wam.get_module(name.clone()) {
                     if let Some(ref mut module) = module {
-                        module.use_module(submodule);
+                        let mut imports = ModuleImports { module, messages: &mut messages,
+                                                          conflicts };
+
+                        if let EvalSession::Error(err) = imports.use_module(submodule) {
+                            return EvalSession::Error(err);
+                        }
+
                         continue;
                     }
                 } else {
                     return EvalSession::from(EvalError::ModuleNotFound);
                 }
 
-                wam.use_module_in_toplevel(name);
+                if let EvalSession::Error(err) = wam.use_module_in_toplevel(name) {
+                    return EvalSession::Error(err);
+                }
             },
-            TopLevelPacket::Decl(TopLevel::Declaration(Declaration::UseQualifiedModule(name, exports)), _) => {
+            TopLevelPacket::Decl(TopLevel::Declaration(Declaration::UseQualifiedModule(name, imports)), _) => {
                 if let Some(ref submodule) = wam.get_module(name.clone()) {
                     if let Some(ref mut module) = module {
-                        module.use_qualified_module(submodule, exports);
+                        let mut module_imports = ModuleImports { module, messages: &mut messages,
+                                                                 conflicts };
+
+                        if let EvalSession::Error(err) =
+                            module_imports.use_qualified_module(submodule, imports)
+                        {
+                            return EvalSession::Error(err);
+                        }
+
                         continue;
                     }
                 } else {
                     return EvalSession::from(EvalError::ModuleNotFound);
                 }
 
-                wam.use_qualified_module_in_toplevel(name, exports);
+                if let EvalSession::Error(err) = wam.use_qualified_module_in_toplevel(name, imports) {
+                    return EvalSession::Error(err);
+                }
             },
-            TopLevelPacket::Decl(TopLevel::Declaration(Declaration::Op(..)), _) => {},
-            TopLevelPacket::Decl(decl, queue) => {
-                let p = code.len() + wam.code_size();
-                let mut decl_code = try_eval_session!(compile_relation(&decl));
+            TopLevelPacket::Decl(TopLevel::Declaration(Declaration::Reexport(name)), _) => {
+                if let Some(ref submodule) = wam.get_module(name.clone()) {
+                    if let Some(ref mut module) = module {
+                        let mut imports = ModuleImports { module, messages: &mut messages,
+                                                          conflicts };
 
-                try_eval_session!(compile_appendix(&mut decl_code, queue));
+                        try_eval_session!(imports.reexport_module(submodule));
+                        continue;
+                    }
+                } else {
+                    return EvalSession::from(EvalError::ModuleNotFound);
+                }
+
+                if let EvalSession::Error(err) = wam.use_module_in_toplevel(name) {
+                    return EvalSession::Error(err);
+                }
+            },
+            TopLevelPacket::Decl(TopLevel::Declaration(Declaration::QualifiedReexport(name, imports)), _) => {
+                if let Some(ref submodule) = wam.get_module(name.clone()) {
+                    if let Some(ref mut module) = module {
+                        let mut module_imports = ModuleImports { module, messages: &mut messages,
+                                                                 conflicts };
+
+                        try_eval_session!(module_imports.reexport_qualified_module(submodule,
+                                                                                   imports));
+                        continue;
+                    }
+                } else {
+                    return EvalSession::from(EvalError::ModuleNotFound);
+                }
+
+                if let EvalSession::Error(err) = wam.use_qualified_module_in_toplevel(name, imports) {
+                    return EvalSession::Error(err);
+                }
+            },
+            TopLevelPacket::Decl(TopLevel::Declaration(Declaration::MetaPredicate(meta_decls)), _) =>
+                for (name, specs) in meta_decls {
+                    let key = (name, specs.len());
+
+                    match module {
+                        Some(ref mut module) => module.meta_predicates.insert(key, specs),
+                        None => wam.toplevel_dirs().2.insert(key, specs)
+                    };
+                },
+            TopLevelPacket::Decl(TopLevel::Declaration(Declaration::Op(..)), _) => {},
+            TopLevelPacket::Decl(mut decl, queue) => {
+                decl.qualify_goals();
 
                 let name = try_eval_session!(if let Some(name) = decl.name() {
                     Ok(name)
@@ -672,34 +1991,86 @@ pub fn compile_listing(wam: &mut Machine, src_str: &str) -> EvalSession
                     Err(EvalError::NamelessEntry)
                 });
 
-                let module_name = get_module_name(&module);
+                let module_name = get_module_name(&module, &toplevel);
+                let arity = decl.arity();
 
-                let decl_info = DeclInfo { name, arity: decl.arity(), module_name };
-                decl_info.label_clauses(p, &mut code_dir, &mut decl_code);
+                let modes = wam.modes(&module_name, &(name.clone(), arity)).cloned();
+
+                let p = code.len() + wam.code_size();
+                let mut decl_code = try_eval_session!(compile_relation(&decl, modes.as_ref()));
+
+                try_eval_session!(compile_appendix(&mut decl_code, queue));
+
+                // clauses are labelled in the code dir of their module,
+                // or of the toplevel, so they share the cells of the
+                // predicates imported there.
+                let decl_code_dir = match module {
+                    Some(ref mut module) => {
+                        try_eval_session!(ModuleImports { module: &mut *module,
+                                                          messages: &mut messages,
+                                                          conflicts }
+                                          .define_local(name.clone(), arity));
+
+                        &mut module.code_dir
+                    },
+                    None => {
+                        try_eval_session!(wam.define_in_toplevel(vec![(name.clone(), arity)]));
+                        wam.toplevel_dirs().0
+                    }
+                };
+
+                let decl_info = DeclInfo { name, arity, module_name };
+                decl_info.label_clauses(p, decl_code_dir, &mut decl_code);
 
                 code.extend(decl_code.into_iter());
 
-                let index = CodeIndex::default();
-                code_dir.insert((decl_info.name.clone(), decl_info.arity), index);
+                let key = (decl_info.name.clone(), decl_info.arity);
+                let idx = decl_code_dir.entry(key).or_insert(CodeIndex::default());
+
+                idx.0.set(IndexPtr::Index(p));
+                idx.1 = decl_info.module_name.clone();
+
+                if let Ok(pred) = decl.as_predicate() {
+                    term_dir.insert((decl_info.name, decl_info.arity), pred);
+                }
             }
         }
     }
 
     if let Some(mut module) = module {
-        module.code_dir.extend(code_dir.into_iter());
         module.op_dir.extend(op_dir.into_iter());
+        module.term_dir = term_dir;
+        module.file = file;
 
         wam.add_module(module, code);
     } else {
-        wam.add_batched_code(code, code_dir);
+        wam.add_batched_code(code, term_dir, file);
         wam.add_batched_ops(op_dir);
     }
 
+    wam.print_messages(messages);
+
     EvalSession::EntrySuccess
 }
 
 fn error_string(e: &String) -> String {
-    format!("error: exception thrown: {}", e)
+    format!("exception thrown: {}", e)
+}
+
+// an uncaught exception, followed by the predicates that were active
+// when it was thrown.
+fn exception_string(e: &String, backtrace: &Vec<String>) -> String {
+    let mut msg = error_string(e);
+
+    if !backtrace.is_empty() {
+        msg += "\n% backtrace:";
+
+        for (depth, frame) in backtrace.iter().enumerate() {
+            msg += &format!("\n%   [{}] {}", depth, frame);
+        }
+    }
+
+    msg
 }
 
 pub fn print(wam: &mut Machine, result: EvalSession) {
@@ -754,9 +2125,20 @@ pub fn print(wam: &mut Machine, result: EvalSession) {
                         return;
                     }
 
-                    if let &EvalSession::Error(EvalError::QueryFailureWithException(ref e)) = &result
+                    if let EvalSession::Error(EvalError::QueryFailureWithException(..)) = result
+                    {
+                        drop(stdout);
+
+                        if let EvalSession::Error(e) = result {
+                            wam.print_message(Message::Error(e));
+                        }
+
+                        return;
+                    }
+
+                    if let &EvalSession::Error(EvalError::QueryAborted) = &result
                     {
-                        write!(stdout, "{}\n\r", error_string(e)).unwrap();
+                        write!(stdout, "{}\n\r", EvalError::QueryAborted).unwrap();
                         stdout.flush().unwrap();
                         return;
                     }
@@ -767,7 +2149,9 @@ pub fn print(wam: &mut Machine, result: EvalSession) {
 
             write!(stdout(), ".\n").unwrap();
         },
-        EvalSession::Error(e) => println!("{}", e),
+        EvalSession::Error(e @ EvalError::QueryFailure) => println!("{}", e),
+        EvalSession::Error(e @ EvalError::QueryAborted) => println!("{}", e),
+        EvalSession::Error(e) => wam.print_message(Message::Error(e)),
         _ => {}
     };
 }
@@ -1,7 +1,10 @@
-:- module(control, [(\=)/2, between/3, call_cleanup/2, once/1, repeat/0]).
+:- module(control, [(\=)/2, between/3, call_cleanup/2,
+		    catch_with_backtrace/3, once/1, repeat/0, time/1]).
 
 :- op(700, xfx, \=).
 
+:- meta_predicate once(0), call_cleanup(0, 0), catch_with_backtrace(0, ?, 0), time(0).
+
 once(G) :- G, !.
 
 X \= X :- !, false.
@@ -18,3 +21,35 @@ between(Lower1, Upper, X) :-
 
 repeat.
 repeat :- repeat.
+
+time(G) :-
+    statistics(inferences, I0),
+    statistics(cputime, T0),
+    statistics(walltime, [W0, _]),
+    (   catch(G, E, ('$report_time'(I0, T0, W0), '$rethrow'(E))),
+        '$report_time'(I0, T0, W0)
+    ;   '$report_time'(I0, T0, W0),
+        false
+    ).
+
+'$report_time'(I0, T0, W0) :-
+    '$time_message'(I0, T0, W0, Message),
+    print_message(informational, Message).
+
+catch_with_backtrace(G, Ball, Recovery) :-
+    catch(G, Ball0, '$handle_backtrace'(Ball0, Ball, Recovery)).
+
+'$handle_backtrace'(Ball0, Ball, Recovery) :-
+    (   \+ Ball0 = Ball ->
+	'$rethrow'(Ball0)
+    ;   '$backtrace'(Frames),
+	'$add_backtrace'(Ball0, Frames, Ball1),
+	(   Ball1 = Ball -> true
+	;   Ball0 = Ball
+	),
+	Recovery
+    ).
+
+'$add_backtrace'(error(Formal, Context), Frames,
+		 error(Formal, backtrace(Context, Frames))) :- !.
+'$add_backtrace'(Ball, _, Ball).
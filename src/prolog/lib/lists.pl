@@ -1,6 +1,11 @@
 :- module(lists, [member/2, select/3, append/3, is_list/1, memberchk/2, reverse/2, maplist/2,
 		  maplist/3, maplist/4, maplist/5, maplist/6, maplist/7, maplist/8, maplist/9]).
 
+:- meta_predicate maplist(1, ?), maplist(2, ?, ?), maplist(3, ?, ?, ?),
+		  maplist(4, ?, ?, ?, ?), maplist(5, ?, ?, ?, ?, ?),
+		  maplist(6, ?, ?, ?, ?, ?, ?), maplist(7, ?, ?, ?, ?, ?, ?, ?),
+		  maplist(8, ?, ?, ?, ?, ?, ?, ?, ?).
+
 member(X, [X|_]).
 member(X, [_|Xs]) :- member(X, Xs).
 
@@ -0,0 +1,263 @@
+use prolog::ast::*;
+use prolog::num::bigint::BigInt;
+use prolog::tabled_rc::*;
+
+use std::rc::Rc;
+
+// the largest arity of a term passed in registers, of which the
+// machine has 64.
+pub(crate) const MAX_ARITY: usize = 63;
+
+// the heap cells of a thrown ball, laid out from the top of the heap.
+pub(crate) type MachineStub = Vec<HeapCellValue>;
+
+// what an error complains about: either a term already on the heap,
+// or a predicate indicator laid out alongside the error.
+#[derive(Clone)]
+pub(crate) enum ErrorCulprit {
+    Term(Addr),
+    Indicator(ClauseName, usize),
+    QualifiedIndicator(ClauseName, ClauseName, usize) // module, name, arity.
+}
+
+// the formal half of an ISO error term, error(Formal, Context).
+#[derive(Clone)]
+pub(crate) enum MachineError {
+    Instantiation,
+    Uninstantiation(Addr),
+    Type(&'static str, ErrorCulprit),
+    Domain(&'static str, ErrorCulprit),
+    Existence(&'static str, ErrorCulprit),
+    Permission(&'static str, &'static str, ErrorCulprit), // the action, and the type of object.
+    ImportInto(ClauseName, ErrorCulprit), // the importing module, and the procedure.
+    Representation(&'static str),
+    Evaluation(&'static str),
+    Resource(&'static str),
+    OccursCheck(Addr, Addr) // the variable, and the term it occurs in.
+}
+
+// the cells of a self-contained term laid out at from, moved to to.
+pub(crate) fn relocate_term(cells: &[HeapCellValue], from: usize, to: usize) -> MachineStub {
+    cells.iter().cloned().map(|cell| match cell {
+        HeapCellValue::Addr(Addr::Lis(l)) =>
+            HeapCellValue::Addr(Addr::Lis(l - from + to)),
+        HeapCellValue::Addr(Addr::HeapCell(h)) =>
+            HeapCellValue::Addr(Addr::HeapCell(h - from + to)),
+        HeapCellValue::Addr(Addr::Str(s)) =>
+            HeapCellValue::Addr(Addr::Str(s - from + to)),
+        cell => cell
+    }).collect()
+}
+
+fn indicator(name: ClauseName, arity: usize) -> MachineStub {
+    let name = HeapCellValue::Addr(Addr::Con(Constant::Atom(name)));
+    functor!("/", 2, [name, heap_integer!(arity)], Fixity::In)
+}
+
+impl ErrorCulprit {
+    // the cell naming the culprit in the formal term, followed by
+    // the cells of the culprit itself when they are laid out at h.
+    fn layout(self, h: usize) -> (HeapCellValue, MachineStub) {
+        match self {
+            ErrorCulprit::Term(addr) => (HeapCellValue::Addr(addr), vec![]),
+            ErrorCulprit::Indicator(name, arity) => (heap_str!(h), indicator(name, arity)),
+            ErrorCulprit::QualifiedIndicator(module_name, name, arity) => {
+                let module_name = HeapCellValue::Addr(Addr::Con(Constant::Atom(module_name)));
+
+                let mut cells = functor!(":", 2, [module_name, heap_str!(h + 3)], Fixity::In);
+                cells.append(&mut indicator(name, arity));
+
+                (heap_str!(h), cells)
+            }
+        }
+    }
+}
+
+impl MachineError {
+    pub(crate) fn type_error(valid_type: &'static str, culprit: Addr) -> Self {
+        MachineError::Type(valid_type, ErrorCulprit::Term(culprit))
+    }
+
+    pub(crate) fn domain_error(valid_domain: &'static str, culprit: Addr) -> Self {
+        MachineError::Domain(valid_domain, ErrorCulprit::Term(culprit))
+    }
+
+    pub(crate) fn permission_error(action: &'static str, object_type: &'static str,
+                                   culprit: Addr)
+                                   -> Self
+    {
+        MachineError::Permission(action, object_type, ErrorCulprit::Term(culprit))
+    }
+
+    pub(crate) fn existence_error(object_type: &'static str, culprit: Addr) -> Self {
+        MachineError::Existence(object_type, ErrorCulprit::Term(culprit))
+    }
+
+    pub(crate) fn evaluable(name: ClauseName, arity: usize) -> Self {
+        MachineError::Type("evaluable", ErrorCulprit::Indicator(name, arity))
+    }
+
+    pub(crate) fn procedure_existence(name: ClauseName, arity: usize) -> Self {
+        MachineError::Existence("procedure", ErrorCulprit::Indicator(name, arity))
+    }
+
+    pub(crate) fn static_procedure_modification(name: ClauseName, arity: usize) -> Self {
+        MachineError::Permission("modify", "static_procedure", ErrorCulprit::Indicator(name, arity))
+    }
+
+    // permission_error(import_into(Importer), procedure, Module:Name/Arity).
+    pub(crate) fn import_into(importer: ClauseName, module_name: ClauseName, name: ClauseName,
+                              arity: usize)
+                              -> Self
+    {
+        let culprit = ErrorCulprit::QualifiedIndicator(module_name, name, arity);
+        MachineError::ImportInto(importer, culprit)
+    }
+
+    pub(crate) fn qualified_procedure_existence(module_name: ClauseName, name: ClauseName,
+                                                arity: usize)
+                                                -> Self
+    {
+        let culprit = ErrorCulprit::QualifiedIndicator(module_name, name, arity);
+        MachineError::Existence("procedure", culprit)
+    }
+
+    pub(crate) fn occurs_check(var: Addr, term: Addr) -> Self {
+        MachineError::OccursCheck(var, term)
+    }
+
+    fn formal(self, h: usize) -> MachineStub {
+        let (name, kind, culprit) = match self {
+            MachineError::Instantiation =>
+                return functor!("instantiation_error"),
+            MachineError::Uninstantiation(culprit) =>
+                return functor!("uninstantiation_error", 1, [HeapCellValue::Addr(culprit)]),
+            MachineError::Representation(flag) =>
+                return functor!("representation_error", 1, [heap_atom!(flag)]),
+            MachineError::Evaluation(error) =>
+                return functor!("evaluation_error", 1, [heap_atom!(error)]),
+            MachineError::Resource(resource) =>
+                return functor!("resource_error", 1, [heap_atom!(resource)]),
+            MachineError::OccursCheck(var, term) =>
+                return functor!("occurs_check", 2, [HeapCellValue::Addr(var),
+                                                    HeapCellValue::Addr(term)]),
+            MachineError::Permission(action, object_type, culprit) => {
+                let (culprit, mut culprit_cells) = culprit.layout(h + 4);
+
+                let mut stub = functor!("permission_error", 3, [heap_atom!(action),
+                                                                heap_atom!(object_type),
+                                                                culprit]);
+                stub.append(&mut culprit_cells);
+
+                return stub;
+            },
+            MachineError::ImportInto(importer, culprit) => {
+                let importer = HeapCellValue::Addr(Addr::Con(Constant::Atom(importer)));
+                let (culprit, mut culprit_cells) = culprit.layout(h + 6);
+
+                let mut stub = functor!("permission_error", 3, [heap_str!(h + 4),
+                                                                heap_atom!("procedure"),
+                                                                culprit]);
+
+                stub.append(&mut functor!("import_into", 1, [importer]));
+                stub.append(&mut culprit_cells);
+
+                return stub;
+            },
+            MachineError::Type(valid_type, culprit) =>
+                ("type_error", valid_type, culprit),
+            MachineError::Domain(valid_domain, culprit) =>
+                ("domain_error", valid_domain, culprit),
+            MachineError::Existence(object_type, culprit) =>
+                ("existence_error", object_type, culprit)
+        };
+
+        let (culprit, mut culprit_cells) = culprit.layout(h + 3);
+
+        let mut stub = functor!(name, 2, [heap_atom!(kind), culprit]);
+        stub.append(&mut culprit_cells);
+
+        stub
+    }
+
+    // lays out error(Formal, Name/Arity) at h, where Name/Arity is
+    // the predicate raising the error.
+    pub(crate) fn to_stub(self, h: usize, name: ClauseName, arity: usize) -> MachineStub {
+        let formal = self.formal(h + 6);
+
+        let mut stub = functor!("error", 2, [HeapCellValue::Addr(formal[0].as_addr(h + 6)),
+                                             heap_str!(h + 3)]);
+
+        stub.append(&mut indicator(name, arity));
+        stub.extend(formal.into_iter());
+
+        stub
+    }
+
+    // lays out error(Formal, _) at h, for errors raised outside of
+    // any known predicate.
+    pub(crate) fn to_stub_in_unknown_context(self, h: usize) -> MachineStub {
+        let formal = self.formal(h + 3);
+
+        let mut stub = functor!("error", 2, [HeapCellValue::Addr(formal[0].as_addr(h + 3)),
+                                             HeapCellValue::Addr(Addr::HeapCell(h + 2))]);
+
+        stub.extend(formal.into_iter());
+        stub
+    }
+}
+
+impl Message {
+    // lays out the term of the message at h, as passed to print_message/2.
+    pub(crate) fn to_stub(self, h: usize, atom_tbl: TabledData<Atom>) -> MachineStub {
+        match self {
+            Message::Error(EvalError::QueryFailureWithException(_, backtrace, ball)) => {
+                let l = h + 3 + ball.len();
+
+                let mut stub = functor!("uncaught_exception", 2, [HeapCellValue::Addr(Addr::HeapCell(h + 3)),
+                                                                  HeapCellValue::Addr(Addr::HeapCell(l))]);
+
+                stub.append(&mut relocate_term(&ball, 0, h + 3));
+
+                for frame in backtrace {
+                    let l = h + stub.len();
+
+                    stub.push(HeapCellValue::Addr(Addr::Lis(l + 1)));
+                    stub.push(heap_atom!(frame, atom_tbl));
+                }
+
+                stub.push(HeapCellValue::Addr(Addr::Con(Constant::EmptyList)));
+                stub
+            },
+            Message::Error(err) =>
+                functor!("eval_error", 1, [heap_atom!(format!("{}", err), atom_tbl)]),
+            Message::CreatedModule(module_name) => {
+                let module_name = HeapCellValue::Addr(Addr::Con(Constant::Atom(module_name)));
+                functor!("created_module", 1, [module_name])
+            },
+            Message::ImportOverwrite(name, arity, module_name) => {
+                let module_name = HeapCellValue::Addr(Addr::Con(Constant::Atom(module_name)));
+
+                let mut stub = functor!("import_overwrite", 2, [heap_str!(h + 3), module_name]);
+                stub.append(&mut indicator(name, arity));
+
+                stub
+            },
+            Message::InvalidOptionValue(option, expected) =>
+                functor!("invalid_option_value", 2, [heap_atom!(option, atom_tbl),
+                                                     heap_atom!(expected)]),
+            Message::LostImport(importer, name, arity, module_name) => {
+                let importer = HeapCellValue::Addr(Addr::Con(Constant::Atom(importer)));
+                let module_name = HeapCellValue::Addr(Addr::Con(Constant::Atom(module_name)));
+
+                let mut stub = functor!("lost_import", 3, [importer, heap_str!(h + 4),
+                                                           module_name]);
+                stub.append(&mut indicator(name, arity));
+
+                stub
+            },
+            Message::UnknownOption(option) =>
+                functor!("unknown_option", 1, [heap_atom!(option, atom_tbl)])
+        }
+    }
+}
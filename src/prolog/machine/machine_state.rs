@@ -1,44 +1,217 @@
 use prolog::and_stack::*;
 use prolog::ast::*;
 use prolog::copier::*;
-use prolog::num::{BigInt, BigUint, Zero, One};
+use prolog::machine::machine_errors::*;
+use prolog::num::{BigInt, BigUint};
 use prolog::or_stack::*;
 use prolog::heap_print::*;
 use prolog::tabled_rc::*;
 
 use downcast::Any;
+use libc;
 
-use std::cmp::Ordering;
+use std::cmp::{max, Ordering};
 use std::collections::HashMap;
+use std::mem;
 use std::mem::swap;
 use std::ops::{Index, IndexMut};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 pub(crate) struct CodeDirs<'a> {
     code_dir: &'a CodeDir,
-    modules: &'a HashMap<ClauseName, Module>
+    op_dir: &'a mut OpDir,
+    meta_predicates: &'a MetaPredicateDir,
+    term_dir: &'a TermDir,
+    user_files: &'a HashMap<PredicateKey, ClauseName>,
+    modules: &'a HashMap<ClauseName, Module>,
+    code_size: usize
 }
 
 impl<'a> CodeDirs<'a> {
-    pub(super) fn new(code_dir: &'a CodeDir, modules: &'a HashMap<ClauseName, Module>) -> Self {
-        CodeDirs { code_dir, modules }
+    pub(super) fn new(code_dir: &'a CodeDir, op_dir: &'a mut OpDir,
+                      meta_predicates: &'a MetaPredicateDir, term_dir: &'a TermDir,
+                      user_files: &'a HashMap<PredicateKey, ClauseName>,
+                      modules: &'a HashMap<ClauseName, Module>, code_size: usize)
+                      -> Self
+    {
+        CodeDirs { code_dir, op_dir, meta_predicates, term_dir, user_files, modules, code_size }
     }
 
-    fn get_current_code_dir(&self, p: &CodePtr) -> &CodeDir {
-        let module_name = p.module_name();
+    pub(super) fn meta_specs(&self, module_name: &ClauseName, key: &PredicateKey)
+                             -> Option<&Vec<MetaSpec>>
+    {
+        match module_name.as_str() {
+            "user" | "builtin" => self.meta_predicates.get(key),
+            _ => self.modules.get(module_name)
+                     .and_then(|module| module.meta_predicates.get(key))
+        }
+    }
 
+    pub(super) fn get(&self, name: ClauseName, arity: usize, module_name: &ClauseName)
+                      -> Option<CodeIndex>
+    {
+        self.get_module_code_dir(module_name)
+            .and_then(|code_dir| code_dir.get(&(name, arity)).cloned())
+    }
+
+    fn get_module_code_dir(&self, module_name: &ClauseName) -> Option<&CodeDir> {
+        match module_name.as_str() {
+            "user" | "builtin" => Some(self.code_dir),
+            _ => self.modules.get(module_name).map(|module| &module.code_dir)
+        }
+    }
+
+    // resolves calls qualified by a module name through that module,
+    // whether or not it exports the predicate, and others through the
+    // context module.
+    pub(super) fn get_qualified(&self, module_name: Option<ClauseName>, name: ClauseName,
+                                arity: usize, context: &ClauseName)
+                                -> Option<CodeIndex>
+    {
         match module_name {
-            ClauseName::BuiltIn("user") | ClauseName::BuiltIn("builtin") => self.code_dir,
-            _ => &self.modules.get(&module_name).unwrap().code_dir
+            Some(module_name) => self.get(name, arity, &module_name),
+            None => self.get(name, arity, context)
+        }
+    }
+
+    // builtin and user, followed by the loaded modules in order of name.
+    pub(super) fn module_names(&self) -> Vec<ClauseName> {
+        let mut module_names: Vec<_> = self.modules.keys().cloned().collect();
+        module_names.sort();
+
+        let mut names = vec![clause_name!("builtin"), clause_name!("user")];
+        names.extend(module_names.into_iter());
+
+        names
+    }
+
+    // the predicates visible in a module, paired with the modules
+    // defining them. internal builtins, whose names begin with '$',
+    // are hidden.
+    pub(super) fn visible_predicates(&self, module_name: &ClauseName)
+                                     -> Vec<(PredicateKey, ClauseName)>
+    {
+        let code_dir = match self.get_module_code_dir(module_name) {
+            Some(code_dir) => code_dir,
+            None => return vec![]
+        };
+
+        let mut preds: Vec<_> = code_dir.iter()
+            .filter(|&(&(ref name, _), ref idx)| match idx.1.as_str() {
+                "builtin" => !name.as_str().starts_with('$'),
+                _ => module_name.as_str() != "builtin"
+            })
+            .map(|(key, idx)| (key.clone(), idx.1.clone()))
+            .collect();
+
+        preds.sort_by(|&(ref k1, _), &(ref k2, _)| k1.cmp(k2));
+        preds
+    }
+
+    // the predicates a module exports. those of builtin are its
+    // visible predicates.
+    pub(super) fn module_exports(&self, module_name: &ClauseName) -> Vec<PredicateKey> {
+        match module_name.as_str() {
+            "builtin" => self.visible_predicates(module_name).into_iter()
+                             .filter(|&(_, ref def_module)| def_module.as_str() == "builtin")
+                             .map(|(key, _)| key)
+                             .collect(),
+            "user" => vec![],
+            _ => match self.modules.get(module_name) {
+                Some(module) => module.module_decl.exports.iter()
+                                      .map(|&(ref name, arity)| {
+                                          (name.clone().defrock_brackets(), arity)
+                                      })
+                                      .collect(),
+                None => vec![]
+            }
+        }
+    }
+
+    pub(super) fn module_file(&self, module_name: &ClauseName) -> Option<ClauseName> {
+        self.modules.get(module_name).and_then(|module| module.file.clone())
+    }
+
+    // the file a predicate was consulted from: that of its module, or
+    // for user, the file its clauses were last loaded from.
+    pub(super) fn predicate_file(&self, module_name: &ClauseName, key: &PredicateKey)
+                                 -> Option<ClauseName>
+    {
+        match module_name.as_str() {
+            "user" => self.user_files.get(key).cloned(),
+            _ => self.module_file(module_name)
+        }
+    }
+
+    // the clause count of a predicate, known for those whose terms
+    // are kept by the module defining them.
+    pub(super) fn number_of_clauses(&self, module_name: &ClauseName, key: &PredicateKey)
+                                    -> Option<usize>
+    {
+        let term_dir = match module_name.as_str() {
+            "user" => Some(self.term_dir),
+            _ => self.modules.get(module_name).map(|module| &module.term_dir)
+        };
+
+        term_dir.and_then(|term_dir| term_dir.get(key)).map(|pred| pred.0.len())
+    }
+
+    pub(super) fn predicate_at(&self, p: usize) -> Option<PredicateKey> {
+        predicate_at(self.code_dir, self.modules, p)
+    }
+
+    // the live operators of a module, in order of name and fixity.
+    // those of user are live everywhere, unless the module redefines
+    // them.
+    pub(super) fn ops(&self, module_name: &ClauseName)
+                      -> Vec<(OpDirKey, (Specifier, usize, ClauseName))>
+    {
+        let mut op_dir = self.op_dir.clone();
+
+        if let Some(module) = self.modules.get(module_name) {
+            op_dir.extend(module.op_dir.iter().map(|(key, op)| (key.clone(), op.clone())));
         }
+
+        let mut ops: Vec<_> = op_dir.into_iter().collect();
+
+        ops.sort_by(|&((ref n1, f1), _), &((ref n2, f2), _)| {
+            n1.cmp(n2).then((f1 as usize).cmp(&(f2 as usize)))
+        });
+
+        ops
     }
 
-    pub(super) fn get(&self, name: ClauseName, arity: usize, p: &CodePtr) -> Option<CodeIndex> {
-        let code_dir = self.get_current_code_dir(p);
-        code_dir.get(&(name, arity)).cloned()
+    pub(super) fn op_dir(&mut self) -> &mut OpDir {
+        self.op_dir
     }
 }
 
+// the predicate whose code contains offset p, ie. the one with the
+// nearest entry point at or below p.
+pub(super) fn predicate_at(code_dir: &CodeDir, modules: &HashMap<ClauseName, Module>, p: usize)
+                           -> Option<PredicateKey>
+{
+    let code_dirs = Some(code_dir).into_iter()
+        .chain(modules.values().map(|module| &module.code_dir));
+
+    let mut nearest: Option<(usize, PredicateKey)> = None;
+
+    for code_dir in code_dirs {
+        for (key, idx) in code_dir.iter() {
+            if let IndexPtr::Index(offset) = idx.0.get() {
+                match nearest {
+                    Some((nearest_offset, _)) if nearest_offset >= offset => {},
+                    _ if offset <= p => nearest = Some((offset, key.clone())),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    nearest.map(|(_, key)| key)
+}
+
 pub(super) struct DuplicateTerm<'a> {
     state: &'a mut MachineState
 }
@@ -179,6 +352,277 @@ impl IndexMut<RegType> for MachineState {
     }
 }
 
+// the CPU time spent by the process so far, in user and system mode.
+fn process_cputime() -> Duration {
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return Duration::from_secs(0);
+    }
+
+    let duration = |tv: libc::timeval| Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000);
+    duration(usage.ru_utime) + duration(usage.ru_stime)
+}
+
+// the clocks behind the runtime, cputime and walltime keys of
+// statistics/2. runtime and cputime are the CPU time of the process,
+// walltime the time elapsed since the machine was created.
+pub(super) struct RunTimes {
+    started: Instant,
+    last_runtime: Duration,
+    last_walltime: Duration
+}
+
+impl RunTimes {
+    pub(super) fn new() -> Self {
+        RunTimes { started: Instant::now(),
+                   last_runtime: Duration::from_secs(0),
+                   last_walltime: Duration::from_secs(0) }
+    }
+
+    pub(super) fn cputime(&self) -> Duration {
+        process_cputime()
+    }
+
+    pub(super) fn walltime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // the total runtime, and the runtime since the last call.
+    pub(super) fn runtime_lap(&mut self) -> (Duration, Duration) {
+        let runtime = self.cputime();
+        let lap = runtime - self.last_runtime;
+
+        self.last_runtime = runtime;
+        (runtime, lap)
+    }
+
+    pub(super) fn walltime_lap(&mut self) -> (Duration, Duration) {
+        let walltime = self.walltime();
+        let lap = walltime - self.last_walltime;
+
+        self.last_walltime = walltime;
+        (walltime, lap)
+    }
+}
+
+// the deadlines installed by call_with_time_limit/2 and the toplevel
+// query timeout, innermost last, each with its handle and whether it
+// has expired. they are checked by Machine::query_stepper.
+pub(super) struct TimeLimits {
+    deadlines: Vec<(Instant, usize, bool)>,
+    next_handle: usize
+}
+
+impl TimeLimits {
+    pub(super) fn new() -> Self {
+        TimeLimits { deadlines: vec![], next_handle: 0 }
+    }
+
+    pub(super) fn add(&mut self, limit: Duration) -> usize {
+        let handle = self.next_handle;
+
+        self.next_handle += 1;
+
+        // a deadline too far off to be represented is never reached.
+        if let Some(deadline) = Instant::now().checked_add(limit) {
+            self.deadlines.push((deadline, handle, false));
+        }
+
+        handle
+    }
+
+    // the enclosing deadlines are restored once an inner limit is
+    // done, so that an expiry caught within it is raised again.
+    pub(super) fn remove(&mut self, handle: usize) {
+        if let Some(idx) = self.deadlines.iter().position(|&(_, h, _)| h == handle) {
+            self.deadlines.remove(idx);
+
+            for limit in self.deadlines[.. idx].iter_mut() {
+                limit.2 = false;
+            }
+        }
+    }
+
+    // marks the earliest deadline as expired if it has passed, so that
+    // the exception it raises is thrown only once.
+    pub(super) fn expire(&mut self) -> bool {
+        let now = Instant::now();
+        let earliest = self.deadlines.iter_mut()
+            .filter(|limit| !limit.2 && limit.0 <= now)
+            .min_by_key(|limit| limit.0);
+
+        match earliest {
+            Some(limit) => {
+                limit.2 = true;
+                true
+            },
+            None => false
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.deadlines.clear();
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ResourceLimit {
+    Heap,     // heap cells.
+    AndStack, // environment frames.
+    OrStack,  // choice points.
+    Trail     // trail entries.
+}
+
+impl ResourceLimit {
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        match flag {
+            "heap_limit"   => Some(ResourceLimit::Heap),
+            "stack_limit"  => Some(ResourceLimit::AndStack),
+            "choice_limit" => Some(ResourceLimit::OrStack),
+            "trail_limit"  => Some(ResourceLimit::Trail),
+            _ => None
+        }
+    }
+
+}
+
+pub(super) struct ResourceLimits {
+    pub(super) heap: usize,
+    pub(super) and_stack: usize,
+    pub(super) or_stack: usize,
+    pub(super) trail: usize
+}
+
+impl ResourceLimits {
+    pub(super) fn new() -> Self {
+        ResourceLimits { heap: 1 << 26, and_stack: 1 << 22, or_stack: 1 << 22, trail: 1 << 24 }
+    }
+
+    pub(super) fn set(&mut self, limit: ResourceLimit, n: usize) {
+        match limit {
+            ResourceLimit::Heap => self.heap = n,
+            ResourceLimit::AndStack => self.and_stack = n,
+            ResourceLimit::OrStack => self.or_stack = n,
+            ResourceLimit::Trail => self.trail = n
+        }
+    }
+
+    pub(super) fn get(&self, limit: ResourceLimit) -> usize {
+        match limit {
+            ResourceLimit::Heap => self.heap,
+            ResourceLimit::AndStack => self.and_stack,
+            ResourceLimit::OrStack => self.or_stack,
+            ResourceLimit::Trail => self.trail
+        }
+    }
+}
+
+// what calls to unknown procedures do, as set by the unknown flag.
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum Unknown {
+    Error,
+    Fail,
+    Warning
+}
+
+impl Unknown {
+    pub(super) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "error"   => Some(Unknown::Error),
+            "fail"    => Some(Unknown::Fail),
+            "warning" => Some(Unknown::Warning),
+            _ => None
+        }
+    }
+
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            Unknown::Error   => "error",
+            Unknown::Fail    => "fail",
+            Unknown::Warning => "warning"
+        }
+    }
+}
+
+// whether unification checks for cyclic bindings, as set by the
+// occurs_check flag.
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum OccursCheck {
+    Error,
+    False,
+    True
+}
+
+impl OccursCheck {
+    pub(super) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(OccursCheck::Error),
+            "false" => Some(OccursCheck::False),
+            "true"  => Some(OccursCheck::True),
+            _ => None
+        }
+    }
+
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            OccursCheck::Error => "error",
+            OccursCheck::False => "false",
+            OccursCheck::True  => "true"
+        }
+    }
+}
+
+// the prolog flags that can be changed by set_prolog_flag/2, save for
+// the resource limits and import_conflicts. the unknown flag of user
+// holds for the modules that don't set their own.
+pub(super) struct PrologFlags {
+    pub(super) double_quotes: DoubleQuotes,
+    pub(super) unknown: Unknown,
+    pub(super) module_unknown: HashMap<ClauseName, Unknown>,
+    pub(super) occurs_check: OccursCheck,
+    pub(super) debug: bool,
+    pub(super) autoload: bool
+}
+
+impl PrologFlags {
+    pub(super) fn new() -> Self {
+        PrologFlags { double_quotes: DoubleQuotes::String,
+                      unknown: Unknown::Error,
+                      module_unknown: HashMap::new(),
+                      occurs_check: OccursCheck::False,
+                      debug: false,
+                      autoload: true }
+    }
+
+    pub(super) fn unknown_in(&self, module_name: &ClauseName) -> Unknown {
+        self.module_unknown.get(module_name).cloned().unwrap_or(self.unknown)
+    }
+
+    pub(super) fn set_unknown_in(&mut self, module_name: ClauseName, unknown: Unknown) {
+        if module_name.as_str() == "user" {
+            self.unknown = unknown;
+        } else {
+            self.module_unknown.insert(module_name, unknown);
+        }
+    }
+}
+
+// the prolog flags, in the order current_prolog_flag/2 enumerates them.
+// integers are unbounded, so max_integer and min_integer are the
+// bounds of the machine integers.
+pub(super) const PROLOG_FLAGS: [&'static str; 19] = [
+    "bounded", "max_integer", "min_integer", "integer_rounding_function", "double_quotes",
+    "unknown", "occurs_check", "debug", "char_conversion", "max_arity", "argv", "version", "home",
+    "import_conflicts", "autoload", "heap_limit", "stack_limit", "choice_limit", "trail_limit"
+];
+
+// the flags fixed by the implementation.
+pub(super) const READ_ONLY_FLAGS: [&'static str; 8] = [
+    "bounded", "max_integer", "min_integer", "integer_rounding_function", "max_arity", "argv",
+    "version", "home"
+];
+
 #[derive(Clone, Copy)]
 pub(super) enum MachineMode {
     Read,
@@ -206,21 +650,49 @@ pub struct MachineState {
     pub(super) block: usize, // an offset into the OR stack.
     pub(super) ball: (usize, Vec<HeapCellValue>), // heap boundary, and a term copy
     pub(super) interms: Vec<Number>, // intermediate numbers.
+    pub(super) inferences: BigUint,
+    pub(super) depth: usize, // the call depth of the running clause.
+    pub(super) run_times: RunTimes,
+    pub(super) time_limits: TimeLimits,
+    pub(super) resource_limits: ResourceLimits,
+    pub(super) import_conflicts: ImportConflicts,
+    pub(super) flags: PrologFlags,
+    pub(super) toplevel_module: ClauseName, // the module of toplevel clauses and queries.
+    pub(super) backtrace: Vec<CodePtr>, // the continuations active at the last throw.
+    pub(super) write_var: Option<Ref>, // the variable bound by get_* in write mode.
+    pub(super) occurs_check_error: Option<(Addr, Addr)> // the variable, and the term it occurs in.
 }
 
-pub(crate) type CallResult = Result<(), Vec<HeapCellValue>>;
+// the number of continuation frames kept in a backtrace.
+pub(super) const MAX_BACKTRACE_FRAMES: usize = 32;
 
-fn predicate_existence_error(name: ClauseName, arity: usize, h: usize) -> Vec<HeapCellValue>
-{
-    let name = HeapCellValue::Addr(Addr::Con(Constant::Atom(name)));
+pub(crate) type CallResult = Result<(), MachineStub>;
 
-    let mut error = functor!("existence_error", 2, [heap_atom!("procedure"), heap_str!(3 + h)]);
-    error.append(&mut functor!("/", 2, [name, heap_integer!(arity)], Fixity::In));
+fn predicate_existence_error(name: ClauseName, arity: usize, h: usize) -> MachineStub
+{
+    MachineError::procedure_existence(name.clone(), arity).to_stub(h, name, arity)
+}
 
-    error
+fn qualified_existence_error(module_name: Option<ClauseName>, name: ClauseName, arity: usize,
+                             h: usize)
+                             -> MachineStub
+{
+    match module_name {
+        Some(module_name) =>
+            MachineError::qualified_procedure_existence(module_name, name.clone(), arity)
+                .to_stub(h, name, arity),
+        None =>
+            predicate_existence_error(name, arity, h)
+    }
 }
 
 pub(crate) trait CallPolicy: Any {
+    // policies that wrap another policy hand it back here, so that
+    // find_policy_slot can see through them.
+    fn prev_policy(&mut self) -> Option<&mut Box<CallPolicy>> {
+        None
+    }
+
     fn context_call(&mut self, machine_st: &mut MachineState, name: ClauseName,
                     arity: usize, idx: CodeIndex, lco: bool)
                     -> CallResult
@@ -238,7 +710,7 @@ pub(crate) trait CallPolicy: Any {
     {
         match idx.0.get() {
             IndexPtr::Undefined =>
-                return Err(predicate_existence_error(name, arity, machine_st.heap.h)),
+                machine_st.undefined_procedure(None, name, arity, false),
             IndexPtr::Index(compiled_tl_index) => {
                 let module_name = idx.1;
 
@@ -258,7 +730,7 @@ pub(crate) trait CallPolicy: Any {
     {
         match idx.0.get() {
             IndexPtr::Undefined =>
-                return Err(predicate_existence_error(name, arity, machine_st.heap.h)),
+                machine_st.undefined_procedure(None, name, arity, true),
             IndexPtr::Index(compiled_tl_index) => {
                 let module_name = idx.1;
 
@@ -282,6 +754,7 @@ pub(crate) trait CallPolicy: Any {
 
         machine_st.e  = machine_st.or_stack[b].e;
         machine_st.cp = machine_st.or_stack[b].cp.clone();
+        machine_st.depth = machine_st.or_stack[b].depth;
 
         machine_st.or_stack[b].bp = machine_st.p.clone() + offset;
 
@@ -312,6 +785,7 @@ pub(crate) trait CallPolicy: Any {
 
         machine_st.e  = machine_st.or_stack[b].e;
         machine_st.cp = machine_st.or_stack[b].cp.clone();
+        machine_st.depth = machine_st.or_stack[b].depth;
 
         machine_st.or_stack[b].bp = machine_st.p.clone() + 1;
 
@@ -342,6 +816,7 @@ pub(crate) trait CallPolicy: Any {
 
         machine_st.e  = machine_st.or_stack[b].e;
         machine_st.cp = machine_st.or_stack[b].cp.clone();
+        machine_st.depth = machine_st.or_stack[b].depth;
 
         let old_tr  = machine_st.or_stack[b].tr;
         let curr_tr = machine_st.tr;
@@ -373,6 +848,7 @@ pub(crate) trait CallPolicy: Any {
 
         machine_st.e  = machine_st.or_stack[b].e;
         machine_st.cp = machine_st.or_stack[b].cp.clone();
+        machine_st.depth = machine_st.or_stack[b].depth;
 
         let old_tr  = machine_st.or_stack[b].tr;
         let curr_tr = machine_st.tr;
@@ -394,10 +870,20 @@ pub(crate) trait CallPolicy: Any {
         Ok(())
     }
 
-    fn try_call_clause<'a>(&mut self, machine_st: &mut MachineState, code_dirs: CodeDirs<'a>,
+    fn try_call_clause<'a>(&mut self, machine_st: &mut MachineState, mut code_dirs: CodeDirs<'a>,
                            ct: &ClauseType, arity: usize, lco: bool)
                            -> CallResult
     {
+        match ct {
+            &ClauseType::Catch | &ClauseType::SetupCallCleanup
+          | &ClauseType::CallWithInferenceLimit => {
+                let caller = machine_st.context_module();
+                machine_st.qualify_meta_args(&code_dirs, caller, &clause_name!("builtin"),
+                                             &(ct.name(), arity));
+            },
+            _ => {}
+        };
+
         match ct {
             &ClauseType::AcyclicTerm => {
                 let addr = machine_st[temp_v!(1)].clone();
@@ -415,6 +901,10 @@ pub(crate) trait CallPolicy: Any {
 
                 Ok(())
             },
+            &ClauseType::Backtrace => {
+                machine_st.unify_backtrace(&code_dirs);
+                return_from_clause!(lco, machine_st)
+            },
             &ClauseType::Catch => {
                 if !lco {
                     machine_st.cp = machine_st.p.clone() + 1;
@@ -426,17 +916,101 @@ pub(crate) trait CallPolicy: Any {
 
                 Ok(())
             },
+            &ClauseType::CallHook => {
+                // hooks are optional, so undefined ones fail quietly.
+                if let Some((module_name, (name, arity))) = machine_st.setup_call_n(arity) {
+                    let context = machine_st.context_module();
+
+                    match code_dirs.get_qualified(module_name, name.clone(), arity, &context) {
+                        Some(idx) if idx.0.get() != IndexPtr::Undefined =>
+                            return self.context_call(machine_st, name, arity, idx, lco),
+                        _ => machine_st.fail = true
+                    }
+                }
+
+                Ok(())
+            },
             &ClauseType::CallN => {
-                if let Some((name, arity)) = machine_st.setup_call_n(arity) {
-                    if let Some(idx) = code_dirs.get(name.clone(), arity, &machine_st.p.clone()) {
-                        return self.context_call(machine_st, name, arity, idx, lco);
-                    } else {
-                        machine_st.fail = true;
+                if let Some((module_name, (name, arity))) = machine_st.setup_call_n(arity) {
+                    let context = machine_st.context_module();
+                    let idx = code_dirs.get_qualified(module_name.clone(), name.clone(), arity,
+                                                      &context);
+
+                    return match idx {
+                        Some(idx) => {
+                            let caller = module_name.unwrap_or(context);
+                            let key = (name.clone(), arity);
+
+                            machine_st.qualify_meta_args(&code_dirs, caller, &idx.1, &key);
+                            self.context_call(machine_st, name, arity, idx, lco)
+                        },
+                        None => {
+                            machine_st.undefined_procedure(module_name, name, arity, lco);
+                            Ok(())
+                        }
+                    };
+                }
+
+                Ok(())
+            },
+            &ClauseType::CurrentOps => {
+                machine_st.current_ops(&code_dirs)?;
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::UnknownProcedure => {
+                // the goal of an undefined call is retried once the
+                // unknown_procedure/2 hook has run. if it's still
+                // undefined, the unknown flag of its module decides.
+                let module_name = match machine_st.store(machine_st.deref(machine_st[temp_v!(1)].clone())) {
+                    Addr::Con(Constant::Atom(name)) => name,
+                    _ => clause_name!("user")
+                };
+
+                machine_st.registers[1] = machine_st[temp_v!(2)].clone();
+
+                if let Some((qualifier, (name, arity))) = machine_st.setup_call_n(1) {
+                    match code_dirs.get(name.clone(), arity, &module_name) {
+                        Some(idx) if idx.0.get() != IndexPtr::Undefined => {
+                            let key = (name.clone(), arity);
+
+                            machine_st.qualify_meta_args(&code_dirs, module_name, &idx.1, &key);
+                            return self.context_call(machine_st, name, arity, idx, lco);
+                        },
+                        _ =>
+                            match machine_st.flags.unknown_in(&module_name) {
+                                Unknown::Error => {
+                                    let h = machine_st.heap.h;
+                                    return Err(qualified_existence_error(qualifier, name, arity, h));
+                                },
+                                Unknown::Fail =>
+                                    machine_st.fail = true,
+                                Unknown::Warning =>
+                                    machine_st.unknown_procedure_warning(module_name, name, arity, lco)
+                            }
                     }
                 }
 
                 Ok(())
             },
+            &ClauseType::CurrentPrologFlags => {
+                machine_st.current_prolog_flags()?;
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::CurrentModules => {
+                machine_st.current_modules(&code_dirs);
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::DeclareOp => {
+                machine_st.op(&mut code_dirs)?;
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::ContextModule => {
+                let a1 = machine_st[temp_v!(1)].clone();
+                let module_name = machine_st.context_module();
+
+                machine_st.unify(a1, Addr::Con(Constant::Atom(module_name)));
+                return_from_clause!(lco, machine_st)
+            },
             &ClauseType::Compare => {
                 let a1 = machine_st[temp_v!(1)].clone();
                 let a2 = machine_st[temp_v!(2)].clone();
@@ -467,6 +1041,22 @@ pub(crate) trait CallPolicy: Any {
                 machine_st.fail = !machine_st.is_cyclic_term(addr);
                 return_from_clause!(lco, machine_st)
             },
+            &ClauseType::DefaultMessage => {
+                machine_st.default_message();
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::PrintMessageLines => {
+                machine_st.print_message_lines()?;
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::TimeMessage => {
+                machine_st.time_message()?;
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::SetPrologFlag => {
+                machine_st.set_prolog_flag()?;
+                return_from_clause!(lco, machine_st)
+            },
             &ClauseType::Display => {
                 let output = machine_st.print_term(machine_st[temp_v!(1)].clone(),
                                                    DisplayFormatter {},
@@ -487,6 +1077,32 @@ pub(crate) trait CallPolicy: Any {
                 machine_st.fail = machine_st.ground_test();
                 return_from_clause!(lco, machine_st)
             },
+            &ClauseType::SubsumesTerm => {
+                let a1 = machine_st[temp_v!(1)].clone();
+                let a2 = machine_st[temp_v!(2)].clone();
+
+                machine_st.fail = !machine_st.subsumes(a1, a2);
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::TermFactorized => {
+                machine_st.term_factorized();
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::Phrase => {
+                // the body is translated as a DCG body, then called.
+                let goal = machine_st.phrase_goal()
+                    .map_err(|err| machine_st.error_form(err, clause_name!("phrase"), 3))?;
+
+                machine_st.registers[1] = goal;
+                self.try_call_clause(machine_st, code_dirs, &ClauseType::CallN, 1, lco)
+            },
+            &ClauseType::UnifyWithOccursCheck => {
+                let a1 = machine_st[temp_v!(1)].clone();
+                let a2 = machine_st[temp_v!(2)].clone();
+
+                machine_st.unify_with_occurs_check(a1, a2, OccursCheck::True);
+                return_from_clause!(lco, machine_st)
+            },
             &ClauseType::Functor => {
                 machine_st.try_functor()?;
                 return_from_clause!(lco, machine_st)
@@ -496,7 +1112,8 @@ pub(crate) trait CallPolicy: Any {
                 return_from_clause!(lco, machine_st)
             },
             &ClauseType::Sort => {
-                let mut list = machine_st.try_from_list(temp_v!(1))?;
+                let mut list = machine_st.try_from_list(temp_v!(1))
+                    .map_err(|err| machine_st.error_form(err, clause_name!("sort"), 2))?;
 
                 list.sort_unstable_by(|a1, a2| machine_st.compare_term_test(a1, a2));
                 machine_st.term_dedup(&mut list);
@@ -509,11 +1126,13 @@ pub(crate) trait CallPolicy: Any {
                 return_from_clause!(lco, machine_st)
             },
             &ClauseType::KeySort => {
-                let mut list = machine_st.try_from_list(temp_v!(1))?;
+                let list = machine_st.try_from_list(temp_v!(1))
+                    .map_err(|err| machine_st.error_form(err, clause_name!("keysort"), 2))?;
                 let mut key_pairs = Vec::new();
 
                 for val in list {
-                    let key = machine_st.project_onto_key(val.clone())?;
+                    let key = machine_st.project_onto_key(val.clone())
+                        .map_err(|err| machine_st.error_form(err, clause_name!("keysort"), 2))?;
                     key_pairs.push((key, val.clone()));
                 }
 
@@ -527,16 +1146,84 @@ pub(crate) trait CallPolicy: Any {
 
                 return_from_clause!(lco, machine_st)
             },
+            &ClauseType::ModuleProperties => {
+                machine_st.module_properties(&code_dirs);
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::PredicateHeads => {
+                machine_st.predicate_heads(&code_dirs)?;
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::PredicateIndicators => {
+                machine_st.predicate_indicators(&code_dirs)?;
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::PredicateProperties => {
+                machine_st.predicate_properties(&code_dirs)?;
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::StripModule => {
+                machine_st.strip_module_3();
+                return_from_clause!(lco, machine_st)
+            },
+            &ClauseType::Statistics => {
+                machine_st.try_statistics(code_dirs.code_size)?;
+                return_from_clause!(lco, machine_st)
+            },
+            // run by the machine, which owns the code and modules they
+            // rewrite.
+            &ClauseType::SetModule | &ClauseType::UnloadModule =>
+                unreachable!(),
             &ClauseType::Throw => {
-                if !lco {
-                    machine_st.cp = machine_st.p.clone() + 1;
-                }
-
+                // throw/1 never returns, so cp is left at the call site
+                // for the backtrace taken by set_ball.
+                machine_st.cp = machine_st.p.clone();
                 machine_st.goto_throw();
                 Ok(())
             },
-            &ClauseType::Named(ref name, ref idx) | &ClauseType::Op(ref name, _, ref idx) =>
-                self.context_call(machine_st, name.clone(), arity, idx.clone(), lco),
+            &ClauseType::Named(ref name, ref idx) | &ClauseType::Op(ref name, _, ref idx) => {
+                let caller = machine_st.context_module();
+                machine_st.qualify_meta_args(&code_dirs, caller, &idx.1, &(name.clone(), arity));
+
+                self.context_call(machine_st, name.clone(), arity, idx.clone(), lco)
+            },
+            &ClauseType::ModuleCall => {
+                // M:G is call(M:G), its goal being the structure laid out here.
+                let h = machine_st.heap.h;
+                let (a1, a2) = (machine_st[temp_v!(1)].clone(), machine_st[temp_v!(2)].clone());
+
+                machine_st.heap.append(functor!(":", 2, [HeapCellValue::Addr(a1),
+                                                         HeapCellValue::Addr(a2)],
+                                                Fixity::In));
+                machine_st.registers[1] = Addr::Str(h);
+
+                self.try_call_clause(machine_st, code_dirs, &ClauseType::CallN, 1, lco)
+            },
+            &ClauseType::Qualified(ref module_name, ref name, ref idx) => {
+                let caller = machine_st.context_module();
+                let module_name = Some(module_name.clone());
+
+                // the index is looked up here only if the predicate was
+                // unknown when the call was compiled.
+                let idx = match idx.0.get() {
+                    IndexPtr::Index(_) => Some(idx.clone()),
+                    IndexPtr::Undefined =>
+                        code_dirs.get_qualified(module_name.clone(), name.clone(), arity, &caller)
+                };
+
+                match idx {
+                    Some(idx) => {
+                        machine_st.qualify_meta_args(&code_dirs, caller, &idx.1,
+                                                     &(name.clone(), arity));
+
+                        self.context_call(machine_st, name.clone(), arity, idx, lco)
+                    },
+                    None => {
+                        machine_st.undefined_procedure(module_name, name.clone(), arity, lco);
+                        Ok(())
+                    }
+                }
+            },
             &ClauseType::CallWithInferenceLimit => {
                 machine_st.goto_ptr(CodePtr::DirEntry(409, clause_name!("builtin")), 3, lco);
                 Ok(())
@@ -547,7 +1234,8 @@ pub(crate) trait CallPolicy: Any {
             },
             &ClauseType::Is => {
                 let a = machine_st[temp_v!(1)].clone();
-                let result = machine_st.arith_eval_by_metacall(temp_v!(2))?;
+                let result = machine_st.arith_eval_by_metacall(temp_v!(2))
+                    .map_err(|err| machine_st.error_form(err, clause_name!("is"), 2))?;
 
                 machine_st.unify(a, Addr::Con(Constant::Number(result)));
                 machine_st.p += 1;
@@ -564,44 +1252,61 @@ pub(crate) trait CallPolicy: Any {
 
 downcast!(CallPolicy);
 
+// find the box holding the policy of type T in the chain of wrapped
+// policies starting at policy, if there is one.
+pub(crate) fn find_policy_slot<T: CallPolicy>(policy: &mut Box<CallPolicy>)
+                                             -> Option<&mut Box<CallPolicy>>
+{
+    if policy.is::<T>() {
+        return Some(policy);
+    }
+
+    match policy.prev_policy() {
+        Some(prev_policy) => find_policy_slot::<T>(prev_policy),
+        None => None
+    }
+}
+
+pub(crate) fn find_policy<T: CallPolicy>(policy: &mut Box<CallPolicy>) -> Option<&mut T> {
+    find_policy_slot::<T>(policy).and_then(|slot| slot.downcast_mut::<T>().ok())
+}
+
 pub(crate) struct DefaultCallPolicy {}
 
 impl CallPolicy for DefaultCallPolicy {}
 
 pub(crate) struct CallWithInferenceLimitCallPolicy {
     pub(crate) prev_policy: Box<CallPolicy>,
-    count:  BigUint,
     limits: Vec<(BigUint, usize)>
 }
 
+// the policy keeps no count of its own. limits are measured against
+// the machine's inference counter, which is also what statistics/2
+// reports.
 impl CallWithInferenceLimitCallPolicy {
     pub(crate) fn new_in_place(policy: &mut Box<CallPolicy>)
     {
         let mut prev_policy: Box<CallPolicy> = Box::new(DefaultCallPolicy {});
         swap(&mut prev_policy, policy);
 
-        let new_policy = CallWithInferenceLimitCallPolicy { prev_policy,
-                                                            count:  BigUint::zero(),
-                                                            limits: vec![] };
+        let new_policy = CallWithInferenceLimitCallPolicy { prev_policy, limits: vec![] };
         *policy = Box::new(new_policy);
     }
 
-    fn increment(&mut self) -> CallResult {
+    fn check_limit(&self, machine_st: &MachineState) -> CallResult {
         if let Some(&(ref limit, bp)) = self.limits.last() {
-            if self.count == *limit {
+            if machine_st.inferences >= *limit {
                 return Err(functor!("inference_limit_exceeded", 1,
                                     [HeapCellValue::Addr(Addr::Con(Constant::Usize(bp)))]));
-            } else {
-                self.count += BigUint::one();
             }
         }
 
         Ok(())
     }
 
-    pub(crate) fn add_limit(&mut self, limit: Rc<BigInt>, b: usize) -> Rc<BigInt> {
+    pub(crate) fn add_limit(&mut self, limit: Rc<BigInt>, b: usize, count: &BigUint) {
         let limit = match limit.to_biguint() {
-            Some(limit) => limit + &self.count,
+            Some(limit) => limit + count,
             None => panic!("install_inference_counter: limit must be positive")
         };
 
@@ -609,18 +1314,14 @@ impl CallWithInferenceLimitCallPolicy {
             Some((ref inner_limit, _)) if *inner_limit <= limit => {},
             _ => self.limits.push((limit, b))
         };
-
-        Rc::new(BigInt::from(self.count.clone()))
     }
 
-    pub(crate) fn remove_limit(&mut self, b: usize) -> Rc<BigInt> {
+    pub(crate) fn remove_limit(&mut self, b: usize) {
         if let Some((_, bp)) = self.limits.last().cloned() {
             if bp == b {
                 self.limits.pop();
             }
         }
-
-        Rc::new(BigInt::from(self.count.clone()))
     }
 
     pub(crate) fn is_empty(&self) -> bool {
@@ -635,28 +1336,32 @@ impl CallWithInferenceLimitCallPolicy {
 }
 
 impl CallPolicy for CallWithInferenceLimitCallPolicy {
+    fn prev_policy(&mut self) -> Option<&mut Box<CallPolicy>> {
+        Some(&mut self.prev_policy)
+    }
+
     fn retry_me_else(&mut self, machine_st: &mut MachineState, offset: usize) -> CallResult
     {
         self.prev_policy.retry_me_else(machine_st, offset)?;
-        self.increment()
+        self.check_limit(machine_st)
     }
 
     fn retry(&mut self, machine_st: &mut MachineState, offset: usize) -> CallResult
     {
         self.prev_policy.retry(machine_st, offset)?;
-        self.increment()
+        self.check_limit(machine_st)
     }
 
     fn trust_me(&mut self, machine_st: &mut MachineState) -> CallResult
     {
         self.prev_policy.trust_me(machine_st)?;
-        self.increment()
+        self.check_limit(machine_st)
     }
 
     fn trust(&mut self, machine_st: &mut MachineState, offset: usize) -> CallResult
     {
         self.prev_policy.trust(machine_st, offset)?;
-        self.increment()
+        self.check_limit(machine_st)
     }
 
     fn try_call_clause<'a>(&mut self, machine_st: &mut MachineState, code_dirs: CodeDirs<'a>,
@@ -664,7 +1369,182 @@ impl CallPolicy for CallWithInferenceLimitCallPolicy {
                            -> CallResult
     {
         self.prev_policy.try_call_clause(machine_st, code_dirs, ct, arity, lco)?;
-        self.increment()
+        self.check_limit(machine_st)
+    }
+}
+
+struct DepthLimit {
+    limit: usize,
+    base: usize,
+    reached: usize,
+    exceeded: bool,
+    active: bool,
+    guard: (usize, usize) // index and global index of the guarding choice point.
+}
+
+pub(crate) struct CallWithDepthLimitCallPolicy {
+    pub(crate) prev_policy: Box<CallPolicy>,
+    limits: Vec<DepthLimit>
+}
+
+// depths are read off the machine's depth register, and measured
+// relative to the depth at which each limit was installed. a limit is
+// inactive while its goal has exited, and is removed when the choice
+// point guarding it is retried or cut away.
+impl CallWithDepthLimitCallPolicy {
+    pub(crate) fn new_in_place(policy: &mut Box<CallPolicy>)
+    {
+        let mut prev_policy: Box<CallPolicy> = Box::new(DefaultCallPolicy {});
+        swap(&mut prev_policy, policy);
+
+        let new_policy = CallWithDepthLimitCallPolicy { prev_policy, limits: vec![] };
+        *policy = Box::new(new_policy);
+    }
+
+    fn within_limits(&mut self, depth: usize) -> bool {
+        let mut within_limits = true;
+
+        for limit in self.limits.iter_mut().filter(|limit| limit.active) {
+            if depth.saturating_sub(limit.base) > limit.limit {
+                limit.exceeded = true;
+                within_limits = false;
+            }
+        }
+
+        if within_limits {
+            for limit in self.limits.iter_mut().filter(|limit| limit.active) {
+                limit.reached = max(limit.reached, depth.saturating_sub(limit.base));
+            }
+        }
+
+        within_limits
+    }
+
+    pub(crate) fn add_limit(&mut self, machine_st: &MachineState, limit: usize) -> usize {
+        while let Some(guard) = self.limits.last().map(|limit| limit.guard) {
+            if is_guarded_by(machine_st, guard) {
+                break;
+            }
+
+            self.limits.pop();
+        }
+
+        let b = machine_st.b - 1;
+        let guard = (b, machine_st.or_stack[b].global_index);
+
+        self.limits.push(DepthLimit { limit, base: machine_st.depth, reached: 0,
+                                      exceeded: false, active: true, guard });
+
+        self.limits.len() - 1
+    }
+
+    pub(crate) fn exit_limit(&mut self, handle: usize) -> usize {
+        match self.limits.get_mut(handle) {
+            Some(limit) => {
+                limit.active = false;
+                limit.reached
+            },
+            None => 0
+        }
+    }
+
+    pub(crate) fn redo_limit(&mut self, handle: usize) {
+        if let Some(limit) = self.limits.get_mut(handle) {
+            limit.active = true;
+        }
+    }
+
+    pub(crate) fn remove_limit(&mut self, handle: usize) {
+        self.limits.truncate(handle);
+    }
+
+    // remove the limit whose guard was the choice point at index b,
+    // just retried, returning whether it was ever exceeded.
+    pub(crate) fn pop_limit(&mut self, b: usize) -> bool {
+        while let Some(guard) = self.limits.last().map(|limit| limit.guard) {
+            if guard.0 < b {
+                break;
+            }
+
+            if let Some(limit) = self.limits.pop() {
+                if guard.0 == b {
+                    return limit.exceeded;
+                }
+            }
+        }
+
+        false
+    }
+
+    // unwrap the policy from the chain once it holds no more limits.
+    pub(crate) fn remove_if_empty(policy: &mut Box<CallPolicy>) {
+        if let Some(slot) = find_policy_slot::<CallWithDepthLimitCallPolicy>(policy) {
+            let inner = match slot.downcast_mut::<CallWithDepthLimitCallPolicy>().ok() {
+                Some(policy) =>
+                    if policy.limits.is_empty() {
+                        let mut new_inner: Box<CallPolicy> = Box::new(DefaultCallPolicy {});
+                        swap(&mut policy.prev_policy, &mut new_inner);
+                        Some(new_inner)
+                    } else {
+                        None
+                    },
+                None => None
+            };
+
+            if let Some(inner) = inner {
+                *slot = inner;
+            }
+        }
+    }
+}
+
+fn is_guarded_by(machine_st: &MachineState, guard: (usize, usize)) -> bool {
+    let (b, global_index) = guard;
+    b < machine_st.b && machine_st.or_stack[b].global_index == global_index
+}
+
+impl CallPolicy for CallWithDepthLimitCallPolicy {
+    fn prev_policy(&mut self) -> Option<&mut Box<CallPolicy>> {
+        Some(&mut self.prev_policy)
+    }
+
+    fn retry_me_else(&mut self, machine_st: &mut MachineState, offset: usize) -> CallResult
+    {
+        self.prev_policy.retry_me_else(machine_st, offset)
+    }
+
+    fn retry(&mut self, machine_st: &mut MachineState, offset: usize) -> CallResult
+    {
+        self.prev_policy.retry(machine_st, offset)
+    }
+
+    fn trust_me(&mut self, machine_st: &mut MachineState) -> CallResult
+    {
+        self.prev_policy.trust_me(machine_st)
+    }
+
+    fn trust(&mut self, machine_st: &mut MachineState, offset: usize) -> CallResult
+    {
+        self.prev_policy.trust(machine_st, offset)
+    }
+
+    fn try_execute<'a>(&mut self, machine_st: &mut MachineState, name: ClauseName,
+                       arity: usize, idx: CodeIndex)
+                       -> CallResult
+    {
+        self.prev_policy.try_execute(machine_st, name, arity, idx)
+    }
+
+    fn try_call_clause<'a>(&mut self, machine_st: &mut MachineState, code_dirs: CodeDirs<'a>,
+                           ct: &ClauseType, arity: usize, lco: bool)
+                           -> CallResult
+    {
+        if self.within_limits(machine_st.depth) {
+            self.prev_policy.try_call_clause(machine_st, code_dirs, ct, arity, lco)
+        } else {
+            machine_st.fail = true;
+            Ok(())
+        }
     }
 }
 
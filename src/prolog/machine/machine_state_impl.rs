@@ -1,18 +1,24 @@
 use prolog::and_stack::*;
 use prolog::ast::*;
 use prolog::copier::*;
+use prolog::dcg::*;
 use prolog::heap_iter::*;
 use prolog::heap_print::*;
+use prolog::machine::machine_errors::*;
 use prolog::machine::machine_state::*;
-use prolog::num::{Integer, ToPrimitive, Zero};
+use prolog::num::{Integer, One, ToPrimitive, Zero};
 use prolog::num::bigint::{BigInt, BigUint};
 use prolog::num::rational::Ratio;
 use prolog::or_stack::*;
+use prolog::ordered_float::*;
 use prolog::tabled_rc::*;
 
+use std::cell::Cell;
 use std::cmp::{max, Ordering};
 use std::collections::{HashMap, HashSet};
+use std::env;
 use std::rc::Rc;
+use std::time::Duration;
 
 macro_rules! try_or_fail {
     ($s:ident, $e:expr) => {{
@@ -23,9 +29,63 @@ macro_rules! try_or_fail {
                 return;
             }
         }
+    }};
+    ($s:ident, $e:expr, $name:expr, $arity:expr) => {{
+        match $e {
+            Ok(val)  => val,
+            Err(err) => {
+                let stub = $s.error_form(err, clause_name!($name), $arity);
+                $s.throw_exception(stub);
+                return;
+            }
+        }
     }}
 }
 
+// the steps of reading a term off the heap: a cell to read, or a
+// list cell or structure to build from the terms read last.
+enum TermStep {
+    Read(Addr),
+    Cons,
+    Clause(ClauseName, usize, Option<Fixity>)
+}
+
+// the steps of laying out a term on the heap: a term to write, or a
+// list cell or structure to build from the addresses written last.
+enum TermLayoutStep<'a> {
+    Write(&'a Term),
+    Cons,
+    Clause(&'a ClauseName, usize, Option<Fixity>)
+}
+
+fn specifier_from_name(name: &str) -> Option<Specifier> {
+    match name {
+        "xfx" => Some(XFX), "xfy" => Some(XFY), "yfx" => Some(YFX),
+        "xf" => Some(XF), "yf" => Some(YF),
+        "fx" => Some(FX), "fy" => Some(FY),
+        _ => None
+    }
+}
+
+fn specifier_name(spec: Specifier) -> &'static str {
+    match spec {
+        XFX => "xfx", XFY => "xfy", YFX => "yfx",
+        XF => "xf", YF => "yf",
+        FX => "fx", _ => "fy"
+    }
+}
+
+// the type error of an integer operation applied to the first of
+// its operands that isn't an integer.
+fn integer_type_error(n1: Number, n2: Number) -> MachineError {
+    let culprit = match n1 {
+        Number::Integer(_) => n2,
+        n1 => n1
+    };
+
+    MachineError::type_error("integer", Addr::Con(Constant::Number(culprit)))
+}
+
 impl MachineState {
     pub(super) fn new(atom_tbl: TabledData<Atom>) -> MachineState {
         MachineState {
@@ -48,15 +108,31 @@ impl MachineState {
             hb: 0,
             block: 0,
             ball: (0, Vec::new()),
-            interms: vec![Number::default(); 256]
+            interms: vec![Number::default(); 256],
+            inferences: BigUint::zero(),
+            depth: 0,
+            run_times: RunTimes::new(),
+            time_limits: TimeLimits::new(),
+            resource_limits: ResourceLimits::new(),
+            import_conflicts: ImportConflicts::Error,
+            flags: PrologFlags::new(),
+            toplevel_module: clause_name!("user"),
+            backtrace: Vec::new(),
+            write_var: None,
+            occurs_check_error: None
         }
     }
 
-    fn next_global_index(&self) -> usize {
+    pub(super) fn next_global_index(&self) -> usize {
         max(if self.and_stack.len() > 0 { self.and_stack[self.e].global_index } else { 0 },
             if self.b > 0 { self.or_stack[self.b - 1].global_index } else { 0 }) + 1
     }
 
+    // the depth of the clause owning the current environment.
+    fn env_depth(&self) -> usize {
+        if self.and_stack.len() > 0 { self.and_stack[self.e].depth } else { 0 }
+    }
+
     pub(crate) fn store(&self, a: Addr) -> Addr {
         match a {
             Addr::HeapCell(r)       => self.heap[r].as_addr(r),
@@ -94,15 +170,323 @@ impl MachineState {
     pub(super) fn print_term<Fmt, Outputter>(&self, a: Addr, fmt: Fmt, output: Outputter) -> Outputter
       where Fmt: HeapCellValueFormatter, Outputter: HeapCellValueOutputter
     {
-        let iter    = HeapCellPreOrderIterator::new(&self, a);
+        let iter    = HeapCellRationalTreeIterator::new(&self, a);
         let printer = HeapCellPrinter::new(iter, fmt, output);
 
         printer.print()
     }
 
+    // the term at a, whose unbound variables are named by var_names,
+    // or else by the first of _G0, _G1, ... not among those names. the
+    // term is read with an explicit stack, so deep terms don't exhaust
+    // the native one.
+    pub(super) fn heap_term(&self, a: Addr, var_names: &mut HashMap<Addr, Rc<Var>>) -> Term {
+        let mut steps = vec![TermStep::Read(a)];
+        let mut terms = vec![];
+
+        while let Some(step) = steps.pop() {
+            match step {
+                TermStep::Read(addr) => {
+                    let var = self.deref(addr);
+
+                    match self.store(var.clone()) {
+                        Addr::Con(c) => terms.push(Term::Constant(Cell::default(), c)),
+                        Addr::Lis(l) => {
+                            steps.push(TermStep::Cons);
+                            steps.push(TermStep::Read(Addr::HeapCell(l + 1)));
+                            steps.push(TermStep::Read(Addr::HeapCell(l)));
+                        },
+                        Addr::Str(s) =>
+                            match self.heap[s].clone() {
+                                HeapCellValue::NamedStr(arity, name, fixity) => {
+                                    steps.push(TermStep::Clause(name, arity, fixity));
+
+                                    for i in (1 .. arity + 1).rev() {
+                                        steps.push(TermStep::Read(Addr::HeapCell(s + i)));
+                                    }
+                                },
+                                HeapCellValue::Addr(addr) => steps.push(TermStep::Read(addr))
+                            },
+                        _ => terms.push(Term::Var(Cell::default(), Self::var_name(var, var_names)))
+                    }
+                },
+                TermStep::Cons => {
+                    let tail = terms.pop().unwrap();
+                    let head = terms.pop().unwrap();
+
+                    terms.push(Term::Cons(Cell::default(), Box::new(head), Box::new(tail)));
+                },
+                TermStep::Clause(name, arity, fixity) => {
+                    let n = terms.len() - arity;
+                    let args = terms.split_off(n).into_iter().map(Box::new).collect();
+
+                    terms.push(Term::Clause(Cell::default(), name, args, fixity));
+                }
+            }
+        }
+
+        terms.pop().unwrap()
+    }
+
+    fn var_name(var: Addr, var_names: &mut HashMap<Addr, Rc<Var>>) -> Rc<Var> {
+        if let Some(name) = var_names.get(&var) {
+            return name.clone();
+        }
+
+        // fresh names skip those already given to variables.
+        let mut count = var_names.len();
+
+        let name = loop {
+            let name = format!("_G{}", count);
+
+            if !var_names.values().any(|var| var.as_str() == name.as_str()) {
+                break Rc::new(name);
+            }
+
+            count += 1;
+        };
+
+        var_names.insert(var, name.clone());
+        name
+    }
+
+    // lays out term on the heap, its variables bound to the cells of
+    // vars, or else to fresh cells. structures are laid out after
+    // their arguments, using an explicit stack.
+    pub(super) fn term_to_heap(&mut self, term: &Term, vars: &mut HashMap<Rc<Var>, Addr>) -> Addr {
+        let mut steps = vec![TermLayoutStep::Write(term)];
+        let mut addrs = vec![];
+
+        while let Some(step) = steps.pop() {
+            match step {
+                TermLayoutStep::Write(&Term::AnonVar) => {
+                    let h = self.heap.h;
+
+                    self.heap.push(HeapCellValue::Addr(Addr::HeapCell(h)));
+                    addrs.push(Addr::HeapCell(h));
+                },
+                TermLayoutStep::Write(&Term::Var(_, ref name)) => {
+                    if let Some(addr) = vars.get(name) {
+                        addrs.push(addr.clone());
+                        continue;
+                    }
+
+                    let h = self.heap.h;
+
+                    self.heap.push(HeapCellValue::Addr(Addr::HeapCell(h)));
+                    vars.insert(name.clone(), Addr::HeapCell(h));
+                    addrs.push(Addr::HeapCell(h));
+                },
+                TermLayoutStep::Write(&Term::Constant(_, ref c)) =>
+                    addrs.push(Addr::Con(c.clone())),
+                TermLayoutStep::Write(&Term::Clause(_, ref name, ref terms, _)) if terms.is_empty() =>
+                    addrs.push(Addr::Con(Constant::Atom(name.clone()))),
+                TermLayoutStep::Write(&Term::Clause(_, ref name, ref terms, fixity)) => {
+                    steps.push(TermLayoutStep::Clause(name, terms.len(), fixity));
+
+                    for term in terms.iter().rev() {
+                        steps.push(TermLayoutStep::Write(term));
+                    }
+                },
+                TermLayoutStep::Write(&Term::Cons(_, ref head, ref tail)) => {
+                    steps.push(TermLayoutStep::Cons);
+                    steps.push(TermLayoutStep::Write(tail));
+                    steps.push(TermLayoutStep::Write(head));
+                },
+                TermLayoutStep::Cons => {
+                    let tail = addrs.pop().unwrap();
+                    let head = addrs.pop().unwrap();
+                    let l = self.heap.h;
+
+                    self.heap.push(HeapCellValue::Addr(head));
+                    self.heap.push(HeapCellValue::Addr(tail));
+
+                    addrs.push(Addr::Lis(l));
+                },
+                TermLayoutStep::Clause(name, arity, fixity) => {
+                    let n = addrs.len() - arity;
+                    let h = self.heap.h;
+
+                    self.heap.push(HeapCellValue::NamedStr(arity, name.clone(), fixity));
+
+                    for arg in addrs.split_off(n) {
+                        self.heap.push(HeapCellValue::Addr(arg));
+                    }
+
+                    addrs.push(Addr::Str(h));
+                }
+            }
+        }
+
+        addrs.pop().unwrap()
+    }
+
+    // the goal of phrase(Body, S0, S), Body translated as the body of a
+    // DCG rule with S0 and S threaded through it. a qualified body is
+    // called in its module.
+    pub(super) fn phrase_goal(&mut self) -> Result<Addr, MachineError> {
+        let mut body = self.store(self.deref(self[temp_v!(1)].clone()));
+        let mut module_name = None;
+
+        while let Addr::Str(s) = body.clone() {
+            match self.heap[s].clone() {
+                HeapCellValue::NamedStr(2, ref name, _) if name.as_str() == ":" => {
+                    module_name = Some(self.store(self.deref(Addr::HeapCell(s + 1))));
+                    body = self.store(self.deref(Addr::HeapCell(s + 2)));
+                },
+                _ => break
+            }
+        }
+
+        if body.is_ref() {
+            return Err(MachineError::Instantiation);
+        }
+
+        // S0 and S are named apart from the variables of the body,
+        // which are named _G0, _G1, ...
+        let s0 = Rc::new(String::from("$S0"));
+        let s  = Rc::new(String::from("$S"));
+
+        let mut var_names = HashMap::new();
+        let body_term = self.heap_term(body.clone(), &mut var_names);
+
+        let mut vars: HashMap<Rc<Var>, Addr> =
+            var_names.into_iter().map(|(addr, name)| (name, addr)).collect();
+
+        vars.insert(s0.clone(), self[temp_v!(2)].clone());
+        vars.insert(s.clone(), self[temp_v!(3)].clone());
+
+        let goal = DcgTranslator::new(self.atom_tbl.clone())
+            .translate_body(&body_term, &Term::Var(Cell::default(), s0),
+                            &Term::Var(Cell::default(), s))
+            .map_err(|_| MachineError::type_error("callable", body))?;
+
+        let goal = self.term_to_heap(&goal, &mut vars);
+
+        Ok(match module_name {
+            Some(module_name) => {
+                let h = self.heap.h;
+
+                self.heap.append(functor!(":", 2, [HeapCellValue::Addr(module_name),
+                                                   HeapCellValue::Addr(goal)],
+                                          Fixity::In));
+                Addr::Str(h)
+            },
+            None => goal
+        })
+    }
+
+    // true if the term at addr holds target, which is either an
+    // unbound variable or the heap cell about to be pushed. the
+    // structures of the term are visited once, so cyclic terms don't
+    // loop.
+    pub(super) fn occurs_in(&self, target: &Addr, addr: Addr) -> bool {
+        let mut pdl = vec![addr];
+        let mut visited = HashSet::new();
+
+        while let Some(addr) = pdl.pop() {
+            if addr == *target {
+                return true;
+            }
+
+            match self.store(self.deref(addr)) {
+                ref addr if addr == target =>
+                    return true,
+                Addr::Lis(l) =>
+                    if visited.insert(l) {
+                        pdl.push(Addr::HeapCell(l + 1));
+                        pdl.push(Addr::HeapCell(l));
+                    },
+                Addr::Str(s) =>
+                    if visited.insert(s) {
+                        if let &HeapCellValue::NamedStr(arity, ..) = &self.heap[s] {
+                            // the arguments are popped in order, so that an
+                            // unfinished structure isn't read past target.
+                            for i in (1 .. arity + 1).rev() {
+                                pdl.push(Addr::HeapCell(s + i));
+                            }
+                        }
+                    },
+                _ => {}
+            }
+        }
+
+        false
+    }
+
+    // the occurs check failed: fails, and in error mode, leaves the
+    // occurs_check error for the query stepper to throw.
+    fn occurs_check_failure(&mut self, var: Addr, addr: Addr, occurs_check: OccursCheck) {
+        if let OccursCheck::Error = occurs_check {
+            self.occurs_check_error = Some((var, addr));
+        }
+
+        self.fail = true;
+    }
+
+    fn bind_with_occurs_check(&mut self, r1: Ref, a2: Addr, occurs_check: OccursCheck) {
+        if let OccursCheck::False = occurs_check {
+            return self.bind(r1, a2);
+        }
+
+        let var = Addr::from(r1);
+
+        if self.occurs_in(&var, a2.clone()) {
+            self.occurs_check_failure(var, a2, occurs_check);
+        } else {
+            self.bind(r1, a2);
+        }
+    }
+
+    // with the occurs check on, checks the term at addr before it's
+    // pushed into the structure being built by get_* in write mode.
+    // returns true if the check failed.
+    fn occurs_check_on_push(&mut self, addr: Addr) -> bool {
+        let occurs_check = self.flags.occurs_check;
+
+        if let OccursCheck::False = occurs_check {
+            return false;
+        }
+
+        let h = self.heap.h;
+
+        if !self.occurs_in(&Addr::HeapCell(h), addr.clone()) {
+            return false;
+        }
+
+        // unbind the variable from the unfinished structure, which
+        // can't be part of an error term.
+        let var = match self.write_var {
+            Some(Ref::HeapCell(hc)) => {
+                self.heap[hc] = HeapCellValue::Addr(Addr::HeapCell(hc));
+                Addr::HeapCell(hc)
+            },
+            Some(Ref::StackCell(fr, sc)) => {
+                self.and_stack[fr][sc] = Addr::StackCell(fr, sc);
+                Addr::StackCell(fr, sc)
+            },
+            None => addr.clone()
+        };
+
+        self.occurs_check_failure(var, addr, occurs_check);
+        true
+    }
+
     pub(super) fn unify(&mut self, a1: Addr, a2: Addr) {
+        let occurs_check = self.flags.occurs_check;
+        self.unify_with_occurs_check(a1, a2, occurs_check)
+    }
+
+    pub(super) fn unify_with_occurs_check(&mut self, a1: Addr, a2: Addr,
+                                          occurs_check: OccursCheck)
+    {
         let mut pdl = vec![a1, a2];
 
+        // the pairs of structures already unified, or being unified,
+        // which are passed over when they come up again in the
+        // subterms of rational trees.
+        let mut visited = HashSet::new();
+
         self.fail = false;
 
         while !(pdl.is_empty() || self.fail) {
@@ -112,14 +496,18 @@ impl MachineState {
             if d1 != d2 {
                 match (self.store(d1.clone()), self.store(d2.clone())) {
                     (Addr::HeapCell(hc), _) =>
-                        self.bind(Ref::HeapCell(hc), d2),
+                        self.bind_with_occurs_check(Ref::HeapCell(hc), d2, occurs_check),
                     (_, Addr::HeapCell(hc)) =>
-                        self.bind(Ref::HeapCell(hc), d1),
+                        self.bind_with_occurs_check(Ref::HeapCell(hc), d1, occurs_check),
                     (Addr::StackCell(fr, sc), _) =>
-                        self.bind(Ref::StackCell(fr, sc), d2),
+                        self.bind_with_occurs_check(Ref::StackCell(fr, sc), d2, occurs_check),
                     (_, Addr::StackCell(fr, sc)) =>
-                        self.bind(Ref::StackCell(fr, sc), d1),
+                        self.bind_with_occurs_check(Ref::StackCell(fr, sc), d1, occurs_check),
                     (Addr::Lis(a1), Addr::Lis(a2)) => {
+                        if !visited.insert((a1, a2)) {
+                            continue;
+                        }
+
                         pdl.push(Addr::HeapCell(a1));
                         pdl.push(Addr::HeapCell(a2));
 
@@ -132,6 +520,10 @@ impl MachineState {
                         }
                     },
                     (Addr::Str(a1), Addr::Str(a2)) => {
+                        if !visited.insert((a1, a2)) {
+                            continue;
+                        }
+
                         let r1 = &self.heap[a1];
                         let r2 = &self.heap[a2];
 
@@ -260,7 +652,7 @@ impl MachineState {
         };
     }
 
-    fn get_number(&self, at: &ArithmeticTerm) -> Result<Number, Vec<HeapCellValue>> {
+    fn get_number(&self, at: &ArithmeticTerm) -> Result<Number, MachineError> {
         match at {
             &ArithmeticTerm::Reg(r) =>        self.arith_eval_by_metacall(r),
             &ArithmeticTerm::Interm(i)     => Ok(self.interms[i-1].clone()),
@@ -268,7 +660,7 @@ impl MachineState {
         }
     }
 
-    fn get_rational(&self, at: &ArithmeticTerm) -> Result<Rc<Ratio<BigInt>>, Vec<HeapCellValue>> {
+    fn get_rational(&self, at: &ArithmeticTerm) -> Result<Rc<Ratio<BigInt>>, MachineError> {
         let n = self.get_number(at)?;
 
         match n {
@@ -277,7 +669,7 @@ impl MachineState {
                 if let Some(r) = Ratio::from_float(fl.into_inner()) {
                     Ok(Rc::new(r))
                 } else {
-                    Err(functor!("instantiation_error", 1, [heap_atom!("(is)/2")]))
+                    Err(MachineError::Evaluation("undefined"))
                 },
             Number::Integer(bi) =>
                 Ok(Rc::new(Ratio::from_integer((*bi).clone())))
@@ -296,9 +688,8 @@ impl MachineState {
         Rc::new(BigInt::from_signed_bytes_le(&f(&u_n1, &u_n2).to_bytes_le()))
     }
 
-    pub(super) fn arith_eval_by_metacall(&self, r: RegType) -> Result<Number, Vec<HeapCellValue>>
+    pub(super) fn arith_eval_by_metacall(&self, r: RegType) -> Result<Number, MachineError>
     {
-        let instantiation_err = functor!("instantiation_error", 1, [heap_atom!("(is)/2")]);
         let a = self[r].clone();
 
         let mut interms: Vec<Number> = Vec::with_capacity(64);
@@ -329,7 +720,7 @@ impl MachineState {
                         "xor" => interms.push(Number::Integer(self.xor(a1, a2)?)),
                         "mod" => interms.push(Number::Integer(self.modulus(a1, a2)?)),
                         "rem" => interms.push(Number::Integer(self.remainder(a1, a2)?)),
-                        _     => return Err(instantiation_err)
+                        _     => return Err(MachineError::evaluable(name.clone(), 2))
                     }
                 },
                 HeapCellValue::NamedStr(1, name, Some(Fixity::Pre)) => {
@@ -337,13 +728,21 @@ impl MachineState {
 
                     match name.as_str() {
                         "-" => interms.push(- a1),
-                         _  => return Err(instantiation_err)
+                         _  => return Err(MachineError::evaluable(name.clone(), 1))
                     }
                 },
                 HeapCellValue::Addr(Addr::Con(Constant::Number(n))) =>
                     interms.push(n),
-                _ =>
-                    return Err(instantiation_err)
+                HeapCellValue::NamedStr(arity, name, _) =>
+                    return Err(MachineError::evaluable(name, arity)),
+                HeapCellValue::Addr(Addr::Con(Constant::Atom(name))) =>
+                    return Err(MachineError::evaluable(name, 0)),
+                HeapCellValue::Addr(Addr::Lis(_)) =>
+                    return Err(MachineError::evaluable(clause_name!("."), 2)),
+                HeapCellValue::Addr(Addr::HeapCell(_)) | HeapCellValue::Addr(Addr::StackCell(..)) =>
+                    return Err(MachineError::Instantiation),
+                HeapCellValue::Addr(addr) =>
+                    return Err(MachineError::type_error("evaluable", addr))
             }
         };
 
@@ -351,52 +750,52 @@ impl MachineState {
     }
 
     fn rdiv(&self, r1: Rc<Ratio<BigInt>>, r2: Rc<Ratio<BigInt>>)
-            -> Result<Rc<Ratio<BigInt>>, Vec<HeapCellValue>>
+            -> Result<Rc<Ratio<BigInt>>, MachineError>
     {
         if *r2 == Ratio::zero() {
-            Err(functor!("evaluation_error", 1, [heap_atom!("zero_divisor")]))
+            Err(MachineError::Evaluation("zero_divisor"))
         } else {
             Ok(Rc::new(&*r1 / &*r2))
         }
     }
 
-    fn fidiv(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, Vec<HeapCellValue>>
+    fn fidiv(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, MachineError>
     {
         match (n1, n2) {
             (Number::Integer(n1), Number::Integer(n2)) =>
                 if *n2 == BigInt::zero() {
-                    Err(functor!("evaluation_error", 1, [heap_atom!("zero_divisor")]))
+                    Err(MachineError::Evaluation("zero_divisor"))
                 } else {
                     Ok(Rc::new(n1.div_floor(&n2)))
                 },
-            _ => Err(functor!("evaluation_error", 1, [heap_atom!("expected_integer_args")]))
+            (n1, n2) => Err(integer_type_error(n1, n2))
         }
     }
 
-    fn idiv(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, Vec<HeapCellValue>>
+    fn idiv(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, MachineError>
     {
         match (n1, n2) {
             (Number::Integer(n1), Number::Integer(n2)) =>
                 if *n2 == BigInt::zero() {
-                    Err(functor!("evaluation_error", 1, [heap_atom!("zero_divisor")]))
+                    Err(MachineError::Evaluation("zero_divisor"))
                 } else {
                     Ok(Rc::new(&*n1 / &*n2))
                 },
-            _ =>
-                Err(functor!("evaluation_error", 1, [heap_atom!("expected_integer_args")]))
+            (n1, n2) =>
+                Err(integer_type_error(n1, n2))
         }
     }
 
-    fn div(&self, n1: Number, n2: Number) -> Result<Number, Vec<HeapCellValue>>
+    fn div(&self, n1: Number, n2: Number) -> Result<Number, MachineError>
     {
         if n2.is_zero() {
-            Err(functor!("evaluation_error", 1, [heap_atom!("zero_divisor")]))
+            Err(MachineError::Evaluation("zero_divisor"))
         } else {
             Ok(n1 / n2)
         }
     }
 
-    fn shr(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, Vec<HeapCellValue>>
+    fn shr(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, MachineError>
     {
         match (n1, n2) {
             (Number::Integer(n1), Number::Integer(n2)) =>
@@ -404,12 +803,12 @@ impl MachineState {
                     Some(n2) => Ok(Rc::new(&*n1 >> n2)),
                     _        => Ok(Rc::new(&*n1 >> usize::max_value()))
                 },
-            _ =>
-                Err(functor!("evaluation_error", 1, [heap_atom!("expected_integer_args")]))
+            (n1, n2) =>
+                Err(integer_type_error(n1, n2))
         }
     }
 
-    fn shl(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, Vec<HeapCellValue>>
+    fn shl(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, MachineError>
     {
         match (n1, n2) {
             (Number::Integer(n1), Number::Integer(n2)) =>
@@ -417,180 +816,259 @@ impl MachineState {
                     Some(n2) => Ok(Rc::new(&*n1 << n2)),
                     _        => Ok(Rc::new(&*n1 << usize::max_value()))
                 },
-            _ =>
-                Err(functor!("evaluation_error", 1, [heap_atom!("expected_integer_args")]))
+            (n1, n2) =>
+                Err(integer_type_error(n1, n2))
         }
     }
 
-    fn xor(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, Vec<HeapCellValue>>
+    fn xor(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, MachineError>
     {
         match (n1, n2) {
             (Number::Integer(n1), Number::Integer(n2)) =>
                 Ok(self.signed_bitwise_op(&*n1, &*n2, |u_n1, u_n2| u_n1 ^ u_n2)),
-            _ =>
-                Err(functor!("evaluation_error", 1, [heap_atom!("expected_integer_args")]))
+            (n1, n2) =>
+                Err(integer_type_error(n1, n2))
         }
     }
 
-    fn and(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, Vec<HeapCellValue>>
+    fn and(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, MachineError>
     {
         match (n1, n2) {
             (Number::Integer(n1), Number::Integer(n2)) =>
                 Ok(self.signed_bitwise_op(&*n1, &*n2, |u_n1, u_n2| u_n1 & u_n2)),
-            _ =>
-                Err(functor!("evaluation_error", 1, [heap_atom!("expected_integer_args")]))
+            (n1, n2) =>
+                Err(integer_type_error(n1, n2))
         }
     }
 
-    fn modulus(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, Vec<HeapCellValue>>
+    fn modulus(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, MachineError>
     {
         match (n1, n2) {
             (Number::Integer(n1), Number::Integer(n2)) =>
                 if *n2 == BigInt::zero() {
-                    Err(functor!("evaluation_error", 1, [heap_atom!("zero_divisor")]))
+                    Err(MachineError::Evaluation("zero_divisor"))
                 } else {
                     Ok(Rc::new(n1.mod_floor(&n2)))
                 },
-            _ =>
-                Err(functor!("evaluation_error", 1, [heap_atom!("expected_integer_args")]))
+            (n1, n2) =>
+                Err(integer_type_error(n1, n2))
         }
     }
 
-    fn remainder(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, Vec<HeapCellValue>>
+    fn remainder(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, MachineError>
     {
         match (n1, n2) {
             (Number::Integer(n1), Number::Integer(n2)) =>
                 if *n2 == BigInt::zero() {
-                    Err(functor!("evaluation_error", 1, [heap_atom!("zero_divisor")]))
+                    Err(MachineError::Evaluation("zero_divisor"))
                 } else {
                     Ok(Rc::new(&*n1 % &*n2))
                 },
-            _ =>
-                Err(functor!("evaluation_error", 1, [heap_atom!("expected_integer_args")]))
+            (n1, n2) =>
+                Err(integer_type_error(n1, n2))
         }
     }
 
-    fn or(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, Vec<HeapCellValue>>
+    fn or(&self, n1: Number, n2: Number) -> Result<Rc<BigInt>, MachineError>
     {
         match (n1, n2) {
             (Number::Integer(n1), Number::Integer(n2)) =>
                 Ok(self.signed_bitwise_op(&*n1, &*n2, |u_n1, u_n2| u_n1 & u_n2)),
-            _ =>
-                Err(functor!("evaluation_error", 1, [heap_atom!("expected_integer_args")]))
+            (n1, n2) =>
+                Err(integer_type_error(n1, n2))
         }
     }
 
-    pub(super) fn execute_arith_instr(&mut self, instr: &ArithmeticInstruction) {
+    // the errors of arithmetic instructions are thrown by the machine,
+    // which knows the predicate evaluating them.
+    pub(super) fn execute_arith_instr(&mut self, instr: &ArithmeticInstruction)
+                                      -> Result<(), MachineError>
+    {
         match instr {
             &ArithmeticInstruction::Add(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
                 self.interms[t - 1] = n1 + n2;
                 self.p += 1;
             },
             &ArithmeticInstruction::Sub(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
                 self.interms[t - 1] = n1 - n2;
                 self.p += 1;
             },
             &ArithmeticInstruction::Mul(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
                 self.interms[t - 1] = n1 * n2;
                 self.p += 1;
             },
             &ArithmeticInstruction::RDiv(ref a1, ref a2, t) => {
-                let r1 = try_or_fail!(self, self.get_rational(a1));
-                let r2 = try_or_fail!(self, self.get_rational(a2));
+                let r1 = self.get_rational(a1)?;
+                let r2 = self.get_rational(a2)?;
 
-                self.interms[t - 1] = Number::Rational(try_or_fail!(self, self.rdiv(r1, r2)));
+                self.interms[t - 1] = Number::Rational(self.rdiv(r1, r2)?);
                 self.p += 1;
             },
             &ArithmeticInstruction::FIDiv(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
-                self.interms[t - 1] = Number::Integer(try_or_fail!(self, self.fidiv(n1, n2)));
+                self.interms[t - 1] = Number::Integer(self.fidiv(n1, n2)?);
                 self.p += 1;
             },
             &ArithmeticInstruction::IDiv(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
-                self.interms[t - 1] = Number::Integer(try_or_fail!(self, self.idiv(n1, n2)));
+                self.interms[t - 1] = Number::Integer(self.idiv(n1, n2)?);
                 self.p += 1;
             },
             &ArithmeticInstruction::Neg(ref a1, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
+                let n1 = self.get_number(a1)?;
 
                 self.interms[t - 1] = - n1;
                 self.p += 1;
             },
             &ArithmeticInstruction::Div(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
-                self.interms[t - 1] = try_or_fail!(self, self.div(n1, n2));
+                self.interms[t - 1] = self.div(n1, n2)?;
                 self.p += 1;
             },
             &ArithmeticInstruction::Shr(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
-                self.interms[t - 1] = Number::Integer(try_or_fail!(self, self.shr(n1, n2)));
+                self.interms[t - 1] = Number::Integer(self.shr(n1, n2)?);
                 self.p += 1;
             },
             &ArithmeticInstruction::Shl(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
-                self.interms[t - 1] = Number::Integer(try_or_fail!(self, self.shl(n1, n2)));
+                self.interms[t - 1] = Number::Integer(self.shl(n1, n2)?);
                 self.p += 1;
             },
             &ArithmeticInstruction::Xor(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
-                self.interms[t - 1] = Number::Integer(try_or_fail!(self, self.xor(n1, n2)));
+                self.interms[t - 1] = Number::Integer(self.xor(n1, n2)?);
                 self.p += 1;
             },
             &ArithmeticInstruction::And(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
-                self.interms[t - 1] = Number::Integer(try_or_fail!(self, self.and(n1, n2)));
+                self.interms[t - 1] = Number::Integer(self.and(n1, n2)?);
                 self.p += 1;
             },
             &ArithmeticInstruction::Or(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
-                self.interms[t - 1] = Number::Integer(try_or_fail!(self, self.or(n1, n2)));
+                self.interms[t - 1] = Number::Integer(self.or(n1, n2)?);
                 self.p += 1;
             },
             &ArithmeticInstruction::Mod(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
-                self.interms[t - 1] = Number::Integer(try_or_fail!(self, self.modulus(n1, n2)));
+                self.interms[t - 1] = Number::Integer(self.modulus(n1, n2)?);
                 self.p += 1;
             },
             &ArithmeticInstruction::Rem(ref a1, ref a2, t) => {
-                let n1 = try_or_fail!(self, self.get_number(a1));
-                let n2 = try_or_fail!(self, self.get_number(a2));
+                let n1 = self.get_number(a1)?;
+                let n2 = self.get_number(a2)?;
 
-                self.interms[t - 1] = Number::Integer(try_or_fail!(self, self.remainder(n1, n2)));
+                self.interms[t - 1] = Number::Integer(self.remainder(n1, n2)?);
                 self.p += 1;
             }
         };
+
+        Ok(())
     }
 
     pub(super) fn execute_fact_instr(&mut self, instr: &FactInstruction) {
         match instr {
+            &FactInstruction::GetBoundConstant(ref c, reg) =>
+                match self[reg].clone() {
+                    Addr::Con(ref c1) if c1 == c => {},
+                    _ => self.fail = true
+                },
+            &FactInstruction::GetBoundList(reg) =>
+                match self[reg].clone() {
+                    Addr::Lis(a) => {
+                        self.s = a;
+                        self.mode = MachineMode::Read;
+                    },
+                    _ => self.fail = true
+                },
+            &FactInstruction::GetBoundStructure(ref ct, arity, reg) =>
+                match self[reg].clone() {
+                    Addr::Str(a) =>
+                        if let HeapCellValue::NamedStr(narity, ref s, _) = self.heap[a] {
+                            if narity == arity && ct.name() == *s {
+                                self.s = a + 1;
+                                self.mode = MachineMode::Read;
+                            } else {
+                                self.fail = true;
+                            }
+                        },
+                    _ => self.fail = true
+                },
+            &FactInstruction::GetFreshConstant(ref c, reg) => {
+                let addr = self[reg].clone();
+
+                // an earlier argument of the head may have bound it.
+                match addr.as_var() {
+                    Some(r) if self.store(addr.clone()) == addr =>
+                        self.bind(r, Addr::Con(c.clone())),
+                    _ => self.write_constant_to_var(addr, c.clone())
+                }
+            },
+            &FactInstruction::GetFreshList(reg) => {
+                let addr = self[reg].clone();
+
+                match addr.as_var() {
+                    Some(r) if self.store(addr.clone()) == addr => {
+                        let h = self.heap.h;
+
+                        self.heap.push(HeapCellValue::Addr(Addr::Lis(h+1)));
+                        self.bind(r, Addr::HeapCell(h));
+
+                        self.write_var = Some(r);
+                        self.mode = MachineMode::Write;
+                    },
+                    _ => self.execute_fact_instr(&FactInstruction::GetList(Level::Shallow, reg))
+                }
+            },
+            &FactInstruction::GetFreshStructure(ref ct, arity, reg) => {
+                let addr = self[reg].clone();
+
+                match addr.as_var() {
+                    Some(r) if self.store(addr.clone()) == addr => {
+                        let h = self.heap.h;
+
+                        self.heap.push(HeapCellValue::Addr(Addr::Str(h + 1)));
+                        self.heap.push(HeapCellValue::NamedStr(arity, ct.name(), ct.fixity()));
+
+                        self.bind(r, Addr::HeapCell(h));
+
+                        self.write_var = Some(r);
+                        self.mode = MachineMode::Write;
+                    },
+                    _ => {
+                        let instr = FactInstruction::GetStructure(ct.clone(), arity, reg);
+                        self.execute_fact_instr(&instr)
+                    }
+                }
+            },
             &FactInstruction::GetConstant(_, ref c, reg) => {
                 let addr = self[reg].clone();
                 self.write_constant_to_var(addr, c.clone());
@@ -605,6 +1083,7 @@ impl MachineState {
                         self.heap.push(HeapCellValue::Addr(Addr::Lis(h+1)));
                         self.bind(Ref::HeapCell(hc), Addr::HeapCell(h));
 
+                        self.write_var = Some(Ref::HeapCell(hc));
                         self.mode = MachineMode::Write;
                     },
                     Addr::StackCell(fr, sc) => {
@@ -613,6 +1092,7 @@ impl MachineState {
                         self.heap.push(HeapCellValue::Addr(Addr::Lis(h+1)));
                         self.bind(Ref::StackCell(fr, sc), Addr::HeapCell(h));
 
+                        self.write_var = Some(Ref::StackCell(fr, sc));
                         self.mode = MachineMode::Write;
                     },
                     Addr::Lis(a) => {
@@ -646,6 +1126,7 @@ impl MachineState {
 
                         self.bind(addr.as_var().unwrap(), Addr::HeapCell(h));
 
+                        self.write_var = addr.as_var();
                         self.mode = MachineMode::Write;
                     },
                     _ => self.fail = true
@@ -698,6 +1179,10 @@ impl MachineState {
                         let addr = self.deref(self[reg].clone());
                         let h    = self.heap.h;
 
+                        if self.occurs_check_on_push(addr.clone()) {
+                            return;
+                        }
+
                         if let Addr::HeapCell(hc) = addr {
                             if hc < h {
                                 let val = self.heap[hc].clone();
@@ -726,6 +1211,11 @@ impl MachineState {
                     },
                     MachineMode::Write => {
                         let heap_val = self.store(self[reg].clone());
+
+                        if self.occurs_check_on_push(heap_val.clone()) {
+                            return;
+                        }
+
                         self.heap.push(HeapCellValue::Addr(heap_val));
                     }
                 };
@@ -908,8 +1398,14 @@ impl MachineState {
         if arity > 1 {
             self.registers[arity - 1] = pred;
 
-            if let Some((name, arity)) = self.setup_call_n(arity - 1) {
-                if let Some(idx) = code_dirs.get(name.clone(), arity, &self.p.clone()) {
+            if let Some((module_name, (name, arity))) = self.setup_call_n(arity - 1) {
+                let context = self.context_module();
+                let idx = code_dirs.get_qualified(module_name.clone(), name.clone(), arity, &context);
+
+                if let Some(idx) = idx {
+                    let caller = module_name.unwrap_or(context);
+                    self.qualify_meta_args(&code_dirs, caller, &idx.1, &(name.clone(), arity));
+
                     try_or_fail!(self, call_policy.try_execute(self, name, arity, idx));
                     return;
                 }
@@ -932,7 +1428,31 @@ impl MachineState {
         self.fail = true;
     }  
 
-    fn throw_exception(&mut self, hcv: Vec<HeapCellValue>) {
+    // the error term raised by the predicate Name/Arity, laid out to
+    // be thrown from the top of the heap.
+    pub(super) fn error_form(&self, err: MachineError, name: ClauseName, arity: usize)
+                             -> MachineStub
+    {
+        err.to_stub(self.heap.h, name, arity)
+    }
+
+    // throws the occurs_check error left by a failed unification of
+    // the predicate Name/Arity, if it's known.
+    pub(super) fn throw_occurs_check_error(&mut self, var: Addr, term: Addr,
+                                           context: Option<(ClauseName, usize)>)
+    {
+        let err = MachineError::occurs_check(var, term);
+
+        let stub = match context {
+            Some((name, arity)) => self.error_form(err, name, arity),
+            None => err.to_stub_in_unknown_context(self.heap.h)
+        };
+
+        self.fail = false;
+        self.throw_exception(stub);
+    }
+
+    pub(super) fn throw_exception(&mut self, hcv: MachineStub) {
         let h = self.heap.h;
 
         self.ball.0 = 0;
@@ -940,772 +1460,2426 @@ impl MachineState {
 
         self.registers[1] = Addr::HeapCell(h);
 
+        // throw/1 never returns, so cp is free to record where the
+        // error was raised for the backtrace taken by set_ball.
+        self.cp = self.p.clone();
+
         self.heap.append(hcv);
         self.goto_throw();
     }
 
-    pub(super) fn setup_call_n(&mut self, arity: usize) -> Option<PredicateKey>
-    {
-        let addr = self.store(self.deref(self.registers[arity].clone()));
+    // unifies A1 with the predicate indicators of the backtrace taken
+    // at the last throw, innermost first.
+    pub(super) fn unify_backtrace(&mut self, code_dirs: &CodeDirs) {
+        let mut frames = Vec::new();
 
-        let (name, narity) = match addr {
-            Addr::Str(a) => {
-                let result = self.heap[a].clone();
+        for p in self.backtrace.clone() {
+            if let CodePtr::DirEntry(p, _) = p {
+                if let Some((name, arity)) = code_dirs.predicate_at(p) {
+                    let h = self.heap.h;
+                    let name = HeapCellValue::Addr(Addr::Con(Constant::Atom(name)));
 
-                if let HeapCellValue::NamedStr(narity, name, _) = result {
-                    if narity + arity > 63 {
-                        self.throw_exception(functor!("representation_error", 1,
-                                                      [heap_atom!("exceeds_max_arity")]));
-                        return None;
-                    }
+                    self.heap.append(functor!("/", 2, [name, heap_integer!(arity)], Fixity::In));
+                    frames.push(Addr::Str(h));
+                }
+            }
+        }
 
-                    for i in (1 .. arity).rev() {
-                        self.registers[i + narity] = self.registers[i].clone();
-                    }
+        let list = Addr::HeapCell(self.to_list(frames.into_iter()));
+        let a1 = self[temp_v!(1)].clone();
 
-                    for i in 1 .. narity + 1 {
-                        self.registers[i] = self.heap[a + i].as_addr(a + i);
-                    }
+        self.unify(a1, list);
+    }
 
-                    (name, narity)
-                } else {
-                    self.fail = true;
-                    return None;
-                }
-            },
-            Addr::Con(Constant::Atom(name)) => (name, 0),
-            Addr::HeapCell(_) | Addr::StackCell(_, _) => {
-                self.throw_exception(functor!("instantiation_error"));
-                return None;
-            },
-            _ => {
-                self.throw_exception(functor!("type_error", 2,
-                                              [heap_atom!("callable"),
-                                               HeapCellValue::Addr(addr)]));
-                return None;
+    // the continuations of the active predicates, starting from p and
+    // walking the and_stack frames back to the toplevel.
+    pub(super) fn continuations(&self, p: CodePtr) -> Vec<CodePtr> {
+        let mut frames = vec![p];
+        let mut e = self.e;
+
+        while frames.len() < MAX_BACKTRACE_FRAMES && e < self.and_stack.len() {
+            let frame = &self.and_stack[e];
+            frames.push(frame.cp.clone());
+
+            if frame.e == e {
+                break;
             }
-        };
 
-        Some((name, arity + narity - 1))
+            e = frame.e;
+        }
+
+        frames
     }
 
-    pub(super) fn copy_and_align_ball_to_heap(&mut self) {
-        let diff = if self.ball.0 > self.heap.h {
-            self.ball.0 - self.heap.h
-        } else {
-            self.heap.h - self.ball.0
+    // the module in whose context the code at p runs. builtins run in
+    // the context of the toplevel, ie. user.
+    pub(super) fn context_module(&self) -> ClauseName {
+        if let CodePtr::TopLevel(..) = self.p {
+            return self.toplevel_module.clone();
+        }
+
+        match self.p.module_name() {
+            ClauseName::BuiltIn("builtin") => clause_name!("user"),
+            module_name => module_name
+        }
+    }
+
+    // the meta arguments of a call crossing from the module caller
+    // into module_name are qualified by caller, unless they already
+    // are.
+    pub(super) fn qualify_meta_args(&mut self, code_dirs: &CodeDirs, caller: ClauseName,
+                                    module_name: &ClauseName, key: &PredicateKey)
+    {
+        let callee = match module_name.as_str() {
+            "builtin" => clause_name!("user"),
+            _ => module_name.clone()
         };
 
-        for heap_value in self.ball.1.iter().cloned() {
-            self.heap.push(match heap_value {
-                HeapCellValue::Addr(Addr::Con(c)) =>
-                    HeapCellValue::Addr(Addr::Con(c)),
-                HeapCellValue::Addr(Addr::Lis(a)) =>
-                    HeapCellValue::Addr(Addr::Lis(a - diff)),
-                HeapCellValue::Addr(Addr::HeapCell(hc)) =>
-                    HeapCellValue::Addr(Addr::HeapCell(hc - diff)),
-                HeapCellValue::Addr(Addr::Str(s)) =>
-                    HeapCellValue::Addr(Addr::Str(s - diff)),
-                _ => heap_value
-            });
+        if caller == callee {
+            return;
         }
-    }
 
-    pub(super) fn is_cyclic_term(&self, addr: Addr) -> bool {
-        let mut seen = HashSet::new();
-        let mut fail = false;
-        
-        let mut iter = self.pre_order_iter(addr);
+        let specs = match code_dirs.meta_specs(module_name, key) {
+            Some(specs) => specs.clone(),
+            None => return
+        };
 
-        loop {
-            if let Some(addr) = iter.stack().last() {
-                if !seen.contains(addr) {                            
-                    seen.insert(addr.clone());
-                } else {
-                    fail = true;
-                    break;
-                }                            
+        for (i, spec) in specs.into_iter().enumerate() {
+            if !spec.is_meta() {
+                continue;
             }
 
-            if iter.next().is_none() {
-                break;
+            let arg = self.store(self.deref(self.registers[i + 1].clone()));
+
+            if let Addr::Str(s) = arg.clone() {
+                if let HeapCellValue::NamedStr(2, ref name, _) = self.heap[s].clone() {
+                    if name.as_str() == ":" {
+                        continue;
+                    }
+                }
             }
-        }
 
-        fail
+            let h = self.heap.h;
+            let caller = HeapCellValue::Addr(Addr::Con(Constant::Atom(caller.clone())));
+
+            self.heap.append(functor!(":", 2, [caller, HeapCellValue::Addr(arg)], Fixity::In));
+            self.registers[i + 1] = Addr::Str(h);
+        }
     }
-    
-    fn try_get_arg(&mut self) -> Result<(), Vec<HeapCellValue>>
-    {
-        let a1 = self.store(self.deref(self[temp_v!(1)].clone()));
 
-        if let Addr::Con(Constant::Number(Number::Integer(i))) = a1 {
-            let a2 = self.store(self.deref(self[temp_v!(2)].clone()));
+    // strip_module/3, which unlike the qualifiers of call/N tolerates
+    // qualifiers that aren't atoms, leaving them in place.
+    pub(super) fn strip_module_3(&mut self) {
+        let mut module_name = self.context_module();
+        let mut addr = self.store(self.deref(self[temp_v!(1)].clone()));
+
+        while let Addr::Str(s) = addr.clone() {
+            match self.heap[s].clone() {
+                HeapCellValue::NamedStr(2, ref name, _) if name.as_str() == ":" =>
+                    match self.store(self.deref(Addr::HeapCell(s + 1))) {
+                        Addr::Con(Constant::Atom(name)) => {
+                            module_name = name;
+                            addr = self.store(self.deref(Addr::HeapCell(s + 2)));
+                        },
+                        _ => break
+                    },
+                _ => break
+            }
+        }
 
-            if let Addr::Str(o) = a2 {
-                match self.heap[o].clone() {
-                    HeapCellValue::NamedStr(arity, _, _) =>
-                        match i.to_usize() {
-                            Some(i) if 1 <= i && i <= arity => {
-                                let a3  = self[temp_v!(3)].clone();
-                                let h_a = Addr::HeapCell(o + i);
+        let a2 = self[temp_v!(2)].clone();
+        let a3 = self[temp_v!(3)].clone();
 
-                                self.unify(a3, h_a);
-                            },
-                            _ => self.fail = true
-                        },
-                    _ => self.fail = true
-                };
-            } else {
-                return Err(functor!("type_error", 1, [heap_atom!("compound_expected")]))
+        self.unify(a2, Addr::Con(Constant::Atom(module_name)));
+
+        if !self.fail {
+            self.unify(a3, addr);
+        }
+    }
+
+    // the modules a reflection goal ranges over, whether its answers
+    // are qualified, and the unqualified term. an unbound qualifier
+    // ranges over every module but builtin.
+    fn reflection_scope(&self, code_dirs: &CodeDirs, addr: Addr)
+                        -> (Vec<ClauseName>, bool, Addr)
+    {
+        let mut addr = self.store(self.deref(addr));
+        let mut scope = None;
+
+        while let Addr::Str(s) = addr.clone() {
+            match self.heap[s].clone() {
+                HeapCellValue::NamedStr(2, ref name, _) if name.as_str() == ":" => {
+                    scope = Some(match self.store(self.deref(Addr::HeapCell(s + 1))) {
+                        Addr::Con(Constant::Atom(name)) => vec![name],
+                        Addr::HeapCell(_) | Addr::StackCell(..) =>
+                            code_dirs.module_names().into_iter()
+                                     .filter(|name| name.as_str() != "builtin")
+                                     .collect(),
+                        _ => vec![]
+                    });
+
+                    addr = self.store(self.deref(Addr::HeapCell(s + 2)));
+                },
+                _ => break
             }
         }
 
-        Ok(())
+        match scope {
+            Some(module_names) => (module_names, true, addr),
+            None => (vec![self.context_module()], false, addr)
+        }
     }
 
-    fn compare_numbers(&mut self, cmp: CompareNumberQT, n1: Number, n2: Number) {
-        self.fail = match cmp {
-            CompareNumberQT::GreaterThan if !(n1.gt(n2)) => true,
-            CompareNumberQT::GreaterThanOrEqual if !(n1.gte(n2)) => true,
-            CompareNumberQT::LessThan if !(n1.lt(n2)) => true,
-            CompareNumberQT::LessThanOrEqual if !(n1.lte(n2)) => true,
-            CompareNumberQT::NotEqual if !(n1.ne(n2)) => true,
-            CompareNumberQT::Equal if !(n1.eq(n2)) => true,
-            _ => false
-        };
+    fn qualify_answer(&mut self, module_name: &ClauseName, qualified: bool, addr: Addr) -> Addr {
+        if !qualified {
+            return addr;
+        }
 
-        self.p += 1;
+        let h = self.heap.h;
+        let module_name = HeapCellValue::Addr(Addr::Con(Constant::Atom(module_name.clone())));
+
+        self.heap.append(functor!(":", 2, [module_name, HeapCellValue::Addr(addr)], Fixity::In));
+        Addr::Str(h)
     }
 
-    pub(super) fn compare_term(&mut self, qt: CompareTermQT) {
-        let a1 = self[temp_v!(1)].clone();
-        let a2 = self[temp_v!(2)].clone();
+    fn indicator_term(&mut self, name: ClauseName, arity: usize) -> Addr {
+        let h = self.heap.h;
+        let name = HeapCellValue::Addr(Addr::Con(Constant::Atom(name)));
 
-        match self.compare_term_test(&a1, &a2) {
-            Ordering::Greater =>
-                match qt {
-                    CompareTermQT::GreaterThan | CompareTermQT::GreaterThanOrEqual => return,
-                    _ => self.fail = true
-                },
-            Ordering::Equal =>
-                match qt {
-                    CompareTermQT::GreaterThanOrEqual | CompareTermQT::LessThanOrEqual => return,
-                    _ => self.fail = true
-                },
-            Ordering::Less =>
-                match qt {
-                    CompareTermQT::LessThan | CompareTermQT::LessThanOrEqual => return,
-                    _ => self.fail = true
-                }
-        };
+        self.heap.append(functor!("/", 2, [name, heap_integer!(arity)], Fixity::In));
+        Addr::Str(h)
     }
 
-    pub(super) fn compare_term_test(&self, a1: &Addr, a2: &Addr) -> Ordering {
-        let iter = self.zipped_acyclic_pre_order_iter(a1.clone(), a2.clone());
+    // the most general goal of name/arity.
+    fn head_term(&mut self, name: ClauseName, arity: usize) -> Addr {
+        if arity == 0 {
+            return Addr::Con(Constant::Atom(name));
+        }
 
-        for (v1, v2) in iter {
-            match (v1, v2) {
-                (HeapCellValue::Addr(Addr::HeapCell(hc1)),
-                 HeapCellValue::Addr(Addr::HeapCell(hc2))) =>
-                    if hc1 != hc2 {
-                        return hc1.cmp(&hc2);
-                    },
-                (HeapCellValue::Addr(Addr::HeapCell(_)), _) =>
-                    return Ordering::Less,
-                (HeapCellValue::Addr(Addr::StackCell(fr1, sc1)),
-                 HeapCellValue::Addr(Addr::StackCell(fr2, sc2))) =>
-                    if fr1 > fr2 {
-                        return Ordering::Greater;
-                    } else if fr1 < fr2 || sc1 < sc2 {
-                        return Ordering::Less;
-                    } else if sc1 > sc2 {
-                        return Ordering::Greater;
-                    },
-                (HeapCellValue::Addr(Addr::StackCell(..)),
-                 HeapCellValue::Addr(Addr::HeapCell(_))) =>
-                    return Ordering::Greater,
-                (HeapCellValue::Addr(Addr::StackCell(..)), _) =>
-                    return Ordering::Less,
-                (HeapCellValue::Addr(Addr::Con(Constant::Number(..))),
-                 HeapCellValue::Addr(Addr::HeapCell(_))) =>
-                    return Ordering::Greater,
-                (HeapCellValue::Addr(Addr::Con(Constant::Number(..))),
-                 HeapCellValue::Addr(Addr::StackCell(..))) =>
-                    return Ordering::Greater,
-                (HeapCellValue::Addr(Addr::Con(Constant::Number(n1))),
-                 HeapCellValue::Addr(Addr::Con(Constant::Number(n2)))) =>
-                    if n1 != n2 {
-                        return n1.cmp(&n2);
-                    },
-                (HeapCellValue::Addr(Addr::Con(Constant::Number(_))), _) =>
-                    return Ordering::Less,
-                (HeapCellValue::Addr(Addr::Con(Constant::String(..))),
-                 HeapCellValue::Addr(Addr::HeapCell(_))) =>
-                    return Ordering::Greater,
-                (HeapCellValue::Addr(Addr::Con(Constant::String(..))),
-                 HeapCellValue::Addr(Addr::StackCell(..))) =>
-                    return Ordering::Greater,
-                (HeapCellValue::Addr(Addr::Con(Constant::String(_))),
-                 HeapCellValue::Addr(Addr::Con(Constant::Number(_)))) =>
-                    return Ordering::Greater,
-                (HeapCellValue::Addr(Addr::Con(Constant::String(s1))),
-                 HeapCellValue::Addr(Addr::Con(Constant::String(s2)))) =>
-                    if s1 != s2 {
-                        return s1.cmp(&s2);
-                    },
-                (HeapCellValue::Addr(Addr::Con(Constant::String(_))), _) =>
-                    return Ordering::Less,
-                (HeapCellValue::Addr(Addr::Con(Constant::Atom(..))),
-                 HeapCellValue::Addr(Addr::HeapCell(_))) =>
-                    return Ordering::Greater,
-                (HeapCellValue::Addr(Addr::Con(Constant::Atom(..))),
-                 HeapCellValue::Addr(Addr::StackCell(..))) =>
-                    return Ordering::Greater,
-                (HeapCellValue::Addr(Addr::Con(Constant::Atom(_))),
-                 HeapCellValue::Addr(Addr::Con(Constant::Number(_)))) =>
-                    return Ordering::Greater,
-                (HeapCellValue::Addr(Addr::Con(Constant::Atom(_))),
-                 HeapCellValue::Addr(Addr::Con(Constant::String(_)))) =>
-                    return Ordering::Greater,
-                (HeapCellValue::Addr(Addr::Con(Constant::Atom(s1))),
-                 HeapCellValue::Addr(Addr::Con(Constant::Atom(s2)))) =>
-                    if s1 != s2 {
-                        return s1.cmp(&s2);
-                    },
-                (HeapCellValue::Addr(Addr::Con(Constant::Atom(_))), _) =>
-                    return Ordering::Less,
-                (HeapCellValue::NamedStr(ar1, n1, _), HeapCellValue::NamedStr(ar2, n2, _)) =>
-                    if ar1 < ar2 {
-                        return Ordering::Less;
-                    } else if ar1 > ar2 {
-                        return Ordering::Greater;
-                    } else if n1 != n2 {
-                        return n1.cmp(&n2);
-                    },
-                (HeapCellValue::Addr(Addr::Lis(_)), HeapCellValue::Addr(Addr::Lis(_))) =>
-                    continue,
-                (HeapCellValue::Addr(Addr::Lis(_)), HeapCellValue::NamedStr(ar, n, _))
-              | (HeapCellValue::NamedStr(ar, n, _), HeapCellValue::Addr(Addr::Lis(_))) =>
-                    if ar == 2 && n.as_str() == "." {
-                        continue;
-                    } else if ar < 2 {
-                        return Ordering::Greater;
-                    } else if ar > 2 {
-                        return Ordering::Less;
-                    } else {
-                        return n.as_str().cmp(".");
-                    },
-                (HeapCellValue::NamedStr(..), _) =>
-                    return Ordering::Greater,
-                (HeapCellValue::Addr(Addr::Lis(_)), _) =>
-                    return Ordering::Greater,
-                _ => {}
-            }
-        };
+        let h = self.heap.h;
 
-        Ordering::Equal
+        self.heap.push(HeapCellValue::NamedStr(arity, name, None));
+
+        for i in 1 .. arity + 1 {
+            self.heap.push(HeapCellValue::Addr(Addr::HeapCell(h + i)));
+        }
+
+        Addr::Str(h)
     }
 
-    fn reset_block(&mut self, addr: Addr) {
-        match self.store(addr) {
-            Addr::Con(Constant::Usize(b)) => {
-                self.block = b;
-                self.p += 1;
+    fn pair_term(&mut self, key: Addr, value: Addr) -> Addr {
+        let h = self.heap.h;
+
+        self.heap.append(functor!("-", 2, [HeapCellValue::Addr(key), HeapCellValue::Addr(value)],
+                                  Fixity::In));
+        Addr::Str(h)
+    }
+
+    // the name and arity of a bound head, which narrows the predicates
+    // a reflection goal ranges over.
+    fn head_key(&self, addr: Addr) -> Result<Option<PredicateKey>, MachineError> {
+        match addr {
+            Addr::Con(Constant::Atom(name)) => Ok(Some((name, 0))),
+            Addr::Str(s) => match self.heap[s].clone() {
+                HeapCellValue::NamedStr(arity, name, _) => Ok(Some((name, arity))),
+                _ => Ok(None)
             },
-            _ => self.fail = true
+            Addr::HeapCell(_) | Addr::StackCell(..) => Ok(None),
+            culprit => Err(MachineError::type_error("callable", culprit))
+        }
+    }
+
+    fn indicator_key(&self, addr: Addr) -> Result<Option<PredicateKey>, MachineError> {
+        let s = match addr.clone() {
+            Addr::HeapCell(_) | Addr::StackCell(..) => return Ok(None),
+            Addr::Str(s) => s,
+            culprit => return Err(MachineError::type_error("predicate_indicator", culprit))
         };
+
+        match self.heap[s].clone() {
+            HeapCellValue::NamedStr(2, ref name, _) if name.as_str() == "/" => {
+                let name  = self.store(self.deref(Addr::HeapCell(s + 1)));
+                let arity = self.store(self.deref(Addr::HeapCell(s + 2)));
+
+                match (name, arity) {
+                    (Addr::Con(Constant::Atom(name)),
+                     Addr::Con(Constant::Number(Number::Integer(arity)))) =>
+                        Ok(arity.to_usize().map(|arity| (name, arity))),
+                    (Addr::Con(Constant::Atom(_)), Addr::HeapCell(_))
+                  | (Addr::Con(Constant::Atom(_)), Addr::StackCell(..))
+                  | (Addr::HeapCell(_), _) | (Addr::StackCell(..), _) =>
+                        Ok(None),
+                    _ => Err(MachineError::type_error("predicate_indicator", addr))
+                }
+            },
+            _ => Err(MachineError::type_error("predicate_indicator", addr))
+        }
     }
 
-    pub(super) fn execute_inlined(&mut self, inlined: &InlinedClauseType, rs: &Vec<RegType>)
+    // the predicates of the reflection scope of a1, narrowed to key if
+    // it's known and, unless builtins are asked for, defined outside of
+    // the builtin module.
+    fn reflected_predicates(&self, code_dirs: &CodeDirs, module_names: &Vec<ClauseName>,
+                            key: &Option<PredicateKey>, builtins: bool)
+                            -> Vec<(ClauseName, PredicateKey, ClauseName)>
     {
-        let r1 = rs[0].clone();
+        let mut preds = vec![];
 
-        match inlined {
-            &InlinedClauseType::CompareNumber(cmp) => {
-                let r2 = rs[1].clone();
+        for module_name in module_names {
+            for (pred_key, def_module) in code_dirs.visible_predicates(module_name) {
+                if !builtins && def_module.as_str() == "builtin" {
+                    continue;
+                }
 
-                let n1 = try_or_fail!(self, self.arith_eval_by_metacall(r1));
-                let n2 = try_or_fail!(self, self.arith_eval_by_metacall(r2));
+                match key {
+                    &Some(ref key) if key != &pred_key => continue,
+                    _ => preds.push((module_name.clone(), pred_key, def_module))
+                }
+            }
+        }
 
-                self.compare_numbers(cmp, n1, n2);
-            },
-            &InlinedClauseType::IsAtom => {
-                let d = self.store(self.deref(self[r1].clone()));
+        preds
+    }
 
-                match d {
-                    Addr::Con(Constant::Atom(_)) => self.p += 1,
-                    _ => self.fail = true
-                };
-            },
-            &InlinedClauseType::IsAtomic => {
-                let d = self.store(self.deref(self[r1].clone()));
+    pub(super) fn current_modules(&mut self, code_dirs: &CodeDirs) {
+        let module_names = code_dirs.module_names().into_iter()
+            .map(|name| Addr::Con(Constant::Atom(name)));
 
-                match d {
-                    Addr::Con(_) => self.p += 1,
-                    _ => self.fail = true
-                };
-            },
-            &InlinedClauseType::IsInteger => {
-                let d = self.store(self.deref(self[r1].clone()));
+        let list = Addr::HeapCell(self.to_list(module_names));
+        let a1 = self[temp_v!(1)].clone();
+
+        self.unify(a1, list);
+    }
+
+    // the properties of the module in A1, as exports(PIs) and, for
+    // modules loaded from files, file(F).
+    pub(super) fn module_properties(&mut self, code_dirs: &CodeDirs) {
+        let module_name = match self.store(self.deref(self[temp_v!(1)].clone())) {
+            Addr::Con(Constant::Atom(name)) => name,
+            _ => {
+                self.fail = true;
+                return;
+            }
+        };
+
+        let exports: Vec<_> = code_dirs.module_exports(&module_name).into_iter()
+            .map(|(name, arity)| self.indicator_term(name, arity))
+            .collect();
+
+        let exports = Addr::HeapCell(self.to_list(exports.into_iter()));
+        let h = self.heap.h;
+
+        self.heap.append(functor!("exports", 1, [HeapCellValue::Addr(exports)]));
+
+        let mut props = vec![Addr::Str(h)];
+
+        if let Some(file) = code_dirs.module_file(&module_name) {
+            let h = self.heap.h;
+            let file = HeapCellValue::Addr(Addr::Con(Constant::Atom(file)));
+
+            self.heap.append(functor!("file", 1, [file]));
+            props.push(Addr::Str(h));
+        }
+
+        let list = Addr::HeapCell(self.to_list(props.into_iter()));
+        let a2 = self[temp_v!(2)].clone();
+
+        self.unify(a2, list);
+    }
+
+    // the candidate answers of current_predicate/1, shaped like the
+    // indicator in A1.
+    pub(super) fn predicate_indicators(&mut self, code_dirs: &CodeDirs) -> CallResult {
+        let (module_names, qualified, spec) =
+            self.reflection_scope(code_dirs, self[temp_v!(1)].clone());
+
+        let key = match self.indicator_key(spec) {
+            Ok(key) => key,
+            Err(err) => return Err(self.error_form(err, clause_name!("current_predicate"), 1))
+        };
+
+        let mut answers = vec![];
+
+        for (module_name, (name, arity), _) in
+            self.reflected_predicates(code_dirs, &module_names, &key, false)
+        {
+            let pi = self.indicator_term(name, arity);
+            answers.push(self.qualify_answer(&module_name, qualified, pi));
+        }
+
+        let list = Addr::HeapCell(self.to_list(answers.into_iter()));
+        let a2 = self[temp_v!(2)].clone();
+
+        self.unify(a2, list);
+        Ok(())
+    }
+
+    // the candidate answers of current_predicate/2, as Name-Head pairs
+    // with heads shaped like the one in A1.
+    pub(super) fn predicate_heads(&mut self, code_dirs: &CodeDirs) -> CallResult {
+        let (module_names, qualified, head) =
+            self.reflection_scope(code_dirs, self[temp_v!(1)].clone());
+
+        let key = match self.head_key(head) {
+            Ok(key) => key,
+            Err(err) => return Err(self.error_form(err, clause_name!("current_predicate"), 2))
+        };
+
+        let mut answers = vec![];
+
+        for (module_name, (name, arity), _) in
+            self.reflected_predicates(code_dirs, &module_names, &key, false)
+        {
+            let head = self.head_term(name.clone(), arity);
+            let head = self.qualify_answer(&module_name, qualified, head);
+
+            answers.push(self.pair_term(Addr::Con(Constant::Atom(name)), head));
+        }
+
+        let list = Addr::HeapCell(self.to_list(answers.into_iter()));
+        let a2 = self[temp_v!(2)].clone();
+
+        self.unify(a2, list);
+        Ok(())
+    }
+
+    // the candidate answers of predicate_property/2, as Head-Props
+    // pairs with heads shaped like the one in A1.
+    pub(super) fn predicate_properties(&mut self, code_dirs: &CodeDirs) -> CallResult {
+        let (module_names, qualified, head) =
+            self.reflection_scope(code_dirs, self[temp_v!(1)].clone());
+
+        let key = match self.head_key(head) {
+            Ok(key) => key,
+            Err(err) => return Err(self.error_form(err, clause_name!("predicate_property"), 2))
+        };
+
+        let mut answers = vec![];
+
+        for (module_name, key, def_module) in
+            self.reflected_predicates(code_dirs, &module_names, &key, true)
+        {
+            let mut props = vec![Addr::Con(atom!("defined"))];
+
+            let exported = match def_module.as_str() {
+                "builtin" => {
+                    props.push(Addr::Con(atom!("built_in")));
+                    true
+                },
+                _ => code_dirs.module_exports(&def_module).contains(&key)
+            };
+
+            if exported {
+                props.push(Addr::Con(atom!("exported")));
+            }
+
+            // predicates are only ever defined by consulting clauses, so
+            // all of them are static.
+            props.push(Addr::Con(atom!("static")));
+
+            if def_module != module_name && def_module.as_str() != "builtin" {
+                let h = self.heap.h;
+                let def_module = HeapCellValue::Addr(Addr::Con(Constant::Atom(def_module.clone())));
+
+                self.heap.append(functor!("imported_from", 1, [def_module]));
+                props.push(Addr::Str(h));
+            }
+
+            if let Some(n) = code_dirs.number_of_clauses(&def_module, &key) {
+                let h = self.heap.h;
+
+                self.heap.append(functor!("number_of_clauses", 1, [heap_integer!(n)]));
+                props.push(Addr::Str(h));
+            }
+
+            if let Some(file) = code_dirs.predicate_file(&def_module, &key) {
+                let h = self.heap.h;
+                let file = HeapCellValue::Addr(Addr::Con(Constant::Atom(file)));
+
+                self.heap.append(functor!("file", 1, [file]));
+                props.push(Addr::Str(h));
+            }
+
+            let (name, arity) = key;
+
+            let head  = self.head_term(name, arity);
+            let head  = self.qualify_answer(&module_name, qualified, head);
+            let props = Addr::HeapCell(self.to_list(props.into_iter()));
+
+            answers.push(self.pair_term(head, props));
+        }
+
+        let list = Addr::HeapCell(self.to_list(answers.into_iter()));
+        let a2 = self[temp_v!(2)].clone();
+
+        self.unify(a2, list);
+        Ok(())
+    }
+
+    // strips the module qualifiers of the goal in A(arity), the innermost
+    // of which names the module the goal is called in.
+    fn strip_module(&mut self, arity: usize) -> Option<(Option<ClauseName>, Addr)>
+    {
+        let mut module_name = None;
+        let mut addr = self.store(self.deref(self.registers[arity].clone()));
+
+        while let Addr::Str(a) = addr.clone() {
+            match self.heap[a].clone() {
+                HeapCellValue::NamedStr(2, ref name, _) if name.as_str() == ":" => {
+                    let err = match self.store(self.deref(Addr::HeapCell(a + 1))) {
+                        Addr::Con(Constant::Atom(name)) => {
+                            module_name = Some(name);
+                            addr = self.store(self.deref(Addr::HeapCell(a + 2)));
+                            continue;
+                        },
+                        Addr::HeapCell(_) | Addr::StackCell(..) =>
+                            MachineError::Instantiation,
+                        culprit =>
+                            MachineError::type_error("atom", culprit)
+                    };
+
+                    let stub = self.error_form(err, clause_name!("call"), arity);
+
+                    self.throw_exception(stub);
+                    return None;
+                },
+                _ => break
+            }
+        }
+
+        Some((module_name, addr))
+    }
+
+    pub(super) fn setup_call_n(&mut self, arity: usize)
+                               -> Option<(Option<ClauseName>, PredicateKey)>
+    {
+        let (module_name, addr) = self.strip_module(arity)?;
+
+        let (name, narity) = match addr {
+            Addr::Str(a) => {
+                let result = self.heap[a].clone();
+
+                if let HeapCellValue::NamedStr(narity, name, _) = result {
+                    if narity + arity > MAX_ARITY {
+                        let err = MachineError::Representation("max_arity");
+                        let stub = self.error_form(err, clause_name!("call"), arity);
+
+                        self.throw_exception(stub);
+                        return None;
+                    }
+
+                    for i in (1 .. arity).rev() {
+                        self.registers[i + narity] = self.registers[i].clone();
+                    }
+
+                    for i in 1 .. narity + 1 {
+                        self.registers[i] = self.heap[a + i].as_addr(a + i);
+                    }
+
+                    (name, narity)
+                } else {
+                    self.fail = true;
+                    return None;
+                }
+            },
+            Addr::Con(Constant::Atom(name)) => (name, 0),
+            Addr::HeapCell(_) | Addr::StackCell(_, _) => {
+                let stub = self.error_form(MachineError::Instantiation, clause_name!("call"), arity);
+
+                self.throw_exception(stub);
+                return None;
+            },
+            _ => {
+                let err  = MachineError::type_error("callable", addr);
+                let stub = self.error_form(err, clause_name!("call"), arity);
+
+                self.throw_exception(stub);
+                return None;
+            }
+        };
+
+        Some((module_name, (name, arity + narity - 1)))
+    }
+
+    pub(super) fn copy_and_align_ball_to_heap(&mut self) {
+        let diff = if self.ball.0 > self.heap.h {
+            self.ball.0 - self.heap.h
+        } else {
+            self.heap.h - self.ball.0
+        };
+
+        for heap_value in self.ball.1.iter().cloned() {
+            self.heap.push(match heap_value {
+                HeapCellValue::Addr(Addr::Con(c)) =>
+                    HeapCellValue::Addr(Addr::Con(c)),
+                HeapCellValue::Addr(Addr::Lis(a)) =>
+                    HeapCellValue::Addr(Addr::Lis(a - diff)),
+                HeapCellValue::Addr(Addr::HeapCell(hc)) =>
+                    HeapCellValue::Addr(Addr::HeapCell(hc - diff)),
+                HeapCellValue::Addr(Addr::Str(s)) =>
+                    HeapCellValue::Addr(Addr::Str(s - diff)),
+                _ => heap_value
+            });
+        }
+    }
+
+    pub(super) fn is_cyclic_term(&self, addr: Addr) -> bool {
+        !self.cycle_targets(addr).is_empty()
+    }
+
+    fn subterm_addrs(&self, addr: &Addr) -> Vec<Addr> {
+        match addr {
+            &Addr::Lis(l) => vec![Addr::HeapCell(l), Addr::HeapCell(l + 1)],
+            &Addr::Str(s) =>
+                match &self.heap[s] {
+                    &HeapCellValue::NamedStr(arity, ..) =>
+                        (1 .. arity + 1).map(|i| Addr::HeapCell(s + i)).collect(),
+                    _ => vec![]
+                },
+            _ => vec![]
+        }
+    }
+
+    fn cycle_targets(&self, addr: Addr) -> Vec<Addr> {
+        self.subterm_targets(addr, None)
+    }
+
+    // the classes of the structures of the term at addr, by heap
+    // address: structures that are the same term, built of the same
+    // variables, share the class of one of them. the structures closing
+    // a cycle can't be compared by their subterms, and are classes of
+    // their own.
+    fn structure_classes(&self, addr: Addr) -> HashMap<usize, usize> {
+        let mut classes = HashMap::new();
+        let mut signatures = HashMap::new();
+        let mut enclosing = HashSet::new();
+
+        let mut stack = vec![(addr, false)]; // the term, and whether it's being left.
+
+        while let Some((addr, leaving)) = stack.pop() {
+            let addr = self.store(self.deref(addr));
+
+            let key = match addr {
+                Addr::Lis(a) | Addr::Str(a) => a,
+                _ => continue
+            };
+
+            if !leaving {
+                if !enclosing.contains(&key) && !classes.contains_key(&key) {
+                    enclosing.insert(key);
+
+                    let subterms = self.subterm_addrs(&addr);
+                    stack.push((addr, true));
+
+                    for subterm in subterms.into_iter().rev() {
+                        stack.push((subterm, false));
+                    }
+                }
+
+                continue;
+            }
+
+            enclosing.remove(&key);
+
+            let mut cyclic = false;
+            let mut cells = vec![];
+
+            for subterm in self.subterm_addrs(&addr) {
+                let subterm = match self.store(self.deref(subterm)) {
+                    Addr::Lis(a) => classes.get(&a).map(|&class| Addr::Lis(class)),
+                    Addr::Str(a) => classes.get(&a).map(|&class| Addr::Str(class)),
+                    subterm => Some(subterm)
+                };
+
+                match subterm {
+                    Some(subterm) => cells.push(subterm),
+                    None => cyclic = true
+                };
+            }
+
+            let functor = match addr {
+                Addr::Str(s) => match self.heap[s] {
+                    HeapCellValue::NamedStr(arity, ref name, _) => Some((name.clone(), arity)),
+                    _ => None
+                },
+                _ => None
+            };
+
+            let class = if cyclic {
+                key
+            } else {
+                *signatures.entry((functor, cells)).or_insert(key)
+            };
+
+            classes.insert(key, class);
+        }
+
+        classes
+    }
+
+    // the structures of the term at addr referred back to by their
+    // own subterms, found by a depth-first search that marks the
+    // structures enclosing the subterm it's at. a shared subterm is
+    // searched once, and if classes are given, structures of the same
+    // class are shared, and themselves targets.
+    fn subterm_targets(&self, addr: Addr, classes: Option<&HashMap<usize, usize>>) -> Vec<Addr> {
+        let mut targets = vec![];
+        let mut targets_keys = HashSet::new();
+        let mut enclosing = HashSet::new();
+        let mut searched  = HashSet::new();
+
+        let mut stack = vec![(addr, false)]; // the term, and whether it's being left.
+
+        while let Some((addr, leaving)) = stack.pop() {
+            let addr = self.store(self.deref(addr));
+
+            let key = match addr {
+                Addr::Lis(a) | Addr::Str(a) => classes.map(|classes| classes[&a]).unwrap_or(a),
+                _ => continue
+            };
+
+            if leaving {
+                enclosing.remove(&key);
+                searched.insert(key);
+            } else if enclosing.contains(&key) {
+                if !targets_keys.contains(&key) {
+                    targets_keys.insert(key);
+                    targets.push(addr);
+                }
+            } else if !searched.contains(&key) {
+                enclosing.insert(key);
+
+                let subterms = self.subterm_addrs(&addr);
+                stack.push((addr, true));
+
+                for subterm in subterms.into_iter().rev() {
+                    stack.push((subterm, false));
+                }
+            } else if classes.is_some() && !targets_keys.contains(&key) {
+                targets_keys.insert(key);
+                targets.push(addr);
+            }
+        }
+
+        targets
+    }
+
+    // copies the structure at addr to the top of the heap, bottom up.
+    // the targets among its subterms become their variables in vars,
+    // and the subterms copied already are shared. both are keyed by
+    // the classes of the structures.
+    fn copy_factorized(&mut self, addr: Addr, classes: &HashMap<usize, usize>,
+                       vars: &HashMap<usize, Addr>, copies: &mut HashMap<usize, Addr>)
+                       -> Addr
+    {
+        let key = |addr: &Addr| match addr {
+            &Addr::Lis(a) | &Addr::Str(a) => classes.get(&a).cloned(),
+            _ => None
+        };
+
+        let root = key(&addr);
+        let mut stack = vec![(addr, false)]; // the structure, and whether its subterms are copied.
+
+        while let Some((addr, ready)) = stack.pop() {
+            let k = match key(&addr) {
+                Some(k) if !copies.contains_key(&k) => k,
+                _ => continue
+            };
+
+            let subterms: Vec<_> = self.subterm_addrs(&addr).into_iter()
+                .map(|subterm| self.store(self.deref(subterm)))
+                .collect();
+
+            if !ready {
+                stack.push((addr, true));
+
+                for subterm in subterms {
+                    match key(&subterm) {
+                        Some(k) if !vars.contains_key(&k) && !copies.contains_key(&k) =>
+                            stack.push((subterm, false)),
+                        _ => {}
+                    }
+                }
+
+                continue;
+            }
+
+            let subterms: Vec<_> = subterms.into_iter().map(|subterm| {
+                match key(&subterm) {
+                    Some(k) => vars.get(&k).or(copies.get(&k)).cloned().unwrap_or(subterm),
+                    None => subterm
+                }
+            }).collect();
+
+            let h = self.heap.h;
+
+            let copy = match addr {
+                Addr::Str(s) => {
+                    let cell = self.heap[s].clone();
+                    self.heap.push(cell);
+                    Addr::Str(h)
+                },
+                _ => Addr::Lis(h)
+            };
+
+            for subterm in subterms {
+                self.heap.push(HeapCellValue::Addr(subterm));
+            }
+
+            copies.insert(k, copy);
+        }
+
+        root.and_then(|k| copies.get(&k).cloned()).unwrap_or(addr)
+    }
+
+    // term_factorized(Term, Skeleton, Substitutions) breaks the cycles
+    // of Term: the structures referred back to by their subterms, and
+    // those occurring more than once in it, are replaced by fresh
+    // variables in Skeleton, and each V = Structure of Substitutions
+    // gives the structure of the variable V, itself factorized.
+    // structures are shared when they're the same term, whether or not
+    // they're the same copy on the heap.
+    pub(super) fn term_factorized(&mut self) {
+        let term = self.store(self.deref(self[temp_v!(1)].clone()));
+
+        let classes = self.structure_classes(term.clone());
+        let targets = self.subterm_targets(term.clone(), Some(&classes));
+
+        let mut vars = HashMap::new();
+
+        for target in targets.iter() {
+            if let &Addr::Lis(k) | &Addr::Str(k) = target {
+                let h = self.heap.h;
+
+                self.heap.push(HeapCellValue::Addr(Addr::HeapCell(h)));
+                vars.insert(classes[&k], Addr::HeapCell(h));
+            }
+        }
+
+        let mut copies = HashMap::new();
+
+        let skeleton = match term {
+            Addr::Lis(k) | Addr::Str(k) if vars.contains_key(&classes[&k]) =>
+                vars[&classes[&k]].clone(),
+            _ => self.copy_factorized(term, &classes, &vars, &mut copies)
+        };
+
+        let mut substitutions = vec![];
+
+        for target in targets {
+            let value = self.copy_factorized(target.clone(), &classes, &vars, &mut copies);
+
+            let var = match target {
+                Addr::Lis(k) | Addr::Str(k) => vars[&classes[&k]].clone(),
+                _ => continue
+            };
+
+            let h = self.heap.h;
+
+            self.heap.append(functor!("=", 2, [HeapCellValue::Addr(var),
+                                               HeapCellValue::Addr(value)],
+                                      Fixity::In));
+            substitutions.push(Addr::Str(h));
+        }
+
+        let substitutions = Addr::HeapCell(self.to_list(substitutions.into_iter()));
+
+        let a2 = self[temp_v!(2)].clone();
+        let a3 = self[temp_v!(3)].clone();
+
+        self.unify(a2, skeleton);
+
+        if !self.fail {
+            self.unify(a3, substitutions);
+        }
+    }
+    
+    fn try_get_arg(&mut self) -> CallResult
+    {
+        let a1 = self.store(self.deref(self[temp_v!(1)].clone()));
+
+        if let Addr::Con(Constant::Number(Number::Integer(i))) = a1 {
+            let a2 = self.store(self.deref(self[temp_v!(2)].clone()));
+
+            if let Addr::Str(o) = a2 {
+                match self.heap[o].clone() {
+                    HeapCellValue::NamedStr(arity, _, _) =>
+                        match i.to_usize() {
+                            Some(i) if 1 <= i && i <= arity => {
+                                let a3  = self[temp_v!(3)].clone();
+                                let h_a = Addr::HeapCell(o + i);
+
+                                self.unify(a3, h_a);
+                            },
+                            _ => self.fail = true
+                        },
+                    _ => self.fail = true
+                };
+            } else {
+                let err = MachineError::type_error("compound", a2);
+                return Err(self.error_form(err, clause_name!("arg"), 3));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compare_numbers(&mut self, cmp: CompareNumberQT, n1: Number, n2: Number) {
+        self.fail = match cmp {
+            CompareNumberQT::GreaterThan if !(n1.gt(n2)) => true,
+            CompareNumberQT::GreaterThanOrEqual if !(n1.gte(n2)) => true,
+            CompareNumberQT::LessThan if !(n1.lt(n2)) => true,
+            CompareNumberQT::LessThanOrEqual if !(n1.lte(n2)) => true,
+            CompareNumberQT::NotEqual if !(n1.ne(n2)) => true,
+            CompareNumberQT::Equal if !(n1.eq(n2)) => true,
+            _ => false
+        };
+
+        self.p += 1;
+    }
+
+    pub(super) fn compare_term(&mut self, qt: CompareTermQT) {
+        let a1 = self[temp_v!(1)].clone();
+        let a2 = self[temp_v!(2)].clone();
+
+        match self.compare_term_test(&a1, &a2) {
+            Ordering::Greater =>
+                match qt {
+                    CompareTermQT::GreaterThan | CompareTermQT::GreaterThanOrEqual => return,
+                    _ => self.fail = true
+                },
+            Ordering::Equal =>
+                match qt {
+                    CompareTermQT::GreaterThanOrEqual | CompareTermQT::LessThanOrEqual => return,
+                    _ => self.fail = true
+                },
+            Ordering::Less =>
+                match qt {
+                    CompareTermQT::LessThan | CompareTermQT::LessThanOrEqual => return,
+                    _ => self.fail = true
+                }
+        };
+    }
+
+    pub(super) fn compare_term_test(&self, a1: &Addr, a2: &Addr) -> Ordering {
+        let iter = self.zipped_acyclic_pre_order_iter(a1.clone(), a2.clone());
+
+        for (v1, v2) in iter {
+            match (v1, v2) {
+                (HeapCellValue::Addr(Addr::HeapCell(hc1)),
+                 HeapCellValue::Addr(Addr::HeapCell(hc2))) =>
+                    if hc1 != hc2 {
+                        return hc1.cmp(&hc2);
+                    },
+                (HeapCellValue::Addr(Addr::HeapCell(_)), _) =>
+                    return Ordering::Less,
+                (HeapCellValue::Addr(Addr::StackCell(fr1, sc1)),
+                 HeapCellValue::Addr(Addr::StackCell(fr2, sc2))) =>
+                    if fr1 > fr2 {
+                        return Ordering::Greater;
+                    } else if fr1 < fr2 || sc1 < sc2 {
+                        return Ordering::Less;
+                    } else if sc1 > sc2 {
+                        return Ordering::Greater;
+                    },
+                (HeapCellValue::Addr(Addr::StackCell(..)),
+                 HeapCellValue::Addr(Addr::HeapCell(_))) =>
+                    return Ordering::Greater,
+                (HeapCellValue::Addr(Addr::StackCell(..)), _) =>
+                    return Ordering::Less,
+                (HeapCellValue::Addr(Addr::Con(Constant::Number(..))),
+                 HeapCellValue::Addr(Addr::HeapCell(_))) =>
+                    return Ordering::Greater,
+                (HeapCellValue::Addr(Addr::Con(Constant::Number(..))),
+                 HeapCellValue::Addr(Addr::StackCell(..))) =>
+                    return Ordering::Greater,
+                (HeapCellValue::Addr(Addr::Con(Constant::Number(n1))),
+                 HeapCellValue::Addr(Addr::Con(Constant::Number(n2)))) =>
+                    if n1 != n2 {
+                        return n1.cmp(&n2);
+                    },
+                (HeapCellValue::Addr(Addr::Con(Constant::Number(_))), _) =>
+                    return Ordering::Less,
+                (HeapCellValue::Addr(Addr::Con(Constant::String(..))),
+                 HeapCellValue::Addr(Addr::HeapCell(_))) =>
+                    return Ordering::Greater,
+                (HeapCellValue::Addr(Addr::Con(Constant::String(..))),
+                 HeapCellValue::Addr(Addr::StackCell(..))) =>
+                    return Ordering::Greater,
+                (HeapCellValue::Addr(Addr::Con(Constant::String(_))),
+                 HeapCellValue::Addr(Addr::Con(Constant::Number(_)))) =>
+                    return Ordering::Greater,
+                (HeapCellValue::Addr(Addr::Con(Constant::String(s1))),
+                 HeapCellValue::Addr(Addr::Con(Constant::String(s2)))) =>
+                    if s1 != s2 {
+                        return s1.cmp(&s2);
+                    },
+                (HeapCellValue::Addr(Addr::Con(Constant::String(_))), _) =>
+                    return Ordering::Less,
+                (HeapCellValue::Addr(Addr::Con(Constant::Atom(..))),
+                 HeapCellValue::Addr(Addr::HeapCell(_))) =>
+                    return Ordering::Greater,
+                (HeapCellValue::Addr(Addr::Con(Constant::Atom(..))),
+                 HeapCellValue::Addr(Addr::StackCell(..))) =>
+                    return Ordering::Greater,
+                (HeapCellValue::Addr(Addr::Con(Constant::Atom(_))),
+                 HeapCellValue::Addr(Addr::Con(Constant::Number(_)))) =>
+                    return Ordering::Greater,
+                (HeapCellValue::Addr(Addr::Con(Constant::Atom(_))),
+                 HeapCellValue::Addr(Addr::Con(Constant::String(_)))) =>
+                    return Ordering::Greater,
+                (HeapCellValue::Addr(Addr::Con(Constant::Atom(s1))),
+                 HeapCellValue::Addr(Addr::Con(Constant::Atom(s2)))) =>
+                    if s1 != s2 {
+                        return s1.cmp(&s2);
+                    },
+                (HeapCellValue::Addr(Addr::Con(Constant::Atom(_))), _) =>
+                    return Ordering::Less,
+                (HeapCellValue::NamedStr(ar1, n1, _), HeapCellValue::NamedStr(ar2, n2, _)) =>
+                    if ar1 < ar2 {
+                        return Ordering::Less;
+                    } else if ar1 > ar2 {
+                        return Ordering::Greater;
+                    } else if n1 != n2 {
+                        return n1.cmp(&n2);
+                    },
+                (HeapCellValue::Addr(Addr::Lis(_)), HeapCellValue::Addr(Addr::Lis(_))) =>
+                    continue,
+                (HeapCellValue::Addr(Addr::Lis(_)), HeapCellValue::NamedStr(ar, n, _))
+              | (HeapCellValue::NamedStr(ar, n, _), HeapCellValue::Addr(Addr::Lis(_))) =>
+                    if ar == 2 && n.as_str() == "." {
+                        continue;
+                    } else if ar < 2 {
+                        return Ordering::Greater;
+                    } else if ar > 2 {
+                        return Ordering::Less;
+                    } else {
+                        return n.as_str().cmp(".");
+                    },
+                (HeapCellValue::NamedStr(..), _) =>
+                    return Ordering::Greater,
+                (HeapCellValue::Addr(Addr::Lis(_)), _) =>
+                    return Ordering::Greater,
+                _ => {}
+            }
+        };
+
+        Ordering::Equal
+    }
+
+    fn reset_block(&mut self, addr: Addr) {
+        match self.store(addr) {
+            Addr::Con(Constant::Usize(b)) => {
+                self.block = b;
+                self.p += 1;
+            },
+            _ => self.fail = true
+        };
+    }
+
+    pub(super) fn execute_inlined(&mut self, inlined: &InlinedClauseType, rs: &Vec<RegType>)
+    {
+        let r1 = rs[0].clone();
+
+        match inlined {
+            &InlinedClauseType::CompareNumber(cmp) => {
+                let r2 = rs[1].clone();
+
+                let n1 = try_or_fail!(self, self.arith_eval_by_metacall(r1), cmp.name(), 2);
+                let n2 = try_or_fail!(self, self.arith_eval_by_metacall(r2), cmp.name(), 2);
+
+                self.compare_numbers(cmp, n1, n2);
+            },
+            &InlinedClauseType::IsAtom => {
+                let d = self.store(self.deref(self[r1].clone()));
+
+                match d {
+                    Addr::Con(Constant::Atom(_)) => self.p += 1,
+                    _ => self.fail = true
+                };
+            },
+            &InlinedClauseType::IsAtomic => {
+                let d = self.store(self.deref(self[r1].clone()));
+
+                match d {
+                    Addr::Con(_) => self.p += 1,
+                    _ => self.fail = true
+                };
+            },
+            &InlinedClauseType::IsInteger => {
+                let d = self.store(self.deref(self[r1].clone()));
+
+                match d {
+                    Addr::Con(Constant::Number(Number::Integer(_))) => self.p += 1,
+                    _ => self.fail = true
+                };
+            },
+            &InlinedClauseType::IsCompound => {
+                let d = self.store(self.deref(self[r1].clone()));
+
+                match d {
+                    Addr::Str(_) | Addr::Lis(_) => self.p += 1,
+                    _ => self.fail = true
+                };
+            },
+            &InlinedClauseType::IsFloat => {
+                let d = self.store(self.deref(self[r1].clone()));
+
+                match d {
+                    Addr::Con(Constant::Number(Number::Float(_))) => self.p += 1,
+                    _ => self.fail = true
+                };
+            },
+            &InlinedClauseType::IsRational => {
+                let d = self.store(self.deref(self[r1].clone()));
+
+                match d {
+                    Addr::Con(Constant::Number(Number::Rational(_))) => self.p += 1,
+                    _ => self.fail = true
+                };
+            },
+            &InlinedClauseType::IsString => {
+                let d = self.store(self.deref(self[r1].clone()));
+
+                match d {
+                    Addr::Con(Constant::String(_)) => self.p += 1,
+                    _ => self.fail = true
+                };
+            },
+            &InlinedClauseType::IsNonVar => {
+                let d = self.store(self.deref(self[r1].clone()));
+
+                match d {
+                    Addr::HeapCell(_) | Addr::StackCell(..) => self.fail = true,
+                    _ => self.p += 1
+                };
+            },
+            &InlinedClauseType::IsVar => {
+                let d = self.store(self.deref(self[r1].clone()));
+
+                match d {
+                    Addr::HeapCell(_) | Addr::StackCell(_,_) => self.p += 1,
+                    _ => self.fail = true
+                };
+            },
+        }
+    }
+
+    pub(super) fn execute_built_in_instr<'a>(&mut self, code_dirs: CodeDirs<'a>,
+                                             call_policy: &mut Box<CallPolicy>,
+                                             cut_policy:  &mut Box<CutPolicy>,
+                                             instr: &BuiltInInstruction)
+    {
+        match instr {
+            &BuiltInInstruction::CallInlined(ref inlined, ref rs) =>
+                self.execute_inlined(inlined, rs),
+            &BuiltInInstruction::CheckModes(ref name, ref modes) => {
+                for (i, mode) in modes.iter().enumerate() {
+                    let addr = self.store(self.deref(self.registers[i+1].clone()));
+
+                    let err = match mode {
+                        &Mode::In if addr.is_ref() =>
+                            MachineError::Instantiation,
+                        &Mode::Out if !addr.is_ref() =>
+                            MachineError::Uninstantiation(addr),
+                        _ => {
+                            // the get instructions of the clauses
+                            // needn't chase the bindings again.
+                            self.registers[i+1] = addr;
+                            continue;
+                        }
+                    };
+
+                    let stub = self.error_form(err, name.clone(), modes.len());
+                    self.throw_exception(stub);
+
+                    return;
+                }
+
+                self.p += 1;
+            },
+            &BuiltInInstruction::CompareNumber(cmp, ref at_1, ref at_2) => {
+                let n1 = try_or_fail!(self, self.get_number(at_1), cmp.name(), 2);
+                let n2 = try_or_fail!(self, self.get_number(at_2), cmp.name(), 2);
+
+                self.compare_numbers(cmp, n1, n2);
+            },
+            &BuiltInInstruction::DefaultRetryMeElse(o) => {
+                let mut call_policy = DefaultCallPolicy {};
+                try_or_fail!(self, call_policy.retry_me_else(self, o));
+            },
+            &BuiltInInstruction::DefaultSetCutPoint(r) => {
+                let mut cut_policy = DefaultCutPolicy {};
+                cut_policy.cut(self, r);
+            },
+            &BuiltInInstruction::DefaultTrustMe => {
+                let mut call_policy = DefaultCallPolicy {};
+                try_or_fail!(self, call_policy.trust_me(self));
+            },
+            &BuiltInInstruction::EraseBall => {
+                self.ball.0 = 0;
+                self.ball.1.truncate(0);
+                self.p += 1;
+            },
+            &BuiltInInstruction::GetArg(lco) =>
+                try_or_fail!(self, {
+                    let val = self.try_get_arg();
+
+                    if lco {
+                        self.p = self.cp.clone();
+                    } else {
+                        self.p += 1;
+                    }
+
+                    val
+                }),
+            &BuiltInInstruction::GetCurrentBlock => {
+                let c = Constant::Usize(self.block);
+                let addr = self[temp_v!(1)].clone();
+
+                self.write_constant_to_var(addr, c);
+                self.p += 1;
+            },
+            &BuiltInInstruction::GetBall => {
+                let addr = self.store(self.deref(self[temp_v!(1)].clone()));
+                let h = self.heap.h;
+
+                if self.ball.1.len() > 0 {
+                    self.copy_and_align_ball_to_heap();
+                } else {
+                    self.fail = true;
+                    return;
+                }
+
+                let ball = self.heap[h].as_addr(h);
+
+                match addr.as_var() {
+                    Some(r) => {
+                        self.bind(r, ball);
+                        self.p += 1;
+                    },
+                    _ => self.fail = true
+                };
+            },
+            &BuiltInInstruction::GetCutPoint(r) => {
+                let c = Constant::Usize(self.b);
+                self[r] = Addr::Con(c);
+
+                self.p += 1;
+            },
+            &BuiltInInstruction::InferenceLevel(r1, r2) => { // X1 = R, X2 = B.
+                let a1 = self[r1].clone();
+                let a2 = self.store(self.deref(self[r2].clone()));
+
+                match a2 {
+                    Addr::Con(Constant::Usize(bp)) =>
+                        if self.b <= bp + 1 {
+                            let a2 = Addr::Con(atom!("!", self.atom_tbl));
+                            self.unify(a1, a2);
+                        } else {
+                            let a2 = Addr::Con(atom!("true", self.atom_tbl));
+                            self.unify(a1, a2);
+                        },
+                    _ => self.fail = true
+                };
+
+                self.p += 1;
+            },
+            &BuiltInInstruction::InstallCleaner => {
+                let addr = self[temp_v!(1)].clone();
+                let b = self.b;
+                let block = self.block;
+
+                if cut_policy.downcast_ref::<SetupCallCleanupCutPolicy>().is_err() {
+                    *cut_policy = Box::new(SetupCallCleanupCutPolicy::new());
+                }
+
+                match cut_policy.downcast_mut::<SetupCallCleanupCutPolicy>().ok()
+                {
+                    Some(cut_policy) => cut_policy.push_cont_pt(addr, b, block),
+                    None => panic!("install_cleaner: should have installed \\
+                                    SetupCallCleanupCutPolicy.")
+                };
+
+                self.p += 1;
+            },
+            &BuiltInInstruction::InstallDepthLimit(r1, r2) => { // A1 = L, A2 = H
+                let limit = try_or_fail!(self, self.try_depth_limit(r1), "call_with_depth_limit", 3);
+
+                if find_policy_slot::<CallWithDepthLimitCallPolicy>(call_policy).is_none() {
+                    CallWithDepthLimitCallPolicy::new_in_place(call_policy);
+                }
+
+                match find_policy::<CallWithDepthLimitCallPolicy>(call_policy) {
+                    Some(call_policy) => {
+                        let handle = call_policy.add_limit(self, limit);
+                        self[r2] = Addr::Con(Constant::Usize(handle));
+                    },
+                    None => panic!("install_depth_limit: should have installed \
+                                    CallWithDepthLimitCallPolicy.")
+                };
+
+                self.p += 1;
+            },
+            &BuiltInInstruction::ExitDepthLimit(r1, r2) => { // A1 = H, A2 = R
+                let a1 = self.store(self.deref(self[r1].clone()));
+
+                match (a1, find_policy::<CallWithDepthLimitCallPolicy>(call_policy)) {
+                    (Addr::Con(Constant::Usize(handle)), Some(call_policy)) => {
+                        let reached = Rc::new(BigInt::from(call_policy.exit_limit(handle)));
+                        self[r2] = Addr::Con(Constant::Number(Number::Integer(reached)));
+                    },
+                    _ => panic!("exit_depth_limit: requires a handle and \
+                                 CallWithDepthLimitCallPolicy.")
+                };
+
+                self.p += 1;
+            },
+            &BuiltInInstruction::RedoDepthLimit(r) => {
+                let a = self.store(self.deref(self[r].clone()));
+
+                if let (Addr::Con(Constant::Usize(handle)), Some(call_policy)) =
+                    (a, find_policy::<CallWithDepthLimitCallPolicy>(call_policy))
+                {
+                    call_policy.redo_limit(handle);
+                }
+
+                self.p += 1;
+            },
+            &BuiltInInstruction::RemoveDepthLimit(r) => {
+                let a = self.store(self.deref(self[r].clone()));
+
+                if let (Addr::Con(Constant::Usize(handle)), Some(call_policy)) =
+                    (a, find_policy::<CallWithDepthLimitCallPolicy>(call_policy))
+                {
+                    call_policy.remove_limit(handle);
+                }
+
+                CallWithDepthLimitCallPolicy::remove_if_empty(call_policy);
+                self.p += 1;
+            },
+            &BuiltInInstruction::PopDepthLimit => {
+                let exceeded = match find_policy::<CallWithDepthLimitCallPolicy>(call_policy) {
+                    Some(call_policy) => call_policy.pop_limit(self.b),
+                    None => false
+                };
+
+                CallWithDepthLimitCallPolicy::remove_if_empty(call_policy);
+
+                if exceeded {
+                    self.p += 1;
+                } else {
+                    self.fail = true;
+                }
+            },
+            &BuiltInInstruction::InstallInferenceCounter(r1, r2, r3) => { // A1 = B, A2 = L
+                let a1 = self.store(self.deref(self[r1].clone()));
+                let a2 = self.store(self.deref(self[r2].clone()));
+
+                if find_policy_slot::<CallWithInferenceLimitCallPolicy>(call_policy).is_none() {
+                    CallWithInferenceLimitCallPolicy::new_in_place(call_policy);
+                }
+
+                self.p += 1;
+
+                let err = match (a1, a2.clone()) {
+                    (Addr::Con(Constant::Usize(bp)),
+                     Addr::Con(Constant::Number(Number::Integer(ref n))))
+                        if **n >= BigInt::zero() =>
+                        match find_policy::<CallWithInferenceLimitCallPolicy>(call_policy) {
+                            Some(call_policy) => {
+                                call_policy.add_limit(n.clone(), bp, &self.inferences);
+
+                                let count = Rc::new(BigInt::from(self.inferences.clone()));
+                                self[r3] = Addr::Con(Constant::Number(Number::Integer(count)));
+
+                                return;
+                            },
+                            None => panic!("install_inference_counter: should have installed \
+                                            CallWithInferenceLimitCallPolicy.")
+                        },
+                    (Addr::Con(Constant::Usize(_)), Addr::Con(Constant::Number(Number::Integer(_)))) =>
+                        MachineError::domain_error("not_less_than_zero", a2),
+                    (_, Addr::HeapCell(_)) | (_, Addr::StackCell(..)) =>
+                        MachineError::Instantiation,
+                    _ => MachineError::type_error("integer", a2)
+                };
+
+                let stub = self.error_form(err, clause_name!("call_with_inference_limit"), 3);
+                self.throw_exception(stub);
+            },
+            &BuiltInInstruction::RemoveCallPolicyCheck => {
+                match find_policy_slot::<CallWithInferenceLimitCallPolicy>(call_policy) {
+                    Some(slot) => {
+                        let restore_default =
+                            match slot.downcast_mut::<CallWithInferenceLimitCallPolicy>().ok() {
+                                Some(call_policy) => {
+                                    let a1 = self.store(self.deref(self[temp_v!(1)].clone()));
+
+                                    if let Addr::Con(Constant::Usize(bp)) = a1 {
+                                        if call_policy.is_empty() && bp == self.b {
+                                            Some(call_policy.into_inner())
+                                        } else {
+                                            None
+                                        }
+                                    } else {
+                                        panic!("remove_call_policy_check: expected Usize in A1.");
+                                    }
+                                },
+                                None => None
+                            };
+
+                        if let Some(new_policy) = restore_default {
+                            *slot = new_policy;
+                        }
+                    },
+                    None => panic!("remove_call_policy_check: requires \
+                                    CallWithInferenceLimitCallPolicy.")
+                };
+
+                self.p += 1;
+            },
+            &BuiltInInstruction::RemoveInferenceCounter(r1, r2) => { // A1 = B
+                match find_policy::<CallWithInferenceLimitCallPolicy>(call_policy) {
+                    Some(call_policy) => {
+                        let a1 = self.store(self.deref(self[r1].clone()));
+
+                        if let Addr::Con(Constant::Usize(bp)) = a1 {
+                            call_policy.remove_limit(bp);
+
+                            let count = Rc::new(BigInt::from(self.inferences.clone()));
+                            self[r2] = Addr::Con(Constant::Number(Number::Integer(count)));
+                        } else {
+                            panic!("remove_inference_counter: expected Usize in A1.");
+                        }
+                    },
+                    None => panic!("remove_inference_counters: requires \
+                                    CallWithInferenceLimitCallPolicy.")
+                };
+
+                self.p += 1;
+            },
+            &BuiltInInstruction::RestoreCutPolicy => {
+                let restore_default =
+                    if let Ok(cut_policy) = cut_policy.downcast_ref::<SetupCallCleanupCutPolicy>() {
+                        cut_policy.out_of_cont_pts()
+                    } else {
+                        false
+                    };
+
+                if restore_default {
+                    *cut_policy = Box::new(DefaultCutPolicy {});
+                }
+
+                self.p += 1;
+            },
+            &BuiltInInstruction::SetBall(take_backtrace) => {
+                let addr = self[temp_v!(1)].clone();
+
+                match self.store(self.deref(addr.clone())) {
+                    Addr::HeapCell(_) | Addr::StackCell(..) => {
+                        let err  = MachineError::Instantiation;
+                        let stub = self.error_form(err, clause_name!("throw"), 1);
+
+                        self.throw_exception(stub);
+                        return;
+                    },
+                    _ => {}
+                };
+
+                if take_backtrace {
+                    self.backtrace = self.continuations(self.cp.clone());
+                }
+                self.ball.0 = self.heap.h;
+
+                {
+                    let mut duplicator = DuplicateBallTerm::new(self);
+                    duplicator.duplicate_term(addr);
+                }
+
+                self.p += 1;
+            },
+            &BuiltInInstruction::SetCutPoint(r) =>
+                cut_policy.cut(self, r),
+            &BuiltInInstruction::CleanUpBlock => {
+                let nb = self.store(self.deref(self[temp_v!(1)].clone()));
+
+                match nb {
+                    Addr::Con(Constant::Usize(nb)) => {
+                        let b = self.b - 1;
+
+                        if nb > 0 && self.or_stack[b].b == nb {
+                            self.b = self.or_stack[nb - 1].b;
+                            self.or_stack.truncate(self.b);
+                        }
+
+                        self.p += 1;
+                    },
+                    _ => self.fail = true
+                };
+            },
+            &BuiltInInstruction::InstallNewBlock => {
+                self.block = self.b;
+                let c = Constant::Usize(self.block);
+                let addr = self[temp_v!(1)].clone();
+
+                self.write_constant_to_var(addr, c);
+                self.p += 1;
+            },
+            &BuiltInInstruction::InstallTimeLimit(r1, r2) => { // A1 = T, A2 = H
+                let limit = try_or_fail!(self, self.try_time_limit(r1), "call_with_time_limit", 2);
+                let handle = self.time_limits.add(limit);
+
+                self[r2] = Addr::Con(Constant::Usize(handle));
+                self.p += 1;
+            },
+            &BuiltInInstruction::RemoveTimeLimit(r) => {
+                if let Addr::Con(Constant::Usize(handle)) = self.store(self.deref(self[r].clone())) {
+                    self.time_limits.remove(handle);
+                }
+
+                self.p += 1;
+            },
+            &BuiltInInstruction::ResetBlock => {
+                let addr = self.deref(self[temp_v!(1)].clone());
+                self.reset_block(addr);
+            },
+            &BuiltInInstruction::UnwindStack =>
+                self.unwind_stack(),
+            &BuiltInInstruction::InternalCallN =>
+                self.handle_internal_call_n(call_policy, code_dirs),
+            &BuiltInInstruction::Fail => {
+                self.fail = true;
+                self.p += 1;
+            },
+            &BuiltInInstruction::Succeed => {
+                self.p += 1;
+            },
+            &BuiltInInstruction::Unify => {
+                let a1 = self[temp_v!(1)].clone();
+                let a2 = self[temp_v!(2)].clone();
+
+                self.unify(a1, a2);
+                self.p += 1;
+            },
+        };
+    }
+
+    fn try_functor_arity(&self) -> Result<usize, MachineError> {
+        let arity = self.store(self.deref(self[temp_v!(3)].clone()));
+
+        match arity.clone() {
+            Addr::Con(Constant::Number(Number::Integer(n))) =>
+                if *n < BigInt::zero() {
+                    Err(MachineError::domain_error("not_less_than_zero", arity))
+                } else {
+                    match n.to_usize() {
+                        Some(n) if n <= MAX_ARITY => Ok(n),
+                        _ => Err(MachineError::Representation("max_arity"))
+                    }
+                },
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                Err(MachineError::Instantiation),
+            _ => Err(MachineError::type_error("integer", arity))
+        }
+    }
+
+    pub(super) fn try_functor(&mut self) -> CallResult {
+        let a1 = self.store(self.deref(self[temp_v!(1)].clone()));
+
+        match a1.clone() {
+            Addr::Str(o) =>
+                match self.heap[o].clone() {
+                    HeapCellValue::NamedStr(arity, name, _) => {
+                        let name  = Addr::Con(Constant::Atom(name)); // A2
+                        let arity = Addr::Con(Constant::Number(rc_integer!(arity)));
+
+                        let a2 = self[temp_v!(2)].clone();
+                        self.unify(a2, name);
+
+                        if !self.fail {
+                            let a3 = self[temp_v!(3)].clone();
+                            self.unify(a3, arity);
+                        }
+                    },
+                    _ => self.fail = true
+                },
+            Addr::HeapCell(_) | Addr::StackCell(_, _) => {
+                let name = self.store(self.deref(self[temp_v!(2)].clone()));
+
+                let arity = match name {
+                    Addr::HeapCell(_) | Addr::StackCell(..) => Err(MachineError::Instantiation),
+                    Addr::Con(_) => self.try_functor_arity(),
+                    _ => Err(MachineError::type_error("atomic", name.clone()))
+                };
+
+                let arity = match arity {
+                    Ok(arity) => arity,
+                    Err(err) => return Err(self.error_form(err, clause_name!("functor"), 3))
+                };
+
+                match name {
+                    Addr::Con(Constant::Atom(name)) => {
+                        let f_a = Addr::Str(self.heap.h);
+
+                        if arity > 0 {
+                            self.heap.push(HeapCellValue::NamedStr(arity, name, None));
+                        } else {
+                            let c = Constant::Atom(name.clone());
+                            self.heap.push(HeapCellValue::Addr(Addr::Con(c)));
+                        }
+
+                        for _ in 0 .. arity {
+                            let h = self.heap.h;
+                            self.heap.push(HeapCellValue::Addr(Addr::HeapCell(h)));
+                        }
+
+                        self.unify(a1, f_a);
+                    },
+                    name => if arity == 0 {
+                        self.unify(a1, name);
+                    } else {
+                        let err = MachineError::type_error("atomic", name);
+                        return Err(self.error_form(err, clause_name!("functor"), 3));
+                    }
+                }
+            },
+            _ => {
+                let a2 = self[temp_v!(2)].clone();
+                self.unify(a1, a2);
+
+                if !self.fail {
+                    let a3 = self[temp_v!(3)].clone();
+                    self.unify(a3, Addr::Con(Constant::Number(rc_integer!(0))));
+                }
+            }
+        };
+
+        Ok(())
+    }
+
+    pub(super) fn term_dedup(&self, list: &mut Vec<Addr>) {
+        let mut result = vec![];
+
+        for a2 in list.iter().cloned() {
+            if let Some(a1) = result.last().cloned() {
+                if self.compare_term_test(&a1, &a2) == Ordering::Equal {
+                    continue;
+                }
+            }
+
+            result.push(a2);
+        }
+
+        *list = result;
+    }
+
+    pub(super) fn to_list<Iter: Iterator<Item=Addr>>(&mut self, values: Iter) -> usize {
+        let head_addr = self.heap.h;
+
+        for value in values {
+            let h = self.heap.h;
+
+            self.heap.push(HeapCellValue::Addr(Addr::Lis(h+1)));
+            self.heap.push(HeapCellValue::Addr(value));
+        }
+
+        self.heap.push(HeapCellValue::Addr(Addr::Con(Constant::EmptyList)));
+        head_addr
+    }
+
+    pub(super) fn try_from_list(&self, r: RegType) -> Result<Vec<Addr>, MachineError>
+    {
+        self.try_from_list_at(self[r].clone())
+    }
+
+    pub(super) fn try_from_list_at(&self, addr: Addr) -> Result<Vec<Addr>, MachineError>
+    {
+        let a1 = self.store(self.deref(addr));
+
+        match a1.clone() {
+            Addr::Lis(mut l) => {
+                let mut result = Vec::new();
+
+                result.push(self.heap[l].as_addr(l));
+                l += 1;
+
+                loop {
+                    match self.store(self.deref(Addr::HeapCell(l))) {
+                        Addr::Lis(hcp) => {
+                            result.push(self.heap[hcp].as_addr(hcp));
+                            l = hcp + 1;
+                        },
+                        Addr::Con(Constant::EmptyList) =>
+                            break,
+                        Addr::HeapCell(_) | Addr::StackCell(..) =>
+                            return Err(MachineError::Instantiation),
+                        _ =>
+                            return Err(MachineError::type_error("list", a1))
+                    };
+                }
+
+                Ok(result)
+            },
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                Err(MachineError::Instantiation),
+            addr =>
+                Err(MachineError::type_error("list", addr))
+        }
+    }
+
+    pub(super) fn project_onto_key(&self, a: Addr) -> Result<Addr, MachineError> {
+        match self.store(self.deref(a)) {
+            Addr::Str(s) =>
+                match self.heap[s].clone() {
+                    HeapCellValue::NamedStr(2, ref name, Some(Fixity::In))
+                        if *name == clause_name!("-") =>
+                           Ok(Addr::HeapCell(s+1)),
+                    _ =>
+                        Err(MachineError::type_error("pair", Addr::Str(s)))
+                },
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                Err(MachineError::Instantiation),
+            a => Err(MachineError::type_error("pair", a))
+        }
+    }
+
+    fn inference_count(&self) -> Addr {
+        let count = Rc::new(BigInt::from(self.inferences.clone()));
+        Addr::Con(Constant::Number(Number::Integer(count)))
+    }
+
+    fn duration_as_millis(d: Duration) -> Addr {
+        let millis = d.as_secs() * 1000 + (d.subsec_nanos() / 1_000_000) as u64;
+        Addr::Con(integer!(millis))
+    }
+
+    fn duration_as_secs(d: Duration) -> f64 {
+        d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+    }
+
+    pub(super) fn try_statistics(&mut self, code_size: usize) -> CallResult {
+        let key = self.store(self.deref(self[temp_v!(1)].clone()));
+
+        let value = match key.clone() {
+            Addr::Con(Constant::Atom(ref name)) =>
+                match name.as_str() {
+                    "runtime" => {
+                        let (runtime, lap) = self.run_times.runtime_lap();
+                        let items = vec![Self::duration_as_millis(runtime),
+                                         Self::duration_as_millis(lap)];
+
+                        Addr::HeapCell(self.to_list(items.into_iter()))
+                    },
+                    "cputime" => {
+                        let secs = Self::duration_as_secs(self.run_times.cputime());
+                        Addr::Con(Constant::Number(Number::Float(OrderedFloat(secs))))
+                    },
+                    "walltime" => {
+                        let (walltime, lap) = self.run_times.walltime_lap();
+                        let items = vec![Self::duration_as_millis(walltime),
+                                         Self::duration_as_millis(lap)];
+
+                        Addr::HeapCell(self.to_list(items.into_iter()))
+                    },
+                    "inferences" => self.inference_count(),
+                    "heapused" => Addr::Con(integer!(self.heap.h)),
+                    "trailused" => Addr::Con(integer!(self.tr)),
+                    "localused" => Addr::Con(integer!(self.and_stack.len())),
+                    "choicepoints" => Addr::Con(integer!(self.b)),
+                    "atoms" => Addr::Con(integer!(self.atom_tbl.borrow().len())),
+                    "code_size" => Addr::Con(integer!(code_size)),
+                    _ => {
+                        let err = MachineError::domain_error("statistics_key", key.clone());
+                        return Err(self.error_form(err, clause_name!("statistics"), 2));
+                    }
+                },
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                return Err(self.error_form(MachineError::Instantiation,
+                                           clause_name!("statistics"), 2)),
+            _ => {
+                let err = MachineError::domain_error("statistics_key", key);
+                return Err(self.error_form(err, clause_name!("statistics"), 2));
+            }
+        };
+
+        let a2 = self[temp_v!(2)].clone();
+        self.unify(a2, value);
+
+        Ok(())
+    }
 
-                match d {
-                    Addr::Con(Constant::Number(Number::Integer(_))) => self.p += 1,
-                    _ => self.fail = true
+    // time_message/4 unifies A4 with the time/4 message of time/1 given
+    // the inference count, cputime and walltime sampled before the goal ran.
+    pub(super) fn time_message(&mut self) -> CallResult {
+        let a1 = self.store(self.deref(self[temp_v!(1)].clone()));
+        let a2 = self.store(self.deref(self[temp_v!(2)].clone()));
+        let a3 = self.store(self.deref(self[temp_v!(3)].clone()));
+
+        match (a1, a2, a3) {
+            (Addr::Con(Constant::Number(Number::Integer(i0))),
+             Addr::Con(Constant::Number(Number::Float(t0))),
+             Addr::Con(Constant::Number(Number::Integer(w0)))) => {
+                let inferences = BigInt::from(self.inferences.clone()) - &*i0;
+
+                let cputime = Self::duration_as_secs(self.run_times.cputime())
+                            - t0.into_inner();
+                let walltime = Self::duration_as_secs(self.run_times.walltime())
+                             - w0.to_f64().unwrap_or(0.0) / 1000.0;
+
+                let lips = match inferences.to_f64() {
+                    Some(inferences) if cputime > 0.0 =>
+                        heap_integer!((inferences / cputime) as u64),
+                    _ => heap_atom!("Infinite")
                 };
-            },
-            &InlinedClauseType::IsCompound => {
-                let d = self.store(self.deref(self[r1].clone()));
 
-                match d {
-                    Addr::Str(_) | Addr::Lis(_) => self.p += 1,
-                    _ => self.fail = true
+                let h = self.heap.h;
+                let float = |f: f64| {
+                    HeapCellValue::Addr(Addr::Con(Constant::Number(Number::Float(OrderedFloat(f)))))
                 };
-            },
-            &InlinedClauseType::IsFloat => {
-                let d = self.store(self.deref(self[r1].clone()));
 
-                match d {
-                    Addr::Con(Constant::Number(Number::Float(_))) => self.p += 1,
-                    _ => self.fail = true
-                };
-            },
-            &InlinedClauseType::IsRational => {
-                let d = self.store(self.deref(self[r1].clone()));
+                self.heap.append(functor!("time", 4, [heap_integer!(inferences),
+                                                      float(cputime),
+                                                      float(walltime),
+                                                      lips]));
 
-                match d {
-                    Addr::Con(Constant::Number(Number::Rational(_))) => self.p += 1,
-                    _ => self.fail = true
-                };
-            },
-            &InlinedClauseType::IsString => {
-                let d = self.store(self.deref(self[r1].clone()));
+                let a4 = self[temp_v!(4)].clone();
+                self.unify(a4, Addr::Str(h));
 
-                match d {
-                    Addr::Con(Constant::String(_)) => self.p += 1,
-                    _ => self.fail = true
-                };
+                Ok(())
             },
-            &InlinedClauseType::IsNonVar => {
-                let d = self.store(self.deref(self[r1].clone()));
+            _ => Err(self.error_form(MachineError::Instantiation, clause_name!("time"), 1))
+        }
+    }
 
-                match d {
-                    Addr::HeapCell(_) | Addr::StackCell(..) => self.fail = true,
-                    _ => self.p += 1
-                };
-            },
-            &InlinedClauseType::IsVar => {
-                let d = self.store(self.deref(self[r1].clone()));
+    // the translation of the messages raised by the machine, used in
+    // the absence of a message//1 rule. A2 is unified with its lines.
+    pub(super) fn default_message(&mut self) {
+        let term = self.store(self.deref(self[temp_v!(1)].clone()));
 
-                match d {
-                    Addr::HeapCell(_) | Addr::StackCell(_,_) => self.p += 1,
-                    _ => self.fail = true
-                };
-            },
+        let (fmt, args): (&'static str, Vec<Addr>) = match term.clone() {
+            Addr::Str(s) =>
+                match self.heap[s].clone() {
+                    HeapCellValue::NamedStr(arity, name, _) => {
+                        let args = (1 .. arity + 1).map(|i| Addr::HeapCell(s + i)).collect();
+
+                        match (name.as_str(), arity) {
+                            ("created_module", 1) =>
+                                ("~w is not a current module (created)", args),
+                            ("import_overwrite", 2) =>
+                                ("overwriting ~w (imported from ~w)", args),
+                            ("invalid_option_value", 2) =>
+                                ("~w expects ~w.", args),
+                            ("lost_import", 3) =>
+                                ("~w: ~w is no longer exported by ~w", args),
+                            ("unknown_option", 1) =>
+                                ("unrecognized option ~w", args),
+                            ("uncaught_exception", 2) =>
+                                match self.store(self.deref(Addr::HeapCell(s + 2))) {
+                                    Addr::Con(Constant::EmptyList) =>
+                                        ("exception thrown: ~w", args[.. 1].to_vec()),
+                                    _ =>
+                                        ("exception thrown: ~w~nbacktrace: ~w", args)
+                                },
+                            ("eval_error", 1) =>
+                                ("~a", args),
+                            ("unknown_procedure", 1) =>
+                                ("unknown procedure ~w", args),
+                            ("time", 4) =>
+                                ("~d inferences, ~3f CPU in ~3f seconds (~w Lips)", args),
+                            ("error", 2) =>
+                                ("~p", vec![term]),
+                            _ =>
+                                ("unknown message: ~p", vec![term])
+                        }
+                    },
+                    _ => ("unknown message: ~p", vec![term])
+                },
+            _ => ("unknown message: ~p", vec![term])
+        };
+
+        let args = self.to_list(args.into_iter());
+        let h = self.heap.h;
+
+        self.heap.append(functor!("-", 2, [heap_atom!(fmt),
+                                           HeapCellValue::Addr(Addr::HeapCell(args))],
+                                  Fixity::In));
+
+        let lines = Addr::HeapCell(self.to_list(vec![Addr::Str(h)].into_iter()));
+        let a2 = self[temp_v!(2)].clone();
+
+        self.unify(a2, lines);
+    }
+
+    fn format_arg(&self, args: &mut Vec<Addr>, args_addr: &Addr) -> Result<Addr, MachineError> {
+        if args.is_empty() {
+            Err(MachineError::domain_error("format_arguments", args_addr.clone()))
+        } else {
+            Ok(self.store(self.deref(args.remove(0))))
         }
     }
 
-    pub(super) fn execute_built_in_instr<'a>(&mut self, code_dirs: CodeDirs<'a>,
-                                             call_policy: &mut Box<CallPolicy>,
-                                             cut_policy:  &mut Box<CutPolicy>,
-                                             instr: &BuiltInInstruction)
-    {
-        match instr {
-            &BuiltInInstruction::CallInlined(ref inlined, ref rs) =>
-                self.execute_inlined(inlined, rs),
-            &BuiltInInstruction::CompareNumber(cmp, ref at_1, ref at_2) => {
-                let n1 = try_or_fail!(self, self.get_number(at_1));
-                let n2 = try_or_fail!(self, self.get_number(at_2));
+    // expands the directives ~w, ~p, ~q, ~a, ~d, ~Nf, ~n and ~~ of a
+    // Format-Args message line.
+    fn format_message(&self, fmt: Addr, args: Addr) -> Result<String, MachineError> {
+        let fmt = match self.store(self.deref(fmt)) {
+            Addr::Con(Constant::Atom(name)) => String::from(name.as_str()),
+            Addr::Con(Constant::String(s)) => (*s).clone(),
+            Addr::HeapCell(_) | Addr::StackCell(..) => return Err(MachineError::Instantiation),
+            addr => return Err(MachineError::type_error("atom", addr))
+        };
 
-                self.compare_numbers(cmp, n1, n2);
-            },
-            &BuiltInInstruction::DefaultRetryMeElse(o) => {
-                let mut call_policy = DefaultCallPolicy {};
-                try_or_fail!(self, call_policy.retry_me_else(self, o));
-            },
-            &BuiltInInstruction::DefaultSetCutPoint(r) => {
-                let mut cut_policy = DefaultCutPolicy {};
-                cut_policy.cut(self, r);
-            },
-            &BuiltInInstruction::DefaultTrustMe => {
-                let mut call_policy = DefaultCallPolicy {};
-                try_or_fail!(self, call_policy.trust_me(self));
-            },
-            &BuiltInInstruction::EraseBall => {
-                self.ball.0 = 0;
-                self.ball.1.truncate(0);
-                self.p += 1;
-            },
-            &BuiltInInstruction::GetArg(lco) =>
-                try_or_fail!(self, {
-                    let val = self.try_get_arg();
+        let args_addr = self.store(self.deref(args));
+        let mut args = match args_addr.clone() {
+            Addr::Con(Constant::EmptyList) => vec![],
+            Addr::Lis(_) => self.try_from_list_at(args_addr.clone())?,
+            _ => vec![args_addr.clone()]
+        };
 
-                    if lco {
-                        self.p = self.cp.clone();
-                    } else {
-                        self.p += 1;
-                    }
+        let mut result = String::new();
+        let mut chars  = fmt.chars().peekable();
 
-                    val
-                }),
-            &BuiltInInstruction::GetCurrentBlock => {
-                let c = Constant::Usize(self.block);
-                let addr = self[temp_v!(1)].clone();
+        while let Some(c) = chars.next() {
+            if c != '~' {
+                result.push(c);
+                continue;
+            }
 
-                self.write_constant_to_var(addr, c);
-                self.p += 1;
-            },
-            &BuiltInInstruction::GetBall => {
-                let addr = self.store(self.deref(self[temp_v!(1)].clone()));
-                let h = self.heap.h;
+            let mut column = String::new();
 
-                if self.ball.1.len() > 0 {
-                    self.copy_and_align_ball_to_heap();
+            while let Some(&d) = chars.peek() {
+                if d.is_digit(10) {
+                    column.push(d);
+                    chars.next();
                 } else {
-                    self.fail = true;
-                    return;
+                    break;
                 }
+            }
 
-                let ball = self.heap[h].as_addr(h);
-
-                match addr.as_var() {
-                    Some(r) => {
-                        self.bind(r, ball);
-                        self.p += 1;
+            match chars.next() {
+                Some('w') | Some('p') | Some('q') => {
+                    let arg = self.format_arg(&mut args, &args_addr)?;
+                    result += &self.print_term(arg, TermFormatter {}, PrinterOutputter::new())
+                                   .result();
+                },
+                Some('a') =>
+                    match self.format_arg(&mut args, &args_addr)? {
+                        Addr::Con(Constant::Atom(name)) => result += name.as_str(),
+                        Addr::HeapCell(_) | Addr::StackCell(..) =>
+                            return Err(MachineError::Instantiation),
+                        addr => return Err(MachineError::type_error("atom", addr))
                     },
-                    _ => self.fail = true
-                };
-            },
-            &BuiltInInstruction::GetCutPoint(r) => {
-                let c = Constant::Usize(self.b);
-                self[r] = Addr::Con(c);
+                Some('d') =>
+                    match self.format_arg(&mut args, &args_addr)? {
+                        Addr::Con(Constant::Number(Number::Integer(n))) =>
+                            result += &format!("{}", n),
+                        Addr::HeapCell(_) | Addr::StackCell(..) =>
+                            return Err(MachineError::Instantiation),
+                        addr => return Err(MachineError::type_error("integer", addr))
+                    },
+                Some('f') => {
+                    let digits = column.parse::<usize>().unwrap_or(6);
+
+                    let f = match self.format_arg(&mut args, &args_addr)? {
+                        Addr::Con(Constant::Number(Number::Float(f))) => f.into_inner(),
+                        Addr::Con(Constant::Number(Number::Integer(n))) =>
+                            n.to_f64().unwrap_or(0.0),
+                        Addr::HeapCell(_) | Addr::StackCell(..) =>
+                            return Err(MachineError::Instantiation),
+                        addr => return Err(MachineError::type_error("number", addr))
+                    };
 
-                self.p += 1;
-            },
-            &BuiltInInstruction::InferenceLevel(r1, r2) => { // X1 = R, X2 = B.
-                let a1 = self[r1].clone();
-                let a2 = self.store(self.deref(self[r2].clone()));
+                    result += &format!("{:.*}", digits, f);
+                },
+                Some('n') => result.push('\n'),
+                Some('~') => result.push('~'),
+                _ => {
+                    let fmt = Addr::Con(Constant::String(Rc::new(fmt.clone())));
+                    return Err(MachineError::domain_error("format_string", fmt));
+                }
+            }
+        }
 
-                match a2 {
-                    Addr::Con(Constant::Usize(bp)) =>
-                        if self.b <= bp + 1 {
-                            let a2 = Addr::Con(atom!("!", self.atom_tbl));
-                            self.unify(a1, a2);
-                        } else {
-                            let a2 = Addr::Con(atom!("true", self.atom_tbl));
-                            self.unify(a1, a2);
-                        },
-                    _ => self.fail = true
-                };
+        Ok(result)
+    }
 
-                self.p += 1;
-            },
-            &BuiltInInstruction::InstallCleaner => {
-                let addr = self[temp_v!(1)].clone();
-                let b = self.b;
-                let block = self.block;
+    // prints the lines of a message according to its kind, A1. lines
+    // are Format-Args pairs, nl, or terms printed as they are.
+    pub(super) fn print_message_lines(&self) -> CallResult {
+        let kind_addr = self.store(self.deref(self[temp_v!(1)].clone()));
 
-                if cut_policy.downcast_ref::<SetupCallCleanupCutPolicy>().is_err() {
-                    *cut_policy = Box::new(SetupCallCleanupCutPolicy::new());
-                }
+        let kind = match kind_addr.clone() {
+            Addr::Con(Constant::Atom(name)) =>
+                MessageKind::from(name.as_str())
+                    .ok_or(MachineError::domain_error("message_kind", kind_addr)),
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                Err(MachineError::Instantiation),
+            addr =>
+                Err(MachineError::type_error("atom", addr))
+        }.map_err(|err| self.error_form(err, clause_name!("print_message"), 2))?;
+
+        let lines = self.try_from_list(temp_v!(2))
+            .map_err(|err| self.error_form(err, clause_name!("print_message"), 2))?;
+
+        let mut text = String::new();
+
+        for line in lines {
+            match self.store(self.deref(line)) {
+                Addr::Con(Constant::Atom(ref name)) if name.as_str() == "nl" =>
+                    text.push('\n'),
+                Addr::Con(Constant::Atom(name)) =>
+                    text += name.as_str(),
+                Addr::Con(Constant::String(s)) =>
+                    text += s.as_str(),
+                Addr::Str(s) =>
+                    match self.heap[s].clone() {
+                        HeapCellValue::NamedStr(2, ref name, _) if name.as_str() == "-" => {
+                            let fmt  = Addr::HeapCell(s + 1);
+                            let args = Addr::HeapCell(s + 2);
+
+                            text += &self.format_message(fmt, args).map_err(|err| {
+                                self.error_form(err, clause_name!("print_message"), 2)
+                            })?;
+                        },
+                        _ =>
+                            text += &self.print_term(Addr::Str(s), TermFormatter {},
+                                                     PrinterOutputter::new()).result()
+                    },
+                addr =>
+                    text += &self.print_term(addr, TermFormatter {}, PrinterOutputter::new())
+                                 .result()
+            }
+        }
 
-                match cut_policy.downcast_mut::<SetupCallCleanupCutPolicy>().ok()
-                {
-                    Some(cut_policy) => cut_policy.push_cont_pt(addr, b, block),
-                    None => panic!("install_cleaner: should have installed \\
-                                    SetupCallCleanupCutPolicy.")
-                };
+        if let MessageKind::Silent = kind {
+            return Ok(());
+        }
 
-                self.p += 1;
-            },
-            &BuiltInInstruction::InstallInferenceCounter(r1, r2, r3) => { // A1 = B, A2 = L
-                let a1 = self.store(self.deref(self[r1].clone()));
-                let a2 = self.store(self.deref(self[r2].clone()));
+        // errors and warnings go to stderr, apart from the answers.
+        for line in text.lines() {
+            match kind {
+                MessageKind::Error | MessageKind::Warning =>
+                    eprintln!("{}{}", kind.prefix(), line),
+                _ =>
+                    println!("{}{}", kind.prefix(), line)
+            }
+        }
 
-                if call_policy.downcast_ref::<CallWithInferenceLimitCallPolicy>().is_err() {
-                    CallWithInferenceLimitCallPolicy::new_in_place(call_policy);
-                }
+        Ok(())
+    }
 
-                self.p += 1;
+    // frames above the current environment and those of the choice
+    // points are unreachable, since frames are always allocated above
+    // their parents.
+    fn compact_and_stack(&mut self) {
+        let top = (0 .. self.b).map(|b| self.or_stack[b].e).fold(self.e, max);
 
-                match (a1, a2) {
-                    (Addr::Con(Constant::Usize(bp)),
-                     Addr::Con(Constant::Number(Number::Integer(n)))) =>
-                        match call_policy.downcast_mut::<CallWithInferenceLimitCallPolicy>().ok() {
-                            Some(call_policy) => {
-                                let count = call_policy.add_limit(n, bp);
-                                self[r3] = Addr::Con(Constant::Number(Number::Integer(count)));
-                            },
-                            None => panic!("install_inference_counter: should have installed \\
-                                            CallWithInferenceLimitCallPolicy.")
-                        },
-                    _ => self.throw_exception(functor!("type_error", 1, [heap_atom!("integer_expected")]))
-                };
-            },
-            &BuiltInInstruction::RemoveCallPolicyCheck => {
-                let restore_default =
-                    match call_policy.downcast_mut::<CallWithInferenceLimitCallPolicy>().ok() {
-                        Some(call_policy) => {
-                            let a1 = self.store(self.deref(self[temp_v!(1)].clone()));
-
-                            if let Addr::Con(Constant::Usize(bp)) = a1 {
-                                if call_policy.is_empty() && bp == self.b {
-                                    Some(call_policy.into_inner())
-                                } else {
-                                    None
-                                }
-                            } else {
-                                panic!("remove_call_policy_check: expected Usize in A1.");
-                            }
-                        },
-                        None => panic!("remove_call_policy_check: requires \\
-                                        CallWithInferenceLimitCallPolicy.")
-                    };
+        if top + 1 < self.and_stack.len() {
+            self.and_stack.truncate(top + 1);
+        }
+    }
 
-                if let Some(new_policy) = restore_default {
-                    *call_policy = new_policy;
-                }
+    // error(resource_error(memory), Name/Arity), where Name/Arity is
+    // the predicate being called, if it is known.
+    fn resource_error(&self, culprit: Option<(ClauseName, usize)>) -> MachineStub {
+        let err = MachineError::Resource("memory");
 
-                self.p += 1;
-            },
-            &BuiltInInstruction::RemoveInferenceCounter(r1, r2) => { // A1 = B
-                match call_policy.downcast_mut::<CallWithInferenceLimitCallPolicy>().ok() {
-                    Some(call_policy) => {
-                        let a1 = self.store(self.deref(self[r1].clone()));
+        match culprit {
+            Some((name, arity)) => self.error_form(err, name, arity),
+            None => err.to_stub_in_unknown_context(self.heap.h)
+        }
+    }
 
-                        if let Addr::Con(Constant::Usize(bp)) = a1 {
-                            let count = call_policy.remove_limit(bp);
-                            self[r2] = Addr::Con(Constant::Number(Number::Integer(count)));
-                        } else {
-                            panic!("remove_inference_counter: expected Usize in A1.");
-                        }
-                    },
-                    None => panic!("remove_inference_counters: requires \\
-                                    CallWithInferenceLimitCallPolicy.")
-                };
+    pub(super) fn check_resource_limits(&mut self, culprit: Option<(ClauseName, usize)>)
+                                        -> CallResult
+    {
+        let exceeded = self.heap.h > self.resource_limits.heap
+            || self.b > self.resource_limits.or_stack
+            || self.tr > self.resource_limits.trail
+            || self.and_stack.len() > self.resource_limits.and_stack && {
+                self.compact_and_stack();
+                self.and_stack.len() > self.resource_limits.and_stack
+            };
 
-                self.p += 1;
-            },
-            &BuiltInInstruction::RestoreCutPolicy => {
-                let restore_default =
-                    if let Ok(cut_policy) = cut_policy.downcast_ref::<SetupCallCleanupCutPolicy>() {
-                        cut_policy.out_of_cont_pts()
-                    } else {
-                        false
-                    };
+        if !exceeded {
+            return Ok(());
+        }
 
-                if restore_default {
-                    *cut_policy = Box::new(DefaultCutPolicy {});
-                }
+        Err(self.resource_error(culprit))
+    }
 
-                self.p += 1;
-            },
-            &BuiltInInstruction::SetBall => {
-                let addr = self[temp_v!(1)].clone();
-                self.ball.0 = self.heap.h;
+    fn try_resource_limit(&self) -> Result<(ResourceLimit, usize), MachineError> {
+        let flag  = self.store(self.deref(self[temp_v!(1)].clone()));
+        let value = self.store(self.deref(self[temp_v!(2)].clone()));
 
-                {
-                    let mut duplicator = DuplicateBallTerm::new(self);
-                    duplicator.duplicate_term(addr);
-                }
+        let limit = match flag.clone() {
+            Addr::Con(Constant::Atom(ref name)) =>
+                match ResourceLimit::from_flag(name.as_str()) {
+                    Some(limit) => limit,
+                    None => return Err(MachineError::domain_error("prolog_flag", flag))
+                },
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                return Err(MachineError::Instantiation),
+            _ => return Err(MachineError::type_error("atom", flag))
+        };
+
+        match value.clone() {
+            Addr::Con(Constant::Number(Number::Integer(n))) =>
+                match n.to_usize() {
+                    Some(n) if n > 0 => Ok((limit, n)),
+                    _ => Err(MachineError::domain_error("flag_value", value))
+                },
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                Err(MachineError::Instantiation),
+            _ => Err(MachineError::type_error("integer", value))
+        }
+    }
 
-                self.p += 1;
-            },
-            &BuiltInInstruction::SetCutPoint(r) =>
-                cut_policy.cut(self, r),
-            &BuiltInInstruction::CleanUpBlock => {
-                let nb = self.store(self.deref(self[temp_v!(1)].clone()));
+    // the value of a flag set by set_prolog_flag/2, one of values.
+    fn try_flag_value(&self, values: &[&'static str]) -> Result<&'static str, MachineError> {
+        let value = self.store(self.deref(self[temp_v!(2)].clone()));
 
-                match nb {
-                    Addr::Con(Constant::Usize(nb)) => {
-                        let b = self.b - 1;
+        match value.clone() {
+            Addr::Con(Constant::Atom(ref name)) =>
+                match values.iter().find(|v| **v == name.as_str()) {
+                    Some(v) => Ok(*v),
+                    None => Err(MachineError::domain_error("flag_value", value.clone()))
+                },
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                Err(MachineError::Instantiation),
+            _ => Err(MachineError::type_error("atom", value))
+        }
+    }
 
-                        if nb > 0 && self.or_stack[b].b == nb {
-                            self.b = self.or_stack[nb - 1].b;
-                            self.or_stack.truncate(self.b);
-                        }
+    fn try_set_flag(&mut self, flag: Addr) -> Result<(), MachineError> {
+        let name = match flag.clone() {
+            Addr::Con(Constant::Atom(name)) => name,
+            _ => return self.try_resource_limit().map(|(limit, n)| self.resource_limits.set(limit, n))
+        };
 
-                        self.p += 1;
-                    },
-                    _ => self.fail = true
+        match name.as_str() {
+            "import_conflicts" => {
+                self.import_conflicts = match self.try_flag_value(&["error", "warning"])? {
+                    "error" => ImportConflicts::Error,
+                    _ => ImportConflicts::Warning
                 };
             },
-            &BuiltInInstruction::InstallNewBlock => {
-                self.block = self.b;
-                let c = Constant::Usize(self.block);
-                let addr = self[temp_v!(1)].clone();
-
-                self.write_constant_to_var(addr, c);
-                self.p += 1;
+            "double_quotes" => {
+                let value = self.try_flag_value(&["atom", "chars", "codes", "string"])?;
+                self.flags.double_quotes = DoubleQuotes::from_name(value).unwrap();
             },
-            &BuiltInInstruction::ResetBlock => {
-                let addr = self.deref(self[temp_v!(1)].clone());
-                self.reset_block(addr);
+            "unknown" => {
+                let value = self.try_flag_value(&["error", "fail", "warning"])?;
+                let module_name = self.context_module();
+
+                self.flags.set_unknown_in(module_name, Unknown::from_name(value).unwrap());
             },
-            &BuiltInInstruction::UnwindStack =>
-                self.unwind_stack(),
-            &BuiltInInstruction::InternalCallN =>
-                self.handle_internal_call_n(call_policy, code_dirs),
-            &BuiltInInstruction::Fail => {
-                self.fail = true;
-                self.p += 1;
+            "occurs_check" => {
+                let value = self.try_flag_value(&["error", "false", "true"])?;
+                self.flags.occurs_check = OccursCheck::from_name(value).unwrap();
             },
-            &BuiltInInstruction::Succeed => {
-                self.p += 1;
+            "debug" =>
+                self.flags.debug = self.try_flag_value(&["off", "on"])? == "on",
+            // there's no character conversion to turn on.
+            "char_conversion" => {
+                self.try_flag_value(&["off"])?;
             },
-            &BuiltInInstruction::Unify => {
-                let a1 = self[temp_v!(1)].clone();
-                let a2 = self[temp_v!(2)].clone();
+            "autoload" =>
+                self.flags.autoload = self.try_flag_value(&["false", "true"])? == "true",
+            name if READ_ONLY_FLAGS.iter().any(|f| *f == name) =>
+                return Err(MachineError::permission_error("modify", "flag", flag)),
+            _ => {
+                let (limit, n) = self.try_resource_limit()?;
+                self.resource_limits.set(limit, n);
+            }
+        };
 
-                self.unify(a1, a2);
-                self.p += 1;
+        Ok(())
+    }
+
+    pub(super) fn set_prolog_flag(&mut self) -> CallResult {
+        let flag = self.store(self.deref(self[temp_v!(1)].clone()));
+
+        match self.try_set_flag(flag) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(self.error_form(err, clause_name!("set_prolog_flag"), 2))
+        }
+    }
+
+
+    fn prolog_flag_value(&mut self, flag: &'static str) -> Addr {
+        match flag {
+            "bounded" => Addr::Con(atom!("false")),
+            "integer_rounding_function" => Addr::Con(atom!("toward_zero")),
+            "double_quotes" => Addr::Con(atom!(self.flags.double_quotes.as_str())),
+            "unknown" => {
+                let module_name = self.context_module();
+                Addr::Con(atom!(self.flags.unknown_in(&module_name).as_str()))
             },
-        };
+            "occurs_check" => Addr::Con(atom!(self.flags.occurs_check.as_str())),
+            "max_integer" => Addr::Con(integer!(isize::max_value())),
+            "min_integer" => Addr::Con(integer!(isize::min_value())),
+            "debug" => Addr::Con(atom!(if self.flags.debug { "on" } else { "off" })),
+            "char_conversion" => Addr::Con(atom!("off")),
+            "autoload" => Addr::Con(atom!(if self.flags.autoload { "true" } else { "false" })),
+            "max_arity" => Addr::Con(atom!("unbounded")),
+            "argv" => {
+                let args: Vec<_> = env::args().map(|arg| Addr::Con(atom!(arg, self.atom_tbl)))
+                                              .collect();
+                Addr::HeapCell(self.to_list(args.into_iter()))
+            },
+            "version" => Addr::Con(atom!(env!("CARGO_PKG_VERSION"))),
+            "home" => {
+                // the directory of the running executable.
+                let home = env::current_exe().ok()
+                    .and_then(|path| path.parent().map(|dir| dir.to_string_lossy().into_owned()))
+                    .unwrap_or(String::from("."));
+
+                Addr::Con(atom!(home, self.atom_tbl))
+            },
+            "import_conflicts" =>
+                Addr::Con(atom!(match self.import_conflicts {
+                    ImportConflicts::Error => "error",
+                    ImportConflicts::Warning => "warning"
+                })),
+            _ => {
+                let limit = ResourceLimit::from_flag(flag).unwrap();
+                Addr::Con(integer!(self.resource_limits.get(limit)))
+            }
+        }
     }
 
-    pub(super) fn try_functor(&mut self) -> Result<(), Vec<HeapCellValue>> {
-        let a1 = self.store(self.deref(self[temp_v!(1)].clone()));
+    // the candidate answers of current_prolog_flag/2, as Flag-Value
+    // pairs.
+    pub(super) fn current_prolog_flags(&mut self) -> CallResult {
+        let flag = self.store(self.deref(self[temp_v!(1)].clone()));
+
+        let err = match flag.clone() {
+            Addr::HeapCell(_) | Addr::StackCell(..) => None,
+            Addr::Con(Constant::Atom(ref name))
+                if PROLOG_FLAGS.iter().chain(READ_ONLY_FLAGS.iter())
+                               .any(|f| *f == name.as_str()) => None,
+            Addr::Con(Constant::Atom(_)) => Some(MachineError::domain_error("prolog_flag", flag)),
+            _ => Some(MachineError::type_error("atom", flag))
+        };
 
-        match a1.clone() {
-            Addr::Str(o) =>
-                match self.heap[o].clone() {
-                    HeapCellValue::NamedStr(arity, name, _) => {
-                        let name  = Addr::Con(Constant::Atom(name)); // A2
-                        let arity = Addr::Con(Constant::Number(rc_integer!(arity)));
+        if let Some(err) = err {
+            return Err(self.error_form(err, clause_name!("current_prolog_flag"), 2));
+        }
 
-                        let a2 = self[temp_v!(2)].clone();
-                        self.unify(a2, name);
+        let mut answers = vec![];
 
-                        if !self.fail {
-                            let a3 = self[temp_v!(3)].clone();
-                            self.unify(a3, arity);
-                        }
-                    },
-                    _ => self.fail = true
-                },
-            Addr::HeapCell(_) | Addr::StackCell(_, _) => {
-                let name  = self.store(self.deref(self[temp_v!(2)].clone()));
-                let arity = self.store(self.deref(self[temp_v!(3)].clone()));
+        for &name in PROLOG_FLAGS.iter() {
+            let value = HeapCellValue::Addr(self.prolog_flag_value(name));
+            let h = self.heap.h;
 
-                if let Addr::Con(Constant::Atom(name)) = name {
-                    if let Addr::Con(Constant::Number(Number::Integer(arity))) = arity {
-                        let f_a = Addr::Str(self.heap.h);
-                        let arity = match arity.to_usize() {
-                            Some(arity) => arity,
-                            None => {
-                                self.fail = true;
-                                return Ok(());
-                            }
-                        };
+            self.heap.append(functor!("-", 2, [heap_atom!(name), value], Fixity::In));
+            answers.push(Addr::Str(h));
+        }
 
-                        if arity > 0 {
-                            self.heap.push(HeapCellValue::NamedStr(arity, name, None));
-                        } else {
-                            let c = Constant::Atom(name.clone());
-                            self.heap.push(HeapCellValue::Addr(Addr::Con(c)));
-                        }
+        let list = Addr::HeapCell(self.to_list(answers.into_iter()));
+        let a3 = self[temp_v!(3)].clone();
 
-                        for _ in 0 .. arity {
-                            let h = self.heap.h;
-                            self.heap.push(HeapCellValue::Addr(Addr::HeapCell(h)));
-                        }
+        self.unify(a3, list);
+        Ok(())
+    }
 
-                        self.unify(a1, f_a);
-                    } else {
-                        return Err(functor!("instantiation_error"));
-                    }
-                } else {
-                    return Err(functor!("instantiation_error"));
-                }
-            },
-            _ => {
-                let a2 = self[temp_v!(2)].clone();
-                self.unify(a1, a2);
+    fn try_op_priority(&self, r: RegType) -> Result<usize, MachineError> {
+        let a = self.store(self.deref(self[r].clone()));
 
-                if !self.fail {
-                    let a3 = self[temp_v!(3)].clone();
-                    self.unify(a3, Addr::Con(Constant::Number(rc_integer!(0))));
-                }
-            }
+        match a.clone() {
+            Addr::Con(Constant::Number(Number::Integer(n))) =>
+                match n.to_usize() {
+                    Some(n) if n <= 1200 => Ok(n),
+                    _ => Err(MachineError::domain_error("operator_priority", a))
+                },
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                Err(MachineError::Instantiation),
+            _ => Err(MachineError::type_error("integer", a))
+        }
+    }
+
+    fn try_op_specifier(&self, r: RegType) -> Result<Specifier, MachineError> {
+        let a = self.store(self.deref(self[r].clone()));
+
+        match a.clone() {
+            Addr::Con(Constant::Atom(name)) =>
+                match specifier_from_name(name.as_str()) {
+                    Some(spec) => Ok(spec),
+                    None => Err(MachineError::domain_error("operator_specifier", a))
+                },
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                Err(MachineError::Instantiation),
+            _ => Err(MachineError::type_error("atom", a))
+        }
+    }
+
+    // the operator names of op/3, given as an atom or a list of atoms.
+    fn try_op_names(&self, r: RegType) -> Result<Vec<ClauseName>, MachineError> {
+        let a = self.store(self.deref(self[r].clone()));
+
+        let names = match a {
+            Addr::Con(Constant::Atom(_)) | Addr::Con(Constant::EmptyList) => vec![a],
+            _ => self.try_from_list_at(a)?
         };
 
-        Ok(())
+        names.into_iter().map(|name| match self.store(self.deref(name)) {
+            Addr::Con(Constant::Atom(name)) => Ok(name),
+            Addr::HeapCell(_) | Addr::StackCell(..) => Err(MachineError::Instantiation),
+            culprit @ Addr::Con(Constant::EmptyList) =>
+                Err(MachineError::permission_error("create", "operator", culprit)),
+            culprit => Err(MachineError::type_error("atom", culprit))
+        }).collect()
     }
 
-    pub(super) fn term_dedup(&self, list: &mut Vec<Addr>) {
-        let mut result = vec![];
+    fn try_op(&self, op_dir: &OpDir) -> Result<Vec<OpDecl>, MachineError> {
+        let prec  = self.try_op_priority(temp_v!(1))?;
+        let spec  = self.try_op_specifier(temp_v!(2))?;
+        let names = self.try_op_names(temp_v!(3))?;
 
-        for a2 in list.iter().cloned() {
-            if let Some(a1) = result.last().cloned() {
-                if self.compare_term_test(&a1, &a2) == Ordering::Equal {
-                    continue;
-                }
+        let mut op_decls = vec![];
+
+        for name in names {
+            let culprit = Addr::Con(Constant::Atom(name.clone()));
+
+            let conflict = match name.as_str() {
+                "," => return Err(MachineError::permission_error("modify", "operator", culprit)),
+                "{}" | "[]" => true,
+                "|" => prec > 0 && (!is_infix!(spec) || prec < 1001),
+                _ if prec == 0 => false,
+                _ if is_infix!(spec) => op_dir.contains_key(&(name.clone(), Fixity::Post)),
+                _ if is_postfix!(spec) => op_dir.contains_key(&(name.clone(), Fixity::In)),
+                _ => false
+            };
+
+            if conflict {
+                return Err(MachineError::permission_error("create", "operator", culprit));
             }
 
-            result.push(a2);
+            op_decls.push(OpDecl(prec, spec, name));
         }
 
-        *list = result;
+        Ok(op_decls)
     }
 
-    pub(super) fn to_list<Iter: Iterator<Item=Addr>>(&mut self, values: Iter) -> usize {
-        let head_addr = self.heap.h;
+    // op/3, which checks every operator before defining any of them.
+    pub(super) fn op(&mut self, code_dirs: &mut CodeDirs) -> CallResult {
+        let op_decls = match self.try_op(code_dirs.op_dir()) {
+            Ok(op_decls) => op_decls,
+            Err(err) => return Err(self.error_form(err, clause_name!("op"), 3))
+        };
 
-        for value in values {
-            let h = self.heap.h;
+        let module_name = self.context_module();
 
-            self.heap.push(HeapCellValue::Addr(Addr::Lis(h+1)));
-            self.heap.push(HeapCellValue::Addr(value));
+        for op_decl in op_decls {
+            // conflicts were ruled out by try_op.
+            let _ = op_decl.submit(module_name.clone(), code_dirs.op_dir());
         }
 
-        self.heap.push(HeapCellValue::Addr(Addr::Con(Constant::EmptyList)));
-        head_addr
+        Ok(())
     }
 
-    pub(super) fn try_from_list(&self, r: RegType) -> Result<Vec<Addr>, Vec<HeapCellValue>>
-    {
-        let a1 = self.store(self.deref(self[r].clone()));
+    // the candidate answers of current_op/3, as op(Priority, Specifier,
+    // Name) terms, after the ISO checks of its arguments.
+    pub(super) fn current_ops(&mut self, code_dirs: &CodeDirs) -> CallResult {
+        let prec = self.store(self.deref(self[temp_v!(1)].clone()));
+        let spec = self.store(self.deref(self[temp_v!(2)].clone()));
+        let name = self.store(self.deref(self[temp_v!(3)].clone()));
+
+        let err = match prec.clone() {
+            Addr::HeapCell(_) | Addr::StackCell(..) => None,
+            Addr::Con(Constant::Number(Number::Integer(n)))
+                if n.to_usize().map(|n| n <= 1200).unwrap_or(false) => None,
+            _ => Some(MachineError::domain_error("operator_priority", prec))
+        };
 
-        match a1.clone() {
-            Addr::Lis(mut l) => {
-                let mut result = Vec::new();
+        let err = err.or(match spec.clone() {
+            Addr::HeapCell(_) | Addr::StackCell(..) => None,
+            Addr::Con(Constant::Atom(spec)) if specifier_from_name(spec.as_str()).is_some() =>
+                None,
+            _ => Some(MachineError::domain_error("operator_specifier", spec))
+        });
 
-                result.push(self.heap[l].as_addr(l));
-                l += 1;
+        let err = err.or(match name.clone() {
+            Addr::HeapCell(_) | Addr::StackCell(..) | Addr::Con(Constant::Atom(_)) => None,
+            _ => Some(MachineError::type_error("atom", name))
+        });
 
-                loop {
-                    match self.heap[l].clone() {
-                        HeapCellValue::Addr(Addr::Lis(hcp)) => {
-                            result.push(self.heap[hcp].as_addr(hcp));
-                            l = hcp + 1;
-                        },
-                        HeapCellValue::Addr(Addr::Con(Constant::EmptyList)) =>
-                            break,
-                        hcv =>
-                            return Err(functor!("type_error", 2, [heap_atom!("list"), hcv]))
-                    };
-                }
+        if let Some(err) = err {
+            return Err(self.error_form(err, clause_name!("current_op"), 3));
+        }
 
-                Ok(result)
-            },
+        // the calling module, or failing that, the toplevel one.
+        let module_name = match self.context_module() {
+            ref module_name if module_name.as_str() == "user" => self.toplevel_module.clone(),
+            module_name => module_name
+        };
+
+        let mut answers = vec![];
+
+        for ((name, _), (spec, prec, _)) in code_dirs.ops(&module_name) {
+            let h = self.heap.h;
+            let name = HeapCellValue::Addr(Addr::Con(Constant::Atom(name)));
+
+            self.heap.append(functor!("op", 3, [heap_integer!(prec),
+                                                heap_atom!(specifier_name(spec)),
+                                                name]));
+            answers.push(Addr::Str(h));
+        }
+
+        let list = Addr::HeapCell(self.to_list(answers.into_iter()));
+        let a4 = self[temp_v!(4)].clone();
+
+        self.unify(a4, list);
+        Ok(())
+    }
+
+    fn try_depth_limit(&self, r: RegType) -> Result<usize, MachineError> {
+        let a = self.store(self.deref(self[r].clone()));
+
+        match a.clone() {
+            Addr::Con(Constant::Number(Number::Integer(n))) =>
+                if *n < BigInt::zero() {
+                    Err(MachineError::domain_error("not_less_than_zero", a))
+                } else {
+                    Ok(n.to_usize().unwrap_or(usize::max_value()))
+                },
             Addr::HeapCell(_) | Addr::StackCell(..) =>
-                Err(functor!("instantiation_error")),
-            addr =>
-                Err(functor!("type_error", 2, [heap_atom!("list"), HeapCellValue::Addr(addr)]))
+                Err(MachineError::Instantiation),
+            _ => Err(MachineError::type_error("integer", a))
         }
     }
 
-    pub(super) fn project_onto_key(&self, a: Addr) -> Result<Addr, Vec<HeapCellValue>> {
-        match self.store(self.deref(a)) {
-            Addr::Str(s) =>
-                match self.heap[s].clone() {
-                    HeapCellValue::NamedStr(2, ref name, Some(Fixity::In))
-                        if *name == clause_name!("-") =>
-                           Ok(Addr::HeapCell(s+1)),
-                    _ =>
-                        panic!("Addr::Str doesn't point to NamedStr.")
+    // time limits are given in seconds, as an integer, a float or a
+    // rational. a limit of 0 expires at once, and negative limits are
+    // domain errors.
+    fn try_time_limit(&self, r: RegType) -> Result<Duration, MachineError> {
+        let a = self.store(self.deref(self[r].clone()));
+
+        let secs = match a.clone() {
+            Addr::Con(Constant::Number(Number::Integer(n))) => n.to_f64(),
+            Addr::Con(Constant::Number(Number::Float(f))) => Some(f.into_inner()),
+            Addr::Con(Constant::Number(Number::Rational(r))) =>
+                match (r.numer().to_f64(), r.denom().to_f64()) {
+                    (Some(n), Some(d)) => Some(n / d),
+                    _ => None
                 },
-            a => Err(functor!("type_error", 2, [heap_atom!("callable"), HeapCellValue::Addr(a)]))
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                return Err(MachineError::Instantiation),
+            _ => None
+        };
+
+        match secs {
+            Some(secs) if secs.is_nan() || secs < 0.0 =>
+                Err(MachineError::domain_error("not_less_than_zero", a)),
+            // limits past the range of Duration are unlimited.
+            Some(secs) if secs >= u64::max_value() as f64 =>
+                Ok(Duration::new(u64::max_value(), 0)),
+            Some(secs) => {
+                let nanos = (secs.fract() * 1e9) as u32;
+                Ok(Duration::new(secs.trunc() as u64, nanos))
+            },
+            None => Err(MachineError::type_error("number", a))
         }
     }
 
@@ -1731,25 +3905,85 @@ impl MachineState {
         let a1 = self[temp_v!(1)].clone();
         let a2 = self[temp_v!(2)].clone();
 
+        !self.is_identical(a1, a2)
+    }
+
+    fn is_identical(&self, a1: Addr, a2: Addr) -> bool
+    {
         let iter = self.zipped_acyclic_pre_order_iter(a1, a2);
 
         for (v1, v2) in iter {
             match (v1, v2) {
                 (HeapCellValue::NamedStr(ar1, n1, _), HeapCellValue::NamedStr(ar2, n2, _)) =>
                     if ar1 != ar2 || n1 != n2 {
-                        return true;
+                        return false;
                     },
                 (HeapCellValue::Addr(Addr::Lis(_)), HeapCellValue::Addr(Addr::Lis(_))) =>
                     continue,
                 (HeapCellValue::Addr(a1), HeapCellValue::Addr(a2)) =>
                     if a1 != a2 {
-                        return true;
+                        return false;
                     },
-                _ => return true
+                _ => return false
             }
         }
 
-        false
+        true
+    }
+
+    // true if the term at specific is an instance of the term at
+    // general. general is matched against specific without binding
+    // either, and the variables of specific must be left alone.
+    pub(super) fn subsumes(&self, general: Addr, specific: Addr) -> bool
+    {
+        let mut bindings = HashMap::new();
+        let mut pdl = vec![(general, specific.clone())];
+
+        while let Some((g, s)) = pdl.pop() {
+            let g = self.store(self.deref(g));
+            let s = self.store(self.deref(s));
+
+            match (g, s) {
+                (g @ Addr::HeapCell(_), s) | (g @ Addr::StackCell(..), s) =>
+                    match bindings.get(&g).cloned() {
+                        Some(t) =>
+                            if !self.is_identical(t, s) {
+                                return false;
+                            },
+                        None => {
+                            bindings.insert(g, s);
+                        }
+                    },
+                (Addr::Con(c1), Addr::Con(c2)) =>
+                    if c1 != c2 {
+                        return false;
+                    },
+                (Addr::Lis(l1), Addr::Lis(l2)) => {
+                    pdl.push((Addr::HeapCell(l1), Addr::HeapCell(l2)));
+                    pdl.push((Addr::HeapCell(l1 + 1), Addr::HeapCell(l2 + 1)));
+                },
+                (Addr::Str(s1), Addr::Str(s2)) =>
+                    match (&self.heap[s1], &self.heap[s2]) {
+                        (&HeapCellValue::NamedStr(ar1, ref n1, _),
+                         &HeapCellValue::NamedStr(ar2, ref n2, _)) if ar1 == ar2 && n1 == n2 =>
+                            for i in 1 .. ar1 + 1 {
+                                pdl.push((Addr::HeapCell(s1 + i), Addr::HeapCell(s2 + i)));
+                            },
+                        _ => return false
+                    },
+                _ => return false
+            }
+        }
+
+        let specific_vars: HashSet<_> = self.acyclic_pre_order_iter(specific)
+            .filter_map(|v| match v {
+                HeapCellValue::Addr(v @ Addr::HeapCell(_)) |
+                HeapCellValue::Addr(v @ Addr::StackCell(..)) => Some(v),
+                _ => None
+            })
+            .collect();
+
+        bindings.iter().all(|(v, t)| !specific_vars.contains(v) || v == t)
     }
 
     // returns true on failure.
@@ -1836,6 +4070,7 @@ impl MachineState {
                         self.and_stack[index].e  = self.e;
                         self.and_stack[index].cp = self.cp.clone();
                         self.and_stack[index].global_index = gi;
+                        self.and_stack[index].depth = self.depth;
 
                         self.and_stack.resize(index, num_cells);
 
@@ -1847,9 +4082,20 @@ impl MachineState {
 
                 self.and_stack.push(gi, self.e, self.cp.clone(), num_cells);
                 self.e = self.and_stack.len() - 1;
+                self.and_stack[self.e].depth = self.depth;
+            },
+            &ControlInstruction::CallClause(ref ct, arity, _, lco) => {
+                try_or_fail!(self, self.check_resource_limits(Some((ct.name(), arity))));
+                self.depth = if lco { self.depth + 1 } else { self.env_depth() + 1 };
+                try_or_fail!(self, call_policy.try_call_clause(self, code_dirs, ct, arity, lco));
+                self.inferences += BigUint::one();
+
+                // a builtin can grow the heap by any amount in one step,
+                // so the limits are checked again once it returns.
+                if !self.fail {
+                    try_or_fail!(self, self.check_resource_limits(Some((ct.name(), arity))));
+                }
             },
-            &ControlInstruction::CallClause(ref ct, arity, _, lco) =>
-                try_or_fail!(self, call_policy.try_call_clause(self, code_dirs, ct, arity, lco)),
             &ControlInstruction::CheckCpExecute => {
                 let a = self.store(self.deref(self[temp_v!(2)].clone()));
 
@@ -1870,6 +4116,7 @@ impl MachineState {
 
                 self.cp = self.and_stack[e].cp.clone();
                 self.e  = self.and_stack[e].e;
+                self.depth = self.and_stack[e].depth;
 
                 self.p += 1;
             },
@@ -1898,18 +4145,30 @@ impl MachineState {
 
                 self.fail = true;
             },
-            &ControlInstruction::Goto(p, arity, lco) =>
-                self.goto_ptr(CodePtr::DirEntry(p, clause_name!("builtin")), arity, lco),
+            &ControlInstruction::Goto(p, arity, lco) => {
+                try_or_fail!(self, self.check_resource_limits(None));
+
+                // jumps within builtin code are transparent to the call
+                // depth, they run at the depth of the jumping clause.
+                if !lco {
+                    self.depth = self.env_depth();
+                }
+
+                self.goto_ptr(CodePtr::DirEntry(p, clause_name!("builtin")), arity, lco)
+            },
             &ControlInstruction::IsClause(lco, r, ref at) => {
                 let a1 = self[r].clone();
-                let a2 = try_or_fail!(self, self.get_number(at));
+                let a2 = try_or_fail!(self, self.get_number(at), "is", 2);
 
                 self.unify(a1, Addr::Con(Constant::Number(a2)));
                 try_or_fail!(self, return_from_clause!(lco, self));
             },
             &ControlInstruction::JmpBy(arity, offset, _, lco) => {
+                try_or_fail!(self, self.check_resource_limits(None));
+
                 if !lco {
                     self.cp = self.p.clone() + 1;
+                    self.depth = self.env_depth();
                 }
 
                 self.num_of_args = arity;
@@ -1921,6 +4180,72 @@ impl MachineState {
         };
     }
 
+    // an undefined procedure is handed to $undefined_procedure/3, which
+    // gives the unknown_procedure/2 hook of user the chance to define
+    // it, as M:G, before it's called again.
+    pub(super) fn undefined_procedure(&mut self, qualifier: Option<ClauseName>, name: ClauseName,
+                                      arity: usize, lco: bool)
+    {
+        let module_name = qualifier.clone().unwrap_or_else(|| self.context_module());
+
+        let mut goal = if arity == 0 {
+            Addr::Con(Constant::Atom(name.clone()))
+        } else {
+            let h = self.heap.h;
+            self.heap.push(HeapCellValue::NamedStr(arity, name.clone(), None));
+
+            for i in 1 .. arity + 1 {
+                let addr = self.registers[i].clone();
+                self.heap.push(HeapCellValue::Addr(addr));
+            }
+
+            Addr::Str(h)
+        };
+
+        // qualified calls are retried as such, so their existence
+        // errors name the module.
+        if let Some(qualifier) = qualifier {
+            let h = self.heap.h;
+            let qualifier = HeapCellValue::Addr(Addr::Con(Constant::Atom(qualifier)));
+
+            self.heap.append(functor!(":", 2, [qualifier, HeapCellValue::Addr(goal)],
+                                      Fixity::In));
+            goal = Addr::Str(h);
+        }
+
+        let h = self.heap.h;
+        let name = HeapCellValue::Addr(Addr::Con(Constant::Atom(name)));
+
+        self.heap.append(functor!("/", 2, [name, heap_integer!(arity)], Fixity::In));
+
+        self.registers[1] = Addr::Con(Constant::Atom(module_name));
+        self.registers[2] = goal;
+        self.registers[3] = Addr::Str(h);
+
+        self.goto_ptr(CodePtr::DirEntry(620, clause_name!("builtin")), 3, lco);
+    }
+
+    // prints a warning of the call to an undefined procedure, which
+    // then fails.
+    pub(super) fn unknown_procedure_warning(&mut self, module_name: ClauseName, name: ClauseName,
+                                            arity: usize, lco: bool)
+    {
+        let h = self.heap.h;
+
+        let module_name = HeapCellValue::Addr(Addr::Con(Constant::Atom(module_name)));
+        let name = HeapCellValue::Addr(Addr::Con(Constant::Atom(name)));
+
+        self.heap.append(functor!("unknown_procedure", 1, [HeapCellValue::Addr(Addr::Str(h + 2))]));
+        self.heap.append(functor!(":", 2, [module_name, HeapCellValue::Addr(Addr::Str(h + 5))],
+                                  Fixity::In));
+        self.heap.append(functor!("/", 2, [name, heap_integer!(arity)], Fixity::In));
+
+        self.registers[1] = Addr::Con(atom!("warning"));
+        self.registers[2] = Addr::Str(h);
+
+        self.goto_ptr(CodePtr::DirEntry(632, clause_name!("builtin")), 2, lco);
+    }
+
     pub(super) fn goto_ptr(&mut self, p: CodePtr, arity: usize, lco:bool) {
         if !lco {
             self.cp = self.p.clone() + 1;
@@ -1950,6 +4275,7 @@ impl MachineState {
                                    self.num_of_args);
 
                 self.b = self.or_stack.len();
+                self.or_stack[self.b - 1].depth = self.depth;
                 let b = self.b - 1;
 
                 for i in 1 .. n + 1 {
@@ -1959,10 +4285,14 @@ impl MachineState {
                 self.hb = self.heap.h;
                 self.p += l;
             },
-            &IndexedChoiceInstruction::Retry(l) =>
-                try_or_fail!(self, call_policy.retry(self, l)),
-            &IndexedChoiceInstruction::Trust(l) =>
-                try_or_fail!(self, call_policy.trust(self, l))
+            &IndexedChoiceInstruction::Retry(l) => {
+                try_or_fail!(self, call_policy.retry(self, l));
+                self.inferences += BigUint::one();
+            },
+            &IndexedChoiceInstruction::Trust(l) => {
+                try_or_fail!(self, call_policy.trust(self, l));
+                self.inferences += BigUint::one();
+            }
         };
     }
 
@@ -1985,6 +4315,7 @@ impl MachineState {
                                    self.num_of_args);
 
                 self.b = self.or_stack.len();
+                self.or_stack[self.b - 1].depth = self.depth;
                 let b  = self.b - 1;
 
                 for i in 1 .. n + 1 {
@@ -1994,10 +4325,14 @@ impl MachineState {
                 self.hb = self.heap.h;
                 self.p += 1;
             },
-            &ChoiceInstruction::RetryMeElse(offset) =>
-                try_or_fail!(self, call_policy.retry_me_else(self, offset)),
-            &ChoiceInstruction::TrustMe =>
-                try_or_fail!(self, call_policy.trust_me(self))
+            &ChoiceInstruction::RetryMeElse(offset) => {
+                try_or_fail!(self, call_policy.retry_me_else(self, offset));
+                self.inferences += BigUint::one();
+            },
+            &ChoiceInstruction::TrustMe => {
+                try_or_fail!(self, call_policy.trust_me(self));
+                self.inferences += BigUint::one();
+            }
         }
     }
 
@@ -2030,6 +4365,7 @@ impl MachineState {
 
     pub(super) fn reset(&mut self) {
         self.hb = 0;
+        self.depth = 0;
         self.e = 0;
         self.b = 0;
         self.b0 = 0;
@@ -2048,5 +4384,9 @@ impl MachineState {
         self.registers = vec![Addr::HeapCell(0); 64];
         self.block = 0;
         self.ball = (0, Vec::new());
+        self.backtrace.clear();
+        self.time_limits.clear();
+        self.write_var = None;
+        self.occurs_check_error = None;
     }
 }
@@ -1,23 +1,36 @@
 use prolog::ast::*;
 use prolog::builtins::*;
 use prolog::heap_print::*;
+use prolog::io::*;
 use prolog::tabled_rc::*;
 
+pub(crate) mod machine_errors;
 pub(crate) mod machine_state;
 #[macro_use]
 mod machine_state_impl;
 
+pub use prolog::machine::machine_state::ResourceLimit;
+
+use prolog::machine::machine_errors::*;
 use prolog::machine::machine_state::*;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
 use std::mem::swap;
-use std::ops::Index;
+use std::ops::{Index, Range};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// set by the SIGINT handler, and polled by the query stepper.
+pub static INTERRUPT: AtomicBool = AtomicBool::new(false);
 
 struct MachineCodeIndex<'a> {
     code_dir: &'a mut CodeDir,
     op_dir: &'a mut OpDir,
+    messages: &'a mut Vec<Message>,
+    conflicts: ImportConflicts
 }
 
 pub struct Machine {
@@ -27,9 +40,23 @@ pub struct Machine {
     code: Code,
     pub(super) code_dir: CodeDir,
     pub(super) op_dir: OpDir,
+    pub(super) meta_predicates: MetaPredicateDir,
     term_dir: TermDir,
+    user_files: HashMap<PredicateKey, ClauseName>, // the files of consulted user predicates.
     modules: HashMap<ClauseName, Module>,
-    cached_query: Option<Code>
+    module_code: HashMap<ClauseName, Range<usize>>,
+    dead_code: Vec<Range<usize>>, // the code of replaced modules, not yet compacted.
+    autoload_index: HashMap<PredicateKey, ClauseName>,
+    libraries: HashMap<ClauseName, (&'static str, ClauseName)>, // the sources and files of unloaded libraries.
+    defer_messages: bool, // set while a library is loaded, possibly in a running query.
+    modes: HashMap<ClauseName, ModeDir>, // the declared modes, by module.
+    pending_messages: Vec<Message>,
+    cached_query: Option<Code>,
+    query_timeout: Option<Duration>,
+    interrupt: Arc<AtomicBool>, // the interrupts of this machine alone.
+    interrupt_reader: fn() -> InterruptAction,
+    tracing: bool,
+    aborted: bool
 }
 
 impl Index<CodePtr> for Machine {
@@ -56,6 +83,97 @@ impl<'a> SubModuleUser for MachineCodeIndex<'a> {
     fn code_dir(&mut self) -> &mut CodeDir {
         self.code_dir
     }
+
+    fn module_name(&self) -> ClauseName {
+        clause_name!("user")
+    }
+
+    fn conflicts(&self) -> ImportConflicts {
+        self.conflicts
+    }
+
+    fn report(&mut self, message: Message) {
+        self.messages.push(message);
+    }
+
+    fn exports(&mut self) -> Option<&mut Vec<PredicateKey>> {
+        None
+    }
+}
+
+fn exports_key(module: &Module, name: &ClauseName, arity: usize) -> bool {
+    module.module_decl.exports.iter().any(|&(ref export_name, export_arity)| {
+        export_arity == arity && &export_name.clone().defrock_brackets() == name
+    })
+}
+
+// rebinds the imports of module_name to the new module, dropping those
+// it no longer exports.
+fn rebind_imports(importer: ClauseName, code_dir: &mut CodeDir, op_dir: &mut OpDir,
+                  module_name: &ClauseName, new_module: Option<&Module>,
+                  messages: &mut Vec<Message>)
+{
+    let keys: Vec<PredicateKey> = code_dir.iter()
+        .filter(|&(_, idx)| &idx.1 == module_name)
+        .map(|(key, _)| key.clone())
+        .collect();
+
+    for (name, arity) in keys {
+        let new_idx = new_module.and_then(|module| {
+            // renamed imports share the cells of the predicates they name.
+            let idx = &code_dir[&(name.clone(), arity)];
+            let source = module.code_dir.iter()
+                .find(|&(_, module_idx)| Rc::ptr_eq(&module_idx.0, &idx.0))
+                .map(|(key, _)| key.0.clone())
+                .unwrap_or(name.clone());
+
+            if exports_key(module, &source, arity) {
+                module.code_dir.get(&(source, arity)).cloned()
+            } else {
+                None
+            }
+        });
+
+        let key = (name, arity);
+
+        match new_idx {
+            Some(new_idx) =>
+                if let Some(idx) = code_dir.get(&key) {
+                    idx.0.set(new_idx.0.get());
+                },
+            None => {
+                // the old cell may still be shared by the predicates
+                // the module keeps, so the client gets a cell of its own.
+                code_dir.insert(key.clone(), CodeIndex::default());
+
+                let (name, arity) = key;
+                messages.push(Message::LostImport(importer.clone(), name, arity,
+                                                  module_name.clone()));
+            }
+        }
+    }
+
+    let op_keys: Vec<OpDirKey> = op_dir.iter()
+        .filter(|&(_, &(_, _, ref op_module))| op_module == module_name)
+        .map(|(key, _)| key.clone())
+        .collect();
+
+    for key in op_keys {
+        let arity = if key.1 == Fixity::In { 2 } else { 1 };
+
+        let new_op = new_module.and_then(|module| {
+            if exports_key(module, &key.0, arity) {
+                module.op_dir.get(&key).cloned()
+            } else {
+                None
+            }
+        });
+
+        match new_op {
+            Some(op_data) => op_dir.insert(key, op_data),
+            None => op_dir.remove(&key)
+        };
+    }
 }
 
 impl Machine {
@@ -70,22 +188,42 @@ impl Machine {
             code,
             code_dir,
             term_dir: TermDir::new(),
+            user_files: HashMap::new(),
             op_dir,
+            meta_predicates: builtin_meta_predicates(),
             modules: HashMap::new(),
-            cached_query: None
+            module_code: HashMap::new(),
+            dead_code: vec![],
+            autoload_index: HashMap::new(),
+            libraries: HashMap::new(),
+            defer_messages: false,
+            modes: HashMap::new(),
+            pending_messages: vec![],
+            cached_query: None,
+            query_timeout: None,
+            interrupt: Arc::new(AtomicBool::new(false)),
+            interrupt_reader: read_interrupt_action,
+            tracing: false,
+            aborted: false
         }
     }
 
     fn remove_module(&mut self, module_name: ClauseName) {
-        let iter = if let Some(submodule) = self.modules.get(&module_name) {
-            submodule.module_decl.exports.iter().cloned()
+        let submodule = if let Some(submodule) = self.modules.get(&module_name) {
+            submodule
         } else {
             return;
         };
 
-        for (name, arity) in iter {
+        for (name, arity) in submodule.module_decl.exports.iter().cloned() {
             let name = name.defrock_brackets();
 
+            // reexports are tagged with the module defining them.
+            let module_name = match submodule.code_dir.get(&(name.clone(), arity)) {
+                Some(&CodeIndex (_, ref mod_name)) => mod_name.clone(),
+                None => module_name.clone()
+            };
+
             match self.code_dir.get(&(name.clone(), arity)).cloned() {
                 Some(CodeIndex (_, ref mod_name)) if mod_name == &module_name => {
                     self.code_dir.remove(&(name.clone(), arity));
@@ -127,33 +265,322 @@ impl Machine {
         self.ms.atom_tbl.clone()
     }
 
-    pub fn use_qualified_module_in_toplevel(&mut self, name: ClauseName, exports: Vec<PredicateKey>)
-                                            -> EvalSession
+    pub fn toplevel_module(&self) -> ClauseName {
+        self.ms.toplevel_module.clone()
+    }
+
+    // the code, op and meta-predicate dirs of the toplevel module.
+    pub fn toplevel_dirs(&mut self) -> (&mut CodeDir, &mut OpDir, &mut MetaPredicateDir) {
+        match self.modules.get_mut(&self.ms.toplevel_module) {
+            Some(module) => (&mut module.code_dir, &mut module.op_dir, &mut module.meta_predicates),
+            None => (&mut self.code_dir, &mut self.op_dir, &mut self.meta_predicates)
+        }
+    }
+
+    pub fn toplevel_op_dir(&self) -> &OpDir {
+        match self.modules.get(&self.ms.toplevel_module) {
+            Some(module) => &module.op_dir,
+            None => &self.op_dir
+        }
+    }
+
+    // true if module_name defines the predicate of key, rather than
+    // importing it.
+    pub fn defines(&self, module_name: &ClauseName, key: &PredicateKey) -> bool {
+        let code_dir = match self.modules.get(module_name) {
+            Some(module) => &module.code_dir,
+            None if module_name.as_str() == "user" => &self.code_dir,
+            None => return false
+        };
+
+        match code_dir.get(key) {
+            Some(idx) => idx.1 == *module_name && idx.0.get() != IndexPtr::Undefined,
+            None => false
+        }
+    }
+
+    // true if term_expansion/2 or goal_expansion/2 is defined in any
+    // module.
+    pub fn has_expansion_hooks(&self) -> bool {
+        let user = clause_name!("user");
+        let keys = [(clause_name!("term_expansion"), 2), (clause_name!("goal_expansion"), 2)];
+
+        keys.iter().any(|key| {
+            self.defines(&user, key) || self.modules.keys().any(|name| self.defines(name, key))
+        })
+    }
+
+    // the term at addr, whose unbound variables are named after the
+    // variables of var_dir bound to them.
+    pub fn heap_term(&self, addr: Addr, var_dir: &HeapVarDict) -> Term {
+        let mut var_names = HashMap::new();
+
+        for (var, addr) in var_dir {
+            let addr = self.ms.deref(addr.clone());
+
+            if addr.is_ref() {
+                var_names.insert(addr, var.clone());
+            }
+        }
+
+        self.ms.heap_term(addr, &mut var_names)
+    }
+
+    // the meta-predicate specs of name/arity as called from
+    // module_name, declared by the module defining it.
+    pub fn meta_specs(&self, module_name: &ClauseName, key: &PredicateKey) -> Option<Vec<MetaSpec>> {
+        let code_dir = match self.modules.get(module_name) {
+            Some(module) => &module.code_dir,
+            None => &self.code_dir
+        };
+
+        let owner = match code_dir.get(key) {
+            Some(idx) => idx.1.clone(),
+            None => module_name.clone()
+        };
+
+        let meta_predicates = match self.modules.get(&owner) {
+            Some(module) => &module.meta_predicates,
+            None => &self.meta_predicates
+        };
+
+        meta_predicates.get(key).cloned()
+    }
+
+    // calls module_name:hook(Term, X) on term, returning the term bound
+    // to X, or None if the hook fails. a choice point below the call
+    // keeps its failures and exceptions from reaching a running query,
+    // whose state is restored after.
+    pub fn call_expansion_hook(&mut self, module_name: &ClauseName, hook: &'static str,
+                               term: &Term)
+                               -> Result<Option<Term>, EvalError>
+    {
+        let (p, owner) = {
+            let code_dir = match self.modules.get(module_name) {
+                Some(module) => &module.code_dir,
+                None => &self.code_dir
+            };
+
+            match code_dir.get(&(clause_name!(hook), 2)) {
+                Some(idx) => match idx.0.get() {
+                    IndexPtr::Index(p) => (p, idx.1.clone()),
+                    IndexPtr::Undefined => return Ok(None)
+                },
+                None => return Ok(None)
+            }
+        };
+
+        let registers = self.ms.registers.clone();
+        let (e, b, b0, block, hb) = (self.ms.e, self.ms.b, self.ms.b0, self.ms.block, self.ms.hb);
+        let (tr, h, and_len) = (self.ms.tr, self.ms.heap.h, self.ms.and_stack.len());
+        let (p0, cp0, num_of_args) = (self.ms.p.clone(), self.ms.cp.clone(), self.ms.num_of_args);
+        let mut ball = (0, Vec::new());
+        swap(&mut ball, &mut self.ms.ball);
+
+        let gi = self.ms.next_global_index();
+
+        self.ms.or_stack.push(gi, e, cp0.clone(), b, CodePtr::TopLevel(0, 0), tr, h, b0, 0);
+        self.ms.b = b + 1;
+        self.ms.block = self.ms.b;
+        self.ms.hb = h;
+
+        let mut vars = HashMap::new();
+        let a1 = self.ms.term_to_heap(term, &mut vars);
+        let a2 = self.ms.heap.h;
+
+        self.ms.heap.push(HeapCellValue::Addr(Addr::HeapCell(a2)));
+
+        self.ms.registers[1] = a1;
+        self.ms.registers[2] = Addr::HeapCell(a2);
+
+        self.ms.fail = false;
+        self.ms.num_of_args = 2;
+        self.ms.b0 = self.ms.b;
+        self.ms.cp = CodePtr::TopLevel(0, 0);
+        self.ms.p  = CodePtr::DirEntry(p, owner);
+
+        self.query_stepper();
+
+        if self.aborted {
+            self.aborted = false;
+            return Err(EvalError::QueryAborted);
+        }
+
+        let result = if self.failed() {
+            match self.fail() {
+                EvalSession::Error(EvalError::QueryFailure) => Ok(None),
+                EvalSession::Error(err) => Err(err),
+                _ => Ok(None)
+            }
+        } else {
+            Ok(Some(self.heap_term(Addr::HeapCell(a2), &vars)))
+        };
+
+        let tr_top = self.ms.tr;
+
+        self.ms.unwind_trail(tr, tr_top);
+        self.ms.trail.truncate(tr);
+        self.ms.heap.truncate(h);
+        self.ms.or_stack.truncate(b);
+        self.ms.and_stack.truncate(and_len);
+
+        self.ms.registers = registers;
+        self.ms.e = e;
+        self.ms.b = b;
+        self.ms.b0 = b0;
+        self.ms.block = block;
+        self.ms.hb = hb;
+        self.ms.tr = tr;
+        self.ms.p = p0;
+        self.ms.cp = cp0;
+        self.ms.num_of_args = num_of_args;
+        self.ms.fail = false;
+        self.ms.ball = ball;
+
+        result
+    }
+
+    // runs f on the toplevel module, given the other modules, and
+    // prints the messages it raises.
+    fn with_toplevel_user<F, R>(&mut self, f: F) -> R
+        where F: FnOnce(&mut SubModuleUser, &HashMap<ClauseName, Module>) -> R
     {
-        self.remove_module(name.clone());
+        let toplevel = self.ms.toplevel_module.clone();
+        let conflicts = self.ms.import_conflicts;
+
+        let mut messages = vec![];
 
-        match self.modules.get(&name) {
-            Some(ref module) => {
-                let mut indices = MachineCodeIndex { code_dir: &mut self.code_dir,
-                                                     op_dir: &mut self.op_dir };
+        // a toplevel module other than user is taken out of the module
+        // dir while it's in use.
+        let result = match self.modules.remove(&toplevel) {
+            Some(mut module) => {
+                let result = {
+                    let mut user = ModuleImports { module: &mut module,
+                                                   messages: &mut messages,
+                                                   conflicts };
 
-                indices.use_qualified_module(module, exports)
+                    f(&mut user, &self.modules)
+                };
+
+                self.modules.insert(toplevel, module);
+                result
             },
-            None => EvalSession::from(EvalError::ModuleNotFound)
+            None => {
+                let mut user = MachineCodeIndex { code_dir: &mut self.code_dir,
+                                                  op_dir: &mut self.op_dir,
+                                                  messages: &mut messages,
+                                                  conflicts };
+
+                f(&mut user, &self.modules)
+            }
+        };
+
+        self.print_messages(messages);
+        result
+    }
+
+    pub fn use_qualified_module_in_toplevel(&mut self, name: ClauseName, imports: ImportList)
+                                            -> EvalSession
+    {
+        if let EvalSession::Error(err) = self.load_library(&name) {
+            return EvalSession::Error(err);
+        }
+
+        if self.ms.toplevel_module.as_str() == "user" {
+            self.remove_module(name.clone());
         }
+
+        self.with_toplevel_user(|user, modules| match modules.get(&name) {
+            Some(module) => user.use_qualified_module(module, imports),
+            None => EvalSession::from(EvalError::ModuleNotFound)
+        })
+    }
+
+    pub fn import_conflicts(&self) -> ImportConflicts {
+        self.ms.import_conflicts
+    }
+
+    pub fn double_quotes(&self) -> DoubleQuotes {
+        self.ms.flags.double_quotes
+    }
+
+    // runs f with module_name as the toplevel module, so the directives
+    // of a module can be run before it's loaded.
+    pub fn with_toplevel_module<F, T>(&mut self, module_name: ClauseName, f: F) -> T
+        where F: FnOnce(&mut Machine) -> T
+    {
+        let toplevel = self.ms.toplevel_module.clone();
+
+        self.ms.toplevel_module = module_name;
+        let result = f(self);
+        self.ms.toplevel_module = toplevel;
+
+        result
+    }
+
+    // the toplevel definitions of predicates it imports are conflicts,
+    // or drop the imports with warnings.
+    pub fn define_in_toplevel(&mut self, keys: Vec<PredicateKey>) -> Result<(), EvalError> {
+        self.with_toplevel_user(|user, _| {
+            keys.into_iter().map(|(name, arity)| user.define_local(name, arity)).collect()
+        })
     }
 
     pub fn use_module_in_toplevel(&mut self, name: ClauseName) -> EvalSession {
-        self.remove_module(name.clone());
+        if let EvalSession::Error(err) = self.load_library(&name) {
+            return EvalSession::Error(err);
+        }
 
-        match self.modules.get(&name) {
-            Some(ref module) => {
-                let mut indices = MachineCodeIndex { code_dir: &mut self.code_dir,
-                                                     op_dir: &mut self.op_dir };
+        if self.ms.toplevel_module.as_str() == "user" {
+            self.remove_module(name.clone());
+        }
 
-                indices.use_module(module)
-            },
+        self.with_toplevel_user(|user, modules| match modules.get(&name) {
+            Some(module) => user.use_module(module),
             None => EvalSession::from(EvalError::ModuleNotFound)
+        })
+    }
+
+    // runs print_message/2 on the message between queries, which leaves
+    // the machine reset.
+    pub fn print_message(&mut self, message: Message) {
+        let p = match self.code_dir.get(&(clause_name!("print_message"), 2)) {
+            Some(idx) => match idx.0.get() {
+                IndexPtr::Index(p) => p,
+                IndexPtr::Undefined => return
+            },
+            None => return
+        };
+
+        self.reset();
+
+        let h = self.ms.heap.h;
+        let kind = message.kind();
+
+        self.ms.heap.append(message.to_stub(h, self.atom_tbl()));
+
+        self.ms.registers[1] = Addr::Con(atom!(kind.as_str()));
+        self.ms.registers[2] = Addr::HeapCell(h);
+
+        self.ms.num_of_args = 2;
+        self.ms.b0 = self.ms.b;
+        self.ms.cp = CodePtr::TopLevel(0, 0);
+        self.ms.p  = CodePtr::DirEntry(p, clause_name!("builtin"));
+
+        self.query_stepper();
+
+        self.aborted = false;
+        self.reset();
+    }
+
+    pub fn print_messages(&mut self, mut messages: Vec<Message>) {
+        if self.defer_messages {
+            self.pending_messages.append(&mut messages);
+            return;
+        }
+
+        for message in messages {
+            self.print_message(message);
         }
     }
 
@@ -161,45 +588,623 @@ impl Machine {
         self.modules.get(&name)
     }
 
-    pub fn add_batched_code(&mut self, mut code: Code, code_dir: CodeDir) {
+    fn toplevel_term_dir(&mut self) -> &mut TermDir {
+        match self.modules.get_mut(&self.ms.toplevel_module) {
+            Some(module) => &mut module.term_dir,
+            None => &mut self.term_dir
+        }
+    }
+
+    // the clauses of the batch are labelled in the toplevel code dir
+    // as they're compiled.
+    pub fn add_batched_code(&mut self, mut code: Code, term_dir: TermDir, file: Option<ClauseName>) {
+        self.resolve_qualified_calls(&mut code, None);
+
+        if self.ms.toplevel_module.as_str() == "user" {
+            for key in term_dir.keys() {
+                match file {
+                    Some(ref file) => self.user_files.insert(key.clone(), file.clone()),
+                    None => self.user_files.remove(key)
+                };
+            }
+        }
+
         self.code.append(&mut code);
-        self.code_dir.extend(code_dir.into_iter());
+        self.toplevel_term_dir().extend(term_dir.into_iter());
     }
 
     pub fn add_batched_ops(&mut self, op_dir: OpDir) {
-        self.op_dir.extend(op_dir.into_iter());
+        self.toplevel_dirs().1.extend(op_dir.into_iter());
     }
 
-    pub fn add_module(&mut self, module: Module, code: Code) {
-        self.modules.insert(module.module_decl.name.clone(), module);
+    // a module of the same name is replaced, and its importers are
+    // rebound to the new code.
+    pub fn add_module(&mut self, mut module: Module, mut code: Code) {
+        let name = module.module_decl.name.clone();
+
+        let messages = match self.modules.remove(&name) {
+            Some(old_module) => {
+                let messages = self.rebind_module(old_module, Some(&mut module));
+
+                self.reclaim_code(&name);
+                messages
+            },
+            None => vec![]
+        };
+
+        self.resolve_qualified_calls(&mut code, Some(&module));
+
+        let offset = self.code.len();
         self.code.extend(code.into_iter());
+
+        self.module_code.insert(name.clone(), offset .. self.code.len());
+
+        // qualified calls into the old module are pointed at the new
+        // one, whose unexported predicates don't share the old cells.
+        for line in self.code.iter_mut() {
+            if let &mut Line::Control(ControlInstruction::CallClause(ref mut ct, arity, ..)) = line {
+                if let &mut ClauseType::Qualified(ref module_name, ref pred_name, ref mut idx) = ct {
+                    if module_name == &name {
+                        if let Some(entry) = module.code_dir.get(&(pred_name.clone(), arity)) {
+                            *idx = entry.clone();
+                        }
+                    }
+                }
+            }
+        }
+
+        self.modules.insert(name, module);
+        self.print_messages(messages);
+    }
+
+    // a library module is indexed by the exports of its declaration,
+    // and loaded on the first undefined call to one of them, unless
+    // the autoload flag is false, or when a module is imported by
+    // its name.
+    pub fn add_library(&mut self, src: &'static str, file: &'static str) -> Result<(), EvalError> {
+        let module_decl = library_module_decl(self, src)?;
+        let name = module_decl.name;
+
+        for (pred_name, arity) in module_decl.exports {
+            self.autoload_index.insert((pred_name.defrock_brackets(), arity), name.clone());
+        }
+
+        self.libraries.insert(name, (src, ClauseName::BuiltIn(file)));
+        Ok(())
+    }
+
+    // loads the library module name if it's not yet loaded. a library
+    // may be loaded in a running query, so the messages raised while
+    // it's compiled are held back for the end of the query.
+    pub fn load_library(&mut self, name: &ClauseName) -> EvalSession {
+        let (src, file) = match self.libraries.remove(name) {
+            Some(library) => library,
+            None => return EvalSession::EntrySuccess
+        };
+
+        let defer_messages = self.defer_messages;
+
+        self.defer_messages = true;
+        let result = compile_file(self, src, Some(file));
+        self.defer_messages = defer_messages;
+
+        result
+    }
+
+    // the modes of a predicate of module_name, checked on entry to
+    // the code compiled for it from here on.
+    pub fn declare_modes(&mut self, module_name: ClauseName, name: ClauseName, modes: Vec<Mode>) {
+        let key = (name, modes.len());
+        self.modes.entry(module_name).or_insert_with(ModeDir::new).insert(key, modes);
+    }
+
+    pub fn modes(&self, module_name: &ClauseName, key: &PredicateKey) -> Option<&Vec<Mode>> {
+        self.modes.get(module_name).and_then(|mode_dir| mode_dir.get(key))
+    }
+
+    // the predicates of the module are left undefined wherever they
+    // were imported.
+    pub fn unload_module(&mut self, name: ClauseName) -> bool {
+        match self.modules.remove(&name) {
+            Some(module) => {
+                let messages = self.rebind_module(module, None);
+
+                self.reclaim_code(&name);
+                self.print_messages(messages);
+                self.forget_module(&name);
+
+                true
+            },
+            None => false
+        }
+    }
+
+    // drops what's kept of an unloaded module besides its code: its
+    // autoloaded predicates, modes and unknown flag.
+    fn forget_module(&mut self, name: &ClauseName) {
+        self.autoload_index.retain(|_, library| library != name);
+        self.modes.remove(name);
+        self.ms.flags.module_unknown.remove(name);
+
+        if &self.ms.toplevel_module == name {
+            self.ms.toplevel_module = clause_name!("user");
+        }
     }
 
-    pub fn add_user_code(&mut self, name: ClauseName, arity: usize, code: Code, pred: Predicate)
+        // unload_module/1 runs here rather than in try_call_clause, as it
+    // rewrites the code of the machine. the warnings it raises are
+    // printed once the query is done.
+    fn unload_module_goal(&mut self, lco: bool) {
+        let a1 = self.ms.store(self.ms.deref(self.ms[temp_v!(1)].clone()));
+
+        let result = match a1.clone() {
+            Addr::Con(Constant::Atom(name)) =>
+                match self.modules.remove(&name) {
+                    Some(module) => {
+                        let mut messages = self.rebind_module(module, None);
+
+                        self.reclaim_code(&name);
+                        self.pending_messages.append(&mut messages);
+                        self.forget_module(&name);
+
+                        Ok(())
+                    },
+                    None => Err(MachineError::existence_error("module", a1))
+                },
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                Err(MachineError::Instantiation),
+            _ => Err(MachineError::type_error("atom", a1))
+        };
+
+        self.return_from_goal(result, clause_name!("unload_module"), lco);
+    }
+
+    // imports the exports of a loaded module into the importer, or
+    // only those of imports, holding back the messages raised for the
+    // end of the query.
+    fn use_module_in(&mut self, importer: ClauseName, name: ClauseName,
+                     imports: Option<ImportList>)
+                     -> EvalSession
+    {
+        if let EvalSession::Error(err) = self.load_library(&name) {
+            return EvalSession::Error(err);
+        }
+
+        let submodule = match self.modules.remove(&name) {
+            Some(submodule) => submodule,
+            None => return EvalSession::from(EvalError::ModuleNotFound)
+        };
+
+        let conflicts = self.ms.import_conflicts;
+        let mut messages = vec![];
+
+        let result = if importer == name {
+            EvalSession::EntrySuccess
+        } else {
+            match self.modules.remove(&importer) {
+                Some(mut module) => {
+                    let result = {
+                        let mut module_imports = ModuleImports { module: &mut module,
+                                                                 messages: &mut messages,
+                                                                 conflicts };
+
+                        match imports {
+                            Some(imports) =>
+                                module_imports.use_qualified_module(&submodule, imports),
+                            None => module_imports.use_module(&submodule)
+                        }
+                    };
+
+                    self.modules.insert(importer, module);
+                    result
+                },
+                None => {
+                    let mut code_index = MachineCodeIndex { code_dir: &mut self.code_dir,
+                                                            op_dir: &mut self.op_dir,
+                                                            messages: &mut messages, conflicts };
+
+                    match imports {
+                        Some(imports) => code_index.use_qualified_module(&submodule, imports),
+                        None => code_index.use_module(&submodule)
+                    }
+                }
+            }
+        };
+
+        self.modules.insert(name, submodule);
+        self.pending_messages.append(&mut messages);
+
+        result
+    }
+
+    // imports the predicate of an undefined goal from the library
+    // exporting it into the module it was called from, ahead of the
+    // goal's retry by $unknown_procedure/2. A1 is the module and A2
+    // the goal. false if the import failed, and its error was thrown.
+    fn autoload(&mut self) -> bool {
+        if !self.ms.flags.autoload {
+            return true;
+        }
+
+        let mut module_name = match self.ms.store(self.ms.deref(self.ms[temp_v!(1)].clone())) {
+            Addr::Con(Constant::Atom(name)) => name,
+            _ => return true
+        };
+
+        let mut goal = self.ms.store(self.ms.deref(self.ms[temp_v!(2)].clone()));
+
+        let key = loop {
+            match goal {
+                Addr::Str(s) =>
+                    match self.ms.heap[s].clone() {
+                        HeapCellValue::NamedStr(2, ref name, _) if name.as_str() == ":" => {
+                            match self.ms.store(self.ms.deref(Addr::HeapCell(s + 1))) {
+                                Addr::Con(Constant::Atom(name)) => module_name = name,
+                                _ => return true
+                            };
+
+                            goal = self.ms.store(self.ms.deref(Addr::HeapCell(s + 2)));
+                        },
+                        HeapCellValue::NamedStr(arity, name, _) => break (name, arity),
+                        _ => return true
+                    },
+                Addr::Con(Constant::Atom(name)) => break (name, 0),
+                _ => return true
+            }
+        };
+
+        let library = match self.autoload_index.get(&key) {
+            Some(library) if library != &module_name => library.clone(),
+            _ => return true
+        };
+
+        let is_defined = match self.modules.get(&module_name) {
+            Some(module) => module.code_dir.get(&key),
+            None if module_name.as_str() == "user" => self.code_dir.get(&key),
+            None => return true
+        }.map(|idx| idx.0.get() != IndexPtr::Undefined).unwrap_or(false);
+
+        if is_defined {
+            return true;
+        }
+
+        let imports = ImportList::Only(vec![(key.clone(), None)]);
+
+        match self.use_module_in(module_name, library.clone(), Some(imports)) {
+            EvalSession::Error(_) => {
+                let err = MachineError::permission_error("import", "module",
+                                                         Addr::Con(Constant::Atom(library)));
+                let stub = self.ms.error_form(err, key.0, key.1);
+
+                self.ms.throw_exception(stub);
+                false
+            },
+            _ => true
+        }
+    }
+
+    // use_module/1 as a goal imports a loaded module, named as Name or
+    // library(Name), into the context module.
+    fn use_module_goal(&mut self, lco: bool) {
+        let a1 = self.ms.store(self.ms.deref(self.ms[temp_v!(1)].clone()));
+
+        let a1 = match a1 {
+            Addr::Str(s) =>
+                match self.ms.heap[s].clone() {
+                    HeapCellValue::NamedStr(1, ref name, _) if name.as_str() == "library" =>
+                        self.ms.store(self.ms.deref(Addr::HeapCell(s + 1))),
+                    _ => Addr::Str(s)
+                },
+            a1 => a1
+        };
+
+        let result = match a1.clone() {
+            Addr::Con(Constant::Atom(name)) => {
+                let context = self.ms.context_module();
+
+                match self.use_module_in(context, name, None) {
+                    EvalSession::Error(EvalError::ModuleNotFound) =>
+                        Err(MachineError::existence_error("module", a1)),
+                    EvalSession::Error(EvalError::ImportConflict(importer, module_name, name, arity)) =>
+                        Err(MachineError::import_into(importer, module_name, name, arity)),
+                    EvalSession::Error(_) =>
+                        Err(MachineError::permission_error("import", "module", a1)),
+                    _ => Ok(())
+                }
+            },
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                Err(MachineError::Instantiation),
+            _ => Err(MachineError::type_error("atom", a1))
+        };
+
+        self.return_from_goal(result, clause_name!("use_module"), lco);
+    }
+
+    // module/1 switches the toplevel to a module, which is created if
+    // it doesn't exist.
+    fn set_toplevel_module_goal(&mut self, lco: bool) {
+        let a1 = self.ms.store(self.ms.deref(self.ms[temp_v!(1)].clone()));
+
+        let result = match a1.clone() {
+            Addr::Con(Constant::Atom(ref name)) if name.as_str() == "builtin" =>
+                Err(MachineError::permission_error("modify", "module", a1)),
+            Addr::Con(Constant::Atom(name)) => {
+                if name.as_str() != "user" && !self.modules.contains_key(&name) {
+                    let (code_dir, op_dir) = build_code_and_op_dirs();
+                    let mut module = Module::new(ModuleDecl { name: name.clone(),
+                                                              exports: vec![] });
+
+                    module.code_dir = code_dir;
+                    module.op_dir = op_dir;
+
+                    self.modules.insert(name.clone(), module);
+                    self.pending_messages.push(Message::CreatedModule(name.clone()));
+                }
+
+                self.ms.toplevel_module = name;
+                Ok(())
+            },
+            Addr::HeapCell(_) | Addr::StackCell(..) =>
+                Err(MachineError::Instantiation),
+            _ => Err(MachineError::type_error("atom", a1))
+        };
+
+        self.return_from_goal(result, clause_name!("module"), lco);
+    }
+
+    // proceeds from a goal of arity 1 run by the machine, or throws
+    // its error.
+    fn return_from_goal(&mut self, result: Result<(), MachineError>, name: ClauseName, lco: bool) {
+        match result {
+            Ok(()) =>
+                if lco {
+                    self.ms.p = self.ms.cp.clone();
+                } else {
+                    self.ms.p += 1;
+                },
+            Err(err) => {
+                let stub = self.ms.error_form(err, name, 1);
+                self.ms.throw_exception(stub);
+            }
+        }
+    }
+
+    fn rebind_module(&mut self, old_module: Module, mut new_module: Option<&mut Module>)
+                     -> Vec<Message>
+    {
+        let module_name = old_module.module_decl.name.clone();
+
+        // the indices shared by the old code and its importers are
+        // pointed at the new code, and handed over to the new module,
+        // as long as it still exports them.
+        for (key, old_idx) in old_module.code_dir.iter() {
+            if old_idx.1 != module_name {
+                continue;
+            }
+
+            let new_idx = match new_module {
+                Some(ref mut module) if exports_key(module, &key.0, key.1) =>
+                    module.code_dir.get_mut(key),
+                _ => None
+            };
+
+            match new_idx {
+                Some(new_idx) if new_idx.1 == module_name => {
+                    old_idx.0.set(new_idx.0.get());
+                    *new_idx = old_idx.clone();
+                },
+                _ => old_idx.0.set(IndexPtr::Undefined)
+            }
+        }
+
+        let new_module = new_module.map(|module| &*module);
+        let mut messages = vec![];
+
+        rebind_imports(clause_name!("user"), &mut self.code_dir, &mut self.op_dir,
+                       &module_name, new_module, &mut messages);
+
+        for (importer, module) in self.modules.iter_mut() {
+            rebind_imports(importer.clone(), &mut module.code_dir, &mut module.op_dir,
+                           &module_name, new_module, &mut messages);
+        }
+
+        messages
+    }
+
+    // the code of a module is overwritten with fail, since a query
+    // might still be running through it, and cut out of the code area
+    // by compact_code.
+    fn reclaim_code(&mut self, module_name: &ClauseName) {
+        if let Some(range) = self.module_code.remove(module_name) {
+            for line in self.code[range.clone()].iter_mut() {
+                *line = fail!();
+            }
+
+            self.dead_code.push(range);
+        }
+    }
+
+    // removes the reclaimed ranges of code, moving the pointers into
+    // the code that follows them down. it runs ahead of compiling
+    // input, when no query or batch can be using the code.
+    pub fn compact_code(&mut self) {
+        if self.dead_code.is_empty() {
+            return;
+        }
+
+        let mut dead_code = vec![];
+        swap(&mut dead_code, &mut self.dead_code);
+
+        dead_code.sort_by_key(|range| range.start);
+
+        let relocate = |p: usize| {
+            let mut offset = 0;
+
+            for range in dead_code.iter() {
+                if p >= range.end {
+                    offset += range.end - range.start;
+                } else if p >= range.start {
+                    return None;
+                }
+            }
+
+            Some(p - offset)
+        };
+
+        let mut code = vec![];
+        swap(&mut code, &mut self.code);
+
+        self.code = code.into_iter().enumerate()
+            .filter(|&(p, _)| relocate(p).is_some())
+            .map(|(_, line)| line)
+            .collect();
+
+        // cells are shared between dirs and code, so each is moved once.
+        let mut moved = HashSet::new();
+        let mut cells: Vec<Rc<Cell<IndexPtr>>> = vec![];
+
+        cells.extend(self.code_dir.values().map(|idx| idx.0.clone()));
+
+        for module in self.modules.values() {
+            cells.extend(module.code_dir.values().map(|idx| idx.0.clone()));
+        }
+
+        for line in self.code.iter().chain(self.cached_query.iter().flat_map(|code| code.iter())) {
+            if let &Line::Control(ControlInstruction::CallClause(ref ct, ..)) = line {
+                match ct {
+                    &ClauseType::Named(_, ref idx)
+                  | &ClauseType::Op(_, _, ref idx)
+                  | &ClauseType::Qualified(_, _, ref idx) => cells.push(idx.0.clone()),
+                    _ => {}
+                }
+            }
+        }
+
+        for cell in cells {
+            if !moved.insert(&*cell as *const Cell<IndexPtr>) {
+                continue;
+            }
+
+            if let IndexPtr::Index(p) = cell.get() {
+                cell.set(match relocate(p) {
+                    Some(p) => IndexPtr::Index(p),
+                    None => IndexPtr::Undefined
+                });
+            }
+        }
+
+        for range in self.module_code.values_mut() {
+            let start = relocate(range.start).unwrap_or(range.start);
+            *range = start .. start + (range.end - range.start);
+        }
+    }
+
+    // M:G calls into a module already loaded share the index of the
+    // predicate there, and so aren't looked up when they run.
+    fn resolve_qualified_calls(&self, code: &mut Code, module: Option<&Module>) {
+        for line in code.iter_mut() {
+            if let &mut Line::Control(ControlInstruction::CallClause(ref mut ct, arity, ..)) = line {
+                if let &mut ClauseType::Qualified(ref module_name, ref name, ref mut idx) = ct {
+                    let code_dir = match module {
+                        Some(module) if &module.module_decl.name == module_name =>
+                            Some(&module.code_dir),
+                        _ => match module_name.as_str() {
+                            "user" | "builtin" => Some(&self.code_dir),
+                            _ => self.modules.get(module_name).map(|module| &module.code_dir)
+                        }
+                    };
+
+                    if let Some(entry) = code_dir.and_then(|dir| dir.get(&(name.clone(), arity))) {
+                        *idx = entry.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    // import errors raised while loading code are reported as the
+    // uncaught exceptions use_module/1 would throw.
+    pub fn load_error(&mut self, err: EvalError) -> EvalSession {
+        match err {
+            EvalError::ImportConflict(importer, module_name, name, arity)
+          | EvalError::RedefinedImport(importer, module_name, name, arity) => {
+                let err = MachineError::import_into(importer, module_name, name.clone(), arity);
+                self.uncaught_error(err, name, arity)
+            },
+            err => EvalSession::from(err)
+        }
+    }
+
+    // errors raised while adding code are reported as uncaught
+    // exceptions, with the ball a query throwing them would have.
+    fn uncaught_error(&mut self, err: MachineError, name: ClauseName, arity: usize) -> EvalSession {
+        let h = self.ms.heap.h;
+        let stub = self.ms.error_form(err, name, arity);
+
+        self.ms.heap.append(stub);
+
+        let ball = self.ms.print_term(Addr::HeapCell(h), TermFormatter {}, PrinterOutputter::new())
+                       .result();
+
+        let cells = relocate_term(self.ms.heap.cells_from(h), h, 0);
+
+        self.ms.heap.truncate(h);
+        EvalSession::from(EvalError::QueryFailureWithException(ball, vec![], cells))
+    }
+
+    pub fn add_user_code(&mut self, name: ClauseName, arity: usize, mut code: Code, pred: Predicate)
                          -> EvalSession
     {
-        match self.code_dir.get(&(name.clone(), arity)) {
-            Some(&CodeIndex (_, ref mod_name)) if mod_name == &clause_name!("builtin") =>
-                return EvalSession::from(EvalError::ImpermissibleEntry(format!("{}/{}",
-                                                                               name,
-                                                                               arity))),
+        match self.toplevel_dirs().0.get(&(name.clone(), arity)) {
+            Some(&CodeIndex (_, ref mod_name)) if mod_name == &clause_name!("builtin") => {
+                let err = MachineError::static_procedure_modification(name.clone(), arity);
+                return self.uncaught_error(err, name, arity);
+            },
             _ => {}
         };
 
+        let module_name = self.toplevel_module();
         let offset = self.code.len();
 
+        if module_name.as_str() == "user" {
+            self.user_files.remove(&(name.clone(), arity));
+        }
+
+        self.resolve_qualified_calls(&mut code, None);
         self.code.extend(code.into_iter());
-        self.term_dir.insert((name.clone(), arity), pred);
+        self.toplevel_term_dir().insert((name.clone(), arity), pred);
 
-        let entry = self.code_dir.entry((name, arity))
-            .or_insert(CodeIndex::from((offset, clause_name!("user"))));
+        let entry = self.toplevel_dirs().0.entry((name, arity))
+            .or_insert(CodeIndex::from((offset, module_name.clone())));
 
         entry.0.set(IndexPtr::Index(offset));
-        entry.1 = clause_name!("user");
-        
+        entry.1 = module_name;
+
         EvalSession::EntrySuccess
     }
 
+    // a flag that interrupts the running query when set, as SIGINT
+    // does, for embedders that don't go through the signal handler.
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
+    }
+
+    // how the action taken on an interrupt is chosen, by default by
+    // asking on stdin.
+    pub fn set_interrupt_reader(&mut self, reader: fn() -> InterruptAction) {
+        self.interrupt_reader = reader;
+    }
+
+    // the default time limit of toplevel queries. each answer gets the
+    // full allowance, and None lifts the limit.
+    pub fn set_query_timeout(&mut self, timeout: Option<Duration>) {
+        self.query_timeout = timeout;
+    }
+
+    pub fn set_resource_limit(&mut self, limit: ResourceLimit, n: usize) {
+        self.ms.resource_limits.set(limit, n);
+    }
+
     pub fn code_size(&self) -> usize {
         self.code.len()
     }
@@ -211,6 +1216,21 @@ impl Machine {
         }
     }
 
+    // the comparison or is/2 evaluating the arithmetic instruction at
+    // p, the first to follow it.
+    fn arith_context(&self) -> (ClauseName, usize) {
+        let mut p = self.ms.p.clone();
+
+        loop {
+            match &self[p.clone()] {
+                &Line::Arithmetic(_) | &Line::Query(_) => p += 1,
+                &Line::BuiltIn(BuiltInInstruction::CompareNumber(cmp, ..)) =>
+                    return (clause_name!(cmp.name()), 2),
+                _ => return (clause_name!("is"), 2)
+            }
+        }
+    }
+
     fn execute_instr(&mut self)
     {
         let instr = match self.ms.p {
@@ -225,9 +1245,17 @@ impl Machine {
 
         match instr {
             &Line::Arithmetic(ref arith_instr) =>
-                self.ms.execute_arith_instr(arith_instr),
+                if let Err(err) = self.ms.execute_arith_instr(arith_instr) {
+                    let (name, arity) = self.arith_context();
+                    let stub = self.ms.error_form(err, name, arity);
+
+                    self.ms.throw_exception(stub);
+                },
             &Line::BuiltIn(ref built_in_instr) => {
-                let code_dirs = CodeDirs::new(&self.code_dir, &self.modules);
+                let code_size = self.code_size();
+                let code_dirs = CodeDirs::new(&self.code_dir, &mut self.op_dir,
+                                              &self.meta_predicates, &self.term_dir,
+                                              &self.user_files, &self.modules, code_size);
                 self.ms.execute_built_in_instr(code_dirs, &mut self.call_policy,
                                                &mut self.cut_policy, built_in_instr);
             },
@@ -235,8 +1263,31 @@ impl Machine {
                 self.ms.execute_choice_instr(choice_instr, &mut self.call_policy),
             &Line::Cut(ref cut_instr) =>
                 self.ms.execute_cut_instr(cut_instr, &mut self.cut_policy),
+            &Line::Control(ControlInstruction::CallClause(ClauseType::UnloadModule, _, _, lco)) =>
+                self.unload_module_goal(lco),
+            &Line::Control(ControlInstruction::CallClause(ClauseType::SetModule, _, _, lco)) =>
+                self.set_toplevel_module_goal(lco),
+            &Line::Control(ControlInstruction::CallClause(ClauseType::UseModule, _, _, lco)) =>
+                self.use_module_goal(lco),
+            &Line::Control(ControlInstruction::CallClause(ClauseType::UnknownProcedure, arity, bp, lco)) => {
+                if !self.autoload() {
+                    return;
+                }
+
+                let code_size = self.code_size();
+                let code_dirs = CodeDirs::new(&self.code_dir, &mut self.op_dir,
+                                              &self.meta_predicates, &self.term_dir,
+                                              &self.user_files, &self.modules, code_size);
+                let instr = ControlInstruction::CallClause(ClauseType::UnknownProcedure, arity, bp, lco);
+
+                self.ms.execute_ctrl_instr(code_dirs, &mut self.call_policy,
+                                           &mut self.cut_policy, &instr)
+            },
             &Line::Control(ref control_instr) => {
-                let code_dirs = CodeDirs::new(&self.code_dir, &self.modules);
+                let code_size = self.code_size();
+                let code_dirs = CodeDirs::new(&self.code_dir, &mut self.op_dir,
+                                              &self.meta_predicates, &self.term_dir,
+                                              &self.user_files, &self.modules, code_size);
                 self.ms.execute_ctrl_instr(code_dirs, &mut self.call_policy,
                                            &mut self.cut_policy, control_instr)
             },
@@ -287,13 +1338,136 @@ impl Machine {
         }
     }
 
+    // the predicate whose unification failed the occurs check in the
+    // instruction at p: the built-in called there, or else the
+    // predicate whose code holds p.
+    fn occurs_check_context(&self, p: &CodePtr) -> Option<(ClauseName, usize)> {
+        let instr = match p {
+            &CodePtr::TopLevel(_, p) => self.cached_query.as_ref().map(|cq| &cq[p]),
+            &CodePtr::DirEntry(p, _) => Some(&self.code[p])
+        };
+
+        if let Some(&Line::Control(ControlInstruction::CallClause(ref ct, arity, ..))) = instr {
+            return Some((ct.name(), arity));
+        }
+
+        match p {
+            &CodePtr::DirEntry(p, _) => predicate_at(&self.code_dir, &self.modules, p),
+            &CodePtr::TopLevel(..) => None
+        }
+    }
+
+    fn describe_code_ptr(&self, p: &CodePtr) -> String {
+        match p {
+            &CodePtr::DirEntry(p, _) => {
+                match predicate_at(&self.code_dir, &self.modules, p) {
+                    Some((name, arity)) => format!("{}/{}", name, arity),
+                    None => String::from("?")
+                }
+            },
+            &CodePtr::TopLevel(..) => String::from("toplevel")
+        }
+    }
+
+    fn backtrace(&self, frames: &Vec<CodePtr>) -> Vec<String> {
+        frames.iter().map(|p| self.describe_code_ptr(p)).collect()
+    }
+
+    // walks the continuation frames of the and_stack, from the current
+    // environment back to the toplevel.
+    fn print_backtrace(&self) {
+        let frames = self.ms.continuations(self.ms.p.clone());
+
+        eprintln!("% backtrace:");
+
+        for (depth, frame) in self.backtrace(&frames).iter().enumerate() {
+            eprintln!("%   [{}] {}", depth, frame);
+        }
+
+        if frames.len() == MAX_BACKTRACE_FRAMES {
+            eprintln!("%   ...");
+        }
+    }
+
+    fn trace_instr(&self) {
+        let instr = match self.ms.p {
+            CodePtr::TopLevel(_, p) =>
+                match &self.cached_query {
+                    &Some(ref cq) => &cq[p],
+                    &None => return
+                },
+            CodePtr::DirEntry(p, _) => &self.code[p]
+        };
+
+        if let &Line::Control(ControlInstruction::CallClause(ref ct, arity, _, _)) = instr {
+            let mut goal = format!("{}", ct.name());
+
+            if arity > 0 {
+                let args: Vec<String> = (1 .. arity + 1).map(|i| {
+                    self.ms.print_term(self.ms.registers[i].clone(),
+                                       TermFormatter {},
+                                       PrinterOutputter::new())
+                        .result()
+                }).collect();
+
+                goal += &format!("({})", args.join(", "));
+            }
+
+            eprintln!("% call: {}", goal);
+        }
+    }
+
+    // returns true if the query is to be aborted.
+    fn handle_interrupt(&mut self) -> bool {
+        loop {
+            match (self.interrupt_reader)() {
+                InterruptAction::Abort => return true,
+                InterruptAction::Backtrace => self.print_backtrace(),
+                InterruptAction::Continue => return false,
+                InterruptAction::Trace => {
+                    self.tracing = true;
+                    return false;
+                }
+            }
+        }
+    }
+
     fn query_stepper<'a>(&mut self)
     {
         loop {
+            let interrupted = INTERRUPT.swap(false, Ordering::Relaxed)
+                            | self.interrupt.swap(false, Ordering::Relaxed);
+
+            if interrupted && self.handle_interrupt() {
+                self.reset();
+                self.aborted = true;
+                break;
+            }
+
+            if self.ms.time_limits.expire() {
+                self.ms.throw_exception(functor!("time_limit_exceeded"));
+            }
+
+            if self.tracing {
+                self.trace_instr();
+            }
+
+            let p = self.ms.p.clone();
+
             self.execute_instr();
 
+            // an occurs check in error mode fails its unification, and
+            // leaves the error to be thrown here.
+            let occurs_check_error = self.ms.occurs_check_error.take();
+
             if self.failed() {
-                self.backtrack();
+                match occurs_check_error {
+                    Some((var, term)) => {
+                        let context = self.occurs_check_context(&p);
+                        self.ms.throw_occurs_check_error(var, term, context);
+                    },
+                    None => self.backtrack()
+                };
             }
 
             match self.ms.p {
@@ -332,6 +1506,12 @@ impl Machine {
     {
         let end_ptr = CodePtr::TopLevel(0, self.cached_query_size());
 
+        // interrupts received while no query was running are dropped.
+        INTERRUPT.store(false, Ordering::Relaxed);
+        self.interrupt.store(false, Ordering::Relaxed);
+
+        let timeout = self.query_timeout.map(|timeout| self.ms.time_limits.add(timeout));
+
         while self.ms.p < end_ptr {
             if let CodePtr::TopLevel(mut cn, p) = self.ms.p {
                 match &self[CodePtr::TopLevel(cn, p)] {
@@ -347,6 +1527,10 @@ impl Machine {
 
             self.query_stepper();
 
+            if self.aborted {
+                break;
+            }
+
             match self.ms.p {
                 CodePtr::TopLevel(_, p) if p > 0 => {},
                 _ => {
@@ -358,6 +1542,13 @@ impl Machine {
                 }
             };
         }
+
+        if let Some(handle) = timeout {
+            self.ms.time_limits.remove(handle);
+        }
+
+        // tracing started by an interrupt ends with the answer.
+        self.tracing = false;
     }
 
     fn fail(&mut self) -> EvalSession
@@ -371,20 +1562,28 @@ impl Machine {
                                          PrinterOutputter::new())
                           .result();
 
-            EvalSession::from(EvalError::QueryFailureWithException(msg))
+            let cells = relocate_term(self.ms.heap.cells_from(h), h, 0);
+            let backtrace = self.backtrace(&self.ms.backtrace);
+
+            EvalSession::from(EvalError::QueryFailureWithException(msg, backtrace, cells))
         } else {
             EvalSession::from(EvalError::QueryFailure)
         }
     }
 
-    pub fn submit_query(&mut self, code: Code, alloc_locs: AllocVarDict) -> EvalSession
+    pub fn submit_query(&mut self, mut code: Code, alloc_locs: AllocVarDict) -> EvalSession
     {
         let mut heap_locs = HashMap::new();
 
+        self.resolve_qualified_calls(&mut code, None);
+
         self.cached_query = Some(code);
         self.run_query(&alloc_locs, &mut heap_locs);
 
-        if self.failed() {
+        if self.aborted {
+            self.aborted = false;
+            EvalSession::from(EvalError::QueryAborted)
+        } else if self.failed() {
             self.fail()
         } else {
             EvalSession::InitialQuerySuccess(alloc_locs, heap_locs)
@@ -403,7 +1602,10 @@ impl Machine {
 
             self.run_query(alloc_l, heap_l);
 
-            if self.failed() {
+            if self.aborted {
+                self.aborted = false;
+                EvalSession::from(EvalError::QueryAborted)
+            } else if self.failed() {
                 self.fail()
             } else {
                 EvalSession::SubsequentQuerySuccess
@@ -438,7 +1640,14 @@ impl Machine {
     }
 
     pub fn reset(&mut self) {
+        self.tracing = false;
+        self.call_policy = Box::new(DefaultCallPolicy {});
         self.cut_policy = Box::new(DefaultCutPolicy {});
         self.ms.reset();
+
+        let mut messages = vec![];
+        swap(&mut messages, &mut self.pending_messages);
+
+        self.print_messages(messages);
     }
 }
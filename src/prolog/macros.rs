@@ -125,6 +125,12 @@ macro_rules! set_value {
     )
 }
 
+macro_rules! set_var {
+    ($r:expr) => (
+        QueryInstruction::SetVariable($r)
+    )
+}
+
 macro_rules! get_var_in_fact {
     ($r:expr, $arg:expr) => (
         FactInstruction::GetVariable($r, $arg)
@@ -352,7 +358,13 @@ macro_rules! clean_up_block {
 
 macro_rules! set_ball {
     () => (
-        Line::BuiltIn(BuiltInInstruction::SetBall)
+        Line::BuiltIn(BuiltInInstruction::SetBall(true))
+    )
+}
+
+macro_rules! rethrow_ball {
+    () => (
+        Line::BuiltIn(BuiltInInstruction::SetBall(false))
     )
 }
 
@@ -682,6 +694,48 @@ macro_rules! install_inference_counter {
     )
 }
 
+macro_rules! install_time_limit {
+    ($r1:expr, $r2:expr) => (
+        Line::BuiltIn(BuiltInInstruction::InstallTimeLimit($r1, $r2))
+    )
+}
+
+macro_rules! remove_time_limit {
+    ($r:expr) => (
+        Line::BuiltIn(BuiltInInstruction::RemoveTimeLimit($r))
+    )
+}
+
+macro_rules! install_depth_limit {
+    ($r1:expr, $r2:expr) => (
+        Line::BuiltIn(BuiltInInstruction::InstallDepthLimit($r1, $r2))
+    )
+}
+
+macro_rules! exit_depth_limit {
+    ($r1:expr, $r2:expr) => (
+        Line::BuiltIn(BuiltInInstruction::ExitDepthLimit($r1, $r2))
+    )
+}
+
+macro_rules! redo_depth_limit {
+    ($r:expr) => (
+        Line::BuiltIn(BuiltInInstruction::RedoDepthLimit($r))
+    )
+}
+
+macro_rules! remove_depth_limit {
+    ($r:expr) => (
+        Line::BuiltIn(BuiltInInstruction::RemoveDepthLimit($r))
+    )
+}
+
+macro_rules! pop_depth_limit {
+    () => (
+        Line::BuiltIn(BuiltInInstruction::PopDepthLimit)
+    )
+}
+
 macro_rules! remove_inference_counter {
     ($r1:expr, $r2:expr) => (
         Line::BuiltIn(BuiltInInstruction::RemoveInferenceCounter($r1, $r2))
@@ -763,6 +817,48 @@ macro_rules! cyclic_term_execute {
     )
 }
 
+macro_rules! set_prolog_flag_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::SetPrologFlag, 2, 0, true))
+    )
+}
+
+macro_rules! statistics_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::Statistics, 2, 0, true))
+    )
+}
+
+macro_rules! backtrace_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::Backtrace, 1, 0, true))
+    )
+}
+
+macro_rules! time_message_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::TimeMessage, 4, 0, true))
+    )
+}
+
+macro_rules! call_hook {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::CallHook, 1, 0, false))
+    )
+}
+
+macro_rules! default_message_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::DefaultMessage, 2, 0, true))
+    )
+}
+
+macro_rules! print_message_lines_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::PrintMessageLines, 2, 0, true))
+    )
+}
+
 macro_rules! return_from_clause {
     ($lco:expr, $machine_st:expr) => {{
         if $lco {
@@ -774,3 +870,111 @@ macro_rules! return_from_clause {
         Ok(())
     }}
 }
+
+macro_rules! context_module_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::ContextModule, 1, 0, true))
+    )
+}
+
+macro_rules! strip_module_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::StripModule, 3, 0, true))
+    )
+}
+
+macro_rules! current_modules_call {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::CurrentModules, 1, 0, false))
+    )
+}
+
+macro_rules! module_properties_call {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::ModuleProperties, 2, 0, false))
+    )
+}
+
+macro_rules! predicate_indicators_call {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::PredicateIndicators, 2, 0, false))
+    )
+}
+
+macro_rules! predicate_heads_call {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::PredicateHeads, 2, 0, false))
+    )
+}
+
+macro_rules! predicate_properties_call {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::PredicateProperties, 2, 0, false))
+    )
+}
+
+macro_rules! declare_op_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::DeclareOp, 3, 0, true))
+    )
+}
+
+macro_rules! unload_module_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::UnloadModule, 1, 0, true))
+    )
+}
+
+macro_rules! subsumes_term_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::SubsumesTerm, 2, 0, true))
+    )
+}
+
+macro_rules! phrase_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::Phrase, 3, 0, true))
+    )
+}
+
+macro_rules! term_factorized_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::TermFactorized, 3, 0, true))
+    )
+}
+
+macro_rules! unify_with_occurs_check_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::UnifyWithOccursCheck, 2, 0, true))
+    )
+}
+
+macro_rules! use_module_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::UseModule, 1, 0, true))
+    )
+}
+
+macro_rules! set_module_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::SetModule, 1, 0, true))
+    )
+}
+
+macro_rules! current_ops_call {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::CurrentOps, 4, 0, false))
+    )
+}
+
+macro_rules! unknown_procedure_execute {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::UnknownProcedure, 2, 0, true))
+    )
+}
+
+macro_rules! current_prolog_flags_call {
+    () => (
+        Line::Control(ControlInstruction::CallClause(ClauseType::CurrentPrologFlags, 3, 0, false))
+    )
+}
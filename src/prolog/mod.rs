@@ -12,6 +12,7 @@ pub mod arithmetic;
 pub mod builtins;
 pub mod codegen;
 pub mod copier;
+pub mod dcg;
 pub mod debray_allocator;
 pub mod fixtures;
 pub mod heap_iter;
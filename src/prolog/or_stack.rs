@@ -12,6 +12,7 @@ pub struct Frame {
     pub tr: usize,
     pub h: usize,
     pub b0: usize,
+    pub depth: usize,
     args: Vec<Addr>
 }
 
@@ -36,6 +37,7 @@ impl Frame {
             tr: tr,
             h: h,
             b0: b0,
+            depth: 0,
             args: vec![Addr::HeapCell(0); n]
         }
     }
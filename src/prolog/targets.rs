@@ -10,6 +10,8 @@ pub trait CompilationTarget<'a> {
     fn to_list(Level, RegType) -> Self;
     fn to_structure(ClauseType, usize, RegType) -> Self;
 
+    fn specialize(self, Mode) -> Self;
+
     fn to_void(usize) -> Self;
     fn is_void_instr(&self) -> bool;
 
@@ -48,6 +50,24 @@ impl<'a> CompilationTarget<'a> for FactInstruction {
         FactInstruction::GetList(lvl, reg)
     }
 
+    fn specialize(self, mode: Mode) -> Self {
+        match (self, mode) {
+            (FactInstruction::GetConstant(_, c, reg), Mode::In) =>
+                FactInstruction::GetBoundConstant(c, reg),
+            (FactInstruction::GetList(_, reg), Mode::In) =>
+                FactInstruction::GetBoundList(reg),
+            (FactInstruction::GetStructure(ct, arity, reg), Mode::In) =>
+                FactInstruction::GetBoundStructure(ct, arity, reg),
+            (FactInstruction::GetConstant(_, c, reg), Mode::Out) =>
+                FactInstruction::GetFreshConstant(c, reg),
+            (FactInstruction::GetList(_, reg), Mode::Out) =>
+                FactInstruction::GetFreshList(reg),
+            (FactInstruction::GetStructure(ct, arity, reg), Mode::Out) =>
+                FactInstruction::GetFreshStructure(ct, arity, reg),
+            (instr, _) => instr
+        }
+    }
+
     fn to_void(subterms: usize) -> Self {
         FactInstruction::UnifyVoid(subterms)
     }
@@ -115,6 +135,10 @@ impl<'a> CompilationTarget<'a> for QueryInstruction {
         QueryInstruction::PutList(lvl, reg)
     }
 
+    fn specialize(self, _: Mode) -> Self {
+        self
+    }
+
     fn to_void(subterms: usize) -> Self {
         QueryInstruction::SetVoid(subterms)
     }
@@ -2,9 +2,13 @@ use prolog::ast::*;
 use prolog::heap_print::*;
 use prolog::io::*;
 use prolog::machine::*;
+use prolog::parser::toplevel::*;
 
 use std::collections::HashSet;
 use std::mem::swap;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
 
 pub struct TestOutputter {
     results: Vec<HashSet<String>>,
@@ -123,7 +127,7 @@ pub fn submit(wam: &mut Machine, buffer: &str) -> bool
                     true,
                 _ => false
             },
-        Err(e) => panic!("parse error: {:?}", e)
+        Err(e) => panic!("parse error: {}", e)
     }
 }
 
@@ -140,7 +144,7 @@ pub fn submit_query(wam: &mut Machine, buffer: &str, result: Vec<HashSet<String>
                 EvalSession::EntrySuccess => true,
                 _ => false
             },
-        Err(e) => panic!("parse error: {:?}", e)
+        Err(e) => panic!("parse error: {}", e)
     }
 }
 
@@ -160,7 +164,7 @@ pub fn submit_query_with_limit(wam: &mut Machine, buffer: &str,
                 EvalSession::EntrySuccess => true,
                 _ => false
             },
-        Err(e) => panic!("parse error: {:?}", e)
+        Err(e) => panic!("parse error: {}", e)
     }
 }
 
@@ -1036,6 +1040,77 @@ fn test_queries_on_exceptions()
                             ["X = z", "E = _2"],
                             ["E = an_error_1", "X = _1"],
                             ["E = an_error_2", "X = _1"]]);
+
+    // builtins throw ISO error terms carrying the predicate indicator.
+    assert_prolog_success!(&mut wam, "?- catch(call(_), error(instantiation_error, call/1), true).");
+    assert_prolog_success!(&mut wam, "?- catch(call(1), error(type_error(callable, 1), call/1), true).");
+    assert_prolog_success!(&mut wam, "?- catch(undefined, error(existence_error(procedure, undefined/0),
+                                                                undefined/0), true).");
+    assert_prolog_success!(&mut wam, "?- catch(call(undefined, a), error(existence_error(procedure, undefined/1),
+                                                                         undefined/1), true).");
+    assert_prolog_success!(&mut wam, "?- catch(call(is, X, foo + 1), error(type_error(evaluable, foo/0), is/2),
+                                               true).");
+    assert_prolog_success!(&mut wam, "?- catch(call(is, X, 1.5 // 2), error(type_error(integer, 1.5), is/2),
+                                               true).");
+    assert_prolog_success!(&mut wam, "?- catch(call(is, X, 1 // 0), error(evaluation_error(zero_divisor), is/2),
+                                               true).");
+    assert_prolog_success!(&mut wam, "?- A = a, catch(X is 1 + A, error(type_error(evaluable, a/0), is/2),
+                                                        true).");
+    assert_prolog_success!(&mut wam, "?- A = a, catch(2 < A + 1, error(type_error(evaluable, a/0), N/2), true),
+                                         N == '<'.");
+    assert_prolog_success!(&mut wam, "?- F = foo, catch(1 =:= F * 2, error(type_error(evaluable, foo/0), N/2),
+                                                         true),
+                                         N == '=:='.");
+    assert_prolog_success!(&mut wam, "?- catch(call_with_inference_limit(true, -1, _),
+                                               error(domain_error(not_less_than_zero, -1),
+                                                     call_with_inference_limit/3), true).");
+    assert_prolog_success!(&mut wam, "?- catch(functor(_, f, 100), error(representation_error(max_arity),
+                                                                         functor/3), true).");
+    assert_prolog_success!(&mut wam, "?- catch(sort(a, _), error(type_error(list, a), sort/2), true).");
+    assert_prolog_success!(&mut wam, "?- catch(keysort([a], _), error(type_error(pair, a), keysort/2), true).");
+    assert_prolog_success!(&mut wam, "?- catch(throw(_), error(instantiation_error, throw/1), true).");
+
+    // redefining a builtin is a permission error.
+    wam.reset();
+
+    match compile_packet(&mut wam, parse_code(&mut wam, "atomic(x).").unwrap()) {
+        EvalSession::Error(EvalError::QueryFailureWithException(ball, ..)) =>
+            assert_eq!(ball, "error(permission_error(modify, static_procedure, atomic/1), atomic/1)"),
+        _ => panic!("expected a permission error.")
+    };
+
+    submit(&mut wam, "f :- g, true. g :- throw(oops), true.");
+
+    // the backtrace of the last throw names the active predicates, innermost first.
+    assert_prolog_success!(&mut wam, "?- catch(f, _, true), '$backtrace'([g/0, f/0 | _]).");
+
+    compile_listing(&mut wam, ::CONTROL);
+    submit(&mut wam, ":- use_module(control).");
+
+    submit(&mut wam, "h :- i, true. i :- throw(error(type_error(t, x), ctx)), true.");
+
+    assert_prolog_success!(&mut wam, "?- catch_with_backtrace(h, E, true),
+                                        E = error(_, backtrace(C, Frames)),
+                                        C == ctx, Frames = [i/0, h/0 | _].");
+    assert_prolog_success!(&mut wam, "?- catch_with_backtrace(h, error(_, C), true),
+                                        C = backtrace(ctx, [i/0, h/0 | _]).");
+
+    // the ball is matched as thrown, before the backtrace is attached.
+    assert_prolog_success!(&mut wam, "?- catch_with_backtrace(h, error(type_error(t, x), ctx), true).");
+    assert_prolog_failure!(&mut wam, "?- catch(catch_with_backtrace(h, error(_, backtrace(_, _)), true),
+                                              _, fail).");
+
+    // a ball the recovery doesn't match is rethrown with its original backtrace.
+    assert_prolog_success!(&mut wam, "?- catch(catch_with_backtrace(f, nomatch, true), oops, true),
+                                        '$backtrace'([g/0, f/0 | _]).");
+
+    wam.reset();
+
+    match compile_packet(&mut wam, parse_code(&mut wam, "?- catch_with_backtrace(f, nomatch, true).").unwrap()) {
+        EvalSession::Error(EvalError::QueryFailureWithException(_, backtrace, _)) =>
+            assert_eq!(&backtrace[.. 2], &["g/0", "f/0"]),
+        _ => panic!("expected an uncaught exception.")
+    };
 }
 
 #[test]
@@ -1067,17 +1142,17 @@ fn test_queries_on_arithmetic()
 
     submit(&mut wam, "f(X) :- X is 5 // 0.");
 
-    assert_prolog_success!(&mut wam, "?- catch(f(X), evaluation_error(E), true), E = zero_divisor.",
+    assert_prolog_success!(&mut wam, "?- catch(f(X), error(evaluation_error(E), is/2), true), E = zero_divisor.",
                            [["E = zero_divisor", "X = _1"]]);
 
     submit(&mut wam, "f(X) :- X is (5 rdiv 1) / 0.");
 
-    assert_prolog_success!(&mut wam, "?- catch(f(X), evaluation_error(E), true), E = zero_divisor.",
+    assert_prolog_success!(&mut wam, "?- catch(f(X), error(evaluation_error(E), is/2), true), E = zero_divisor.",
                            [["E = zero_divisor", "X = _1"]]);
 
     submit(&mut wam, "f(X) :- X is 5.0 / 0.");
 
-    assert_prolog_success!(&mut wam, "?- catch(f(X), evaluation_error(E), true), E = zero_divisor.",
+    assert_prolog_success!(&mut wam, "?- catch(f(X), error(evaluation_error(E), is/2), true), E = zero_divisor.",
                            [["E = zero_divisor", "X = _1"]]);
 
     assert_prolog_success!(&mut wam, "?- X is ((3 + 4) // 2) + 2 - 1 // 1, Y is 2+2, Z is X+Y.",
@@ -1108,7 +1183,7 @@ fn test_queries_on_arithmetic()
     assert_prolog_success!(&mut wam, "?- X is 3 + 3, call(<, 3, X).", [["X = 6"]]);
     assert_prolog_success!(&mut wam, "?- X is 3 + 3, X =:= 3 + 3.", [["X = 6"]]);
 
-    assert_prolog_success!(&mut wam, "?- catch(call(is, X, 3 // 0), E, true).",
+    assert_prolog_success!(&mut wam, "?- catch(call(is, X, 3 // 0), error(E, _), true).",
                            [["X = _5", "E = evaluation_error(zero_divisor)"]]);
 
     assert_prolog_success!(&mut wam, "?- catch(call(is, X, 3 // 3), _, true).", [["X = 1"]]);
@@ -1134,7 +1209,7 @@ fn test_queries_on_conditionals()
                                  ;   A = \"not 2 or 3\"
                                  ).");
 
-    assert_prolog_success!(&mut wam, "?- catch(test(A), instantiation_error(_), true).");
+    assert_prolog_success!(&mut wam, "?- catch(test(A), error(instantiation_error, _/2), true).");
     assert_prolog_success!(&mut wam, "?- A = 2, test(A).", [["A = 2"]]);
     assert_prolog_success!(&mut wam, "?- A = 3, test(A), B = 3, test(B).", [["A = 3", "B = 3"]]);
 
@@ -1227,9 +1302,9 @@ fn test_queries_on_builtins()
     assert_prolog_success!(&mut wam, "?- arg(3, f(a,b,c,d), Arg).", [["Arg = c"]]);
     assert_prolog_success!(&mut wam, "?- arg(4, f(a,b,c,d), Arg).", [["Arg = d"]]);
 
-    assert_prolog_success!(&mut wam, "?- catch(arg(N, f, Arg), type_error(E), true).",
-                           [["E = compound_expected", "Arg = _3", "N = _1"]]);
-    assert_prolog_success!(&mut wam, "?- catch(arg(N, _, Arg), E, true).",
+    assert_prolog_success!(&mut wam, "?- catch(arg(N, f, Arg), error(type_error(E, f), _), true).",
+                           [["E = compound", "Arg = _3", "N = _1"]]);
+    assert_prolog_success!(&mut wam, "?- catch(arg(N, _, Arg), error(E, _), true).",
                            [["E = instantiation_error", "Arg = _3", "N = _1"]]);
 
     assert_prolog_success!(&mut wam, "?- arg(N, f(X, Y, Z), arg_val).",
@@ -1262,11 +1337,11 @@ fn test_queries_on_builtins()
     assert_prolog_success!(&mut wam, "?- functor(Func, f, 3).", [["Func = f(_2, _3, _4)"]]);
     assert_prolog_success!(&mut wam, "?- functor(Func, f, 4).", [["Func = f(_2, _3, _4, _5)"]]);
 
-    assert_prolog_success!(&mut wam, "?- catch(functor(F, \"sdf\", 3), E, true).",
-                           [["E = instantiation_error", "F = _1"]]);
-    assert_prolog_success!(&mut wam, "?- catch(functor(Func, F, 3), E, true).",
+    assert_prolog_success!(&mut wam, "?- catch(functor(F, \"sdf\", 3), error(E, _), true).",
+                           [["E = type_error(atomic, \"sdf\")", "F = _1"]]);
+    assert_prolog_success!(&mut wam, "?- catch(functor(Func, F, 3), error(E, _), true).",
                            [["E = instantiation_error", "Func = _1", "F = _2"]]);
-    assert_prolog_success!(&mut wam, "?- catch(functor(Func, f, N), E, true).",
+    assert_prolog_success!(&mut wam, "?- catch(functor(Func, f, N), error(E, _), true).",
                            [["E = instantiation_error", "Func = _1", "N = _3"]]);
     assert_prolog_failure!(&mut wam, "?- catch(functor(Func, f, N), E, false).");
 
@@ -1278,7 +1353,7 @@ fn test_queries_on_builtins()
     assert_prolog_success!(&mut wam, "?- Func =.. [atom].", [["Func = atom"]]);
     assert_prolog_success!(&mut wam, "?- Func =.. [\"sdf\"].", [["Func = \"sdf\""]]);
     assert_prolog_success!(&mut wam, "?- Func =.. [1].", [["Func = 1"]]);
-    assert_prolog_success!(&mut wam, "?- catch(Func =.. [1,2], instantiation_error, true).");
+    assert_prolog_success!(&mut wam, "?- catch(Func =.. [1,2], error(type_error(atomic, 1), _), true).");
     assert_prolog_success!(&mut wam, "?- f(1,2,3) =.. List.", [["List = [f, 1, 2, 3]"]]);
     assert_prolog_success!(&mut wam, "?- f(1,2,3) =.. [f,1,2,3].");
     assert_prolog_failure!(&mut wam, "?- f(1,2,3) =.. [f,1].");
@@ -1301,7 +1376,7 @@ fn test_queries_on_builtins()
     assert_prolog_success!(&mut wam, "?- length(Xs, 0).", [["Xs = []"]]);
     assert_prolog_success!(&mut wam, "?- length([a,b,[a,b,c]], 3).");
     assert_prolog_failure!(&mut wam, "?- length([a,b,[a,b,c]], 2).");
-    assert_prolog_success!(&mut wam, "?- catch(length(a, []), type_error(_, E), true).",
+    assert_prolog_success!(&mut wam, "?- catch(length(a, []), error(type_error(integer, E), length/2), true).",
                            [["E = []"]]);
 
     assert_prolog_success!(&mut wam, "?- duplicate_term([1,2,3], [X,Y,Z]).",
@@ -1425,13 +1500,13 @@ fn test_queries_on_setup_call_cleanup()
 
     // Test examples from the ISO Prolog page for setup_call_catch.
     assert_prolog_failure!(&mut wam, "?- setup_call_cleanup(false, _, _).");
-    assert_prolog_success!(&mut wam, "?- catch(setup_call_cleanup(true, throw(unthrown), _), instantiation_error, true).");
+    assert_prolog_success!(&mut wam, "?- catch(setup_call_cleanup(true, throw(unthrown), _), error(instantiation_error, _), true).");
     assert_prolog_success!(&mut wam, "?- setup_call_cleanup(true, true, (true ; throw(x))).");
     assert_prolog_success!(&mut wam, "?- setup_call_cleanup(true, X = 1, X = 2).",
                            [["X = 1"]]);
     assert_prolog_success!(&mut wam, "?- setup_call_cleanup(true, true, X = 2).",
                            [["X = 2"]]);
-    assert_prolog_success!(&mut wam, "?- catch(setup_call_cleanup(true, X=true, X), E, true).",
+    assert_prolog_success!(&mut wam, "?- catch(setup_call_cleanup(true, X=true, X), error(E, _), true).",
                            [["E = instantiation_error", "X = _1"]]);
     assert_prolog_success!(&mut wam, "?- catch(setup_call_cleanup(X=throw(ex), true, X), E, true).",
                            [["E = ex", "X = _3"]]);
@@ -1593,3 +1668,1116 @@ fn test_queries_on_call_with_inference_limit()
                            [["R = inference_limit_exceeded", "X = _1"]]);
 
 }
+
+#[test]
+fn test_queries_on_statistics()
+{
+    let mut wam = Machine::new();
+
+    assert_prolog_success!(&mut wam, "?- statistics(runtime, [T, D]), integer(T), integer(D).");
+    assert_prolog_success!(&mut wam, "?- statistics(walltime, [T, D]), integer(T), integer(D).");
+    assert_prolog_success!(&mut wam, "?- statistics(cputime, T), float(T).");
+    assert_prolog_success!(&mut wam, "?- statistics(heapused, H), integer(H).");
+    assert_prolog_success!(&mut wam, "?- statistics(trailused, T), integer(T).");
+    assert_prolog_success!(&mut wam, "?- statistics(localused, L), integer(L).");
+    assert_prolog_success!(&mut wam, "?- statistics(choicepoints, B), integer(B).");
+    assert_prolog_success!(&mut wam, "?- statistics(atoms, A), integer(A).");
+    assert_prolog_success!(&mut wam, "?- statistics(code_size, C), C > 0.");
+
+    assert_prolog_success!(&mut wam, "?- catch(statistics(nonsense, _), error(domain_error(statistics_key, nonsense), statistics/2), true).");
+    assert_prolog_success!(&mut wam, "?- catch(statistics(_, _), error(instantiation_error, statistics/2), true).");
+
+    submit(&mut wam, "g(1). g(2). g(3).");
+
+    assert_prolog_success!(&mut wam, "?- statistics(inferences, I0), g(3), statistics(inferences, I1), I1 > I0.");
+    assert_prolog_success!(&mut wam, "?- statistics(inferences, I0), statistics(inferences, I1),
+                                         I1 - I0 =:= 1.");
+}
+
+#[test]
+fn test_queries_on_call_with_depth_limit()
+{
+    let mut wam = Machine::new();
+
+    submit(&mut wam, "g(1). g(2). g(3).");
+    submit(&mut wam, "d(0). d(s(X)) :- d(X).");
+    submit(&mut wam, "loop :- loop.");
+
+    assert_prolog_success!(&mut wam, "?- call_with_depth_limit(true, 1, R).", [["R = 1"]]);
+    assert_prolog_success!(&mut wam, "?- call_with_depth_limit(d(s(s(0))), 3, R).", [["R = 3"]]);
+    assert_prolog_success!(&mut wam, "?- call_with_depth_limit(d(s(s(0))), 2, R).",
+                           [["R = depth_limit_exceeded"]]);
+    assert_prolog_success!(&mut wam, "?- call_with_depth_limit(g(1), 0, R).",
+                           [["R = depth_limit_exceeded"]]);
+    assert_prolog_success!(&mut wam, "?- call_with_depth_limit(loop, 100, R).",
+                           [["R = depth_limit_exceeded"]]);
+    assert_prolog_failure!(&mut wam, "?- call_with_depth_limit(g(4), 1, R).");
+
+    assert_prolog_success!(&mut wam, "?- call_with_depth_limit(g(X), 1, R).",
+                           [["X = 1", "R = 1"], ["X = 2", "R = 1"], ["X = 3", "R = 1"]]);
+    assert_prolog_success!(&mut wam, "?- call_with_depth_limit(g(X), 1, R), X >= 2.",
+                           [["X = 2", "R = 1"], ["X = 3", "R = 1"]]);
+
+    assert_prolog_success!(&mut wam, "?- call_with_depth_limit(call_with_depth_limit(d(s(s(0))), 10, R1),
+                                                                5, R2).",
+                           [["R1 = 3", "R2 = 4"]]);
+    assert_prolog_success!(&mut wam, "?- call_with_depth_limit(call_with_depth_limit(d(s(s(0))), 10, _),
+                                                                2, R).",
+                           [["R = depth_limit_exceeded"]]);
+
+    assert_prolog_success!(&mut wam, "?- call_with_inference_limit(call_with_depth_limit(d(s(s(0))), 10, R1),
+                                                                    100, R2).",
+                           [["R1 = 3", "R2 = true"]]);
+    assert_prolog_success!(&mut wam, "?- call_with_depth_limit(call_with_inference_limit(d(s(s(0))), 100, R1),
+                                                                10, R2).",
+                           [["R1 = !", "R2 = 4"]]);
+
+    assert_prolog_success!(&mut wam, "?- catch(call_with_depth_limit(throw(error), 10, _), error, true).");
+    assert_prolog_success!(&mut wam, "?- call_with_depth_limit(d(s(s(0))), 3, R).", [["R = 3"]]);
+
+    assert_prolog_success!(&mut wam, "?- catch(call_with_depth_limit(true, _, _), error(instantiation_error, _), true).");
+    assert_prolog_success!(&mut wam, "?- catch(call_with_depth_limit(true, a, _), error(type_error(integer, a), _), true).");
+}
+
+#[test]
+fn test_queries_on_call_with_time_limit()
+{
+    let mut wam = Machine::new();
+
+    submit(&mut wam, "loop :- loop.");
+    submit(&mut wam, "g(1). g(2). g(3).");
+
+    assert_prolog_success!(&mut wam, "?- call_with_time_limit(10, g(X)).", [["X = 1"]]);
+    assert_prolog_failure!(&mut wam, "?- call_with_time_limit(10, g(4)).");
+    assert_prolog_failure!(&mut wam, "?- call_with_time_limit(0.1, loop).");
+
+    assert_prolog_success!(&mut wam, "?- catch(call_with_time_limit(0.1, loop), E, true).",
+                           [["E = time_limit_exceeded"]]);
+    assert_prolog_success!(&mut wam, "?- catch(call_with_time_limit(10, call_with_time_limit(0.1, loop)),
+                                               E, true).",
+                           [["E = time_limit_exceeded"]]);
+    assert_prolog_success!(&mut wam, "?- catch(call_with_time_limit(0.1, call_with_time_limit(10, loop)),
+                                               E, true).",
+                           [["E = time_limit_exceeded"]]);
+    assert_prolog_success!(&mut wam, "?- call_with_time_limit(10, catch(call_with_time_limit(0.1, loop),
+                                                                        E, true)).",
+                           [["E = time_limit_exceeded"]]);
+    assert_prolog_success!(&mut wam, "?- catch(call_with_time_limit(0.2, (call_with_time_limit(10, true), loop)),
+                                               E, true).",
+                           [["E = time_limit_exceeded"]]);
+    assert_prolog_success!(&mut wam, "?- catch(call_with_time_limit(0.3,
+                                                   (catch(call_with_time_limit(0.1, loop), time_limit_exceeded,
+                                                          true),
+                                                    loop)),
+                                               E, true).",
+                           [["E = time_limit_exceeded"]]);
+    assert_prolog_success!(&mut wam, "?- catch(call_with_time_limit(0.1, setup_call_cleanup(true, loop, true)),
+                                               E, true).",
+                           [["E = time_limit_exceeded"]]);
+    assert_prolog_success!(&mut wam, "?- catch(call_with_time_limit(10, throw(error)), E, true).",
+                           [["E = error"]]);
+    assert_prolog_success!(&mut wam, "?- catch(call_with_time_limit(_, true), error(instantiation_error, _), true).");
+    assert_prolog_success!(&mut wam, "?- call_with_time_limit(1.0e300, g(X)).", [["X = 1"]]);
+    assert_prolog_success!(&mut wam, "?- call_with_time_limit(100000000000000000000000, g(1)).");
+    assert_prolog_success!(&mut wam, "?- catch(call_with_time_limit(-1, true),
+                                               error(domain_error(not_less_than_zero, -1), _), true).");
+
+    wam.set_query_timeout(Some(Duration::from_millis(100)));
+
+    assert_prolog_failure!(&mut wam, "?- loop.");
+    assert_prolog_success!(&mut wam, "?- catch(loop, time_limit_exceeded, true).");
+    assert_prolog_success!(&mut wam, "?- g(X).", [["X = 1"], ["X = 2"], ["X = 3"]]);
+
+    wam.set_query_timeout(None);
+}
+
+#[test]
+fn test_queries_on_interrupts()
+{
+    let mut wam = Machine::new();
+
+    submit(&mut wam, "loop :- loop.");
+
+    let interrupt_after = |wam: &Machine, millis| {
+        let interrupt = wam.interrupt_flag();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(millis));
+            interrupt.store(true, Ordering::Relaxed);
+        })
+    };
+
+    wam.set_interrupt_reader(|| InterruptAction::Abort);
+
+    let handle = interrupt_after(&wam, 100);
+
+    assert_prolog_failure!(&mut wam, "?- loop.");
+    handle.join().unwrap();
+
+    assert_prolog_success!(&mut wam, "?- X = a.", [["X = a"]]);
+
+    wam.set_interrupt_reader(|| InterruptAction::Continue);
+
+    let handle = interrupt_after(&wam, 100);
+
+    assert_prolog_success!(&mut wam, "?- catch(call_with_time_limit(0.5, loop), time_limit_exceeded, true).");
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_queries_on_print_message()
+{
+    let mut wam = Machine::new();
+
+    assert_prolog_success!(&mut wam, "?- print_message(silent, foo).");
+    assert_prolog_success!(&mut wam, "?- print_message(informational, time(1, 0.5, 0.5, 2)).");
+    assert_prolog_success!(&mut wam, "?- catch(print_message(bogus, foo),
+                                               error(domain_error(message_kind, bogus), _), true).");
+    assert_prolog_success!(&mut wam, "?- catch(print_message(_, foo), error(instantiation_error, _), true).");
+
+    assert_prolog_success!(&mut wam, "?- statistics(inferences, I0), statistics(cputime, T0),
+                                         statistics(walltime, [W0, _]),
+                                         '$time_message'(I0, T0, W0, time(_, _, _, _)).");
+
+    submit(&mut wam, "message_hook(custom(X), Kind, Lines) :- throw(hooked(Kind, X, Lines)).
+                      message_hook(quiet, _, _).");
+
+    // message_hook/3 intercepts messages along with their translations.
+    assert_prolog_success!(&mut wam, "?- catch((print_message(warning, custom(a)), false),
+                                               hooked(warning, a, [_-[custom(a)]]), true).");
+    assert_prolog_success!(&mut wam, "?- print_message(error, quiet).");
+
+    submit(&mut wam, "message(custom(X), ['custom ~w'-[X] | T], T).");
+
+    assert_prolog_success!(&mut wam, "?- catch((print_message(error, custom(b)), false),
+                                               hooked(error, b, ['custom ~w'-[b]]), true).");
+
+    // uncaught exceptions are printed with the ball as a term.
+    submit(&mut wam, "message_hook(uncaught_exception(error(type_error(T, _), _), _), error, _) :-
+                          op(700, xfx, T).");
+
+    wam.reset();
+
+    match compile_packet(&mut wam, parse_code(&mut wam, "?- throw(error(type_error(uncaught_t, x), c)).").unwrap()) {
+        EvalSession::Error(e) => wam.print_message(Message::Error(e)),
+        _ => panic!("expected an uncaught exception.")
+    };
+
+    assert_prolog_success!(&mut wam, "?- current_op(700, xfx, uncaught_t).");
+}
+
+#[test]
+fn test_queries_on_module_qualified_goals()
+{
+    let mut wam = Machine::new();
+
+    compile_listing(&mut wam, ":- module(m, [p/1]).
+                               p(X) :- q(X).
+                               q(a).
+                               r(b).");
+
+    submit(&mut wam, "s(X) :- m:r(X).");
+    submit(&mut wam, "t(X) :- ( m:r(X) ; X = c ).");
+    submit(&mut wam, "u(M, X) :- M:r(X).");
+
+    assert_prolog_success!(&mut wam, "?- m:p(X).", [["X = a"]]);
+    assert_prolog_success!(&mut wam, "?- m:r(X).", [["X = b"]]);
+    assert_prolog_success!(&mut wam, "?- s(X).", [["X = b"]]);
+    assert_prolog_success!(&mut wam, "?- t(X).", [["X = b"], ["X = c"]]);
+    assert_prolog_success!(&mut wam, "?- u(m, X).", [["X = b"]]);
+    assert_prolog_success!(&mut wam, "?- call(m:r, X).", [["X = b"]]);
+    assert_prolog_success!(&mut wam, "?- G = r(X), call(m:G).", [["G = r(b)", "X = b"]]);
+
+    assert_prolog_failure!(&mut wam, "?- r(X).");
+
+    assert_prolog_success!(&mut wam, "?- catch(m:nope(1), error(existence_error(procedure, m:nope/1), _), true).");
+    assert_prolog_success!(&mut wam, "?- catch(call(nomod:foo), error(existence_error(procedure, nomod:foo/0), _),
+                                               true).");
+    assert_prolog_success!(&mut wam, "?- catch(u(_, _), error(instantiation_error, _), true).");
+    assert_prolog_success!(&mut wam, "?- catch(u(1, _), error(type_error(atom, 1), _), true).");
+
+    // calls resolved when compiled follow the module as it's reloaded and unloaded.
+    compile_listing(&mut wam, ":- module(m, [p/1]).
+                               p(X) :- q(X).
+                               q(a).
+                               r(d).");
+
+    assert_prolog_success!(&mut wam, "?- s(X).", [["X = d"]]);
+
+    submit(&mut wam, "?- unload_module(m).");
+
+    assert_prolog_success!(&mut wam, "?- catch(s(_), error(existence_error(procedure, m:r/1), _), true).");
+}
+
+#[test]
+fn test_queries_on_meta_predicates()
+{
+    let mut wam = Machine::new();
+
+    compile_listing(&mut wam, ":- module(mm, [app/2, plain_app/2, cm/1]).
+                               :- meta_predicate app(1, ?).
+                               app(G, X) :- call(G, X).
+                               plain_app(G, X) :- call(G, X).
+                               cm(M) :- context_module(M).");
+
+    submit(&mut wam, "p(a).");
+
+    assert_prolog_success!(&mut wam, "?- mm:app(p, X).", [["X = a"]]);
+    assert_prolog_success!(&mut wam, "?- app(p, X).", [["X = a"]]);
+    assert_prolog_success!(&mut wam, "?- catch(plain_app(p, _), error(existence_error(procedure, mm:p/1), _),
+                                               true).");
+
+    assert_prolog_success!(&mut wam, "?- context_module(M).", [["M = user"]]);
+    assert_prolog_success!(&mut wam, "?- cm(M).", [["M = mm"]]);
+
+    assert_prolog_success!(&mut wam, "?- strip_module(m:foo, M, G).", [["G = foo", "M = m"]]);
+    assert_prolog_success!(&mut wam, "?- strip_module(m:n:foo(a), M, G).",
+                           [["G = foo(a)", "M = n"]]);
+    assert_prolog_success!(&mut wam, "?- strip_module(foo, M, G).", [["G = foo", "M = user"]]);
+
+    compile_listing(&mut wam, ":- module(mq, [wrap/2, plain_wrap/2]).
+                               :- meta_predicate wrap(0, ?).
+                               wrap(G, G).
+                               plain_wrap(G, G).");
+
+    submit(&mut wam, ":- use_module(mq).");
+
+    assert_prolog_success!(&mut wam, "?- wrap(foo, G).", [["G = user:foo"]]);
+    assert_prolog_success!(&mut wam, "?- wrap(m:foo, G).", [["G = m:foo"]]);
+    assert_prolog_success!(&mut wam, "?- plain_wrap(foo, G).", [["G = foo"]]);
+
+    assert_prolog_success!(&mut wam, ":- meta_predicate uwrap(0, ?).");
+}
+
+#[test]
+fn test_queries_on_reflection()
+{
+    let mut wam = Machine::new();
+
+    compile_listing(&mut wam, ":- module(r, [p/1]).
+                               p(a).
+                               p(b).
+                               q(c).");
+
+    compile_listing(&mut wam, "f(x).
+                               f(y).
+                               g.");
+
+    assert_prolog_success!(&mut wam, "?- current_predicate(f/1).");
+    assert_prolog_success!(&mut wam, "?- current_predicate(f/A).", [["A = 1"]]);
+    assert_prolog_success!(&mut wam, "?- current_predicate(r:N/1).", [["N = p"], ["N = q"]]);
+    assert_prolog_success!(&mut wam, "?- current_predicate(N, g).", [["N = g"]]);
+    assert_prolog_success!(&mut wam, "?- current_predicate(M:p/1).", [["M = r"]]);
+
+    assert_prolog_failure!(&mut wam, "?- current_predicate(atom/1).");
+    assert_prolog_failure!(&mut wam, "?- current_predicate(p/1).");
+
+    assert_prolog_success!(&mut wam, "?- predicate_property(f(_), number_of_clauses(N)).",
+                           [["N = 2"]]);
+    assert_prolog_success!(&mut wam, "?- predicate_property(atom(_), built_in).");
+    assert_prolog_success!(&mut wam, "?- predicate_property(r:p(_), exported).");
+    assert_prolog_success!(&mut wam, "?- predicate_property(r:q(_), number_of_clauses(N)).",
+                           [["N = 1"]]);
+
+    assert_prolog_failure!(&mut wam, "?- predicate_property(r:q(_), exported).");
+    assert_prolog_failure!(&mut wam, "?- predicate_property(f(_), built_in).");
+
+    // consulted predicates are static, and known by the file they came from.
+    assert_prolog_success!(&mut wam, "?- predicate_property(f(_), static), predicate_property(f(_), defined).");
+    assert_prolog_success!(&mut wam, "?- predicate_property(r:p(_), static).");
+    assert_prolog_failure!(&mut wam, "?- predicate_property(f(_), dynamic).");
+    assert_prolog_failure!(&mut wam, "?- predicate_property(f(_), file(_)).");
+
+    compile_file(&mut wam, "h(1).", Some(ClauseName::BuiltIn("h.pl")));
+
+    assert_prolog_success!(&mut wam, "?- predicate_property(h(_), file(F)), F == 'h.pl'.");
+    assert_prolog_success!(&mut wam, "?- predicate_property(h(_), static).");
+
+    submit(&mut wam, "h(2).");
+
+    assert_prolog_failure!(&mut wam, "?- predicate_property(h(_), file(_)).");
+
+    assert_prolog_success!(&mut wam, "?- current_module(r).");
+    assert_prolog_success!(&mut wam, "?- module_property(r, exports(E)).", [["E = [p/1]"]]);
+
+    assert_prolog_failure!(&mut wam, "?- current_module(nomod).");
+
+    assert_prolog_success!(&mut wam, "?- catch(current_predicate(foo), error(type_error(predicate_indicator, foo), _),
+                                               true).");
+    assert_prolog_success!(&mut wam, "?- catch(predicate_property(1, _), error(type_error(callable, 1), _),
+                                               true).");
+}
+
+#[test]
+fn test_queries_on_module_reloading()
+{
+    let mut wam = Machine::new();
+
+    compile_listing(&mut wam, ":- module(m, [p/1, q/1]).
+                               p(X) :- r(X).
+                               q(a).
+                               r(a).");
+
+    submit(&mut wam, ":- use_module(m).");
+    submit(&mut wam, "s(X) :- p(X).");
+
+    assert_prolog_success!(&mut wam, "?- s(X).", [["X = a"]]);
+
+    compile_listing(&mut wam, ":- module(m, [p/1]).
+                               p(X) :- r(X).
+                               r(b).");
+
+    assert_prolog_success!(&mut wam, "?- p(X).", [["X = b"]]);
+    assert_prolog_success!(&mut wam, "?- s(X).", [["X = b"]]);
+    assert_prolog_success!(&mut wam, "?- m:p(X).", [["X = b"]]);
+
+    assert_prolog_failure!(&mut wam, "?- current_predicate(q/1).");
+
+    // a predicate that is no longer exported is still defined in its
+    // module, and its code is replaced in place.
+    compile_listing(&mut wam, ":- module(n, [u/1, v/1]).
+                               u(a).
+                               v(a).");
+
+    submit(&mut wam, ":- use_module(n).");
+    submit(&mut wam, "w(X) :- n:v(X).");
+
+    let code_size = wam.code_size();
+
+    compile_listing(&mut wam, ":- module(n, [u/1]).
+                               u(b).
+                               v(b).");
+
+    assert_prolog_success!(&mut wam, "?- n:v(X).", [["X = b"]]);
+    assert_prolog_success!(&mut wam, "?- w(X).", [["X = b"]]);
+    assert_prolog_success!(&mut wam, "?- u(X).", [["X = b"]]);
+    assert_prolog_success!(&mut wam, "?- catch(v(_), error(existence_error(procedure, v/1), _), true).");
+
+    assert_eq!(wam.code_size(), code_size);
+
+    assert_prolog_success!(&mut wam, "?- unload_module(m).");
+
+    assert_prolog_failure!(&mut wam, "?- current_module(m).");
+
+    assert_prolog_success!(&mut wam, "?- catch(s(_), error(existence_error(procedure, _), _), true).");
+    assert_prolog_success!(&mut wam, "?- catch(unload_module(m), error(existence_error(module, m), _), true).");
+    assert_prolog_success!(&mut wam, "?- catch(unload_module(_), error(instantiation_error, _), true).");
+    assert_prolog_success!(&mut wam, "?- catch(unload_module(1), error(type_error(atom, 1), _), true).");
+}
+
+#[test]
+fn test_queries_on_reexport()
+{
+    let mut wam = Machine::new();
+
+    compile_listing(&mut wam, ":- module(m1, [p/1]).
+                               p(a).");
+    compile_listing(&mut wam, ":- module(m2, [q/1, r/1]).
+                               q(b).
+                               r(c).");
+    compile_listing(&mut wam, ":- module(facade, [s/1]).
+                               :- reexport(m1).
+                               :- reexport(m2, [q/1]).
+                               s(X) :- p(X).");
+
+    submit(&mut wam, ":- use_module(facade).");
+
+    assert_prolog_success!(&mut wam, "?- p(X).", [["X = a"]]);
+    assert_prolog_success!(&mut wam, "?- q(X).", [["X = b"]]);
+    assert_prolog_success!(&mut wam, "?- s(X).", [["X = a"]]);
+    assert_prolog_success!(&mut wam, "?- facade:q(X).", [["X = b"]]);
+
+    assert_prolog_failure!(&mut wam, "?- current_predicate(r/1).");
+
+    // library modules are reexported by their names.
+    compile_listing(&mut wam, ::LISTS);
+    compile_listing(&mut wam, ":- module(lists_facade, []).
+                               :- reexport(library(lists)).");
+
+    submit(&mut wam, ":- use_module(lists_facade).");
+
+    assert_prolog_success!(&mut wam, "?- member(X, [a]).", [["X = a"]]);
+    assert_prolog_success!(&mut wam, "?- module_property(lists_facade, exports(E)), member(append/3, E).");
+
+    // only the exports of a module can be reexported.
+    match compile_listing(&mut wam, ":- module(bad, [t/1]).
+                                     :- reexport(m2, [nope/1]).
+                                     t(x).") {
+        EvalSession::Error(EvalError::ModuleDoesNotContainExport) => {},
+        _ => panic!("expected the reexport of nope/1 to fail.")
+    };
+
+    assert_prolog_failure!(&mut wam, "?- current_module(bad).");
+}
+
+#[test]
+fn test_queries_on_import_conflicts()
+{
+    let mut wam = Machine::new();
+
+    compile_listing(&mut wam, ":- module(m1, [p/1, q/1]).
+                               p(m1).
+                               q(m1).");
+    compile_listing(&mut wam, ":- module(m2, [p/1, r/1]).
+                               p(m2).
+                               r(m2).");
+
+    submit(&mut wam, ":- use_module(m1).");
+
+    assert_eq!(submit(&mut wam, ":- use_module(m2)."), false);
+    assert_eq!(submit(&mut wam, "q(local)."), false);
+
+    assert_prolog_success!(&mut wam, "?- p(X).", [["X = m1"]]);
+    assert_prolog_success!(&mut wam, "?- q(X).", [["X = m1"]]);
+
+    submit(&mut wam, ":- use_module(m2, [p/1 as p2]).");
+    submit(&mut wam, ":- use_module(m2, except([p/1])).");
+
+    assert_prolog_success!(&mut wam, "?- p2(X).", [["X = m2"]]);
+    assert_prolog_success!(&mut wam, "?- r(X).", [["X = m2"]]);
+
+    compile_listing(&mut wam, ":- module(m3, [t/1, u/1]).
+                               :- use_module(m2, except([p/1])).
+                               :- use_module(m1, [q/1 as q1]).
+                               t(X) :- r(X).
+                               u(X) :- q1(X).");
+
+    assert_prolog_success!(&mut wam, "?- m3:t(X).", [["X = m2"]]);
+    assert_prolog_success!(&mut wam, "?- m3:u(X).", [["X = m1"]]);
+    assert_prolog_success!(&mut wam, "?- catch(m3:p(_), error(existence_error(procedure, m3:p/1), _), true).");
+
+    // clashing imports are permission errors, thrown by use_module/1.
+    assert_prolog_success!(&mut wam, "?- catch(use_module(m2), error(permission_error(import_into(user), procedure,
+                                                                                      m2:p/1),
+                                                                     use_module/1),
+                                               true).");
+
+    wam.reset();
+
+    match compile_packet(&mut wam, parse_code(&mut wam, "q(local).").unwrap()) {
+        EvalSession::Error(EvalError::QueryFailureWithException(ball, ..)) =>
+            assert_eq!(ball, "error(permission_error(import_into(user), procedure, m1:q/1), q/1)"),
+        _ => panic!("expected a permission error.")
+    };
+
+    // library modules can be imported in part.
+    compile_listing(&mut wam, ::LISTS);
+    submit(&mut wam, ":- use_module(library(lists), [member/2]).");
+
+    assert_prolog_success!(&mut wam, "?- member(X, [a]).", [["X = a"]]);
+    assert_prolog_success!(&mut wam, "?- catch(append(_, _, _), error(existence_error(procedure, append/3), _),
+                                               true).");
+
+    assert_prolog_success!(&mut wam, "?- set_prolog_flag(import_conflicts, warning).");
+
+    submit(&mut wam, "q(local).");
+
+    assert_prolog_success!(&mut wam, "?- q(X).", [["X = local"]]);
+    assert_prolog_success!(&mut wam, "?- m1:q(X).", [["X = m1"]]);
+
+    assert_prolog_success!(&mut wam, "?- catch(set_prolog_flag(import_conflicts, never), error(domain_error(flag_value, never), _),
+                                               true).");
+}
+
+#[test]
+fn test_queries_on_toplevel_module()
+{
+    let mut wam = Machine::new();
+
+    assert_prolog_success!(&mut wam, "?- module(m).");
+    assert_eq!(wam.toplevel_module().as_str(), "m");
+
+    submit(&mut wam, "p(a).");
+
+    assert_prolog_success!(&mut wam, "?- p(X).", [["X = a"]]);
+    assert_prolog_success!(&mut wam, "?- set_module(user).");
+    assert_eq!(wam.toplevel_module().as_str(), "user");
+
+    assert_prolog_success!(&mut wam, "?- m:p(X).", [["X = a"]]);
+
+    assert_prolog_success!(&mut wam, "?- catch(module(_), error(instantiation_error, _), true).");
+    assert_prolog_success!(&mut wam, "?- catch(module(f(x)), error(type_error(atom, f(x)), _), true).");
+    assert_prolog_success!(&mut wam, "?- catch(module(builtin), error(permission_error(modify, module, builtin), _), true).");
+}
+
+#[test]
+fn test_queries_on_dcgs()
+{
+    let mut wam = Machine::new();
+
+    compile_listing(&mut wam, "greeting --> [hello], name.
+                               name --> [world].
+                               name --> \"dcg\".
+
+                               digits([D|Ds]) --> digit(D), digits(Ds).
+                               digits([D]) --> digit(D).
+                               digit(D) --> [D], { integer(D) }.
+
+                               anything([]) --> [].
+                               anything([X|Xs]) --> [X], anything(Xs).
+
+                               first_of([X]) --> [X], !.
+                               first_of([]) --> [].
+
+                               not_a --> \\+ [a], anything(_).
+                               look_ahead(X), [X] --> [X].
+                               either --> ( [a] | [b] ).
+                               twice(G) --> call(G), call(G).");
+
+    assert_prolog_success!(&mut wam, "?- phrase(greeting, [hello, world]).");
+    assert_prolog_success!(&mut wam, "?- phrase(greeting, [hello, d, c, g]).");
+    assert_prolog_failure!(&mut wam, "?- phrase(greeting, [hello]).");
+    assert_prolog_success!(&mut wam, "?- phrase(digits(Ds), [1, 2, 3], Rest).",
+                           [["Ds = [1, 2, 3]", "Rest = []"],
+                            ["Ds = [1, 2]", "Rest = [3]"],
+                            ["Ds = [1]", "Rest = [2, 3]"]]);
+    assert_prolog_success!(&mut wam, "?- phrase(anything(Xs), [a, b]).", [["Xs = [a, b]"]]);
+    assert_prolog_success!(&mut wam, "?- phrase(first_of(Xs), [a, b], Rest).",
+                           [["Xs = [a]", "Rest = [b]"]]);
+    assert_prolog_success!(&mut wam, "?- phrase(not_a, [b, a]).");
+    assert_prolog_failure!(&mut wam, "?- phrase(not_a, [a, b]).");
+    assert_prolog_success!(&mut wam, "?- phrase(look_ahead(X), [c, d], Rest).",
+                           [["X = c", "Rest = [c, d]"]]);
+    assert_prolog_success!(&mut wam, "?- phrase(either, [b]).");
+    assert_prolog_success!(&mut wam, "?- phrase(twice(digit(_)), [1, 2]).");
+
+    compile_listing(&mut wam, ":- module(greetings, [hello//0]).
+                               hello --> [hi].");
+
+    submit(&mut wam, ":- use_module(greetings).");
+    submit(&mut wam, "salute --> hello, [there].");
+
+    assert_prolog_success!(&mut wam, "?- phrase(hello, [hi]).");
+    assert_prolog_success!(&mut wam, "?- phrase(greetings:hello, [hi]).");
+    assert_prolog_success!(&mut wam, "?- phrase(salute, [hi, there]).");
+
+    submit(&mut wam, "b --> [b].");
+    submit(&mut wam, "pair(_S1, _S2) --> [_S1], [_S2].");
+
+    assert_prolog_success!(&mut wam, "?- phrase(([a], b), L).", [["L = [a, b]"]]);
+    assert_prolog_success!(&mut wam, "?- phrase(\"ab\", L).", [["L = [a, b]"]]);
+    assert_prolog_success!(&mut wam, "?- phrase({X = 1}, L).", [["L = []", "X = 1"]]);
+    assert_prolog_success!(&mut wam, "?- phrase(pair(X, Y), [a, b]).", [["X = a", "Y = b"]]);
+}
+
+#[test]
+fn test_queries_on_expansion()
+{
+    let mut wam = Machine::new();
+
+    compile_listing(&mut wam, "term_expansion(double(X), [X, X]).
+                               term_expansion(swap(X, Y), swapped(Y, X)).
+                               goal_expansion(plus_one(X, Y), Y is X + 1).
+                               goal_expansion(twice(G), (G, G)).
+                               goal_expansion(never, fail).
+                               term_expansion(grow(z), grow(s(z))).
+                               term_expansion(first(X), second(X)).
+                               term_expansion(second(X), [third(X), third(X)]).
+                               goal_expansion(shout(X), X = loud).
+                               term_expansion(wrap(_G1), wrapped(_G1, _)).
+                               term_expansion(rate(X), rated(X, 1.0e16)).");
+
+    compile_listing(&mut wam, "double(fact(a)).
+                               swap(a, b).
+                               grow(z).
+                               wrap(a).
+                               rate(r).
+                               succ_of(X, Y) :- plus_one(X, Y).
+                               pair(X) :- twice(member(X, [a])).
+                               unreachable :- never.
+                               first(a).
+                               quiet(X) :- \\+ shout(X).
+                               echo(X) :- catch(shout(X), _, true).");
+
+    assert_prolog_success!(&mut wam, "?- fact(X).", [["X = a"], ["X = a"]]);
+    assert_prolog_success!(&mut wam, "?- swapped(X, Y).", [["X = b", "Y = a"]]);
+    assert_prolog_success!(&mut wam, "?- succ_of(1, X).", [["X = 2"]]);
+    assert_prolog_success!(&mut wam, "?- pair(X).", [["X = a"]]);
+    assert_prolog_failure!(&mut wam, "?- unreachable.");
+    assert_prolog_success!(&mut wam, "?- plus_one(2, X).", [["X = 3"]]);
+    assert_prolog_success!(&mut wam, "?- grow(X).", [["X = s(z)"]]);
+    assert_prolog_success!(&mut wam, "?- wrapped(a, b).");
+    assert_prolog_success!(&mut wam, "?- rated(r, 1.0e16).");
+    assert_prolog_success!(&mut wam, "?- third(X).", [["X = a"], ["X = a"]]);
+    assert_prolog_failure!(&mut wam, "?- quiet(loud).");
+    assert_prolog_success!(&mut wam, "?- quiet(soft).");
+    assert_prolog_success!(&mut wam, "?- echo(X).", [["X = loud"]]);
+
+    submit(&mut wam, "double(twin(b)).");
+
+    assert_prolog_success!(&mut wam, "?- twin(X).", [["X = b"], ["X = b"]]);
+
+    compile_listing(&mut wam, ":- module(m, []).
+                               goal_expansion(greet(X), X = hello).");
+
+    assert_prolog_success!(&mut wam, "?- module(m).");
+
+    submit(&mut wam, "g(X) :- greet(X).");
+
+    assert_prolog_success!(&mut wam, "?- g(X).", [["X = hello"]]);
+    assert_prolog_success!(&mut wam, "?- module(user).");
+}
+
+#[test]
+fn test_queries_on_conditional_compilation()
+{
+    let mut wam = Machine::new();
+
+    compile_listing(&mut wam, ":- if(true).
+                               p(a).
+                               :- if(fail).
+                               p(b).
+                               :- else.
+                               p(c).
+                               :- endif.
+                               :- elif(true).
+                               p(d).
+                               :- else.
+                               p(e).
+                               :- endif.
+                               :- if(member(x, [y])).
+                               q(a).
+                               :- elif((X = 1, X > 0)).
+                               q(b).
+                               :- elif(true).
+                               q(c).
+                               :- endif.");
+
+    assert_prolog_success!(&mut wam, "?- p(X).", [["X = a"], ["X = c"]]);
+    assert_prolog_success!(&mut wam, "?- q(X).", [["X = b"]]);
+
+    compile_listing(&mut wam, ":- if(fail).
+                               :- if(undefined_goal).
+                               r(a).
+                               :- endif.
+                               r(b).
+                               :- else.
+                               r(c).
+                               :- endif.");
+
+    assert_prolog_success!(&mut wam, "?- r(X).", [["X = c"]]);
+
+    match compile_listing(&mut wam, "s(a).
+                                     :- else.") {
+        EvalSession::Error(EvalError::ConditionalError(ConditionalError::NoMatchingIf("else/0"))) => {},
+        _ => panic!("expected else/0 without if/1 to be an error.")
+    };
+
+    match compile_listing(&mut wam, ":- endif.") {
+        EvalSession::Error(EvalError::ConditionalError(ConditionalError::NoMatchingIf("endif/0"))) => {},
+        _ => panic!("expected endif/0 without if/1 to be an error.")
+    };
+
+    match compile_listing(&mut wam, ":- if(true).
+                                     s(b).
+                                     :- else.
+                                     s(c).
+                                     :- elif(true).
+                                     s(d).
+                                     :- endif.") {
+        EvalSession::Error(EvalError::ConditionalError(ConditionalError::FollowsElse("elif/1"))) => {},
+        _ => panic!("expected elif/1 after else/0 to be an error.")
+    };
+
+    match compile_listing(&mut wam, ":- if(true).
+                                     s(e).") {
+        EvalSession::Error(EvalError::ConditionalError(ConditionalError::NoMatchingEndif)) => {},
+        _ => panic!("expected if/1 without endif/0 to be an error.")
+    };
+
+    // the clauses of a batch with a conditional error aren't compiled.
+    assert_prolog_failure!(&mut wam, "?- current_predicate(s/1).");
+}
+
+#[test]
+fn test_queries_on_prolog_flags()
+{
+    let mut wam = Machine::new();
+
+    assert_prolog_success!(&mut wam, "?- current_prolog_flag(bounded, X).", [["X = false"]]);
+    assert_prolog_success!(&mut wam, "?- current_prolog_flag(max_arity, X).", [["X = unbounded"]]);
+    assert_prolog_success!(&mut wam, "?- current_prolog_flag(integer_rounding_function, X).",
+                           [["X = toward_zero"]]);
+    assert_prolog_success!(&mut wam, "?- current_prolog_flag(double_quotes, X).", [["X = string"]]);
+    assert_prolog_success!(&mut wam, "?- current_prolog_flag(unknown, error),
+                                         current_prolog_flag(occurs_check, false),
+                                         current_prolog_flag(debug, off).");
+    assert_prolog_success!(&mut wam, "?- current_prolog_flag(version, V), atom(V).");
+    assert_prolog_success!(&mut wam, "?- current_prolog_flag(bounded, _),
+                                         current_prolog_flag(argv, _),
+                                         current_prolog_flag(home, _).");
+
+    assert_prolog_success!(&mut wam, "?- catch(current_prolog_flag(no_such_flag, _), error(E, _), true).",
+                           [["E = domain_error(prolog_flag, no_such_flag)"]]);
+    assert_prolog_success!(&mut wam, "?- catch(current_prolog_flag(1, _), error(E, _), true).",
+                           [["E = type_error(atom, 1)"]]);
+    assert_prolog_success!(&mut wam, "?- catch(set_prolog_flag(bounded, true), error(E, _), true).",
+                           [["E = permission_error(modify, flag, bounded)"]]);
+    assert_prolog_success!(&mut wam, "?- catch(set_prolog_flag(unknown, maybe), error(E, _), true).",
+                           [["E = domain_error(flag_value, maybe)"]]);
+    assert_prolog_success!(&mut wam, "?- catch(set_prolog_flag(debug, _), error(instantiation_error, _),
+                                               true).");
+
+    assert_prolog_success!(&mut wam, "?- set_prolog_flag(debug, on),
+                                         current_prolog_flag(debug, X),
+                                         set_prolog_flag(debug, off),
+                                         current_prolog_flag(debug, Y).",
+                           [["X = on", "Y = off"]]);
+    assert_prolog_success!(&mut wam, "?- catch(set_prolog_flag(debug, maybe), error(E, _), true).",
+                           [["E = domain_error(flag_value, maybe)"]]);
+    assert_prolog_success!(&mut wam, "?- set_prolog_flag(char_conversion, off),
+                                         current_prolog_flag(char_conversion, X).",
+                           [["X = off"]]);
+    assert_prolog_success!(&mut wam, "?- current_prolog_flag(max_integer, Max),
+                                         current_prolog_flag(min_integer, Min),
+                                         integer(Max), integer(Min), Min < 0, 0 < Max.");
+    assert_prolog_success!(&mut wam, "?- catch(set_prolog_flag(min_integer, 0), error(E, _), true).",
+                           [["E = permission_error(modify, flag, min_integer)"]]);
+    assert_prolog_success!(&mut wam, "?- set_prolog_flag(heap_limit, 100000000),
+                                         current_prolog_flag(heap_limit, X).",
+                           [["X = 100000000"]]);
+
+    submit(&mut wam, ":- set_prolog_flag(double_quotes, codes).");
+
+    assert_prolog_success!(&mut wam, "?- X = \"ab\".", [["X = [97, 98]"]]);
+
+    submit(&mut wam, ":- set_prolog_flag(double_quotes, chars).");
+
+    assert_prolog_success!(&mut wam, "?- X = \"ab\".", [["X = [a, b]"]]);
+    assert_prolog_success!(&mut wam, "?- X = \"\".", [["X = []"]]);
+
+    submit(&mut wam, ":- set_prolog_flag(double_quotes, atom).");
+
+    assert_prolog_success!(&mut wam, "?- X = \"ab\".", [["X = ab"]]);
+
+    compile_listing(&mut wam, ":- set_prolog_flag(double_quotes, codes).
+                               word(\"hi\").
+                               greeting --> \"hi\".");
+
+    assert_prolog_success!(&mut wam, "?- word(X).", [["X = [104, 105]"]]);
+    assert_prolog_success!(&mut wam, "?- phrase(greeting, [104, 105]).");
+
+    submit(&mut wam, ":- set_prolog_flag(double_quotes, string).");
+
+    assert_prolog_success!(&mut wam, "?- X = \"ab\".", [["X = \"ab\""]]);
+    assert_prolog_success!(&mut wam, "?- word(X).", [["X = [104, 105]"]]);
+}
+
+#[test]
+fn test_queries_on_unknown_procedures()
+{
+    let mut wam = Machine::new();
+
+    assert_prolog_success!(&mut wam, "?- catch(undefined_pred, error(existence_error(procedure, undefined_pred/0), _),
+                                               true).");
+
+    submit(&mut wam, ":- set_prolog_flag(unknown, fail).");
+
+    assert_prolog_failure!(&mut wam, "?- undefined_pred.");
+    assert_prolog_success!(&mut wam, "?- current_prolog_flag(unknown, fail).");
+
+    submit(&mut wam, ":- set_prolog_flag(unknown, error).");
+
+    compile_listing(&mut wam, ":- module(uk, [p/0]).
+                               :- set_prolog_flag(unknown, fail).
+                               p :- q.");
+
+    assert_prolog_failure!(&mut wam, "?- uk:p.");
+    assert_prolog_success!(&mut wam, "?- catch(q, error(existence_error(procedure, q/0), _), true).");
+
+    // the unknown flag of a module goes with it when it's unloaded.
+    assert_prolog_success!(&mut wam, "?- unload_module(uk).");
+
+    compile_listing(&mut wam, ":- module(uk, [p/0]).
+                               p :- q.");
+
+    assert_prolog_success!(&mut wam, "?- catch(uk:p, error(existence_error(procedure, _), _), true).");
+
+    compile_listing(&mut wam, ":- module(lazy, [lazy_p/1]).
+                               lazy_p(a).");
+
+    submit(&mut wam, "unknown_procedure(user, lazy_p/1) :- use_module(lazy).");
+
+    assert_prolog_success!(&mut wam, "?- lazy_p(X).", [["X = a"]]);
+
+    assert_prolog_success!(&mut wam, "?- catch(use_module(nomod), error(existence_error(module, nomod), _), true).");
+    assert_prolog_success!(&mut wam, "?- catch(use_module(_), error(instantiation_error, _), true).");
+}
+
+#[test]
+fn test_queries_on_autoloading()
+{
+    let mut wam = Machine::new();
+
+    let sets = ":- module(sets, [union/3, subset/2]).
+                union([], Ys, Ys).
+                union([X|Xs], Ys, [X|Zs]) :- union(Xs, Ys, Zs).
+                subset([], _).";
+
+    assert!(wam.add_library(sets, "sets.pl").is_ok());
+
+    // libraries are loaded on first use, and only the missing
+    // predicate is imported.
+    assert_prolog_failure!(&mut wam, "?- current_module(sets).");
+    assert_prolog_success!(&mut wam, "?- current_prolog_flag(autoload, true).");
+    assert_prolog_success!(&mut wam, "?- union([a], [b], X).", [["X = [a, b]"]]);
+    assert_prolog_success!(&mut wam, "?- current_module(sets).");
+    assert_prolog_success!(&mut wam, "?- predicate_property(union(_, _, _), imported_from(sets)).");
+    assert_prolog_failure!(&mut wam, "?- predicate_property(subset(_, _), imported_from(sets)).");
+
+    submit(&mut wam, "p(X) :- subset(X, [a]).");
+    submit(&mut wam, ":- set_prolog_flag(autoload, false).");
+
+    assert_prolog_success!(&mut wam, "?- catch(p([]), error(existence_error(procedure, subset/2), _), true).");
+
+    submit(&mut wam, ":- set_prolog_flag(autoload, true).");
+
+    assert_prolog_success!(&mut wam, "?- p([]).");
+    assert_prolog_success!(&mut wam, "?- catch(set_prolog_flag(autoload, maybe), error(E, _), true).",
+                           [["E = domain_error(flag_value, maybe)"]]);
+
+    assert_prolog_success!(&mut wam, "?- unload_module(sets).");
+    assert_prolog_success!(&mut wam, "?- catch(subset([], []), error(existence_error(procedure, subset/2), _),
+                                               true).");
+}
+
+#[test]
+fn test_queries_on_occurs_check()
+{
+    let mut wam = Machine::new();
+
+    submit(&mut wam, "p(X, f(X)).");
+    submit(&mut wam, "q(f(X), X).");
+
+    assert_prolog_failure!(&mut wam, "?- unify_with_occurs_check(X, f(X)).");
+    assert_prolog_failure!(&mut wam, "?- unify_with_occurs_check(f(X, Y), f(Y, g(X))).");
+    assert_prolog_success!(&mut wam, "?- unify_with_occurs_check(f(X, b), f(a, Y)).",
+                           [["X = a", "Y = b"]]);
+
+    assert_prolog_success!(&mut wam, "?- p(Y, Y).");
+    assert_prolog_success!(&mut wam, "?- q(Y, Y).");
+
+    submit(&mut wam, ":- set_prolog_flag(occurs_check, true).");
+
+    assert_prolog_failure!(&mut wam, "?- X = f(X).");
+    assert_prolog_failure!(&mut wam, "?- p(Y, Y).");
+    assert_prolog_failure!(&mut wam, "?- q(Y, Y).");
+    assert_prolog_success!(&mut wam, "?- p(a, X).", [["X = f(a)"]]);
+
+    submit(&mut wam, ":- set_prolog_flag(occurs_check, error).");
+
+    assert_prolog_success!(&mut wam, "?- catch(X = f(X), error(occurs_check(V, f(W)), _), true), V == W.");
+    assert_prolog_success!(&mut wam, "?- catch(p(Y, Y), error(occurs_check(_, _), p/2), true).");
+    assert_prolog_success!(&mut wam, "?- catch(q(Y, Y), error(occurs_check(_, _), q/2), true).");
+
+    submit(&mut wam, ":- set_prolog_flag(occurs_check, false).");
+
+    assert_prolog_success!(&mut wam, "?- subsumes_term(f(X, Y), f(Z, Z)).");
+    assert_prolog_success!(&mut wam, "?- subsumes_term(X, f(a)), var(X).");
+    assert_prolog_failure!(&mut wam, "?- subsumes_term(f(Z, Z), f(X, Y)).");
+    assert_prolog_failure!(&mut wam, "?- subsumes_term(g(X), g(f(X))).");
+    assert_prolog_failure!(&mut wam, "?- subsumes_term(f(a), f(X)).");
+}
+
+#[test]
+fn test_queries_on_rational_trees()
+{
+    let mut wam = Machine::new();
+
+    assert_prolog_success!(&mut wam, "?- X = f(X), Y = f(Y), X = Y.");
+    assert_prolog_success!(&mut wam, "?- X = f(X), Y = f(f(Y)), X = Y, X == Y.");
+    assert_prolog_success!(&mut wam, "?- X = [a|X], Y = [a, a|Y], X = Y.");
+    assert_prolog_failure!(&mut wam, "?- X = f(X, a), Y = f(Y, b), X = Y.");
+    assert_prolog_success!(&mut wam, "?- X = f(X), compare(=, X, X), ground(X).");
+
+    assert_prolog_success!(&mut wam, "?- X = f(X).", [["X = f(...)"]]);
+    assert_prolog_success!(&mut wam, "?- X = [a|X].", [["X = [a | ...]"]]);
+
+    assert_prolog_success!(&mut wam, "?- X = f(X), duplicate_term(X, Y), cyclic_term(Y), X = Y.");
+    assert_prolog_success!(&mut wam, "?- X = [a|X], duplicate_term(X, Y), Y = [a, a|_].");
+
+    assert_prolog_success!(&mut wam, "?- X = f(X), cyclic_term(X).");
+    assert_prolog_success!(&mut wam, "?- S = f(a), acyclic_term(g(S, S)).");
+
+    assert_prolog_success!(&mut wam, "?- X = f(X), term_factorized(X, S, [V = f(W)]), S == V, V == W.");
+    assert_prolog_success!(&mut wam, "?- X = g(a, Y), Y = h(X), term_factorized(X, S, [V = g(a, h(W))]),
+                                         S == V, V == W.");
+    assert_prolog_success!(&mut wam, "?- X = f(X), term_factorized(k(X, b), S, [V = f(W)]),
+                                         S == k(V, b), V == W.");
+    assert_prolog_success!(&mut wam, "?- term_factorized(f(a, B), S, L), S == f(a, B), L == [].");
+    assert_prolog_success!(&mut wam, "?- S = f(a), term_factorized(g(S, S), T, [V = f(a)]), T == g(V, V).");
+    assert_prolog_success!(&mut wam, "?- S = f(a), T = g(S, S), term_factorized(h(T, T), U, [V = f(a), W = g(X, Y)]),
+                                         U == h(W, W), X == V, Y == V.");
+    assert_prolog_success!(&mut wam, "?- term_factorized(g(f(a), f(a)), T, [V = f(a)]), T == g(V, V).");
+    assert_prolog_success!(&mut wam, "?- term_factorized(g(f(X), f(Y)), T, L), T == g(f(X), f(Y)), L == [].");
+}
+
+#[test]
+fn test_queries_on_resource_limits()
+{
+    let mut wam = Machine::new();
+
+    submit(&mut wam, "c. c.");
+    submit(&mut wam, "p(N) :- N > 0, c, M is N - 1, p(M).");
+
+    assert_prolog_success!(&mut wam, "?- set_prolog_flag(heap_limit, 100000).");
+    assert_prolog_success!(&mut wam, "?- catch(length(_, 1000000),
+                                               error(resource_error(memory), _), true).");
+
+    assert_prolog_success!(&mut wam, "?- set_prolog_flag(choice_limit, 1000).");
+    assert_prolog_success!(&mut wam, "?- catch(p(2000), error(resource_error(memory), p/1),
+                                               true).");
+    assert_prolog_success!(&mut wam, "?- length(L, 3), once(c).");
+}
+
+#[test]
+fn test_queries_on_mode_declarations()
+{
+    let mut wam = Machine::new();
+
+    compile_listing(&mut wam, ":- mode len(+, -).
+                               len([], 0).
+                               len([_|Xs], N) :- len(Xs, M), N is M + 1.
+
+                               :- mode out(-, ?), first(+).
+                               out(a, b).
+                               out(c, d).
+                               first([X|_]) :- X == a.");
+
+    assert_prolog_success!(&mut wam, "?- len([a, b, c], N).", [["N = 3"]]);
+    assert_prolog_success!(&mut wam, "?- catch(len(_, _), error(instantiation_error, len/2), true).");
+    assert_prolog_success!(&mut wam, "?- catch(len([a], 1), error(uninstantiation_error(1), len/2),
+                                               true).");
+
+    assert_prolog_success!(&mut wam, "?- out(X, d).", [["X = c"]]);
+    assert_prolog_success!(&mut wam, "?- catch(out(a, _), error(uninstantiation_error(a), _), true).");
+    assert_prolog_success!(&mut wam, "?- first([a, b]).");
+    assert_prolog_failure!(&mut wam, "?- first([b]).");
+    assert_prolog_success!(&mut wam, "?- catch(first(_), error(instantiation_error, _), true).");
+
+    submit(&mut wam, ":- mode twice(+, ?).");
+    submit(&mut wam, "twice(X, Y) :- Y is 2 * X.");
+
+    assert_prolog_success!(&mut wam, "?- twice(2, 4).");
+    assert_prolog_success!(&mut wam, "?- catch(twice(_, 4), error(instantiation_error, _), true).");
+    assert_prolog_failure!(&mut wam, "?- current_op(_, _, mode).");
+
+    compile_listing(&mut wam, ":- module(m, [inc/2]).
+                               :- mode inc(+, -).
+                               inc(X, Y) :- Y is X + 1.");
+
+    assert_prolog_success!(&mut wam, "?- catch(m:inc(_, _), error(instantiation_error, inc/2), true).");
+    assert_prolog_success!(&mut wam, "?- unload_module(m).");
+
+    compile_listing(&mut wam, ":- module(m, [inc/2]).
+                               inc(1, 2).");
+
+    assert_prolog_success!(&mut wam, "?- m:inc(X, Y).", [["X = 1", "Y = 2"]]);
+
+    compile_listing(&mut wam, ":- mode dup(-, -).
+                               dup(X, X).
+                               dup(f(X), f(X)).");
+
+    assert_prolog_success!(&mut wam, "?- dup(X, Y), X == Y.");
+    assert_prolog_success!(&mut wam, "?- findall(X-Y, dup(X, Y), [A-B, f(C)-f(D)]), A == B, C == D.");
+    assert_prolog_success!(&mut wam, "?- findall(Z, dup(Z, Z), [_, f(_)]).");
+
+    match compile_listing(&mut wam, "late(a).
+                                     :- mode late(+).") {
+        EvalSession::Error(EvalError::LateModeDeclaration(ref name, 1))
+            if name.as_str() == "late" => {},
+        _ => panic!("expected a mode declaration following its clauses to be an error.")
+    };
+
+    submit(&mut wam, "later(a).");
+
+    match parse_code(&mut wam, ":- mode later(+).") {
+        Err(EvalError::LateModeDeclaration(ref name, 1)) if name.as_str() == "later" => {},
+        _ => panic!("expected a mode declaration following its clauses to be an error.")
+    };
+}
+
+fn head_instrs(code: &Code) -> Vec<String> {
+    code.iter().flat_map(|line| match line {
+        &Line::Fact(ref fact) => fact.iter().map(|instr| format!("{}", instr)).collect(),
+        _ => vec![]
+    }).collect()
+}
+
+#[test]
+fn test_mode_specialized_code()
+{
+    let mut wam = Machine::new();
+    let src = "len([_|Xs], s(N)) :- len(Xs, N).";
+
+    let compile = |wam: &mut Machine, modes: Option<&Vec<Mode>>| match parse_code(wam, src) {
+        Ok(TopLevelPacket::Decl(tl, _)) => compile_relation(&tl, modes).unwrap(),
+        _ => panic!("expected a clause.")
+    };
+
+    let plain = compile(&mut wam, None);
+    let moded = compile(&mut wam, Some(&vec![Mode::In, Mode::Out]));
+
+    match moded.first() {
+        Some(&Line::BuiltIn(BuiltInInstruction::CheckModes(..))) => {},
+        _ => panic!("expected the modes to be checked first.")
+    };
+
+    let plain = head_instrs(&plain);
+    let moded = head_instrs(&moded);
+
+    assert_eq!(plain.len(), moded.len());
+
+    for (plain, moded) in plain.iter().zip(moded.iter()) {
+        if plain.starts_with("get_list") {
+            assert!(moded.starts_with("get_bound_list"));
+        } else if plain.starts_with("get_structure s/1") {
+            assert!(moded.starts_with("get_fresh_structure s/1"));
+        } else {
+            assert_eq!(plain, moded);
+        }
+    }
+
+    assert!(plain.iter().any(|instr| instr.starts_with("get_list")));
+    assert!(moded.iter().any(|instr| instr.starts_with("get_fresh_structure")));
+}
+
+#[test]
+fn test_queries_on_op()
+{
+    let mut wam = Machine::new();
+
+    assert_prolog_success!(&mut wam, "?- op(700, xfx, ===).");
+    assert_prolog_success!(&mut wam, "?- current_op(P, T, ===).", [["P = 700", "T = xfx"]]);
+    assert_prolog_success!(&mut wam, "?- (a === b) = ===(a, b).");
+    assert_prolog_success!(&mut wam, "?- op(200, xfy, [aa, bb]), current_op(P, xfy, bb).",
+                           [["P = 200"]]);
+    assert_prolog_success!(&mut wam, "?- current_op(P, xfx, is).", [["P = 700"]]);
+    assert_prolog_success!(&mut wam, "?- op(0, xfx, ===).");
+
+    assert_prolog_failure!(&mut wam, "?- current_op(_, _, ===).");
+
+    assert_prolog_success!(&mut wam, "?- catch(op(1201, xfx, foo), error(domain_error(operator_priority, 1201), _),
+                                               true).");
+    assert_prolog_success!(&mut wam, "?- catch(op(700, yyy, foo), error(domain_error(operator_specifier, yyy), _),
+                                               true).");
+    assert_prolog_success!(&mut wam, "?- catch(op(_, xfx, foo), error(instantiation_error, _), true).");
+    assert_prolog_success!(&mut wam, "?- catch(op(700, xfx, [foo, 1]), error(type_error(atom, 1), _), true).");
+    assert_prolog_success!(&mut wam, "?- catch(op(1000, xfy, ','), error(permission_error(modify, operator, ','), _),
+                                               true).");
+    assert_prolog_success!(&mut wam, "?- catch(current_op(1201, _, _), error(domain_error(operator_priority, 1201), _),
+                                               true).");
+
+    // the operators a module imports are current in it, and not in user.
+    compile_listing(&mut wam, ::CONTROL);
+    compile_listing(&mut wam, ":- module(client, []).
+                               :- use_module(control).");
+
+    assert_prolog_failure!(&mut wam, "?- current_op(_, _, \\=).");
+
+    submit(&mut wam, "?- module(client).");
+
+    assert_prolog_success!(&mut wam, "?- current_op(P, T, \\=).", [["P = 700", "T = xfx"]]);
+
+    submit(&mut wam, "?- module(user).");
+}